@@ -6,6 +6,9 @@ use http_body_util::Empty;
 use qwasr_sdk::{Config, HttpRequest, Identity};
 use serde::{Deserialize, Serialize};
 
+use crate::retry::{self, RetryPolicy};
+use crate::{config, url};
+
 /// Retrieves the block allocation for a specific vehicle.
 ///
 /// # Errors
@@ -16,10 +19,10 @@ pub async fn allocation<P>(vehicle_id: &str, provider: &P) -> Result<Option<Allo
 where
     P: Config + HttpRequest + Identity,
 {
-    let url = Config::get(provider, "BLOCK_MGT_URL").await?;
+    let base_url = Config::get(provider, "BLOCK_MGT_URL").await?;
     let identity = Config::get(provider, "AZURE_IDENTITY").await?;
 
-    let url = format!("{url}/allocations/vehicles/{vehicle_id}?currentTrip=true");
+    let url = url::join(&base_url, &format!("allocations/vehicles/{vehicle_id}?currentTrip=true"));
     let token = Identity::access_token(provider, identity).await?;
 
     let request = http::Request::builder()
@@ -53,12 +56,15 @@ pub async fn cached_allocation<P>(
 where
     P: Config + HttpRequest + Identity,
 {
-    let url = Config::get(provider, "BLOCK_MGT_URL").await?;
+    let base_url = Config::get(provider, "BLOCK_MGT_URL").await?;
     let identity = Config::get(provider, "AZURE_IDENTITY").await?;
 
     let token = Identity::access_token(provider, identity).await?;
-    let endpoint = format!(
-        "{url}/allocations/vehicles/{vehicle_id}?currentTrip=true&siblings=true&nowUnixTimeSeconds={timestamp}"
+    let endpoint = url::join(
+        &base_url,
+        &format!(
+            "allocations/vehicles/{vehicle_id}?currentTrip=true&siblings=true&nowUnixTimeSeconds={timestamp}"
+        ),
     );
 
     let request = http::Request::builder()
@@ -82,8 +88,16 @@ where
     Ok(allocation)
 }
 
+/// Most pages fetched for one listing, as a backstop against paging that
+/// never ends.
+const MAX_PAGES: u32 = 100;
+
 /// Retrieves all block allocations.
 ///
+/// When `BLOCK_MGT_PAGE_SIZE` is set, allocations are fetched a page at a time
+/// until a page comes back short, or repeats the one before it, as when the
+/// API ignores paging.
+///
 /// # Errors
 ///
 /// Returns an error when the block management API request fails or the
@@ -92,21 +106,100 @@ pub async fn allocations<P>(provider: &P) -> Result<Vec<Allocation>>
 where
     P: Config + HttpRequest + Identity,
 {
-    let url = Config::get(provider, "BLOCK_MGT_URL").await?;
+    list_allocations(None, provider).await
+}
+
+/// Retrieves the block allocations for a service date (`YYYYMMDD`).
+///
+/// Allocations for other dates are dropped. When
+/// `BLOCK_MGT_SERVICE_DATE_FILTER` is set, the API is also asked to filter by
+/// service date, for deployments that support it.
+///
+/// # Errors
+///
+/// Returns an error when the block management API request fails or the
+/// response cannot be deserialized.
+pub async fn allocations_for_service_date<P>(
+    service_date: &str, provider: &P,
+) -> Result<Vec<Allocation>>
+where
+    P: Config + HttpRequest + Identity,
+{
+    let mut allocations = list_allocations(Some(service_date), provider).await?;
+    allocations.retain(|allocation| allocation.service_date == service_date);
+    Ok(allocations)
+}
+
+async fn list_allocations<P>(service_date: Option<&str>, provider: &P) -> Result<Vec<Allocation>>
+where
+    P: Config + HttpRequest + Identity,
+{
+    let base_url = Config::get(provider, "BLOCK_MGT_URL").await?;
     let identity = Config::get(provider, "AZURE_IDENTITY").await?;
+    let page_size =
+        config::checked_setting(provider, "BLOCK_MGT_PAGE_SIZE", |size: &usize| *size > 0).await;
+    let service_date = if config::flag(provider, "BLOCK_MGT_SERVICE_DATE_FILTER").await {
+        service_date
+    } else {
+        None
+    };
 
-    let url = format!("{url}/allocations");
     let token = Identity::access_token(provider, identity).await?;
+    let mut query: Vec<String> =
+        service_date.map(|date| format!("serviceDate={date}")).into_iter().collect();
 
-    let request = http::Request::builder()
-        .method(Method::GET)
-        .uri(url)
-        .header(AUTHORIZATION, format!("Bearer {token}"))
-        .header("Content-Type", "application/json")
-        .body(Empty::<Bytes>::new())
-        .context("building all_allocations request")?;
+    let Some(page_size) = page_size else {
+        let url = url::join(&base_url, &allocations_path(&query));
+        return fetch_allocations(&url, &token, provider).await;
+    };
 
-    let response = HttpRequest::fetch(provider, request)
+    let mut allocations = Vec::new();
+    let mut previous: Option<Vec<Allocation>> = None;
+    query.push(format!("pageSize={page_size}"));
+    for page in 1..=MAX_PAGES {
+        query.push(format!("page={page}"));
+        let url = url::join(&base_url, &allocations_path(&query));
+        query.pop();
+
+        let batch = fetch_allocations(&url, &token, provider).await?;
+        if previous.as_ref() == Some(&batch) {
+            tracing::warn!(page, "block allocations page repeated, stopping");
+            return Ok(allocations);
+        }
+        let last = batch.len() < page_size;
+        allocations.extend_from_slice(&batch);
+        if last {
+            return Ok(allocations);
+        }
+        previous = Some(batch);
+    }
+
+    tracing::warn!(max_pages = MAX_PAGES, "stopped paging block allocations");
+    Ok(allocations)
+}
+
+fn allocations_path(query: &[String]) -> String {
+    if query.is_empty() {
+        return "allocations".to_string();
+    }
+    format!("allocations?{}", query.join("&"))
+}
+
+async fn fetch_allocations<P>(url: &str, token: &str, provider: &P) -> Result<Vec<Allocation>>
+where
+    P: HttpRequest,
+{
+    // retry transient failures, such as during Block Management deploys
+    let request = || {
+        http::Request::builder()
+            .method(Method::GET)
+            .uri(url)
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header("Content-Type", "application/json")
+            .body(Empty::<Bytes>::new())
+            .context("building all_allocations request")
+    };
+    let response = retry::fetch_with_retry(provider, request, RetryPolicy::default())
         .await
         .context("Block management list request failed")?;
 
@@ -124,7 +217,7 @@ struct AllocationResponse {
     all: Vec<Allocation>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Allocation {
     pub operational_block_id: String,
@@ -0,0 +1,21 @@
+//! Canary vehicles, for rolling out a behaviour change to a few vehicles in
+//! production while the rest stay on the stable path.
+
+use qwasr_sdk::Config;
+
+/// Whether experimental processing paths should run for the vehicle.
+///
+/// When `CANARY_VEHICLE_IDS` is set to a comma-separated list of vehicle ids,
+/// only the listed vehicles are canaries. Otherwise every vehicle is, so
+/// experimental paths are governed by their own flags alone.
+pub async fn is_canary(vehicle_id: &str, provider: &impl Config) -> bool {
+    let Ok(vehicle_ids) = Config::get(provider, "CANARY_VEHICLE_IDS").await else {
+        return true;
+    };
+    listed(vehicle_id, &vehicle_ids)
+}
+
+fn listed(vehicle_id: &str, vehicle_ids: &str) -> bool {
+    let mut vehicle_ids = vehicle_ids.split(',').map(str::trim).filter(|id| !id.is_empty());
+    vehicle_ids.any(|id| id == vehicle_id.trim())
+}
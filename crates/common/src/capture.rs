@@ -0,0 +1,145 @@
+//! Forensic capture of raw inbound payloads, so an event that corrupts state
+//! can later be replayed exactly as it was received.
+//!
+//! Capture is disabled unless `RAW_CAPTURE_ENABLED` is set. Payloads are kept
+//! for `RAW_CAPTURE_TTL_SECS` (1 day by default), payloads larger than
+//! `RAW_CAPTURE_MAX_BYTES` are skipped, and only the latest
+//! `RAW_CAPTURE_MAX_ENTRIES` payloads per source are kept.
+//!
+//! The per-source index is updated with a plain read-modify-write, as the
+//! state store can't list keys by prefix or write conditionally. Payloads
+//! captured concurrently can drop each other from the index, so the entry
+//! limit is best effort: a payload missing from the index is never evicted
+//! early, and still expires with its TTL.
+
+use anyhow::{Context, Result};
+use qwasr_sdk::{Config, StateStore};
+use serde_json::Value;
+
+use crate::config;
+use crate::topic::TopicKind;
+
+const KEY_RAW: &str = "raw";
+const TTL_SECS: u64 = 24 * 60 * 60; // 1 day
+const MAX_BYTES: usize = 64 * 1024; // 64 KiB
+const MAX_ENTRIES: usize = 500;
+
+/// JSON fields identifying the vehicle, in the order they're tried: SmarTrak
+/// and AVL events, Dilax messages, then passenger counts.
+const VEHICLE_POINTERS: &[&str] =
+    &["/remoteData/externalId", "/remoteData/remoteName", "/device/site", "/vehicle/id"];
+
+/// Store the raw payload received on a topic of the given kind, keyed by the
+/// vehicle it is for and when it was received (milliseconds since the epoch).
+///
+/// Returns the key the payload was stored under, or `None` when capture is
+/// disabled or the payload is too large.
+///
+/// # Errors
+///
+/// Returns an error when the payload or capture index can't be stored.
+pub async fn capture<P>(
+    provider: &P, kind: TopicKind, received_at: u64, payload: &[u8],
+) -> Result<Option<String>>
+where
+    P: Config + StateStore,
+{
+    if !config::flag(provider, "RAW_CAPTURE_ENABLED").await {
+        return Ok(None);
+    }
+
+    let max_bytes = config::setting(provider, "RAW_CAPTURE_MAX_BYTES").await.unwrap_or(MAX_BYTES);
+    if payload.len() > max_bytes {
+        tracing::warn!(size = payload.len(), max_bytes, "payload too large to capture");
+        return Ok(None);
+    }
+
+    let source = source(kind);
+    let vehicle = vehicle(kind, payload).unwrap_or_else(|| "unknown".to_string());
+    let key = format!("{KEY_RAW}:{source}:{vehicle}:{received_at}");
+    let ttl = config::setting(provider, "RAW_CAPTURE_TTL_SECS").await.unwrap_or(TTL_SECS);
+    StateStore::set(provider, &key, payload, Some(ttl)).await?;
+
+    // keep an index of captured keys so the oldest can be evicted, accepting
+    // that a concurrent capture may overwrite this update (see module docs)
+    let index_key = format!("{KEY_RAW}:{source}:index");
+    let mut index: Vec<String> = StateStore::get(provider, &index_key)
+        .await?
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default();
+    index.retain(|captured| *captured != key);
+    index.push(key.clone());
+
+    let max_entries =
+        config::setting(provider, "RAW_CAPTURE_MAX_ENTRIES").await.unwrap_or(MAX_ENTRIES);
+    let evicted = index.len().saturating_sub(max_entries);
+    for captured in index.drain(..evicted) {
+        StateStore::delete(provider, &captured).await?;
+    }
+
+    let bytes = serde_json::to_vec(&index).context("serializing capture index")?;
+    StateStore::set(provider, &index_key, &bytes, Some(ttl)).await?;
+
+    Ok(Some(key))
+}
+
+/// Keys of the payloads captured for a source kind, oldest first.
+///
+/// # Errors
+///
+/// Returns an error when the capture index can't be read.
+pub async fn captured(provider: &impl StateStore, kind: TopicKind) -> Result<Vec<String>> {
+    let index_key = format!("{KEY_RAW}:{}:index", source(kind));
+    let Some(bytes) = StateStore::get(provider, &index_key).await? else {
+        return Ok(Vec::new());
+    };
+    serde_json::from_slice(&bytes).context("deserializing capture index")
+}
+
+const fn source(kind: TopicKind) -> &'static str {
+    match kind {
+        TopicKind::R9k => "r9k",
+        TopicKind::R9kToSmarTrak => "r9kToSmartrak",
+        TopicKind::DilaxApc => "dilax",
+        TopicKind::CafAvl => "cafAvl",
+        TopicKind::TrainAvl => "trainAvl",
+        TopicKind::PassengerCount => "passengerCount",
+        TopicKind::Unknown => "unknown",
+    }
+}
+
+/// Best-effort vehicle identifier: the train id of an R9K update, or the
+/// vehicle of a JSON event.
+fn vehicle(kind: TopicKind, payload: &[u8]) -> Option<String> {
+    if kind == TopicKind::R9k {
+        let xml = std::str::from_utf8(payload).ok()?;
+        return ["trenPar", "trenImpar"].into_iter().find_map(|tag| {
+            let (_, rest) = xml.split_once(&format!("<{tag}>"))?;
+            let (train_id, _) = rest.split_once(&format!("</{tag}>"))?;
+            Some(train_id.trim().to_string()).filter(|id| !id.is_empty())
+        });
+    }
+
+    let json: Value = serde_json::from_slice(payload).ok()?;
+    VEHICLE_POINTERS.iter().find_map(|pointer| match json.pointer(pointer)? {
+        Value::String(id) => Some(id.trim().to_string()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vehicle_ids() {
+        let smartrak = br#"{"remoteData": {"externalId": "AMP        1005"}}"#;
+        assert_eq!(vehicle(TopicKind::TrainAvl, smartrak).as_deref(), Some("AMP        1005"));
+        let count = br#"{"vehicle": {"id": "59123"}, "timestamp": 1762469343}"#;
+        assert_eq!(vehicle(TopicKind::PassengerCount, count).as_deref(), Some("59123"));
+        let odd = b"<CCO><trenImpar>1235</trenImpar></CCO>";
+        assert_eq!(vehicle(TopicKind::R9k, odd).as_deref(), Some("1235"));
+        assert_eq!(vehicle(TopicKind::DilaxApc, b"not json"), None);
+    }
+}
@@ -0,0 +1,54 @@
+//! Typed reads of optional configuration settings.
+//!
+//! Settings that are unset fall back to the caller's default. Settings that
+//! are set but invalid are logged, so a typo isn't silently ignored, and then
+//! fall back to the default too.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use qwasr_sdk::Config;
+
+/// Whether a boolean flag is set: `1`, `true`, `yes` or `on`, ignoring case
+/// and surrounding whitespace. Any other value turns the flag off.
+pub async fn flag(provider: &impl Config, key: &str) -> bool {
+    Config::get(provider, key).await.is_ok_and(|value| {
+        matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on")
+    })
+}
+
+/// A setting parsed as `T`, ignoring surrounding whitespace, or `None` when it
+/// is unset or can't be parsed.
+pub async fn setting<T: FromStr>(provider: &impl Config, key: &str) -> Option<T> {
+    let value = Config::get(provider, key).await.ok()?;
+    let parsed = value.trim().parse().ok();
+    if parsed.is_none() {
+        tracing::warn!(key, value, "invalid setting, using default");
+    }
+    parsed
+}
+
+/// The `key=value` pairs of a comma-separated mapping setting, such as
+/// `caf=realtime-caf-vp.v1,train=only:realtime-train-vp.v1`, with surrounding
+/// whitespace trimmed. Entries without an `=` are skipped.
+pub fn pairs(mapping: &str) -> impl Iterator<Item = (&str, &str)> {
+    mapping.split(',').filter_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        Some((key.trim(), value.trim()))
+    })
+}
+
+/// A setting parsed as `T`, as for [`setting`], that is also `valid`.
+pub async fn checked_setting<T>(
+    provider: &impl Config, key: &str, valid: impl Fn(&T) -> bool,
+) -> Option<T>
+where
+    T: FromStr + Display,
+{
+    let parsed = setting(provider, key).await?;
+    if !valid(&parsed) {
+        tracing::warn!(key, %parsed, "setting out of range, using default");
+        return None;
+    }
+    Some(parsed)
+}
@@ -1,31 +1,38 @@
 use std::convert::Infallible;
+use std::fmt::{self, Display};
 use std::str::FromStr;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use bytes::Bytes;
 use http::Method;
 use http::header::{CACHE_CONTROL, IF_NONE_MATCH};
 use http_body_util::Empty;
-use qwasr_sdk::{Config, HttpRequest, Identity};
+use qwasr_sdk::{Config, HttpRequest, Identity, bad_gateway};
 use serde::{Deserialize, Serialize};
 
+use crate::{config, url};
+
+/// Width of Fleet API train labels, e.g. `AMP        123`.
+pub const TRAIN_LABEL_WIDTH: usize = 14;
+
 /// Retrieves a vehicle (train) by label.
 ///
 /// # Errors
 ///
-/// Returns an error when the fleet API request fails or the
-/// response cannot be deserialized.
+/// Returns an error when the fleet API request fails or the response cannot
+/// be deserialized, or a [`qwasr_sdk::Error::BadGateway`] when the Fleet API
+/// responds with a non-success status.
 pub async fn vehicle<P>(vehicle_id: &str, provider: &P) -> Result<Option<Vehicle>>
 where
     P: Config + HttpRequest + Identity,
 {
-    let identifier = Identifier::from_str(vehicle_id)?;
+    let identifier = Identifier::parse(vehicle_id, label_width(provider).await);
     let query = identifier.to_query();
     let fleet_url = Config::get(provider, "FLEET_URL").await.context("getting `FLEET_URL`")?;
 
     let request = http::Request::builder()
         .method(Method::GET)
-        .uri(format!("{fleet_url}/vehicles?{query}"))
+        .uri(url::join(&fleet_url, &format!("vehicles?{query}")))
         .header(CACHE_CONTROL, "max-age=300") // 5 minutes
         .header(IF_NONE_MATCH, query)
         .header("Content-Type", "application/json")
@@ -35,15 +42,50 @@ where
     let response =
         HttpRequest::fetch(provider, request).await.context("Fleet API request failed")?;
 
+    let status = response.status();
+    if !status.is_success() {
+        let body = String::from_utf8_lossy(response.body());
+        return Err(bad_gateway!("Fleet API returned {status}: {body}").into());
+    }
+
     let body = response.into_body();
-    let records: Vec<Vehicle> =
-        serde_json::from_slice(&body).context("Failed to deserialize Fleet API response")?;
+    let records = records(&body).context("Failed to deserialize Fleet API response")?;
 
     // get first vehicle that is a train
     let vehicle = records.into_iter().find(Vehicle::is_train);
     Ok(vehicle)
 }
 
+/// Train label width, read from `TRAIN_LABEL_WIDTH`.
+pub async fn label_width(provider: &impl Config) -> usize {
+    config::setting(provider, "TRAIN_LABEL_WIDTH").await.unwrap_or(TRAIN_LABEL_WIDTH)
+}
+
+/// Format a train label as the Fleet API stores it: the prefix followed by the
+/// number right-justified to `width` characters overall. Any existing padding
+/// on the number is replaced, and labels wider than `width` are not truncated.
+#[must_use]
+pub fn format_train_label(prefix: &str, number: &str, width: usize) -> String {
+    let width = width.saturating_sub(prefix.len());
+    format!("{prefix}{:>width$}", number.trim_start())
+}
+
+/// Deserialize Fleet API records, which are usually an array but can be a
+/// single object when querying by a unique id.
+fn records(body: &[u8]) -> serde_json::Result<Vec<Vehicle>> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Records {
+        Many(Vec<Vehicle>),
+        One(Vehicle),
+    }
+
+    Ok(match serde_json::from_slice(body)? {
+        Records::Many(records) => records,
+        Records::One(record) => vec![record],
+    })
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Vehicle {
@@ -70,19 +112,79 @@ impl Vehicle {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Capacity {
     pub seating: i64,
-    pub standing: i64,
+    /// Standing capacity, when specified. It can be less than `total - seating`
+    /// where space is given over to wheelchair bays and the like.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub standing: Option<i64>,
     pub total: i64,
 }
 
+impl Capacity {
+    /// The standing capacity, derived as `total - seating` when not
+    /// specified.
+    #[must_use]
+    pub fn standing_capacity(&self) -> i64 {
+        self.standing.unwrap_or(self.total - self.seating)
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct VehicleType {
     #[serde(rename = "type")]
     pub kind: Option<String>,
 }
 
+/// A Fleet API train label: the class (`AMP` or `ADL`) followed by the number
+/// right-justified to the label width, e.g. `AMP        123`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VehicleLabel(String);
+
+impl VehicleLabel {
+    /// Normalise a train label or Dilax site such as `AM123`, `AMP123` or
+    /// `AMP        123` to `width`, or `None` unless `s` is an `AM`, `AMP`,
+    /// `AD` or `ADL` class followed by digits.
+    #[must_use]
+    pub fn parse(s: &str, width: usize) -> Option<Self> {
+        let (class, number) = [("AMP", "AMP"), ("AM", "AMP"), ("ADL", "ADL"), ("AD", "ADL")]
+            .into_iter()
+            .find_map(|(prefix, class)| s.strip_prefix(prefix).map(|number| (class, number)))?;
+
+        let number = number.trim_start();
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(Self(format_train_label(class, number, width)))
+    }
+
+    #[must_use]
+    pub const fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Fleet API query string selecting the vehicle with this label.
+    #[must_use]
+    pub fn as_fleet_query(&self) -> String {
+        format!("label={}", urlencoding::encode(&self.0))
+    }
+}
+
+impl FromStr for VehicleLabel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s, TRAIN_LABEL_WIDTH).ok_or_else(|| anyhow!("invalid vehicle label: {s}"))
+    }
+}
+
+impl Display for VehicleLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Identifier {
-    Label(String),
+    Label(VehicleLabel),
     Id(String),
 }
 
@@ -93,10 +195,17 @@ impl Default for Identifier {
 }
 
 impl Identifier {
+    /// Identify a vehicle by its train label, formatted to `width`, when `s`
+    /// is a train label, or by id otherwise.
+    #[must_use]
+    pub fn parse(s: &str, width: usize) -> Self {
+        VehicleLabel::parse(s, width).map_or_else(|| Self::Id(s.to_string()), Self::Label)
+    }
+
     #[must_use]
     pub fn to_query(&self) -> String {
         match self {
-            Self::Label(label) => format!("label={}", urlencoding::encode(label)),
+            Self::Label(label) => label.as_fleet_query(),
             Self::Id(id) => format!("id={}", urlencoding::encode(id)),
         }
     }
@@ -106,71 +215,121 @@ impl FromStr for Identifier {
     type Err = Infallible;
 
     fn from_str(s: &str) -> Result<Self, Infallible> {
-        let Some((prefix, suffix)) = ["AMP", "AM", "ADL", "AD"]
-            .into_iter()
-            .find_map(|prefix| s.strip_prefix(prefix).map(|suffix| (prefix, suffix)))
-        else {
-            return Ok(Self::Id(s.to_string()));
-        };
-
-        // train label: format as 14 characters
-        let width = 14usize.saturating_sub(prefix.len());
-        Ok(Self::Label(format!("{prefix}{suffix:>width$}")))
+        Ok(Self::parse(s, TRAIN_LABEL_WIDTH))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Identifier;
+    use super::{Identifier, TRAIN_LABEL_WIDTH, VehicleLabel, format_train_label, records};
+
+    // Should accept the usual array of records.
+    #[test]
+    fn array_response() {
+        let body = br#"[
+            { "id": "59123", "label": "AMP        1005", "type": { "type": "train" } },
+            { "id": "59124", "label": "AMP        1006", "type": { "type": "train" } }
+        ]"#;
+        let vehicles = records(body).expect("should deserialize");
+        let ids: Vec<&str> = vehicles.iter().map(|vehicle| vehicle.id.as_str()).collect();
+        assert_eq!(ids, ["59123", "59124"]);
+
+        assert!(records(b"[]").expect("should deserialize").is_empty());
+    }
+
+    // Should wrap a single object into a one-element list.
+    #[test]
+    fn object_response() {
+        let body = br#"{ "id": "59123", "label": "AMP        1005", "type": { "type": "train" } }"#;
+        let vehicles = records(body).expect("should deserialize");
+        assert_eq!(vehicles.len(), 1);
+        assert_eq!(vehicles[0].id, "59123");
+        assert!(vehicles[0].is_train());
+
+        records(b"\"59123\"").expect_err("should not deserialize");
+    }
 
     #[test]
     fn am_label() {
         let identifier = "AM123".parse().expect("valid label");
-        assert_eq!(identifier, Identifier::Label("AM         123".to_string()));
-        let Identifier::Label(s) = identifier else {
+        let Identifier::Label(label) = identifier else {
             panic!("Expected Identifier::Label");
         };
-        assert_eq!(s.len(), 14);
+        assert_eq!(label.as_str(), "AMP        123");
+        assert_eq!(label.as_str().len(), 14);
     }
 
     #[test]
     fn amp_label() {
         let identifier = "AMP123".parse().expect("valid label");
-        assert_eq!(identifier, Identifier::Label("AMP        123".to_string()));
-        let Identifier::Label(s) = identifier else {
+        let Identifier::Label(label) = identifier else {
             panic!("Expected Identifier::Label");
         };
-        assert_eq!(s.len(), 14);
+        assert_eq!(label.as_str(), "AMP        123");
+        assert_eq!(label.as_str().len(), 14);
     }
 
     #[test]
     fn ad_label() {
         let identifier = "AD123".parse().expect("valid label");
-        assert_eq!(identifier, Identifier::Label("AD         123".to_string()));
-        let Identifier::Label(s) = identifier else {
+        let Identifier::Label(label) = identifier else {
             panic!("Expected Identifier::Label");
         };
-        assert_eq!(s.len(), 14);
+        assert_eq!(label.as_str(), "ADL        123");
+        assert_eq!(label.as_str().len(), 14);
     }
 
     #[test]
     fn adl_label() {
         let identifier = "ADL123".parse().expect("valid label");
-        assert_eq!(identifier, Identifier::Label("ADL        123".to_string()));
-        let Identifier::Label(s) = identifier else {
+        let Identifier::Label(label) = identifier else {
             panic!("Expected Identifier::Label");
         };
-        assert_eq!(s.len(), 14);
+        assert_eq!(label.as_str(), "ADL        123");
+        assert_eq!(label.as_str().len(), 14);
     }
 
     #[test]
     fn already_padded() {
         let identifier = "AMP        123".parse().expect("valid label");
-        assert_eq!(identifier, Identifier::Label("AMP        123".to_string()));
-        let Identifier::Label(s) = identifier else {
+        let Identifier::Label(label) = identifier else {
             panic!("Expected Identifier::Label");
         };
-        assert_eq!(s.len(), 14);
+        assert_eq!(label.as_str(), "AMP        123");
+        assert_eq!(label.as_str().len(), 14);
+    }
+
+    // Should right-justify the number to the label width, replacing any
+    // existing padding.
+    #[test]
+    fn train_label_width() {
+        assert_eq!(format_train_label("AMP", "123", TRAIN_LABEL_WIDTH), "AMP        123");
+        assert_eq!(format_train_label("AM", "123", TRAIN_LABEL_WIDTH), "AM         123");
+        assert_eq!(format_train_label("AMP", "        123", TRAIN_LABEL_WIDTH), "AMP        123");
+        assert_eq!(format_train_label("AMP", "        123", 10), "AMP    123");
+        assert_eq!(format_train_label("AMP", "123456789", 10), "AMP123456789");
+
+        let Identifier::Label(label) = Identifier::parse("ADL123", 8) else {
+            panic!("Expected Identifier::Label");
+        };
+        assert_eq!(label.as_str(), "ADL  123");
+        assert_eq!(Identifier::parse("59123", 8), Identifier::Id("59123".to_string()));
+    }
+
+    // Should normalise every spelling of a train label to the same Fleet label.
+    #[test]
+    fn vehicle_label() {
+        let short: VehicleLabel = "AM123".parse().expect("valid label");
+        let class: VehicleLabel = "AMP123".parse().expect("valid label");
+        let padded: VehicleLabel = "AMP        123".parse().expect("valid label");
+        assert_eq!(short.to_string(), "AMP        123");
+        assert_eq!(short, class);
+        assert_eq!(short, padded);
+        assert_eq!(short.as_fleet_query(), "label=AMP%20%20%20%20%20%20%20%20123");
+
+        "AMP12A".parse::<VehicleLabel>().expect_err("trailing class");
+        "AMP".parse::<VehicleLabel>().expect_err("no number");
+        "59123".parse::<VehicleLabel>().expect_err("not a label");
     }
 
     #[test]
@@ -0,0 +1,92 @@
+//! Geodesic helpers for stop proximity decisions.
+
+use anyhow::{Context, Result, bail};
+
+/// Mean Earth radius (IUGG), in meters.
+const EARTH_RADIUS_METERS: f64 = 6_371_008.8;
+
+/// A WGS84 latitude/longitude pair, in decimal degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinate {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Coordinate {
+    #[must_use]
+    pub const fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+
+    /// Parse a coordinate from the string latitude and longitude reported by
+    /// devices such as Dilax APC sensors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either value is not a number or is out of range.
+    pub fn parse(lat: &str, lon: &str) -> Result<Self> {
+        let lat: f64 = lat.trim().parse().with_context(|| format!("parsing latitude {lat:?}"))?;
+        let lon: f64 = lon.trim().parse().with_context(|| format!("parsing longitude {lon:?}"))?;
+
+        if !(-90.0..=90.0).contains(&lat) {
+            bail!("latitude {lat} out of range");
+        }
+        if !(-180.0..=180.0).contains(&lon) {
+            bail!("longitude {lon} out of range");
+        }
+        Ok(Self { lat, lon })
+    }
+
+    /// Great-circle distance to `other`, in meters.
+    #[must_use]
+    pub fn distance_meters(&self, other: &Self) -> f64 {
+        haversine_meters(self.lat, self.lon, other.lat, other.lon)
+    }
+}
+
+/// Great-circle distance between two points given in decimal degrees, in
+/// meters.
+#[must_use]
+pub fn haversine_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lon2 - lon1).to_radians();
+
+    let a = (phi1.cos() * phi2.cos())
+        .mul_add((d_lambda / 2.0).sin().powi(2), (d_phi / 2.0).sin().powi(2));
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BRITOMART: Coordinate = Coordinate::new(-36.8443, 174.7676);
+    const NEWMARKET: Coordinate = Coordinate::new(-36.8697, 174.7784);
+
+    #[test]
+    fn auckland_stations() {
+        let distance = BRITOMART.distance_meters(&NEWMARKET);
+        assert!((distance - 2983.3).abs() < 1.0, "distance was {distance}");
+        assert!((NEWMARKET.distance_meters(&BRITOMART) - distance).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn one_degree_of_latitude() {
+        let distance = haversine_meters(0.0, 0.0, 1.0, 0.0);
+        assert!((distance - 111_195.08).abs() < 0.1, "distance was {distance}");
+        assert!(haversine_meters(-36.8443, 174.7676, -36.8443, 174.7676).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn parse_strings() {
+        let coordinate =
+            Coordinate::parse("-36.862813838151354", " 174.81012224180958").expect("should parse");
+        assert!((coordinate.lat + 36.862_813_838_151_354).abs() < f64::EPSILON);
+        assert!((coordinate.lon - 174.810_122_241_809_58).abs() < f64::EPSILON);
+
+        Coordinate::parse("north", "174.8").expect_err("should not parse");
+        Coordinate::parse("-91", "174.8").expect_err("latitude out of range");
+        Coordinate::parse("-36.8", "181").expect_err("longitude out of range");
+    }
+}
@@ -0,0 +1,76 @@
+//! God Mode vehicle-to-trip overrides, set by operators and honoured wherever
+//! trips are attributed to vehicles.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use qwasr_sdk::{Config, StateStore};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+const KEY_GOD_MODE: &str = "god_mode:overrides";
+const TTL_GOD_MODE: u64 = 24 * 60 * 60; // 24 hours
+
+/// Override value marking a vehicle as not running any trip.
+const NO_TRIP: &str = "empty";
+
+/// Trip overrides keyed by vehicle id.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GodModeState {
+    pub overrides: HashMap<String, String>,
+}
+
+/// The trip a vehicle has been overridden to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TripOverride {
+    /// The vehicle is running the trip.
+    Trip(String),
+
+    /// The vehicle is not running any trip.
+    NoTrip,
+}
+
+/// Whether God Mode is enabled, by `GOD_MODE_ENABLED`.
+pub async fn is_enabled(provider: &impl Config) -> bool {
+    config::flag(provider, "GOD_MODE_ENABLED").await
+}
+
+/// Load the current God Mode state from the state store.
+///
+/// # Errors
+///
+/// Returns an error if the state cannot be read or deserialized.
+pub async fn load_state(state_store: &impl StateStore) -> Result<GodModeState> {
+    let Some(bytes) = state_store.get(KEY_GOD_MODE).await? else {
+        return Ok(GodModeState::default());
+    };
+    let state = serde_json::from_slice(&bytes).context("deserializing god mode state")?;
+    Ok(state)
+}
+
+/// Save the God Mode state to the state store. Overrides expire after 24
+/// hours.
+///
+/// # Errors
+///
+/// Returns an error if the state cannot be persisted to the state store.
+pub async fn save_state(state_store: &impl StateStore, state: &GodModeState) -> Result<()> {
+    let bytes = serde_json::to_vec(state).context("serializing god mode state")?;
+    state_store.set(KEY_GOD_MODE, &bytes, Some(TTL_GOD_MODE)).await?;
+    Ok(())
+}
+
+/// The unexpired trip override for a vehicle, if any.
+///
+/// # Errors
+///
+/// Returns an error if the state cannot be loaded from the state store.
+pub async fn trip_override(
+    state_store: &impl StateStore, vehicle_id: &str,
+) -> Result<Option<TripOverride>> {
+    let mut state = load_state(state_store).await?;
+    Ok(state.overrides.remove(vehicle_id).map(|trip_id| {
+        if trip_id == NO_TRIP { TripOverride::NoTrip } else { TripOverride::Trip(trip_id) }
+    }))
+}
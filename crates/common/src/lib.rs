@@ -3,4 +3,18 @@
 //! Logic common to the train domain.
 
 pub mod block_mgt;
+pub mod canary;
+pub mod capture;
+pub mod config;
 pub mod fleet;
+pub mod geo;
+pub mod god_mode;
+pub mod limit;
+pub mod occupancy;
+pub mod outcome;
+pub mod payload;
+pub mod r9k;
+pub mod retry;
+pub mod topic;
+pub mod trip_info;
+pub mod url;
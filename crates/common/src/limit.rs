@@ -0,0 +1,224 @@
+//! Runtime-agnostic limit on concurrent operations, such as in-flight upstream
+//! fetches.
+//!
+//! Unlike `tokio::sync::Semaphore`, the limiter relies only on `std` wakers so
+//! it can be used from WASI guests, and the limit is given on each acquire so
+//! it can follow configuration.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use qwasr_sdk::Config;
+
+use crate::config;
+
+/// Default cap on concurrent in-flight fetches, overridden by
+/// `HTTP_MAX_CONCURRENT_FETCHES`.
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+/// Shared by every caller of [`fetch`] so the cap holds across concurrent
+/// requests.
+static FETCH_LIMITER: Limiter = Limiter::new();
+
+/// Run an upstream `fetch` once fewer than `HTTP_MAX_CONCURRENT_FETCHES` are
+/// in flight.
+pub async fn fetch<F: Future>(provider: &impl Config, fetch: F) -> F::Output {
+    let limit = config::setting(provider, "HTTP_MAX_CONCURRENT_FETCHES")
+        .await
+        .unwrap_or(MAX_CONCURRENT_FETCHES);
+    let _permit = FETCH_LIMITER.acquire(limit).await;
+    fetch.await
+}
+
+/// Limits the number of concurrently held [`Permit`]s.
+#[derive(Debug, Default)]
+pub struct Limiter {
+    state: Mutex<State>,
+}
+
+/// Waiters are keyed in arrival order, so the longest waiting is woken first
+/// and a re-polled waiter replaces its own waker rather than queueing again.
+#[derive(Debug, Default)]
+struct State {
+    in_flight: usize,
+    next_key: u64,
+    waiters: BTreeMap<u64, Waker>,
+}
+
+impl State {
+    fn wake_next(&mut self) {
+        if let Some((_, waker)) = self.waiters.pop_first() {
+            waker.wake();
+        }
+    }
+}
+
+impl Limiter {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { state: Mutex::new(State { in_flight: 0, next_key: 0, waiters: BTreeMap::new() }) }
+    }
+
+    /// Wait until fewer than `limit` permits are held, then take one. A limit
+    /// of zero is treated as one.
+    pub const fn acquire(&self, limit: usize) -> Acquire<'_> {
+        Acquire { limiter: self, limit, key: None }
+    }
+
+    /// Number of permits currently held.
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).in_flight
+    }
+}
+
+/// Future returned by [`Limiter::acquire`].
+#[derive(Debug)]
+pub struct Acquire<'a> {
+    limiter: &'a Limiter,
+    limit: usize,
+    key: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Permit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let limiter = self.limiter;
+        let mut state = limiter.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.in_flight < self.limit.max(1) {
+            state.in_flight += 1;
+            if let Some(key) = self.key.take() {
+                state.waiters.remove(&key);
+            }
+            return Poll::Ready(Permit { limiter });
+        }
+
+        let key = *self.key.get_or_insert_with(|| {
+            state.next_key += 1;
+            state.next_key
+        });
+        state.waiters.insert(key, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+        let mut state = self.limiter.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // a waiter that was woken but gave up passes the wakeup on
+        if state.waiters.remove(&key).is_none() {
+            state.wake_next();
+        }
+    }
+}
+
+/// A held slot, released when dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    limiter: &'a Limiter,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.in_flight = state.in_flight.saturating_sub(1);
+        state.wake_next();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Wake;
+    use std::time::Duration;
+
+    use super::*;
+
+    static LIMITER: Limiter = Limiter::new();
+    static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    async fn fetch() {
+        let _permit = LIMITER.acquire(3).await;
+        let active = ACTIVE.fetch_add(1, Ordering::SeqCst) + 1;
+        PEAK.fetch_max(active, Ordering::SeqCst);
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // Should never hold more permits than the limit under concurrent load.
+    #[tokio::test]
+    async fn concurrent_load() {
+        let tasks: Vec<_> = (0..20).map(|_| tokio::spawn(fetch())).collect();
+        for task in tasks {
+            task.await.expect("should complete");
+        }
+
+        assert_eq!(PEAK.load(Ordering::SeqCst), 3);
+        assert_eq!(LIMITER.in_flight(), 0);
+    }
+
+    // Should treat a zero limit as one rather than blocking forever.
+    #[tokio::test]
+    async fn zero_limit() {
+        let limiter = Limiter::new();
+        let permit = limiter.acquire(0).await;
+        assert_eq!(limiter.in_flight(), 1);
+        drop(permit);
+        assert_eq!(limiter.in_flight(), 0);
+    }
+
+    #[derive(Default)]
+    struct Wakes(AtomicUsize);
+
+    impl Wake for Wakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn waiters(limiter: &Limiter) -> usize {
+        limiter.state.lock().expect("lock").waiters.len()
+    }
+
+    // Should queue a re-polled waiter once, and pass a wakeup on when the woken
+    // waiter is dropped before taking its permit.
+    #[tokio::test]
+    async fn dropped_waiter() {
+        let limiter = Limiter::new();
+        let permit = limiter.acquire(1).await;
+
+        let first_wakes = Arc::new(Wakes::default());
+        let second_wakes = Arc::new(Wakes::default());
+        let first_waker = Waker::from(Arc::clone(&first_wakes));
+        let second_waker = Waker::from(Arc::clone(&second_wakes));
+
+        let mut first = limiter.acquire(1);
+        let mut second = limiter.acquire(1);
+        let mut cx = Context::from_waker(&first_waker);
+        assert!(Pin::new(&mut first).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut first).poll(&mut cx).is_pending());
+        let mut cx = Context::from_waker(&second_waker);
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+        assert_eq!(waiters(&limiter), 2);
+
+        drop(permit);
+        assert_eq!(first_wakes.0.load(Ordering::SeqCst), 1);
+        assert_eq!(second_wakes.0.load(Ordering::SeqCst), 0);
+
+        drop(first);
+        assert_eq!(second_wakes.0.load(Ordering::SeqCst), 1);
+        assert!(Pin::new(&mut second).poll(&mut cx).is_ready());
+        assert_eq!(waiters(&limiter), 0);
+    }
+}
@@ -0,0 +1,332 @@
+//! GTFS-RT occupancy status, and its banding from passenger counts.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::fleet::Capacity;
+
+/// GTFS-RT occupancy status, displayed as its numeric value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum OccupancyStatus {
+    Empty = 0,
+    ManySeatsAvailable = 1,
+    FewSeatsAvailable = 2,
+    StandingRoomOnly = 3,
+    CrushedStandingRoomOnly = 4,
+    Full = 5,
+    NotAcceptingPassengers = 6,
+}
+
+impl OccupancyStatus {
+    /// Parse a GTFS-RT status name, e.g. `FEW_SEATS_AVAILABLE`, or its numeric
+    /// value.
+    #[must_use]
+    pub fn parse(status: &str) -> Option<Self> {
+        match status.trim().to_ascii_uppercase().as_str() {
+            "0" | "EMPTY" => Some(Self::Empty),
+            "1" | "MANY_SEATS_AVAILABLE" => Some(Self::ManySeatsAvailable),
+            "2" | "FEW_SEATS_AVAILABLE" => Some(Self::FewSeatsAvailable),
+            "3" | "STANDING_ROOM_ONLY" => Some(Self::StandingRoomOnly),
+            "4" | "CRUSHED_STANDING_ROOM_ONLY" => Some(Self::CrushedStandingRoomOnly),
+            "5" | "FULL" => Some(Self::Full),
+            "6" | "NOT_ACCEPTING_PASSENGERS" => Some(Self::NotAcceptingPassengers),
+            _ => None,
+        }
+    }
+
+    /// Occupancy band for the passenger count.
+    ///
+    /// Each band includes its lower threshold and excludes its upper
+    /// threshold, so a count exactly at a threshold falls into the next
+    /// (fuller) band:
+    ///
+    /// | Band                      | Count                                    |
+    /// |---------------------------|------------------------------------------|
+    /// | `Empty`                   | `< 5%` of seating                        |
+    /// | `ManySeatsAvailable`      | `>= 5%` and `< 40%` of seating           |
+    /// | `FewSeatsAvailable`       | `>= 40%` and `< 90%` of seating          |
+    /// | `StandingRoomOnly`        | `>= 90%` of seating and `< 90%` of load  |
+    /// | `CrushedStandingRoomOnly` | `>= 90%` and `< 100%` of load            |
+    /// | `Full`                    | `>= 100%` of load                        |
+    ///
+    /// The load is seating plus standing capacity, which is the total
+    /// capacity unless standing capacity is specified separately.
+    ///
+    /// Thresholds are rounded down.
+    #[must_use]
+    pub const fn from_counts(count: i64, capacity: VehicleCapacity) -> Self {
+        Self::from_counts_rounded(count, capacity, ThresholdRounding::Floor)
+    }
+
+    /// Occupancy band for the passenger count, as [`Self::from_counts`] but
+    /// with thresholds rounded as given.
+    #[must_use]
+    pub const fn from_counts_rounded(
+        count: i64, capacity: VehicleCapacity, rounding: ThresholdRounding,
+    ) -> Self {
+        let seating = capacity.seating;
+        let load = seating + capacity.standing;
+        if count < rounding.threshold(seating, 5) {
+            Self::Empty
+        } else if count < rounding.threshold(seating, 40) {
+            Self::ManySeatsAvailable
+        } else if count < rounding.threshold(seating, 90) {
+            Self::FewSeatsAvailable
+        } else if count < rounding.threshold(load, 90) {
+            Self::StandingRoomOnly
+        } else if count < load {
+            Self::CrushedStandingRoomOnly
+        } else {
+            Self::Full
+        }
+    }
+}
+
+impl Display for OccupancyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&(*self as u8).to_string())
+    }
+}
+
+/// A vehicle's passenger capacity, as used to band its occupancy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VehicleCapacity {
+    pub seating: i64,
+    pub standing: i64,
+    pub total: i64,
+}
+
+impl From<&Capacity> for VehicleCapacity {
+    fn from(capacity: &Capacity) -> Self {
+        Self {
+            seating: capacity.seating,
+            standing: capacity.standing_capacity(),
+            total: capacity.total,
+        }
+    }
+}
+
+/// How a percentage of capacity is rounded to a whole occupancy threshold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThresholdRounding {
+    /// Round down, e.g. 5% of 49 seats is 2.
+    #[default]
+    Floor,
+    /// Round to the nearest, halves up, e.g. 40% of 49 seats is 20.
+    Round,
+    /// Round up, e.g. 5% of 49 seats is 3.
+    Ceil,
+}
+
+impl ThresholdRounding {
+    /// `percent` of `base`, rounded.
+    const fn threshold(self, base: i64, percent: i64) -> i64 {
+        let scaled = base.saturating_mul(percent);
+        let offset = match self {
+            Self::Floor => 0,
+            Self::Round => 50,
+            Self::Ceil => 99,
+        };
+        scaled.saturating_add(offset).div_euclid(100)
+    }
+}
+
+impl FromStr for ThresholdRounding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "floor" => Ok(Self::Floor),
+            "round" => Ok(Self::Round),
+            "ceil" => Ok(Self::Ceil),
+            _ => Err(anyhow::anyhow!("invalid threshold rounding: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEATING: i64 = 200;
+    const TOTAL: i64 = 400;
+    const CAPACITY: VehicleCapacity =
+        VehicleCapacity { seating: SEATING, standing: TOTAL - SEATING, total: TOTAL };
+
+    fn status(count: i64) -> String {
+        OccupancyStatus::from_counts(count, CAPACITY).to_string()
+    }
+
+    fn capacity(seating: i64, standing: Option<i64>, total: i64) -> VehicleCapacity {
+        VehicleCapacity::from(&Capacity { seating, standing, total })
+    }
+
+    #[test]
+    fn empty_band() {
+        assert_eq!(status(0), "0");
+        assert_eq!(status(9), "0");
+    }
+
+    #[test]
+    fn many_seats_band() {
+        assert_eq!(status(10), "1");
+        assert_eq!(status(79), "1");
+    }
+
+    #[test]
+    fn few_seats_band() {
+        assert_eq!(status(80), "2");
+        assert_eq!(status(179), "2");
+    }
+
+    #[test]
+    fn standing_band() {
+        assert_eq!(status(180), "3");
+        assert_eq!(status(359), "3");
+    }
+
+    #[test]
+    fn crushed_standing_band() {
+        assert_eq!(status(360), "4");
+        assert_eq!(status(399), "4");
+    }
+
+    #[test]
+    fn full_band() {
+        assert_eq!(status(400), "5");
+        assert_eq!(status(1_000), "5");
+    }
+
+    // Should move to the fuller band exactly at each threshold.
+    #[test]
+    fn band_boundaries() {
+        let cases = [
+            (9, OccupancyStatus::Empty),
+            (10, OccupancyStatus::ManySeatsAvailable), // 5% of seating
+            (79, OccupancyStatus::ManySeatsAvailable),
+            (80, OccupancyStatus::FewSeatsAvailable), // 40% of seating
+            (179, OccupancyStatus::FewSeatsAvailable),
+            (180, OccupancyStatus::StandingRoomOnly), // 90% of seating
+            (359, OccupancyStatus::StandingRoomOnly),
+            (360, OccupancyStatus::CrushedStandingRoomOnly), // 90% of total
+            (399, OccupancyStatus::CrushedStandingRoomOnly),
+            (400, OccupancyStatus::Full), // total
+        ];
+        for (count, band) in cases {
+            assert_eq!(OccupancyStatus::from_counts(count, CAPACITY), band, "count {count}");
+        }
+    }
+
+    // Should report each of the six bands for an AM-class train, with
+    // thresholds rounded down.
+    #[test]
+    fn am_class_bands() {
+        let cases = [
+            (0, OccupancyStatus::Empty),
+            (10, OccupancyStatus::Empty),
+            (11, OccupancyStatus::ManySeatsAvailable), // 5% of 230 seats
+            (91, OccupancyStatus::ManySeatsAvailable),
+            (92, OccupancyStatus::FewSeatsAvailable), // 40% of 230 seats
+            (206, OccupancyStatus::FewSeatsAvailable),
+            (207, OccupancyStatus::StandingRoomOnly), // 90% of 230 seats
+            (334, OccupancyStatus::StandingRoomOnly),
+            (335, OccupancyStatus::CrushedStandingRoomOnly), // 90% of 373 total
+            (372, OccupancyStatus::CrushedStandingRoomOnly),
+            (373, OccupancyStatus::Full), // 373 total
+            (400, OccupancyStatus::Full),
+        ];
+        let capacity = capacity(230, None, 373);
+        for (count, band) in cases {
+            assert_eq!(OccupancyStatus::from_counts(count, capacity), band, "count {count}");
+        }
+    }
+
+    // Should derive standing capacity from total less seating when it isn't
+    // specified.
+    #[test]
+    fn derived_standing() {
+        assert_eq!(capacity(SEATING, None, TOTAL), CAPACITY);
+        assert_eq!(capacity(SEATING, Some(TOTAL - SEATING), TOTAL), CAPACITY);
+    }
+
+    // Should band standing occupancy against an explicit standing capacity
+    // rather than total less seating.
+    #[test]
+    fn explicit_standing_bands() {
+        let derived = capacity(SEATING, None, TOTAL);
+        let explicit = capacity(SEATING, Some(150), TOTAL);
+        let cases = [
+            (179, OccupancyStatus::FewSeatsAvailable, OccupancyStatus::FewSeatsAvailable),
+            (180, OccupancyStatus::StandingRoomOnly, OccupancyStatus::StandingRoomOnly),
+            (314, OccupancyStatus::StandingRoomOnly, OccupancyStatus::StandingRoomOnly),
+            // 90% of 350 seating and standing
+            (315, OccupancyStatus::StandingRoomOnly, OccupancyStatus::CrushedStandingRoomOnly),
+            (349, OccupancyStatus::StandingRoomOnly, OccupancyStatus::CrushedStandingRoomOnly),
+            (350, OccupancyStatus::StandingRoomOnly, OccupancyStatus::Full),
+            // 90% of 400 total
+            (360, OccupancyStatus::CrushedStandingRoomOnly, OccupancyStatus::Full),
+            (400, OccupancyStatus::Full, OccupancyStatus::Full),
+        ];
+        for (count, with_derived, with_explicit) in cases {
+            assert_eq!(OccupancyStatus::from_counts(count, derived), with_derived, "count {count}");
+            assert_eq!(
+                OccupancyStatus::from_counts(count, explicit),
+                with_explicit,
+                "count {count}"
+            );
+        }
+    }
+
+    // Should round thresholds as configured, rounding down by default.
+    #[test]
+    fn threshold_rounding() {
+        // 5% of 49 seats is 2.45, 40% is 19.6
+        let seating = capacity(49, None, 100);
+        let cases = [
+            (1, ThresholdRounding::Floor, OccupancyStatus::Empty),
+            (2, ThresholdRounding::Floor, OccupancyStatus::ManySeatsAvailable),
+            (2, ThresholdRounding::Round, OccupancyStatus::ManySeatsAvailable),
+            (2, ThresholdRounding::Ceil, OccupancyStatus::Empty),
+            (19, ThresholdRounding::Floor, OccupancyStatus::FewSeatsAvailable),
+            (19, ThresholdRounding::Round, OccupancyStatus::ManySeatsAvailable),
+            (19, ThresholdRounding::Ceil, OccupancyStatus::ManySeatsAvailable),
+        ];
+        for (count, rounding, expected) in cases {
+            assert_eq!(
+                OccupancyStatus::from_counts_rounded(count, seating, rounding),
+                expected,
+                "count {count} {rounding:?}"
+            );
+        }
+        assert_eq!(
+            OccupancyStatus::from_counts(19, seating),
+            OccupancyStatus::from_counts_rounded(19, seating, ThresholdRounding::default())
+        );
+
+        assert_eq!(
+            " Ceil ".parse::<ThresholdRounding>().expect("should parse"),
+            ThresholdRounding::Ceil
+        );
+        assert_eq!(
+            "round".parse::<ThresholdRounding>().expect("should parse"),
+            ThresholdRounding::Round
+        );
+        assert!("nearest".parse::<ThresholdRounding>().is_err());
+    }
+
+    // Should parse GTFS-RT status names and numeric values.
+    #[test]
+    fn parse() {
+        assert_eq!(
+            OccupancyStatus::parse(" few_seats_available "),
+            Some(OccupancyStatus::FewSeatsAvailable)
+        );
+        assert_eq!(OccupancyStatus::parse("5"), Some(OccupancyStatus::Full));
+        assert_eq!(OccupancyStatus::parse("7"), None);
+        assert_eq!(OccupancyStatus::parse("MEDIUM"), None);
+    }
+}
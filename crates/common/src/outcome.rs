@@ -0,0 +1,102 @@
+//! Outcome of processing an inbound event, logged and counted the same way by
+//! every adapter.
+
+use qwasr_sdk::{Error, Result};
+
+/// Output of a processing step, or why the event was skipped.
+#[derive(Debug)]
+pub enum Skippable<T> {
+    /// The step produced output.
+    Output(T),
+    /// The event deliberately produced no output, for the reason given.
+    Skipped(&'static str),
+}
+
+impl<T> Skippable<T> {
+    /// The output, unless the event was skipped.
+    #[must_use]
+    pub fn output(self) -> Option<T> {
+        match self {
+            Self::Output(output) => Some(output),
+            Self::Skipped(_) => None,
+        }
+    }
+}
+
+/// What processing an inbound event came to.
+#[derive(Debug)]
+pub enum ProcessResult {
+    /// The event was processed, publishing this many messages.
+    Emitted(usize),
+    /// The event deliberately produced no output, for the reason given.
+    Skipped(&'static str),
+    /// The event couldn't be processed.
+    Rejected(Error),
+}
+
+impl ProcessResult {
+    /// Record the outcome of processing an event as `processed_events`, by
+    /// adapter and outcome, returning the error of a rejected event.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that rejected the event.
+    pub fn report(result: Result<Self>, adapter: &str) -> Result<()> {
+        let outcome = result.unwrap_or_else(Self::Rejected);
+        outcome.record(adapter);
+        match outcome {
+            Self::Rejected(err) => Err(err),
+            Self::Emitted(_) | Self::Skipped(_) => Ok(()),
+        }
+    }
+
+    fn record(&self, adapter: &str) {
+        match self {
+            Self::Emitted(count) => {
+                tracing::info!(
+                    monotonic_counter.processed_events = 1,
+                    adapter,
+                    outcome = "emitted",
+                    count
+                );
+            }
+            Self::Skipped(reason) => {
+                tracing::info!(
+                    monotonic_counter.processed_events = 1,
+                    adapter,
+                    outcome = "skipped",
+                    reason
+                );
+            }
+            Self::Rejected(err) => {
+                tracing::warn!(
+                    monotonic_counter.processed_events = 1,
+                    adapter,
+                    outcome = "rejected",
+                    code = %err.code(),
+                    description = %err.description(),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Should succeed for emitted and skipped events, and return the error of
+    // rejected ones.
+    #[test]
+    fn report() {
+        assert!(ProcessResult::report(Ok(ProcessResult::Emitted(2)), "test").is_ok());
+        assert!(ProcessResult::report(Ok(ProcessResult::Skipped("throttled")), "test").is_ok());
+
+        let err = Error::BadRequest {
+            code: "missing_vehicle".to_string(),
+            description: "missing vehicle identifier".to_string(),
+        };
+        let rejected = ProcessResult::report(Err(err), "test");
+        assert_eq!(rejected.expect_err("should reject").code(), "missing_vehicle");
+    }
+}
@@ -0,0 +1,44 @@
+//! Guard against inbound payloads large enough to exhaust guest memory while
+//! being parsed.
+
+use anyhow::{Result, bail};
+use qwasr_sdk::Config;
+
+use crate::config;
+
+/// Default maximum inbound payload size, in bytes.
+pub const MAX_PAYLOAD_BYTES: usize = 1024 * 1024; // 1 MiB
+
+/// Maximum inbound payload size, in bytes, read from `MAX_PAYLOAD_BYTES`.
+pub async fn max_size(provider: &impl Config) -> usize {
+    config::setting(provider, "MAX_PAYLOAD_BYTES").await.unwrap_or(MAX_PAYLOAD_BYTES)
+}
+
+/// Check a payload is no larger than `max_size` before it is parsed.
+///
+/// # Errors
+///
+/// Returns an error when the payload exceeds `max_size`.
+pub fn check(payload: &[u8], max_size: usize) -> Result<()> {
+    if payload.len() > max_size {
+        bail!("payload of {} bytes exceeds the {max_size} byte limit", payload.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_limit() {
+        check(&[0; 16], 16).expect("should accept");
+        check(&[], 16).expect("should accept");
+    }
+
+    #[test]
+    fn over_limit() {
+        let err = check(&[0; 17], 16).expect_err("should reject");
+        assert_eq!(err.to_string(), "payload of 17 bytes exceeds the 16 byte limit");
+    }
+}
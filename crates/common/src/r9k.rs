@@ -0,0 +1,133 @@
+//! R9K domain errors shared by the R9K connector and adapter so both report
+//! the same code and description for the same condition.
+
+use qwasr_sdk::Error;
+use thiserror::Error;
+
+/// R9K message error.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum R9kError {
+    /// The message timestamp is invalid.
+    #[error("{0}")]
+    BadTime(String),
+
+    /// The message contains no updates.
+    #[error("{0}")]
+    NoUpdate(String),
+
+    /// The arrival/departure time is invalid (negative or 0).
+    #[error("arrival/departure time <= 0")]
+    NoActualUpdate,
+
+    /// The message is older than allowed, by the given number of seconds.
+    #[error("outdated by {0} seconds")]
+    Outdated(i64),
+
+    /// The message is future-dated, by the given number of seconds.
+    #[error("too early by {0} seconds")]
+    WrongTime(i64),
+
+    /// The XML is invalid.
+    #[error("{0}")]
+    InvalidXml(String),
+}
+
+impl R9kError {
+    /// Kind of error, for matching without comparing codes.
+    #[must_use]
+    pub const fn kind(&self) -> R9kErrorKind {
+        match self {
+            Self::BadTime(_) => R9kErrorKind::BadTime,
+            Self::NoUpdate(_) => R9kErrorKind::NoUpdate,
+            Self::NoActualUpdate => R9kErrorKind::NoActualUpdate,
+            Self::Outdated(_) => R9kErrorKind::Outdated,
+            Self::WrongTime(_) => R9kErrorKind::WrongTime,
+            Self::InvalidXml(_) => R9kErrorKind::InvalidXml,
+        }
+    }
+
+    /// Machine-readable error code.
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// Kind of [`R9kError`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum R9kErrorKind {
+    BadTime,
+    NoUpdate,
+    NoActualUpdate,
+    Outdated,
+    WrongTime,
+    InvalidXml,
+}
+
+impl R9kErrorKind {
+    /// Machine-readable error code. Codes are coarser than kinds: every
+    /// timing error is `bad_time` and every missing update is `no_update`.
+    #[must_use]
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::BadTime | Self::Outdated | Self::WrongTime => "bad_time",
+            Self::NoUpdate | Self::NoActualUpdate => "no_update",
+            Self::InvalidXml => "invalid_message",
+        }
+    }
+}
+
+impl From<R9kError> for Error {
+    fn from(err: R9kError) -> Self {
+        Self::BadRequest { code: err.code().to_string(), description: err.to_string() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_request() {
+        let err = Error::from(R9kError::NoUpdate("contains no updates".to_string()));
+        let Error::BadRequest { code, description } = err else {
+            panic!("should be a bad request");
+        };
+        assert_eq!(code, "no_update");
+        assert_eq!(description, "contains no updates");
+    }
+
+    // Should keep the code and description of each kind through the
+    // conversion to a bad request.
+    #[test]
+    fn kind_codes() {
+        let cases = [
+            (R9kError::BadTime("invalid local time".to_string()), "bad_time", "invalid local time"),
+            (
+                R9kError::NoUpdate("contains no updates".to_string()),
+                "no_update",
+                "contains no updates",
+            ),
+            (R9kError::NoActualUpdate, "no_update", "arrival/departure time <= 0"),
+            (R9kError::Outdated(61), "bad_time", "outdated by 61 seconds"),
+            (R9kError::WrongTime(31), "bad_time", "too early by 31 seconds"),
+            (
+                R9kError::InvalidXml("unexpected end".to_string()),
+                "invalid_message",
+                "unexpected end",
+            ),
+        ];
+
+        for (r9k_err, code, description) in cases {
+            assert_eq!(r9k_err.code(), r9k_err.kind().code());
+            let Error::BadRequest { code: converted, description: described } =
+                Error::from(r9k_err)
+            else {
+                panic!("should be a bad request");
+            };
+            assert_eq!(converted, code);
+            assert_eq!(described, description);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Retries for upstream HTTP calls that fail transiently, such as the 502s
+//! and 504s seen from Block Management and Trip Management during deploys.
+
+use std::any::Any;
+use std::error::Error;
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+use http::{Request, Response};
+use qwasr_sdk::HttpRequest;
+
+/// How many times to attempt a request, and how long to wait before the first
+/// retry. The wait doubles on each subsequent retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 3, base_delay: Duration::from_millis(200) }
+    }
+}
+
+impl RetryPolicy {
+    const fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay.saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+/// Fetch the request built by `build_request`, retrying connection errors and
+/// 5xx responses up to the policy's attempts. The request is rebuilt for each
+/// attempt as requests can't be cloned.
+///
+/// Returns the first response that isn't a 5xx, or the last response once the
+/// attempts are exhausted.
+///
+/// # Errors
+///
+/// Returns an error when the request can't be built, or the last attempt
+/// fails to connect.
+pub async fn fetch_with_retry<P, T, F>(
+    provider: &P, build_request: F, policy: RetryPolicy,
+) -> Result<Response<Bytes>>
+where
+    P: HttpRequest,
+    F: Fn() -> Result<Request<T>>,
+    T: http_body::Body + Any,
+    T::Data: Into<Vec<u8>>,
+    T::Error: Into<Box<dyn Error + Send + Sync + 'static>>,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 1;
+
+    loop {
+        let result = HttpRequest::fetch(provider, build_request()?).await;
+        let retryable = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(_) => true,
+        };
+        if !retryable || attempt >= max_attempts {
+            return result;
+        }
+
+        match &result {
+            Ok(response) => tracing::warn!(attempt, status = %response.status(), "retrying"),
+            Err(err) => tracing::warn!(attempt, "retrying: {err}"),
+        }
+        let delay = policy.delay(attempt);
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        attempt += 1;
+    }
+}
+
+/// Wait for `delay` without blocking the executor, which runs every request
+/// the guest is handling.
+async fn sleep(delay: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let nanos = u64::try_from(delay.as_nanos()).unwrap_or(u64::MAX);
+        wasip3::clocks::monotonic_clock::wait_for(nanos).await;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(delay).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy { max_attempts: 4, base_delay: Duration::from_millis(100) };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+    }
+}
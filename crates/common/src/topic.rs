@@ -0,0 +1,203 @@
+//! Classification of inbound message topics.
+//!
+//! Topics are environment-prefixed (e.g. `dev-realtime-r9k.v1`), so a topic is
+//! classified by the well-known name it contains.
+
+use std::str::FromStr;
+
+use anyhow::{Error, anyhow};
+use qwasr_sdk::Config;
+
+use crate::config;
+
+/// The kind of message carried by a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicKind {
+    /// R9K train updates from KiwiRail.
+    R9k,
+
+    /// SmarTrak events transformed from R9K updates.
+    R9kToSmarTrak,
+
+    /// Dilax APC passenger counts.
+    DilaxApc,
+
+    /// CAF train AVL events.
+    CafAvl,
+
+    /// SmarTrak train AVL events.
+    TrainAvl,
+
+    /// Passenger count (occupancy) updates.
+    PassengerCount,
+
+    /// A topic we don't handle.
+    Unknown,
+}
+
+const TOPICS: &[(&str, TopicKind)] = &[
+    ("realtime-r9k.v1", TopicKind::R9k),
+    ("realtime-r9k-to-smartrak.v1", TopicKind::R9kToSmarTrak),
+    ("realtime-dilax-apc.v2", TopicKind::DilaxApc),
+    ("realtime-caf-avl.v1", TopicKind::CafAvl),
+    ("realtime-train-avl.v1", TopicKind::TrainAvl),
+    ("realtime-passenger-count.v1", TopicKind::PassengerCount),
+];
+
+impl TopicKind {
+    /// Classify a topic, returning [`TopicKind::Unknown`] for unhandled topics.
+    #[must_use]
+    pub fn classify(topic: &str) -> Self {
+        topic.parse().unwrap_or(Self::Unknown)
+    }
+
+    /// The vehicle tag (lowercase) a vehicle must carry for events on this
+    /// topic to be processed, if the topic is restricted by tag.
+    #[must_use]
+    pub const fn vehicle_tag(self) -> Option<&'static str> {
+        match self {
+            Self::CafAvl => Some("caf"),
+            Self::TrainAvl => Some("smartrak"),
+            _ => None,
+        }
+    }
+
+    /// Whether a vehicle with the given tag should be processed for this topic.
+    /// Untagged vehicles are always processed.
+    #[must_use]
+    pub fn accepts_tag(self, tag: Option<&str>) -> bool {
+        match (self.vehicle_tag(), tag) {
+            (Some(expected), Some(tag)) => tag.eq_ignore_ascii_case(expected),
+            _ => true,
+        }
+    }
+}
+
+impl FromStr for TopicKind {
+    type Err = Error;
+
+    fn from_str(topic: &str) -> Result<Self, Self::Err> {
+        TOPICS
+            .iter()
+            .find(|(name, _)| topic.contains(name))
+            .map(|(_, kind)| *kind)
+            .ok_or_else(|| anyhow!("unhandled topic: {topic}"))
+    }
+}
+
+/// Environment prefixes accepted on inbound topics. By default any topic is
+/// accepted, classified by the name it contains; when strict, only the
+/// environment's own prefix and any extras shared across environments, such
+/// as `global`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopicPrefixes {
+    /// Accept any topic.
+    Any,
+    /// Accept only topics with one of these prefixes.
+    Only(Vec<String>),
+}
+
+impl TopicPrefixes {
+    /// Strictly accept the `env` prefix and the comma-separated `extras`.
+    #[must_use]
+    pub fn strict(env: &str, extras: &str) -> Self {
+        let extras = extras.split(',').map(str::trim).filter(|extra| !extra.is_empty());
+        let prefixes = std::iter::once(env.trim()).chain(extras);
+        Self::Only(prefixes.map(|prefix| format!("{prefix}-")).collect())
+    }
+
+    /// Any topic, unless `TOPIC_STRICT_PREFIXES` is set, when prefixes are
+    /// read from `ENV` (`dev` by default) and `TOPIC_EXTRA_PREFIXES`, none by
+    /// default.
+    pub async fn load(provider: &impl Config) -> Self {
+        if !config::flag(provider, "TOPIC_STRICT_PREFIXES").await {
+            return Self::Any;
+        }
+        let env = Config::get(provider, "ENV").await.unwrap_or_else(|_| "dev".to_string());
+        let extras = Config::get(provider, "TOPIC_EXTRA_PREFIXES").await.unwrap_or_default();
+        Self::strict(&env, &extras)
+    }
+
+    /// The topic without its prefix, or `None` when its prefix isn't
+    /// accepted. Any topic is returned as is.
+    #[must_use]
+    pub fn strip<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        match self {
+            Self::Any => Some(topic),
+            Self::Only(prefixes) => {
+                prefixes.iter().find_map(|prefix| topic.strip_prefix(prefix.as_str()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        assert_eq!(TopicKind::classify("dev-realtime-r9k.v1"), TopicKind::R9k);
+        assert_eq!(
+            TopicKind::classify("dev-realtime-r9k-to-smartrak.v1"),
+            TopicKind::R9kToSmarTrak
+        );
+        assert_eq!(TopicKind::classify("prd-realtime-dilax-apc.v2"), TopicKind::DilaxApc);
+        assert_eq!(TopicKind::classify("dev-realtime-caf-avl.v1"), TopicKind::CafAvl);
+        assert_eq!(TopicKind::classify("dev-realtime-train-avl.v1"), TopicKind::TrainAvl);
+        assert_eq!(
+            TopicKind::classify("dev-realtime-passenger-count.v1"),
+            TopicKind::PassengerCount
+        );
+    }
+
+    #[test]
+    fn unknown_topic() {
+        assert_eq!(TopicKind::classify("dev-realtime-gtfs-vp.v1"), TopicKind::Unknown);
+        assert!("dev-realtime-dilax-apc.v1".parse::<TopicKind>().is_err());
+    }
+
+    #[test]
+    fn tag_rules() {
+        assert!(TopicKind::CafAvl.accepts_tag(Some("CAF")));
+        assert!(!TopicKind::CafAvl.accepts_tag(Some("smartrak")));
+        assert!(TopicKind::TrainAvl.accepts_tag(Some("SmarTrak")));
+        assert!(!TopicKind::TrainAvl.accepts_tag(Some("caf")));
+        assert!(TopicKind::TrainAvl.accepts_tag(None));
+        assert!(TopicKind::R9k.accepts_tag(Some("caf")));
+    }
+
+    // Should accept any topic, classified by the name it contains, by default.
+    #[test]
+    fn any_prefix() {
+        let prefixes = TopicPrefixes::Any;
+        assert_eq!(prefixes.strip("prd-realtime-r9k.v1"), Some("prd-realtime-r9k.v1"));
+        assert_eq!(TopicKind::classify("prd-realtime-r9k.v1"), TopicKind::R9k);
+    }
+
+    // Should accept the environment's own prefix only, when strict.
+    #[test]
+    fn primary_prefix() {
+        let prefixes = TopicPrefixes::strict("dev", "");
+        assert_eq!(prefixes.strip("dev-realtime-r9k.v1"), Some("realtime-r9k.v1"));
+        assert_eq!(prefixes.strip("global-realtime-r9k.v1"), None);
+    }
+
+    // Should accept configured extra prefixes.
+    #[test]
+    fn extra_prefix() {
+        let prefixes = TopicPrefixes::strict("dev", "global, shared");
+        assert_eq!(prefixes.strip("global-realtime-r9k.v1"), Some("realtime-r9k.v1"));
+        assert_eq!(prefixes.strip("shared-realtime-dilax-apc.v2"), Some("realtime-dilax-apc.v2"));
+        assert_eq!(prefixes.strip("dev-realtime-r9k.v1"), Some("realtime-r9k.v1"));
+    }
+
+    // Should reject other environments' topics.
+    #[test]
+    fn foreign_prefix() {
+        let prefixes = TopicPrefixes::strict("dev", "global");
+        assert_eq!(prefixes.strip("prd-realtime-r9k.v1"), None);
+        assert_eq!(prefixes.strip("realtime-r9k.v1"), None);
+        assert_eq!(prefixes.strip("devtest-realtime-r9k.v1"), None);
+    }
+}
@@ -0,0 +1,69 @@
+//! Vehicle trip info shared by every crate reading or writing the
+//! `apc:vehicleTripInfo` store keys, so there is one serialization format.
+
+use serde::{Deserialize, Serialize};
+
+const KEY_TRIP_INFO: &str = "apc:vehicleTripInfo";
+
+/// State store key holding the trip info for a vehicle.
+#[must_use]
+pub fn key(vehicle_id: &str) -> String {
+    format!("{KEY_TRIP_INFO}:{vehicle_id}")
+}
+
+/// The trip a vehicle was last seen on, along with the last message received
+/// from it.
+///
+/// The message type is left to the owning crate; readers with no interest in
+/// it can use the default, untyped JSON value.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VehicleTripInfo<M = serde_json::Value> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_received_timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dilax_message: Option<M>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_id: Option<String>,
+    pub vehicle_info: VehicleInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VehicleInfo {
+    pub label: Option<String>,
+    #[serde(rename = "vehicleId")]
+    pub vehicle_id: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn stored_format() {
+        let info: VehicleTripInfo = VehicleTripInfo {
+            last_received_timestamp: Some("1762469343".to_string()),
+            dilax_message: None,
+            trip_id: Some("trip-1".to_string()),
+            stop_id: None,
+            vehicle_info: VehicleInfo {
+                label: Some("AMP        1005".to_string()),
+                vehicle_id: "59123".to_string(),
+            },
+        };
+
+        let value = serde_json::to_value(&info).expect("should serialize");
+        assert_eq!(
+            value,
+            json!({
+                "last_received_timestamp": "1762469343",
+                "trip_id": "trip-1",
+                "vehicle_info": { "label": "AMP        1005", "vehicleId": "59123" }
+            })
+        );
+        assert_eq!(key("59123"), "apc:vehicleTripInfo:59123");
+    }
+}
@@ -0,0 +1,37 @@
+//! URL helpers for service endpoints configured via environment variables.
+
+/// Join a base URL and a path with exactly one `/` between them, regardless of
+/// whether the base has a trailing slash or the path a leading one.
+#[must_use]
+pub fn join(base: &str, path: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn without_trailing_slash() {
+        assert_eq!(join("https://api.at.govt.nz", "vehicles"), "https://api.at.govt.nz/vehicles");
+        assert_eq!(join("https://api.at.govt.nz", "/vehicles"), "https://api.at.govt.nz/vehicles");
+    }
+
+    #[test]
+    fn with_trailing_slash() {
+        assert_eq!(join("https://api.at.govt.nz/", "vehicles"), "https://api.at.govt.nz/vehicles");
+        assert_eq!(
+            join("https://api.at.govt.nz//", "/vehicles"),
+            "https://api.at.govt.nz/vehicles"
+        );
+        assert_eq!(
+            join("https://api.at.govt.nz/fleet/", "vehicles?label=AMP"),
+            "https://api.at.govt.nz/fleet/vehicles?label=AMP"
+        );
+    }
+
+    #[test]
+    fn path_trailing_slash_kept() {
+        assert_eq!(join("https://gtfs/", "stopstypes/"), "https://gtfs/stopstypes/");
+    }
+}
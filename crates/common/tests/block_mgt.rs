@@ -0,0 +1,132 @@
+//! Tests for listing block allocations.
+
+mod provider;
+
+use common::block_mgt::{Allocation, allocations, allocations_for_service_date};
+use http::StatusCode;
+use serde_json::{Value, json};
+
+use self::provider::MockProvider;
+
+fn allocation(trip_id: &str, service_date: &str) -> Value {
+    json!({
+        "operationalBlockId": "101-202",
+        "tripId": trip_id,
+        "serviceDate": service_date,
+        "startTime": "09:49:03",
+        "vehicleId": "59123",
+        "vehicleLabel": "AMP        1005",
+        "routeId": "EAST-201",
+        "directionId": 0,
+        "referenceId": "1005",
+        "endTime": "12:49:03",
+        "delay": 0,
+        "startDatetime": 1_762_462_143,
+        "endDatetime": 1_762_472_943,
+        "isCanceled": false,
+        "isCopied": false,
+        "timezone": "Pacific/Auckland",
+        "creationDatetime": "2025-11-06T12:00:00Z"
+    })
+}
+
+fn page(allocations: &[Value]) -> String {
+    json!({ "all": allocations }).to_string()
+}
+
+fn trip_ids(allocations: &[Allocation]) -> Vec<&str> {
+    allocations.iter().map(|allocation| allocation.trip_id.as_str()).collect()
+}
+
+// Should fetch every page until an empty one when a page size is set.
+#[tokio::test]
+async fn paged() {
+    let provider = MockProvider::default()
+        .with_config("BLOCK_MGT_PAGE_SIZE", "2")
+        .with_response(
+            StatusCode::OK,
+            &page(&[allocation("trip-1", "20251107"), allocation("trip-2", "20251107")]),
+        )
+        .with_response(
+            StatusCode::OK,
+            &page(&[allocation("trip-3", "20251107"), allocation("trip-4", "20251107")]),
+        )
+        .with_response(StatusCode::OK, &page(&[]));
+
+    let all = allocations(&provider).await.expect("should fetch allocations");
+    assert_eq!(trip_ids(&all), ["trip-1", "trip-2", "trip-3", "trip-4"]);
+    assert_eq!(
+        provider.uris(),
+        [
+            "http://localhost:8080/allocations?pageSize=2&page=1",
+            "http://localhost:8080/allocations?pageSize=2&page=2",
+            "http://localhost:8080/allocations?pageSize=2&page=3",
+        ]
+    );
+}
+
+// Should stop at a short page, which must be the last.
+#[tokio::test]
+async fn short_page() {
+    let provider = MockProvider::default()
+        .with_config("BLOCK_MGT_PAGE_SIZE", "2")
+        .with_response(
+            StatusCode::OK,
+            &page(&[allocation("trip-1", "20251107"), allocation("trip-2", "20251107")]),
+        )
+        .with_response(StatusCode::OK, &page(&[allocation("trip-3", "20251107")]));
+
+    let all = allocations(&provider).await.expect("should fetch allocations");
+    assert_eq!(trip_ids(&all), ["trip-1", "trip-2", "trip-3"]);
+    assert_eq!(provider.uris().len(), 2);
+}
+
+// Should stop, keeping one copy, when the API ignores paging and answers
+// every page the same.
+#[tokio::test]
+async fn repeated_page() {
+    let full = page(&[allocation("trip-1", "20251107"), allocation("trip-2", "20251107")]);
+    let provider = MockProvider::default()
+        .with_config("BLOCK_MGT_PAGE_SIZE", "2")
+        .with_response(StatusCode::OK, &full)
+        .with_response(StatusCode::OK, &full);
+
+    let all = allocations(&provider).await.expect("should fetch allocations");
+    assert_eq!(trip_ids(&all), ["trip-1", "trip-2"]);
+    assert_eq!(provider.uris().len(), 2);
+}
+
+// Should fetch all allocations in one request without a page size.
+#[tokio::test]
+async fn unpaged() {
+    let provider = MockProvider::default().with_response(
+        StatusCode::OK,
+        &page(&[allocation("trip-1", "20251107"), allocation("trip-2", "20251108")]),
+    );
+
+    let all = allocations(&provider).await.expect("should fetch allocations");
+    assert_eq!(trip_ids(&all), ["trip-1", "trip-2"]);
+    assert_eq!(provider.uris(), ["http://localhost:8080/allocations"]);
+}
+
+// Should drop other dates, asking the API to filter by service date only
+// when enabled.
+#[tokio::test]
+async fn for_service_date() {
+    let response = page(&[allocation("trip-1", "20251107"), allocation("trip-2", "20251108")]);
+    for (filter, uri) in [
+        (None, "http://localhost:8080/allocations"),
+        (Some("true"), "http://localhost:8080/allocations?serviceDate=20251107"),
+    ] {
+        let mut provider = MockProvider::default().with_response(StatusCode::OK, &response);
+        if let Some(filter) = filter {
+            provider = provider.with_config("BLOCK_MGT_SERVICE_DATE_FILTER", filter);
+        }
+
+        let all = allocations_for_service_date("20251107", &provider)
+            .await
+            .expect("should fetch allocations");
+        assert_eq!(trip_ids(&all), ["trip-1"]);
+        assert_eq!(provider.uris(), [uri]);
+    }
+}
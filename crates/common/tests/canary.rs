@@ -0,0 +1,20 @@
+//! Tests for selecting canary vehicles.
+
+mod provider;
+
+use common::canary;
+
+use self::provider::MockProvider;
+
+// Should only treat listed vehicles as canaries once a list is set.
+#[tokio::test]
+async fn whitelist() {
+    let provider = MockProvider::default().with_config("CANARY_VEHICLE_IDS", "59123, 59124,");
+    assert!(canary::is_canary("59123", &provider).await);
+    assert!(canary::is_canary("59124", &provider).await);
+    assert!(!canary::is_canary("59125", &provider).await);
+    assert!(!canary::is_canary("", &provider).await);
+
+    let unset = MockProvider::default();
+    assert!(canary::is_canary("59125", &unset).await);
+}
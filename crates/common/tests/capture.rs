@@ -0,0 +1,63 @@
+//! Tests for capturing raw inbound payloads.
+
+mod provider;
+
+use common::capture::{capture, captured};
+use common::topic::TopicKind;
+use qwasr_sdk::StateStore;
+
+use self::provider::MockProvider;
+
+const NOW: u64 = 1_762_469_343_000;
+
+fn enabled() -> MockProvider {
+    MockProvider::default().with_config("RAW_CAPTURE_ENABLED", "true")
+}
+
+// Should store the exact payload under its vehicle and receipt time.
+#[tokio::test]
+async fn stored() {
+    let provider = enabled();
+    let payload = br#"{"device": {"site": "AM1005"}, "clock": {"utc": "1762469343"}}"#;
+
+    let key = capture(&provider, TopicKind::DilaxApc, NOW, payload)
+        .await
+        .expect("should capture")
+        .expect("key");
+    assert_eq!(key, "raw:dilax:AM1005:1762469343000");
+
+    let stored = StateStore::get(&provider, &key).await.expect("should get");
+    assert_eq!(stored.as_deref(), Some(payload.as_slice()));
+    let index = captured(&provider, TopicKind::DilaxApc).await.expect("should list");
+    assert_eq!(index, [key]);
+}
+
+// Should do nothing unless enabled, and skip oversized payloads.
+#[tokio::test]
+async fn disabled_or_oversized() {
+    let provider = MockProvider::default();
+    let key = capture(&provider, TopicKind::R9k, NOW, b"<CCO/>").await.expect("should skip");
+    assert!(key.is_none());
+    assert!(provider.is_empty());
+
+    let provider = enabled().with_config("RAW_CAPTURE_MAX_BYTES", "4");
+    let key = capture(&provider, TopicKind::R9k, NOW, b"<CCO/>").await.expect("should skip");
+    assert!(key.is_none());
+    assert!(provider.is_empty());
+}
+
+// Should keep only the latest payloads per source.
+#[tokio::test]
+async fn capped() {
+    let provider = enabled().with_config("RAW_CAPTURE_MAX_ENTRIES", "2");
+    let payload = b"<CCO><ActualizarDatosTren><trenPar>1234</trenPar></ActualizarDatosTren></CCO>";
+
+    for offset in 0..3 {
+        capture(&provider, TopicKind::R9k, NOW + offset, payload).await.expect("should capture");
+    }
+
+    let index = captured(&provider, TopicKind::R9k).await.expect("should list");
+    assert_eq!(index, ["raw:r9k:1234:1762469343001", "raw:r9k:1234:1762469343002"]);
+    let evicted = StateStore::get(&provider, "raw:r9k:1234:1762469343000").await.expect("get");
+    assert!(evicted.is_none());
+}
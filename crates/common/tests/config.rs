@@ -0,0 +1,46 @@
+//! Tests for reading typed settings.
+
+mod provider;
+
+use common::config::{checked_setting, flag, pairs, setting};
+
+use self::provider::MockProvider;
+
+// Should only turn a flag on for the accepted spellings of true.
+#[tokio::test]
+async fn flags() {
+    let provider = MockProvider::default()
+        .with_config("ON", " Yes ")
+        .with_config("ONE", "1")
+        .with_config("OFF", "off")
+        .with_config("TYPO", "ture");
+    assert!(flag(&provider, "ON").await);
+    assert!(flag(&provider, "ONE").await);
+    assert!(!flag(&provider, "OFF").await);
+    assert!(!flag(&provider, "TYPO").await);
+    assert!(!flag(&provider, "UNSET").await);
+}
+
+// Should parse set values, treating invalid or out of range ones as unset.
+#[tokio::test]
+async fn settings() {
+    let provider = MockProvider::default()
+        .with_config("SECS", " 300 ")
+        .with_config("NEGATIVE", "-5")
+        .with_config("TYPO", "5 mins");
+    assert_eq!(setting::<i64>(&provider, "SECS").await, Some(300));
+    assert_eq!(setting::<i64>(&provider, "TYPO").await, None);
+    assert_eq!(setting::<i64>(&provider, "UNSET").await, None);
+
+    let positive = |secs: &i64| *secs > 0;
+    assert_eq!(checked_setting(&provider, "SECS", positive).await, Some(300));
+    assert_eq!(checked_setting(&provider, "NEGATIVE", positive).await, None);
+}
+
+// Should split a mapping into trimmed pairs, skipping entries without a value.
+#[test]
+fn mapping_pairs() {
+    let pairs: Vec<_> = pairs(" caf = realtime-caf-vp.v1,train,smartrak=only:vp ,=x").collect();
+    assert_eq!(pairs, [("caf", "realtime-caf-vp.v1"), ("smartrak", "only:vp"), ("", "x")]);
+    assert_eq!(pairs("").count(), 0);
+}
@@ -0,0 +1,38 @@
+//! Tests for looking up vehicles in the Fleet API.
+
+mod provider;
+
+use common::fleet;
+use http::StatusCode;
+
+use self::provider::MockProvider;
+
+const TRAIN: &str = r#"[{ "id": "59123", "label": "AMP    123", "type": { "type": "train" } }]"#;
+
+// Should look up vehicles using the provider's configuration rather than
+// the process environment.
+#[tokio::test]
+async fn configured_fleet_url() {
+    let provider = MockProvider::default()
+        .with_config("FLEET_URL", "http://fleet.test/api")
+        .with_config("TRAIN_LABEL_WIDTH", "10")
+        .with_response(StatusCode::OK, TRAIN);
+
+    let found = fleet::vehicle("AMP123", &provider).await.expect("should fetch").expect("vehicle");
+    assert_eq!(found.id, "59123");
+    assert_eq!(provider.uris(), ["http://fleet.test/api/vehicles?label=AMP%20%20%20%20123"]);
+}
+
+// Should report a Fleet API failure as a bad gateway rather than failing
+// to deserialize the error body.
+#[tokio::test]
+async fn upstream_failure() {
+    let provider =
+        MockProvider::default().with_response(StatusCode::SERVICE_UNAVAILABLE, "upstream down");
+    let err = fleet::vehicle("AMP123", &provider).await.expect_err("should fail");
+
+    let err = err.downcast_ref::<qwasr_sdk::Error>().expect("should be an SDK error");
+    assert!(matches!(err, qwasr_sdk::Error::BadGateway { .. }));
+    assert!(err.description().contains("503"), "{}", err.description());
+    assert!(err.description().contains("upstream down"), "{}", err.description());
+}
@@ -0,0 +1,123 @@
+#![allow(missing_docs)]
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use http::{Request, Response, StatusCode};
+use qwasr_sdk::{Config, HttpRequest, Identity, StateStore};
+
+/// In-memory provider shared by the common integration tests.
+///
+/// HTTP requests are answered in order from the queued responses, a `None`
+/// response being a connection error. Requests beyond the queue fail.
+#[derive(Default, Clone)]
+pub struct MockProvider {
+    config: HashMap<String, String>,
+    store: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    responses: Arc<Mutex<VecDeque<Option<(StatusCode, String)>>>>,
+    requests: Arc<Mutex<Vec<Request<()>>>>,
+}
+
+impl MockProvider {
+    /// Set a configuration value.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn with_config(mut self, key: &str, value: &str) -> Self {
+        self.config.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Queue a response to the next unanswered HTTP request.
+    #[allow(dead_code)]
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn with_response(self, status: StatusCode, body: &str) -> Self {
+        self.responses.lock().expect("lock").push_back(Some((status, body.to_string())));
+        self
+    }
+
+    /// Queue a connection error for the next unanswered HTTP request.
+    #[allow(dead_code)]
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn with_connection_error(self) -> Self {
+        self.responses.lock().expect("lock").push_back(None);
+        self
+    }
+
+    /// URIs of the HTTP requests made, in order.
+    #[allow(dead_code)]
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn uris(&self) -> Vec<String> {
+        self.requests.lock().expect("lock").iter().map(|r| r.uri().to_string()).collect()
+    }
+
+    /// Whether the store is empty.
+    #[allow(dead_code)]
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.store.lock().expect("lock").is_empty()
+    }
+}
+
+impl Config for MockProvider {
+    async fn get(&self, key: &str) -> Result<String> {
+        if let Some(value) = self.config.get(key) {
+            return Ok(value.clone());
+        }
+        match key {
+            "AZURE_IDENTITY" => Ok("identity".to_string()),
+            k if k.ends_with("_URL") => Ok("http://localhost:8080/".to_string()),
+            _ => Err(anyhow!("{key} not set")),
+        }
+    }
+}
+
+impl StateStore for MockProvider {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.store.lock().map_err(|e| anyhow!("{e}"))?.get(key).cloned())
+    }
+
+    async fn set(
+        &self, key: &str, value: &[u8], _ttl_secs: Option<u64>,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self.store.lock().map_err(|e| anyhow!("{e}"))?.insert(key.to_string(), value.to_vec()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.store.lock().map_err(|e| anyhow!("{e}"))?.remove(key);
+        Ok(())
+    }
+}
+
+impl HttpRequest for MockProvider {
+    async fn fetch<T>(&self, request: Request<T>) -> Result<Response<Bytes>>
+    where
+        T: http_body::Body + Any,
+        T::Data: Into<Vec<u8>>,
+        T::Error: Into<Box<dyn Error + Send + Sync + 'static>>,
+    {
+        let uri = request.uri().to_string();
+        let (parts, _) = request.into_parts();
+        self.requests.lock().map_err(|e| anyhow!("{e}"))?.push(Request::from_parts(parts, ()));
+
+        let response = self.responses.lock().map_err(|e| anyhow!("{e}"))?.pop_front();
+        let Some(response) = response else {
+            return Err(anyhow!("unexpected request to {uri}"));
+        };
+        let (status, body) = response.ok_or_else(|| anyhow!("connection reset"))?;
+        Ok(Response::builder().status(status).body(Bytes::from(body))?)
+    }
+}
+
+impl Identity for MockProvider {
+    async fn access_token(&self, _identity: String) -> Result<String> {
+        Ok("mock_access_token".to_string())
+    }
+}
@@ -0,0 +1,72 @@
+//! Tests for retrying transient upstream failures.
+
+mod provider;
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use bytes::Bytes;
+use common::retry::{RetryPolicy, fetch_with_retry};
+use http::{Request, StatusCode};
+use http_body_util::Empty;
+
+use self::provider::MockProvider;
+
+const NO_DELAY: RetryPolicy = RetryPolicy { max_attempts: 3, base_delay: Duration::ZERO };
+
+fn request() -> Result<Request<Empty<Bytes>>> {
+    Ok(Request::builder().uri("http://localhost/allocations").body(Empty::new())?)
+}
+
+// Should retry connection errors and 5xx responses until one succeeds.
+#[tokio::test]
+async fn transient_failures() {
+    let provider = MockProvider::default()
+        .with_connection_error()
+        .with_response(StatusCode::BAD_GATEWAY, "502")
+        .with_response(StatusCode::OK, "200");
+
+    let response = fetch_with_retry(&provider, request, NO_DELAY).await.expect("should fetch");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.into_body(), "200");
+    assert_eq!(provider.uris().len(), 3);
+}
+
+// Should give up after the last attempt, and not retry client errors.
+#[tokio::test]
+async fn exhausted_or_not_retryable() {
+    let provider = MockProvider::default()
+        .with_response(StatusCode::GATEWAY_TIMEOUT, "504")
+        .with_response(StatusCode::GATEWAY_TIMEOUT, "504")
+        .with_response(StatusCode::GATEWAY_TIMEOUT, "504");
+    let response = fetch_with_retry(&provider, request, NO_DELAY).await.expect("should fetch");
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(provider.uris().len(), 3);
+
+    let provider = MockProvider::default().with_connection_error().with_connection_error();
+    let policy = RetryPolicy { max_attempts: 2, ..NO_DELAY };
+    fetch_with_retry(&provider, request, policy).await.expect_err("should fail");
+    assert_eq!(provider.uris().len(), 2);
+
+    let provider = MockProvider::default()
+        .with_response(StatusCode::NOT_FOUND, "404")
+        .with_response(StatusCode::OK, "200");
+    let response = fetch_with_retry(&provider, request, NO_DELAY).await.expect("should fetch");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(provider.uris().len(), 1);
+}
+
+// Should wait between attempts without blocking other work on the executor.
+#[tokio::test]
+async fn non_blocking_delay() {
+    let provider =
+        MockProvider::default().with_connection_error().with_response(StatusCode::OK, "200");
+    let policy = RetryPolicy { max_attempts: 2, base_delay: Duration::from_millis(50) };
+
+    let started = Instant::now();
+    let (response, other) =
+        tokio::join!(fetch_with_retry(&provider, request, policy), async { started.elapsed() });
+    assert_eq!(response.expect("should fetch").status(), StatusCode::OK);
+    assert!(other < Duration::from_millis(50));
+    assert!(started.elapsed() >= Duration::from_millis(50));
+}
@@ -0,0 +1,184 @@
+//! Per-trip log of how a vehicle's running passenger count was reached.
+//!
+//! When `DILAX_COUNT_AUDIT` is enabled, each count applied to a vehicle on a
+//! trip is appended to the trip's log, so the count can be reconstructed with
+//! [`replay_count`]. A log holds up to 500 entries and expires a day after its
+//! last entry. Later counts are dropped from a full log, which is flagged as
+//! truncated, so what it holds still replays from the start of the trip.
+//!
+//! The store has no conditional write, so an append that finds the log was
+//! written by another instance since it was read merges that instance's
+//! entries and writes the log again.
+
+use anyhow::{Context, Result};
+use qwasr_sdk::StateStore;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+const KEY_COUNT_AUDIT: &str = "apc:countAudit";
+const TTL_COUNT_AUDIT: u64 = 24 * 60 * 60; // 1 day
+const MAX_AUDIT_ENTRIES: usize = 500;
+const MAX_WRITE_ATTEMPTS: usize = 3;
+
+/// A count applied to a vehicle's running count on a trip.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountAuditEntry {
+    /// The stop the count was taken at, if it was resolved.
+    pub stop_id: Option<String>,
+    /// Passengers counted boarding.
+    #[serde(rename = "in")]
+    pub passengers_in: i64,
+    /// Passengers counted alighting, zero when alightings were ignored.
+    #[serde(rename = "out")]
+    pub passengers_out: i64,
+    /// The running count after the entry.
+    pub count: i64,
+    /// The Dilax token of the message counted.
+    pub timestamp: i64,
+    /// Whether the running count restarted from zero, as for a new trip.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reset: bool,
+}
+
+impl CountAuditEntry {
+    /// An entry restarting the running count at `count`, such as passengers
+    /// counted before the trip was allocated.
+    #[must_use]
+    pub const fn opening(count: i64, timestamp: i64) -> Self {
+        Self {
+            stop_id: None,
+            passengers_in: count,
+            passengers_out: 0,
+            count,
+            timestamp,
+            reset: true,
+        }
+    }
+}
+
+/// A vehicle's count audit log for a trip.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountAuditLog {
+    /// The counts applied, oldest first.
+    pub entries: Vec<CountAuditEntry>,
+    /// Whether counts were dropped because the log was full.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+impl CountAuditLog {
+    fn from_bytes(bytes: Option<&[u8]>) -> Result<Self> {
+        let Some(bytes) = bytes else {
+            return Ok(Self::default());
+        };
+        serde_json::from_slice(bytes).context("deserializing count audit log")
+    }
+
+    /// Add the entries not already logged, in token order, dropping those
+    /// that don't fit.
+    fn merge(&mut self, entries: impl IntoIterator<Item = CountAuditEntry>) {
+        for entry in entries {
+            if self.entries.contains(&entry) {
+                continue;
+            }
+            if self.entries.len() >= MAX_AUDIT_ENTRIES {
+                self.truncated = true;
+                continue;
+            }
+            self.entries.push(entry);
+        }
+        self.entries.sort_by_key(|entry| entry.timestamp);
+    }
+}
+
+/// Append entries to the vehicle's log for the trip.
+///
+/// # Errors
+///
+/// Returns an error when the log can't be read or written.
+pub async fn append(
+    vehicle_id: &str, trip_id: &str, entries: impl IntoIterator<Item = CountAuditEntry>,
+    state_store: &impl StateStore,
+) -> Result<()> {
+    let key = format!("{KEY_COUNT_AUDIT}:{vehicle_id}:{trip_id}");
+    let mut expected = state_store.get(&key).await?;
+    let mut log = CountAuditLog::from_bytes(expected.as_deref())?;
+    log.merge(entries);
+
+    for _ in 0..MAX_WRITE_ATTEMPTS {
+        let bytes = serde_json::to_vec(&log).context("serializing count audit log")?;
+        let replaced = state_store.set(&key, &bytes, Some(TTL_COUNT_AUDIT)).await?;
+        if replaced == expected {
+            return Ok(());
+        }
+
+        // keep the entries written by another instance since the log was read
+        let written = CountAuditLog::from_bytes(replaced.as_deref())?;
+        log.truncated |= written.truncated;
+        log.merge(written.entries);
+        expected = Some(bytes);
+    }
+
+    warn!(vehicle_id = %vehicle_id, trip_id = %trip_id, "Count audit log contended");
+    Ok(())
+}
+
+/// The vehicle's log for the trip.
+///
+/// # Errors
+///
+/// Returns an error when the log can't be read.
+pub async fn log(
+    vehicle_id: &str, trip_id: &str, state_store: &impl StateStore,
+) -> Result<CountAuditLog> {
+    let key = format!("{KEY_COUNT_AUDIT}:{vehicle_id}:{trip_id}");
+    CountAuditLog::from_bytes(state_store.get(&key).await?.as_deref())
+}
+
+/// Reconstruct the running count from a log, applying each entry's counts as
+/// the running count is updated: alightings first, never going below zero.
+#[must_use]
+pub fn replay_count(entries: &[CountAuditEntry]) -> i64 {
+    entries.iter().fold(0, |count, entry| {
+        let count = if entry.reset { 0 } else { count };
+        (count - entry.passengers_out).max(0) + entry.passengers_in
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(passengers_in: i64, passengers_out: i64, count: i64) -> CountAuditEntry {
+        CountAuditEntry {
+            stop_id: Some("133".to_string()),
+            passengers_in,
+            passengers_out,
+            count,
+            timestamp: 1_762_469_343,
+            reset: false,
+        }
+    }
+
+    // Should replay counts as the running count applies them.
+    #[test]
+    fn replayed() {
+        let entries = [
+            CountAuditEntry::opening(12, 1_762_469_343),
+            entry(5, 3, 14),
+            entry(0, 20, 0),
+            entry(7, 0, 7),
+        ];
+        assert_eq!(replay_count(&entries), 7);
+        assert_eq!(replay_count(&entries[..2]), 14);
+        assert_eq!(replay_count(&[]), 0);
+    }
+
+    // Should restart the count at a reset entry.
+    #[test]
+    fn replayed_reset() {
+        let entries =
+            [entry(30, 0, 30), CountAuditEntry::opening(4, 1_762_469_403), entry(1, 2, 3)];
+        assert_eq!(replay_count(&entries), 3);
+    }
+}
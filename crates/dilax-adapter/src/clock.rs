@@ -0,0 +1,20 @@
+//! Source of the current time, so time-dependent logic such as lost-connection
+//! detection can be run against a fixed instant.
+
+use chrono::{DateTime, Utc};
+
+/// Tells the current time.
+pub trait Clock {
+    /// The current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
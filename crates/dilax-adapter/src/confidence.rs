@@ -0,0 +1,132 @@
+//! Aggregate confidence in an enriched event, scored from the signals that
+//! its enrichment was degraded.
+
+use common::config;
+use serde::{Deserialize, Serialize};
+
+/// A sign that enrichment was degraded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// A piece of the enrichment (vehicle, capacity, allocation or stop)
+    /// couldn't be resolved.
+    Unresolved,
+
+    /// Several stations were near the vehicle and the nearest was assumed.
+    AmbiguousStop,
+
+    /// The event was received after the allocated trip was due to end.
+    StaleAllocation,
+}
+
+/// Confidence consumers can use to filter enriched events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Confidence {
+    /// Nothing was degraded.
+    #[default]
+    High,
+
+    /// Some signals were degraded, scoring below the `low` threshold.
+    Medium,
+
+    /// Degraded signals scored at or above the `low` threshold.
+    Low,
+}
+
+/// Weight of each signal, and the score at which confidence becomes low.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weights {
+    pub unresolved: u32,
+    pub ambiguous_stop: u32,
+    pub stale_allocation: u32,
+    pub low: u32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self { unresolved: 2, ambiguous_stop: 1, stale_allocation: 1, low: 2 }
+    }
+}
+
+impl Weights {
+    /// Override the default weights from comma-separated `name=weight` pairs,
+    /// e.g. `ambiguous_stop=2,low=3`. Unknown names and invalid weights are
+    /// ignored.
+    #[must_use]
+    pub fn parse(value: &str) -> Self {
+        let mut weights = Self::default();
+        for (name, weight) in config::pairs(value) {
+            let Ok(weight) = weight.parse() else {
+                continue;
+            };
+            match name {
+                "unresolved" => weights.unresolved = weight,
+                "ambiguous_stop" => weights.ambiguous_stop = weight,
+                "stale_allocation" => weights.stale_allocation = weight,
+                "low" => weights.low = weight,
+                _ => {}
+            }
+        }
+        weights
+    }
+
+    const fn weight(&self, signal: Signal) -> u32 {
+        match signal {
+            Signal::Unresolved => self.unresolved,
+            Signal::AmbiguousStop => self.ambiguous_stop,
+            Signal::StaleAllocation => self.stale_allocation,
+        }
+    }
+
+    /// Score the degraded signals: high when none count, low once their
+    /// weights reach the `low` threshold, otherwise medium.
+    #[must_use]
+    pub fn confidence(&self, signals: &[Signal]) -> Confidence {
+        let score =
+            signals.iter().fold(0_u32, |score, signal| score.saturating_add(self.weight(*signal)));
+        if score == 0 {
+            Confidence::High
+        } else if score >= self.low {
+            Confidence::Low
+        } else {
+            Confidence::Medium
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_weights() {
+        let weights = Weights::default();
+
+        assert_eq!(weights.confidence(&[]), Confidence::High);
+        assert_eq!(weights.confidence(&[Signal::AmbiguousStop]), Confidence::Medium);
+        assert_eq!(weights.confidence(&[Signal::StaleAllocation]), Confidence::Medium);
+        assert_eq!(weights.confidence(&[Signal::Unresolved]), Confidence::Low);
+        assert_eq!(
+            weights.confidence(&[Signal::AmbiguousStop, Signal::StaleAllocation]),
+            Confidence::Low
+        );
+    }
+
+    #[test]
+    fn configured_weights() {
+        let weights = Weights::parse("ambiguous_stop=0, unresolved=1,low=3,unknown=9,stale=x");
+        assert_eq!(
+            weights,
+            Weights { unresolved: 1, ambiguous_stop: 0, stale_allocation: 1, low: 3 }
+        );
+
+        assert_eq!(weights.confidence(&[Signal::AmbiguousStop]), Confidence::High);
+        assert_eq!(
+            weights.confidence(&[Signal::Unresolved, Signal::StaleAllocation]),
+            Confidence::Medium
+        );
+        assert_eq!(
+            weights.confidence(&[Signal::Unresolved, Signal::Unresolved, Signal::AmbiguousStop]),
+            Confidence::Low
+        );
+    }
+}
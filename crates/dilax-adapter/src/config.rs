@@ -0,0 +1,272 @@
+//! Dilax settings, with their defaults and validation in one place.
+//!
+//! | Setting                             | Default   | Valid                    |
+//! |-------------------------------------|-----------|--------------------------|
+//! | `DILAX_BEST_EFFORT`                 | off       | boolean flag             |
+//! | `DILAX_SCHEDULED_STOP`              | off       | boolean flag             |
+//! | `DILAX_AT_STOP_DISTANCE_METERS`     | unset     | `>= 0`                   |
+//! | `DILAX_STOP_SEARCH_DISTANCE_METERS` | 150       | `> 0`                    |
+//! | `LOST_CONNECTION_THRESHOLD_SECS`    | 1 hour    | `> 0`                    |
+//! | `DILAX_TRIP_INFO_MAX_AGE_SECS`      | 1 day     | `> 0`                    |
+//! | `DILAX_DETECTION_END_GRACE_SECS`    | 0         | `>= 0`                   |
+//! | `LOST_CONNECTION_RETENTION_DAYS`    | 7 days    | `> 0`                    |
+//! | `DILAX_EXCLUDED_LABEL_PREFIX`       | `ADL`     | any, `""` none           |
+//! | `DILAX_DETECTION_SERVICE_WINDOW`    | all day   | `HH:MM-HH:MM`            |
+//! | `DILAX_CONFIDENCE_WEIGHTS`          | see below | `name=weight` pairs      |
+//! | `DILAX_COUNTED_TRIGGERS`            | all       | trigger list             |
+//! | `DILAX_COUNT_AUDIT`                 | off       | boolean flag             |
+//! | `DILAX_OCCUPANCY_TOPIC`             | unset     | topic name               |
+//! | `DILAX_THRESHOLD_ROUNDING`          | `floor`   | `floor`, `round`, `ceil` |
+//! | `DILAX_MAX_DOORS`                   | 64        | `> 0`                    |
+//! | `DILAX_PRE_ALLOCATION_MAX_MESSAGES` | 120       | `>= 0`                   |
+//! | `DILAX_PRE_ALLOCATION_TTL_SECS`     | 30 min    | `> 0`                    |
+//!
+//! Confidence weights default to those of [`Weights::default`].
+//!
+//! Invalid values are logged and replaced by their default.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::{Context as _, anyhow};
+use chrono::NaiveTime;
+use common::config::{checked_setting, flag, setting};
+use common::occupancy::ThresholdRounding;
+use qwasr_sdk::Config;
+
+use crate::confidence::Weights;
+use crate::trigger::CountedTriggers;
+
+/// Settings for Dilax event processing and lost-connection detection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DilaxConfig {
+    /// Publish events with whatever enrichment could be resolved.
+    pub best_effort: bool,
+    /// Use a trip's scheduled stops to pick between nearby stations.
+    pub scheduled_stop: bool,
+    /// Distance from the previous stop, in meters, from which a count is
+    /// taken in transit rather than at the stop.
+    pub at_stop_distance: Option<i64>,
+    /// Radius, in meters, searched for stops around the vehicle.
+    pub stop_search_distance: u32,
+    /// Seconds a trip runs without a message before its connection is lost.
+    pub detection_threshold_secs: i64,
+    /// Maximum age, in seconds, of trip info considered by detection.
+    pub trip_info_max_age_secs: i64,
+    /// Seconds before a trip's end within which it isn't detected.
+    pub end_grace_secs: i64,
+    /// Seconds detections are kept for.
+    pub detection_retention_secs: u64,
+    /// Vehicle label prefix of vehicles without Dilax units, such as diesel
+    /// trains, excluded from detection.
+    pub excluded_label_prefix: String,
+    /// Local hours during which detection runs, or all day when unset.
+    pub service_window: Option<ServiceWindow>,
+    /// Weight of each sign of degraded enrichment in an event's confidence.
+    pub confidence_weights: Weights,
+    /// Triggers whose counts are accumulated for canary vehicles.
+    pub counted_triggers: CountedTriggers,
+    /// Append the counts applied to each trip's count audit log.
+    pub count_audit: bool,
+    /// Topic occupancy changes are published to, if any.
+    pub occupancy_topic: Option<String>,
+    /// How occupancy thresholds are rounded.
+    pub threshold_rounding: ThresholdRounding,
+    /// Most doors a message may report before it is rejected as malformed.
+    pub max_doors: usize,
+    /// Most events held for a vehicle before it is allocated.
+    pub pre_allocation_max_messages: usize,
+    /// Seconds events are held before allocation, after the last one.
+    pub pre_allocation_ttl_secs: u64,
+}
+
+impl Default for DilaxConfig {
+    fn default() -> Self {
+        Self {
+            best_effort: false,
+            scheduled_stop: false,
+            at_stop_distance: None,
+            stop_search_distance: 150,
+            detection_threshold_secs: 60 * 60,    // 1 hour
+            trip_info_max_age_secs: 24 * 60 * 60, // 1 day
+            end_grace_secs: 0,
+            detection_retention_secs: 7 * 24 * 60 * 60, // 7 days
+            excluded_label_prefix: "ADL".to_string(),
+            service_window: None,
+            confidence_weights: Weights::default(),
+            counted_triggers: CountedTriggers::All,
+            count_audit: false,
+            occupancy_topic: None,
+            threshold_rounding: ThresholdRounding::Floor,
+            max_doors: 64,
+            pre_allocation_max_messages: 120,
+            pre_allocation_ttl_secs: 30 * 60, // 30 minutes
+        }
+    }
+}
+
+impl DilaxConfig {
+    /// Load the settings, falling back to the default of any that are unset
+    /// or invalid.
+    pub async fn load(provider: &impl Config) -> Self {
+        let defaults = Self::default();
+        let positive = |value: &i64| *value > 0;
+        let non_negative = |value: &i64| *value >= 0;
+
+        let at_stop_distance =
+            checked_setting(provider, "DILAX_AT_STOP_DISTANCE_METERS", non_negative).await;
+        let stop_search_distance =
+            checked_setting(provider, "DILAX_STOP_SEARCH_DISTANCE_METERS", |d| *d > 0)
+                .await
+                .unwrap_or(defaults.stop_search_distance);
+        let detection_threshold_secs =
+            checked_setting(provider, "LOST_CONNECTION_THRESHOLD_SECS", positive)
+                .await
+                .unwrap_or(defaults.detection_threshold_secs);
+        let trip_info_max_age_secs =
+            checked_setting(provider, "DILAX_TRIP_INFO_MAX_AGE_SECS", positive)
+                .await
+                .unwrap_or(defaults.trip_info_max_age_secs);
+        let end_grace_secs =
+            checked_setting(provider, "DILAX_DETECTION_END_GRACE_SECS", non_negative)
+                .await
+                .unwrap_or(defaults.end_grace_secs);
+        let detection_retention_secs =
+            checked_setting(provider, "LOST_CONNECTION_RETENTION_DAYS", |days: &u64| *days > 0)
+                .await
+                .map_or(defaults.detection_retention_secs, |days| {
+                    days.saturating_mul(24 * 60 * 60)
+                });
+        let excluded_label_prefix = Config::get(provider, "DILAX_EXCLUDED_LABEL_PREFIX")
+            .await
+            .map_or(defaults.excluded_label_prefix, |prefix| prefix.trim().to_string());
+        let service_window =
+            checked_setting(provider, "DILAX_DETECTION_SERVICE_WINDOW", |w: &ServiceWindow| {
+                w.start != w.end
+            })
+            .await;
+        let confidence_weights = Config::get(provider, "DILAX_CONFIDENCE_WEIGHTS")
+            .await
+            .map_or(defaults.confidence_weights, |value| Weights::parse(&value));
+        let counted_triggers = Config::get(provider, "DILAX_COUNTED_TRIGGERS")
+            .await
+            .map_or(defaults.counted_triggers, |value| CountedTriggers::parse(&value));
+        let occupancy_topic = Config::get(provider, "DILAX_OCCUPANCY_TOPIC")
+            .await
+            .ok()
+            .map(|topic| topic.trim().to_string())
+            .filter(|topic| !topic.is_empty());
+        let threshold_rounding = setting(provider, "DILAX_THRESHOLD_ROUNDING")
+            .await
+            .unwrap_or(defaults.threshold_rounding);
+        let max_doors = checked_setting(provider, "DILAX_MAX_DOORS", |doors| *doors > 0)
+            .await
+            .unwrap_or(defaults.max_doors);
+        let pre_allocation_max_messages = setting(provider, "DILAX_PRE_ALLOCATION_MAX_MESSAGES")
+            .await
+            .unwrap_or(defaults.pre_allocation_max_messages);
+        let pre_allocation_ttl_secs =
+            checked_setting(provider, "DILAX_PRE_ALLOCATION_TTL_SECS", |ttl| *ttl > 0)
+                .await
+                .unwrap_or(defaults.pre_allocation_ttl_secs);
+
+        Self {
+            best_effort: flag(provider, "DILAX_BEST_EFFORT").await,
+            scheduled_stop: flag(provider, "DILAX_SCHEDULED_STOP").await,
+            at_stop_distance,
+            stop_search_distance,
+            detection_threshold_secs,
+            trip_info_max_age_secs,
+            end_grace_secs,
+            detection_retention_secs,
+            excluded_label_prefix,
+            service_window,
+            confidence_weights,
+            counted_triggers,
+            count_audit: flag(provider, "DILAX_COUNT_AUDIT").await,
+            occupancy_topic,
+            threshold_rounding,
+            max_doors,
+            pre_allocation_max_messages,
+            pre_allocation_ttl_secs,
+        }
+    }
+
+    /// Whether the vehicle label is excluded from detection.
+    #[must_use]
+    pub fn is_excluded(&self, vehicle_label: &str) -> bool {
+        !self.excluded_label_prefix.is_empty()
+            && vehicle_label.starts_with(&self.excluded_label_prefix)
+    }
+}
+
+/// Daily hours, in local time, during which trains run, as `HH:MM-HH:MM`. A
+/// window ending before it starts runs past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl ServiceWindow {
+    /// Whether the time is within the window, including its start but not its
+    /// end.
+    #[must_use]
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl FromStr for ServiceWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (start, end) = s.split_once('-').ok_or_else(|| anyhow!("missing '-' in {s}"))?;
+        let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").context("parsing time");
+        Ok(Self { start: time(start)?, end: time(end)? })
+    }
+}
+
+impl Display for ServiceWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: &str, end: &str) -> ServiceWindow {
+        format!("{start}-{end}").parse().expect("should parse")
+    }
+
+    fn time(t: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(t, "%H:%M").expect("should parse")
+    }
+
+    // Should run detection within the window, including one that runs past
+    // midnight.
+    #[test]
+    fn service_window() {
+        let day = window("04:00", "23:00");
+        assert!(day.contains(time("04:00")));
+        assert!(day.contains(time("12:00")));
+        assert!(!day.contains(time("23:00")));
+        assert!(!day.contains(time("02:00")));
+
+        let overnight = window("04:00", "01:00");
+        assert!(overnight.contains(time("23:30")));
+        assert!(overnight.contains(time("00:59")));
+        assert!(!overnight.contains(time("01:00")));
+        assert!(!overnight.contains(time("03:59")));
+        assert_eq!(overnight.to_string(), "04:00-01:00");
+
+        assert!("04:00".parse::<ServiceWindow>().is_err());
+        assert!("4am-1am".parse::<ServiceWindow>().is_err());
+    }
+}
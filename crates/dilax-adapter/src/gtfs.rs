@@ -1,5 +1,9 @@
+use std::collections::HashSet;
+
 use anyhow::{Context, Result};
 use bytes::Bytes;
+use common::geo::Coordinate;
+use common::url;
 use http::Method;
 use http::header::{CACHE_CONTROL, IF_NONE_MATCH};
 use http_body_util::Empty;
@@ -16,6 +20,10 @@ struct CcStopResponse {
     stop_id: String,
     #[serde(rename = "stop_code")]
     stop_code: Option<String>,
+    #[serde(rename = "stop_lat")]
+    stop_lat: Option<f64>,
+    #[serde(rename = "stop_lon")]
+    stop_lon: Option<f64>,
 }
 
 pub async fn location_stops<P>(
@@ -27,8 +35,10 @@ where
     let cc_static_addr =
         Config::get(provider, "CC_STATIC_URL").await.context("getting `CC_STATIC_URL`")?;
 
-    let url =
-        format!("{cc_static_addr}/gtfs/stops/geosearch?lat={lat}&lng={lon}&distance={distance}");
+    let url = url::join(
+        &cc_static_addr,
+        &format!("gtfs/stops/geosearch?lat={lat}&lng={lon}&distance={distance}"),
+    );
 
     let request = http::Request::builder()
         .method(Method::GET)
@@ -47,17 +57,22 @@ where
 
     Ok(stops
         .into_iter()
-        .map(|stop| StopInfo { stop_id: stop.stop_id, stop_code: stop.stop_code })
+        .map(|stop| StopInfo {
+            stop_id: stop.stop_id,
+            stop_code: stop.stop_code,
+            location: stop.stop_lat.zip(stop.stop_lon).map(|(lat, lon)| Coordinate::new(lat, lon)),
+        })
         .collect())
 }
 
-pub async fn stop_types<P>(provider: &P) -> Result<Vec<StopTypeEntry>>
+/// Retrieves the train stop types, indexed by parent stop code.
+pub async fn stop_types<P>(provider: &P) -> Result<StationIndex>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
     let gtfs_static_url =
         Config::get(provider, "GTFS_STATIC_URL").await.context("getting `GTFS_STATIC_URL`")?;
-    let url = format!("{gtfs_static_url}/stopstypes/");
+    let url = url::join(&gtfs_static_url, "stopstypes/");
 
     let request = http::Request::builder()
         .method(Method::GET)
@@ -75,12 +90,35 @@ where
     let payload: StopTypesResponse =
         serde_json::from_slice(&body).context("Failed to decode GTFS Static response")?;
 
-    let train_stops: Vec<StopTypeEntry> = payload
-        .into_iter()
-        .filter(|entry| entry.route_type == Some(StopType::Train as u32))
-        .collect();
+    Ok(StationIndex::new(&payload))
+}
+
+/// Retrieves the scheduled stop times for a trip, in stop sequence order.
+pub async fn trip_stops<P>(trip_id: &str, provider: &P) -> Result<Vec<StopTime>>
+where
+    P: Config + HttpRequest + Publisher + StateStore + Identity,
+{
+    let cc_static_addr =
+        Config::get(provider, "CC_STATIC_URL").await.context("getting `CC_STATIC_URL`")?;
+    let url = url::join(&cc_static_addr, &format!("gtfs/trips/{trip_id}/stoptimes"));
+
+    let request = http::Request::builder()
+        .method(Method::GET)
+        .uri(url)
+        .header(CACHE_CONTROL, "max-age=300") // 5 minutes
+        .header("Accept", "application/json; charset=utf-8")
+        .body(Empty::<Bytes>::new())
+        .context("building cc trip_stops request")?;
 
-    Ok(train_stops)
+    let response =
+        HttpRequest::fetch(provider, request).await.context("CC Static request failed")?;
+
+    let body = response.into_body();
+    let mut stop_times: Vec<StopTime> =
+        serde_json::from_slice(&body).context("Failed to decode CC Static response")?;
+    stop_times.sort_by_key(|stop_time| stop_time.stop_sequence);
+
+    Ok(stop_times)
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -99,6 +137,8 @@ pub struct StopInfo {
     pub stop_id: String,
     #[serde(rename = "stopCode")]
     pub stop_code: Option<String>,
+    #[serde(skip)]
+    pub location: Option<Coordinate>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -110,3 +150,71 @@ pub struct StopTypeEntry {
     #[serde(rename = "stop_code")]
     pub stop_code: Option<String>,
 }
+
+/// Parent stop codes of train stations, so stops can be checked against the
+/// stop types without scanning them.
+#[derive(Debug, Clone, Default)]
+pub struct StationIndex(HashSet<String>);
+
+impl StationIndex {
+    /// Index the parent stop codes of train stop types.
+    pub fn new(entries: &[StopTypeEntry]) -> Self {
+        let codes = entries
+            .iter()
+            .filter(|entry| entry.route_type == Some(StopType::Train as u32))
+            .filter_map(|entry| entry.parent_stop_code.as_deref())
+            .map(|code| code.trim().to_string())
+            .collect();
+        Self(codes)
+    }
+
+    /// Whether the stop code is a train station.
+    pub fn contains(&self, stop_code: &str) -> bool {
+        self.0.contains(stop_code.trim())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StopTime {
+    #[serde(rename = "stop_id")]
+    pub stop_id: String,
+    #[serde(rename = "stop_sequence")]
+    pub stop_sequence: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(parent_stop_code: Option<&str>, route_type: u32) -> StopTypeEntry {
+        StopTypeEntry {
+            parent_stop_code: parent_stop_code.map(ToString::to_string),
+            route_type: Some(route_type),
+            stop_code: None,
+        }
+    }
+
+    // Should index only the parent stop codes of train stop types.
+    #[test]
+    fn station_index() {
+        let entries = [
+            entry(Some("9218"), StopType::Train as u32),
+            entry(Some(" 133 "), StopType::Train as u32),
+            entry(Some("7001"), StopType::Bus as u32),
+            entry(None, StopType::Train as u32),
+        ];
+        let stations = StationIndex::new(&entries);
+
+        assert!(stations.contains("9218"));
+        assert!(stations.contains("133"));
+        assert!(stations.contains(" 9218"));
+        assert!(!stations.contains("7001"));
+        assert!(!stations.contains(""));
+        assert!(!stations.is_empty());
+        assert!(StationIndex::new(&[]).is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::Context as _;
+use qwasr_sdk::{
+    Config, Context, Error, Handler, HttpRequest, Identity, IntoBody, Publisher, Reply, Result,
+    StateStore,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{self, CountAuditEntry, CountAuditLog};
+
+/// Read back a vehicle's count audit log for a trip.
+#[derive(Debug, Clone)]
+pub struct CountAuditRequest {
+    pub vehicle_id: String,
+    pub trip_id: String,
+}
+
+/// A vehicle's count audit log for a trip, with the count it reconstructs.
+///
+/// When `truncated`, counts were dropped from the full log, so `count` only
+/// reconstructs the count up to its last entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountAuditReply {
+    pub count: i64,
+    pub entries: Vec<CountAuditEntry>,
+    pub truncated: bool,
+}
+
+impl IntoBody for CountAuditReply {
+    fn into_body(self) -> anyhow::Result<Vec<u8>> {
+        serde_json::to_vec(&self).context("serializing count audit")
+    }
+}
+
+async fn handle<P>(
+    _owner: &str, request: CountAuditRequest, provider: &P,
+) -> Result<Reply<CountAuditReply>>
+where
+    P: Config + StateStore,
+{
+    let CountAuditRequest { vehicle_id, trip_id } = request;
+
+    let CountAuditLog { entries, truncated } = audit::log(&vehicle_id, &trip_id, provider).await?;
+    if entries.is_empty() {
+        return Err(Error::BadRequest {
+            code: "not_found".to_string(),
+            description: format!("no count audit stored for {vehicle_id} on {trip_id}"),
+        });
+    }
+
+    Ok(CountAuditReply { count: audit::replay_count(&entries), entries, truncated }.into())
+}
+
+impl<P> Handler<P> for CountAuditRequest
+where
+    P: Config + HttpRequest + Publisher + StateStore + Identity,
+{
+    type Error = Error;
+    type Input = (String, String);
+    type Output = CountAuditReply;
+
+    fn from_input((vehicle_id, trip_id): (String, String)) -> Result<Self> {
+        Ok(Self { vehicle_id, trip_id })
+    }
+
+    async fn handle(self, ctx: Context<'_, P>) -> Result<Reply<CountAuditReply>> {
+        handle(ctx.owner, self, ctx.provider).await
+    }
+}
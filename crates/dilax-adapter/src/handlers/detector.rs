@@ -1,37 +1,117 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::Context as _;
-use chrono::{DateTime, Duration, Utc};
-use chrono_tz::Pacific;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use chrono_tz::{Pacific, Tz};
 use common::block_mgt::{self, Allocation};
 use qwasr_sdk::{
     Config, Context, Error, Handler, HttpRequest, Identity, IntoBody, Publisher, Reply, Result,
-    StateStore,
+    StateStore, bad_request,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::clock::{Clock, SystemClock};
+use crate::config::DilaxConfig;
+use crate::store;
 use crate::trip_state::{self, VehicleInfo, VehicleTripInfo};
 
-const DIESEL_TRAIN_PREFIX: &str = "ADL";
-const THRESHOLD: Duration = Duration::hours(1);
 const KEY_LOST_CONNECTION: &str = "apc:lostConnections";
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S %Z";
 
-#[allow(clippy::cast_sign_loss)]
-const TTL_RETENTION: u64 = Duration::days(7).num_seconds() as u64;
-
-async fn handle<P>(_owner: &str, _: DetectionRequest, provider: &P) -> Result<Reply<DetectionReply>>
+async fn handle<P>(
+    _owner: &str, request: DetectionRequest, provider: &P,
+) -> Result<Reply<DetectionReply>>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
-    let detections = lost_connections(provider).await.context("detecting lost connections")?;
-    Ok(DetectionReply { status: "job detection triggered", detections: detections.len() }.into())
+    let (detections, summary) = lost_connections(&request, &SystemClock, provider)
+        .await
+        .context("detecting lost connections")?;
+
+    let status =
+        if request.dry_run { "job detection previewed" } else { "job detection triggered" };
+    let reply = DetectionReply {
+        status,
+        detections: detections.len(),
+        summary,
+        new_detections: detections,
+    };
+    Ok(reply.into())
 }
 
 #[derive(Debug, Clone)]
-pub struct DetectionRequest;
+pub struct DetectionRequest {
+    order: DetectionOrder,
+    dry_run: bool,
+}
+
+/// Query parameters of a detection request.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct DetectionQuery {
+    #[serde(default)]
+    pub order: DetectionOrder,
+    /// Return the detections without recording them, so a pass can be
+    /// previewed without suppressing its detections later in the day.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// The order new detections are logged and returned in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectionOrder {
+    /// Longest lost first, then by vehicle id.
+    #[default]
+    Severity,
+    /// The order allocations were evaluated in.
+    Insertion,
+}
+
+impl DetectionOrder {
+    fn sort(self, detections: &mut [Detection]) {
+        if self == Self::Severity {
+            detections.sort_by(|a, b| {
+                let vehicle_id = |d: &Detection| &d.vehicle_trip_info.vehicle_info.vehicle_id;
+                b.lost_for_secs()
+                    .cmp(&a.lost_for_secs())
+                    .then_with(|| vehicle_id(a).cmp(vehicle_id(b)))
+            });
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DetectionReply {
     pub status: &'static str,
     pub detections: usize,
+    pub summary: DetectionSummary,
+    /// New detections in the requested order: those recorded, or those a
+    /// dry run would have recorded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub new_detections: Vec<Detection>,
+}
+
+/// How a detection pass narrowed today's allocations down to detections.
+///
+/// A vehicle running sibling trips is checked once, against its earliest
+/// starting trip not yet ending, so every vehicle counted as `running` is
+/// either `recovered`, `detected` or `suppressed`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectionSummary {
+    /// Allocations for the current service day.
+    pub evaluated: usize,
+    /// Vehicles with an evaluated allocation whose trip is currently running.
+    pub running: usize,
+    /// Running vehicles newly detected as having lost their connection.
+    pub detected: usize,
+    /// Running vehicles checked and found not to have lost their connection:
+    /// the vehicle is reporting on the trip, or the trip started within the
+    /// threshold.
+    pub recovered: usize,
+    /// Running vehicles not detected: those already detected earlier in the
+    /// day, and those whose trips are all ending within the grace period.
+    pub suppressed: usize,
 }
 
 impl IntoBody for DetectionReply {
@@ -45,11 +125,11 @@ where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
     type Error = Error;
-    type Input = ();
+    type Input = DetectionQuery;
     type Output = DetectionReply;
 
-    fn from_input(_input: ()) -> Result<Self> {
-        Ok(Self)
+    fn from_input(query: DetectionQuery) -> Result<Self> {
+        Ok(Self { order: query.order, dry_run: query.dry_run })
     }
 
     // TODO: implement "owner"
@@ -58,14 +138,36 @@ where
     }
 }
 
-async fn lost_connections<P>(provider: &P) -> anyhow::Result<Vec<Detection>>
+async fn lost_connections<P>(
+    request: &DetectionRequest, clock: &impl Clock, provider: &P,
+) -> anyhow::Result<(Vec<Detection>, DetectionSummary)>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
+    let config = DilaxConfig::load(provider).await;
+
+    // lines without overnight service have no trains to lose
+    if !in_service(&config, clock) {
+        return Ok((Vec::new(), DetectionSummary::default()));
+    }
+
     let allocs: Vec<Allocation> =
-        allocations(provider).await.context("refreshing Dilax allocations")?;
-    let detections = detect(allocs, provider).await.context("detecting lost connections")?;
-    Ok(detections)
+        allocations(&config, clock, provider).await.context("refreshing Dilax allocations")?;
+    detect(allocs, &config, request, clock, provider).await.context("detecting lost connections")
+}
+
+/// Whether detection runs at the clock's local time: within the service
+/// window, when one is configured.
+fn in_service(config: &DilaxConfig, clock: &impl Clock) -> bool {
+    let local_time = clock.now().with_timezone(&Pacific::Auckland).time();
+    let Some(window) = config.service_window else {
+        return true;
+    };
+    let in_service = window.contains(local_time);
+    if !in_service {
+        tracing::debug!(%window, "outside service window, detection suppressed");
+    }
+    in_service
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,122 +182,152 @@ pub struct Detection {
 /// # Errors
 ///
 /// Returns an error if the block management provider or backing store cannot be queried.
-async fn allocations<P>(provider: &P) -> Result<Vec<Allocation>>
+async fn allocations<P>(
+    config: &DilaxConfig, clock: &impl Clock, provider: &P,
+) -> Result<Vec<Allocation>>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
-    let allocations =
-        block_mgt::allocations(provider).await.context("fetching Dilax allocations")?;
-
-    let now_tz = Utc::now().with_timezone(&Pacific::Auckland);
+    let now_tz = clock.now().with_timezone(&Pacific::Auckland);
     let service_date = now_tz.format("%Y%m%d").to_string();
 
+    let allocations = block_mgt::allocations_for_service_date(&service_date, provider)
+        .await
+        .context("fetching Dilax allocations")?;
+
     let filtered: Vec<Allocation> = allocations
         .into_iter()
-        .filter(|alloc| {
-            alloc.service_date == service_date
-                && !alloc.vehicle_id.is_empty()
-                && !alloc.vehicle_label.starts_with(DIESEL_TRAIN_PREFIX)
-        })
+        .filter(|alloc| !alloc.vehicle_id.is_empty() && !config.is_excluded(&alloc.vehicle_label))
         .collect();
 
     Ok(filtered)
 }
 
-/// Runs the lost-connection detection workflow.
+/// Runs the lost-connection detection workflow, summarising how the
+/// allocations were narrowed down to detections.
+///
+/// A dry run only reads the day's detections, so its new detections aren't
+/// recorded or logged.
 ///
 /// # Errors
 ///
 /// Returns an error when Redis access or candidate deserialization fails.
-async fn detect<P>(allocs: Vec<Allocation>, provider: &P) -> anyhow::Result<Vec<Detection>>
+async fn detect<P>(
+    allocs: Vec<Allocation>, config: &DilaxConfig, request: &DetectionRequest, clock: &impl Clock,
+    provider: &P,
+) -> anyhow::Result<(Vec<Detection>, DetectionSummary)>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
     tracing::debug!("Starting Dilax lost connection detection pass");
-    let candidates = detect_candidates(allocs, provider).await?;
+    let (candidates, mut summary) = detect_candidates(allocs, config, clock, provider).await?;
 
     tracing::debug!(candidate_count = candidates.len(), "Dilax detection candidates evaluated");
     if candidates.is_empty() {
         tracing::debug!("No Dilax lost connection candidates found");
-        return Ok(Vec::new());
+        return Ok((Vec::new(), summary));
     }
 
-    // fetch existing vehicle/trip mappings
-    let now = Utc::now().with_timezone(&Pacific::Auckland);
+    // detections are recorded in a daily set of vehicle/trip pairs
+    let now = clock.now().with_timezone(&Pacific::Auckland);
     let set_key = format!("{KEY_LOST_CONNECTION}{}", now.format("%Y%m%d"));
-
-    let mut mapping_set = (StateStore::get(provider, &set_key).await?)
-        .map_or_else(SetEnvelope::default, |raw| {
-            serde_json::from_slice::<SetEnvelope>(&raw).unwrap_or_default()
-        });
-
-    let now_ts = now.timestamp();
-
-    // check whether expired
-    if mapping_set.expires_at.is_some_and(|expires_at| expires_at <= now_ts) {
-        StateStore::delete(provider, &set_key).await?;
-        mapping_set = SetEnvelope::default();
-    }
-
-    let mut trip_vehicles = mapping_set.members;
+    let log_format = log_format(provider).await;
 
     let mut new_detections = Vec::new();
     for c in candidates {
         let vehicle_trip =
             format!("{}|{}", c.vehicle_trip_info.vehicle_info.vehicle_id, c.allocation.trip_id);
-        if trip_vehicles.contains(&vehicle_trip) {
+        if request.dry_run {
+            if store::set_contains(provider, &set_key, &vehicle_trip).await? {
+                summary.suppressed += 1;
+            } else {
+                new_detections.push(c);
+            }
             continue;
         }
 
-        log_detection(&c);
-
-        let member_key = format!("{set_key}:{vehicle_trip}");
         let bytes = serde_json::to_vec(&c)?;
-        StateStore::set(provider, &member_key, &bytes, Some(TTL_RETENTION)).await?;
+        if !store::add_to_set(
+            provider,
+            &set_key,
+            &vehicle_trip,
+            &bytes,
+            config.detection_retention_secs,
+        )
+        .await?
+        {
+            summary.suppressed += 1;
+            continue;
+        }
 
-        trip_vehicles.push(vehicle_trip);
         new_detections.push(c);
     }
 
-    // save vehicle/trip mappings
-    #[allow(clippy::cast_possible_wrap)]
-    let mapping_set =
-        SetEnvelope { expires_at: Some(now_ts + TTL_RETENTION as i64), members: trip_vehicles };
-    let bytes = serde_json::to_vec(&mapping_set)?;
-    StateStore::set(provider, &set_key, &bytes, Some(TTL_RETENTION)).await?;
-    Ok(new_detections)
+    request.order.sort(&mut new_detections);
+    if !request.dry_run {
+        for detection in &new_detections {
+            log_detection(detection, &log_format);
+        }
+    }
+
+    summary.detected = new_detections.len();
+    Ok((new_detections, summary))
 }
 
+/// Find running allocations that have lost their connection. The summary
+/// counts are complete except for `detected`, and `suppressed` for those
+/// detected earlier, which depend on earlier detections.
 async fn detect_candidates<P>(
-    allocs: Vec<Allocation>, provider: &P,
-) -> anyhow::Result<Vec<Detection>>
+    allocs: Vec<Allocation>, config: &DilaxConfig, clock: &impl Clock, provider: &P,
+) -> anyhow::Result<(Vec<Detection>, DetectionSummary)>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
-    let now_ts = Utc::now().with_timezone(&Pacific::Auckland).timestamp();
+    let now_ts = clock.now().timestamp();
 
-    let active: Vec<Allocation> = allocs
+    let evaluated = allocs.len();
+    let running_allocs: Vec<Allocation> = allocs
         .into_iter()
         .filter(|alloc| alloc.start_datetime <= now_ts && alloc.end_datetime >= now_ts)
         .collect();
+    let running =
+        running_allocs.iter().map(|alloc| alloc.vehicle_id.as_str()).collect::<HashSet<_>>().len();
+
+    tracing::debug!("{running} Dilax services currently running");
+
+    // a vehicle going silent as its trip winds down isn't a lost connection,
+    // though a sibling trip it's moving on to may be
+    let active = one_per_vehicle(running_allocs.into_iter().filter(|alloc| {
+        let ending = alloc.end_datetime - now_ts < config.end_grace_secs;
+        if ending {
+            tracing::debug!(vehicle_id = %alloc.vehicle_id, "trip ending, not evaluated");
+        }
+        !ending
+    }));
 
-    tracing::debug!("{} Dilax services currently running", active.len());
+    let checked = active.len();
 
     let mut detections = Vec::new();
     for alloc in active {
         let Some(info) = trip_state::get_trip(&alloc.vehicle_id, provider).await? else {
-            if let Some(detection) = detect_allocation(&alloc, None) {
+            if let Some(detection) = detect_allocation(&alloc, None, config, clock) {
                 detections.push(detection);
             }
             continue;
         };
 
-        if info.trip_id.as_deref() == Some(&alloc.trip_id) {
+        // stale trip info can't vouch for the current trip
+        let stale = is_stale(&info, now_ts, config.trip_info_max_age_secs);
+        if stale {
+            tracing::debug!(vehicle_id = %alloc.vehicle_id, "ignoring stale vehicle trip info");
+        }
+
+        if !stale && info.trip_id.as_deref() == Some(&alloc.trip_id) {
             let last_ts =
                 info.last_received_timestamp.as_deref().and_then(|v| v.parse::<i64>().ok());
 
             if let Some(last) = last_ts
-                && connection_lost(last)
+                && connection_lost(last, config, clock)
             {
                 detections.push(Detection {
                     detection_time: now_ts,
@@ -203,16 +335,45 @@ where
                     vehicle_trip_info: info,
                 });
             }
-        } else if let Some(detection) = detect_allocation(&alloc, Some(info)) {
+        } else if let Some(detection) = detect_allocation(&alloc, Some(info), config, clock) {
             detections.push(detection);
         }
     }
 
-    Ok(detections)
+    let summary = DetectionSummary {
+        evaluated,
+        running,
+        recovered: checked - detections.len(),
+        suppressed: running - checked,
+        ..DetectionSummary::default()
+    };
+    Ok((detections, summary))
+}
+
+/// Keep the earliest starting of each vehicle's running allocations not yet
+/// ending, as Block Management returns overlapping sibling trips for a
+/// vehicle.
+fn one_per_vehicle(active: impl IntoIterator<Item = Allocation>) -> Vec<Allocation> {
+    let mut kept: Vec<Allocation> = Vec::new();
+    let mut by_vehicle = HashMap::new();
+    for alloc in active {
+        if let Some(&index) = by_vehicle.get(&alloc.vehicle_id) {
+            let existing = &mut kept[index];
+            if alloc.start_datetime < existing.start_datetime {
+                *existing = alloc;
+            }
+        } else {
+            by_vehicle.insert(alloc.vehicle_id.clone(), kept.len());
+            kept.push(alloc);
+        }
+    }
+    kept
 }
 
-fn detect_allocation(alloc: &Allocation, existing: Option<VehicleTripInfo>) -> Option<Detection> {
-    if !connection_lost(alloc.start_datetime) {
+fn detect_allocation(
+    alloc: &Allocation, existing: Option<VehicleTripInfo>, config: &DilaxConfig, clock: &impl Clock,
+) -> Option<Detection> {
+    if !connection_lost(alloc.start_datetime, config, clock) {
         return None;
     }
 
@@ -228,84 +389,455 @@ fn detect_allocation(alloc: &Allocation, existing: Option<VehicleTripInfo>) -> O
     });
 
     Some(Detection {
-        detection_time: Utc::now().with_timezone(&Pacific::Auckland).timestamp(),
+        detection_time: clock.now().timestamp(),
         allocation: alloc.clone(),
         vehicle_trip_info,
     })
 }
 
-fn connection_lost(timestamp: i64) -> bool {
-    let now_ts = Utc::now().with_timezone(&Pacific::Auckland).timestamp();
-    (timestamp + THRESHOLD.num_seconds()) <= now_ts
-}
-
-fn log_detection(detection: &Detection) {
-    let vehicle_info = &detection.vehicle_trip_info.vehicle_info;
-    let mut vehicle_label = detection
-        .vehicle_trip_info
-        .dilax_message
-        .as_ref()
-        .and_then(|msg| msg.device.as_ref())
-        .map(|device| device.site.trim())
-        .filter(|site| !site.is_empty())
-        .map(|site| format!("{site} - "))
-        .unwrap_or_default();
-
-    if let Some(label) = &vehicle_info.label {
-        vehicle_label.push_str(label);
-    }
-
-    let timestamp_str = detection
-        .vehicle_trip_info
-        .last_received_timestamp
+/// Whether the trip info's last received message is older than `max_age` seconds.
+fn is_stale(info: &VehicleTripInfo, now_ts: i64, max_age: i64) -> bool {
+    info.last_received_timestamp
         .as_deref()
         .and_then(|v| v.parse::<i64>().ok())
-        .map_or_else(|| String::from("Never received a Dilax message"), format_timestamp);
-
-    let coordinates = detection
-        .vehicle_trip_info
-        .dilax_message
-        .as_ref()
-        .and_then(|msg| msg.wpt.as_ref())
-        .map_or_else(
-            || String::from("No GPS Position available"),
-            |message| {
-                let mut parts = Vec::new();
-                if !message.lat.is_empty() {
-                    parts.push(format!("Latitude: {}", message.lat));
-                }
-                if !message.lon.is_empty() {
-                    parts.push(format!("Longitude: {}", message.lon));
-                }
-                if parts.is_empty() {
-                    String::from("No GPS Position available")
-                } else {
-                    format!("Last Coordinates: {}", parts.join("; "))
-                }
-            },
-        );
+        .is_some_and(|last| now_ts - last > max_age)
+}
 
-    let vehicle_field = format!("{vehicle_label}{}", vehicle_info.vehicle_id);
+fn connection_lost(timestamp: i64, config: &DilaxConfig, clock: &impl Clock) -> bool {
+    (timestamp + config.detection_threshold_secs) <= clock.now().timestamp()
+}
 
+fn log_detection(detection: &Detection, format: &LogFormat) {
+    let entry = detection.log_entry(format);
     tracing::warn!(
-        vehicle = %vehicle_field,
-        trip_id = %detection.allocation.trip_id,
-        timestamp = %timestamp_str,
-        coordinates = %coordinates,
+        vehicle = %entry.vehicle,
+        trip_id = %entry.trip_id,
+        timestamp = %entry.timestamp,
+        coordinates = %entry.coordinates,
         "Dilax connection lost"
     );
 }
 
-fn format_timestamp(timestamp: i64) -> String {
+/// Human-readable description of a lost-connection detection, as logged when
+/// the connection loss is detected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectionLog {
+    pub vehicle: String,
+    pub trip_id: String,
+    pub timestamp: String,
+    pub coordinates: String,
+}
+
+impl IntoBody for DetectionLog {
+    fn into_body(self) -> anyhow::Result<Vec<u8>> {
+        serde_json::to_vec(&self).context("serializing detection log")
+    }
+}
+
+/// Timezone and `strftime` format used to render timestamps in detection logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFormat {
+    pub tz: Tz,
+    pub format: String,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self { tz: Pacific::Auckland, format: TIMESTAMP_FORMAT.to_string() }
+    }
+}
+
+/// Detection log format, read from `DILAX_LOG_TIMEZONE` (an IANA name such as
+/// `Australia/Sydney`) and `DILAX_LOG_TIMESTAMP_FORMAT`. Invalid values fall
+/// back to the defaults.
+pub async fn log_format(provider: &impl Config) -> LogFormat {
+    let mut log_format = LogFormat::default();
+
+    if let Ok(value) = Config::get(provider, "DILAX_LOG_TIMEZONE").await {
+        match value.trim().parse::<Tz>() {
+            Ok(tz) => log_format.tz = tz,
+            Err(e) => tracing::warn!(value, "invalid detection log timezone: {e}"),
+        }
+    }
+    if let Ok(value) = Config::get(provider, "DILAX_LOG_TIMESTAMP_FORMAT").await {
+        // an invalid format would panic when rendered
+        if StrftimeItems::new(&value).any(|item| matches!(item, Item::Error)) {
+            tracing::warn!(value, "invalid detection log timestamp format");
+        } else {
+            log_format.format = value;
+        }
+    }
+
+    log_format
+}
+
+impl Detection {
+    /// Seconds the connection had been lost for when detected: since the last
+    /// message on the trip, or since the trip started when there was none.
+    #[must_use]
+    pub fn lost_for_secs(&self) -> i64 {
+        let last_received = self
+            .vehicle_trip_info
+            .last_received_timestamp
+            .as_deref()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(i64::MIN);
+        self.detection_time - last_received.max(self.allocation.start_datetime)
+    }
+
+    /// Describe the detection: the vehicle's device site and label, the trip,
+    /// when the vehicle was last heard from, and where.
+    #[must_use]
+    pub fn log_entry(&self, format: &LogFormat) -> DetectionLog {
+        let vehicle_info = &self.vehicle_trip_info.vehicle_info;
+        let mut vehicle_label = self
+            .vehicle_trip_info
+            .dilax_message
+            .as_ref()
+            .and_then(|msg| msg.device.as_ref())
+            .map(|device| device.site.trim())
+            .filter(|site| !site.is_empty())
+            .map(|site| format!("{site} - "))
+            .unwrap_or_default();
+
+        if let Some(label) = &vehicle_info.label {
+            vehicle_label.push_str(label);
+        }
+
+        let timestamp = self
+            .vehicle_trip_info
+            .last_received_timestamp
+            .as_deref()
+            .and_then(|v| v.parse::<i64>().ok())
+            .map_or_else(
+                || String::from("Never received a Dilax message"),
+                |timestamp| format_timestamp(timestamp, format.tz, &format.format),
+            );
+
+        let coordinates = self
+            .vehicle_trip_info
+            .dilax_message
+            .as_ref()
+            .and_then(|msg| msg.wpt.as_ref())
+            .map_or_else(
+                || String::from("No GPS Position available"),
+                |message| {
+                    let mut parts = Vec::new();
+                    if !message.lat.is_empty() {
+                        parts.push(format!("Latitude: {}", message.lat));
+                    }
+                    if !message.lon.is_empty() {
+                        parts.push(format!("Longitude: {}", message.lon));
+                    }
+                    if parts.is_empty() {
+                        String::from("No GPS Position available")
+                    } else {
+                        format!("Last Coordinates: {}", parts.join("; "))
+                    }
+                },
+            );
+
+        DetectionLog {
+            vehicle: format!("{vehicle_label}{}", vehicle_info.vehicle_id),
+            trip_id: self.allocation.trip_id.clone(),
+            timestamp,
+            coordinates,
+        }
+    }
+}
+
+/// Request to re-render a stored detection, identified by its daily set key
+/// (e.g. `apc:lostConnections20251107`) and `vehicle_id|trip_id` member.
+#[derive(Debug, Clone)]
+pub struct DetectionLogRequest {
+    pub set_key: String,
+    pub vehicle_trip: String,
+}
+
+async fn handle_log<P>(
+    _owner: &str, request: DetectionLogRequest, provider: &P,
+) -> Result<Reply<DetectionLog>>
+where
+    P: Config + StateStore,
+{
+    let DetectionLogRequest { set_key, vehicle_trip } = request;
+
+    // only detections may be read back
+    if !set_key.starts_with(KEY_LOST_CONNECTION) {
+        return Err(bad_request!("{set_key} is not a lost connection set"));
+    }
+
+    let Some(bytes) = store::set_member(provider, &set_key, &vehicle_trip).await? else {
+        return Err(Error::BadRequest {
+            code: "not_found".to_string(),
+            description: format!("no detection stored for {set_key}:{vehicle_trip}"),
+        });
+    };
+    let detection: Detection = serde_json::from_slice(&bytes).context("deserializing detection")?;
+
+    Ok(detection.log_entry(&log_format(provider).await).into())
+}
+
+impl<P> Handler<P> for DetectionLogRequest
+where
+    P: Config + HttpRequest + Publisher + StateStore + Identity,
+{
+    type Error = Error;
+    type Input = (String, String);
+    type Output = DetectionLog;
+
+    fn from_input((set_key, vehicle_trip): (String, String)) -> Result<Self> {
+        Ok(Self { set_key, vehicle_trip })
+    }
+
+    async fn handle(self, ctx: Context<'_, P>) -> Result<Reply<DetectionLog>> {
+        handle_log(ctx.owner, self, ctx.provider).await
+    }
+}
+
+fn format_timestamp(timestamp: i64, tz: Tz, format: &str) -> String {
     DateTime::<Utc>::from_timestamp(timestamp, 0)
         .unwrap_or_else(|| DateTime::<Utc>::from_timestamp(0, 0).unwrap())
-        .with_timezone(&Pacific::Auckland)
-        .format("%Y-%m-%d %H:%M:%S %Z")
+        .with_timezone(&tz)
+        .format(format)
         .to_string()
 }
 
-#[derive(Default, Serialize, Deserialize)]
-struct SetEnvelope {
-    expires_at: Option<i64>,
-    members: Vec<String>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_762_469_343;
+
+    fn trip_info(last_received: Option<i64>) -> VehicleTripInfo {
+        VehicleTripInfo {
+            last_received_timestamp: last_received.map(|ts| ts.to_string()),
+            dilax_message: None,
+            trip_id: Some("trip-1".to_string()),
+            stop_id: None,
+            vehicle_info: VehicleInfo { label: None, vehicle_id: "59123".to_string() },
+        }
+    }
+
+    struct FixedClock(i64);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime<Utc> {
+            DateTime::from_timestamp(self.0, 0).expect("valid timestamp")
+        }
+    }
+
+    fn allocation(started_secs_ago: i64) -> Allocation {
+        serde_json::from_value(serde_json::json!({
+            "operationalBlockId": "101-202",
+            "tripId": "trip-1",
+            "serviceDate": "20251107",
+            "startTime": "09:49:03",
+            "vehicleId": "59123",
+            "vehicleLabel": "AMP        1005",
+            "routeId": "EAST-201",
+            "directionId": 0,
+            "referenceId": "1005",
+            "endTime": "12:49:03",
+            "delay": 0,
+            "startDatetime": NOW - started_secs_ago,
+            "endDatetime": NOW + 60 * 60,
+            "isCanceled": false,
+            "isCopied": false,
+            "timezone": "Pacific/Auckland",
+            "creationDatetime": "2025-11-06T12:00:00Z"
+        }))
+        .expect("should deserialize")
+    }
+
+    // Should detect a trip that started without a message more than the
+    // threshold before now, stamped with the clock's time.
+    #[test]
+    fn allocation_lost() {
+        let config = DilaxConfig::default();
+        let detection =
+            detect_allocation(&allocation(2 * 60 * 60), None, &config, &FixedClock(NOW))
+                .expect("should be detected");
+        assert_eq!(detection.detection_time, NOW);
+        assert_eq!(detection.vehicle_trip_info.trip_id.as_deref(), Some("trip-1"));
+    }
+
+    // Should not detect a trip started within the threshold.
+    #[test]
+    fn allocation_within_threshold() {
+        let config = DilaxConfig::default();
+        assert!(detect_allocation(&allocation(30 * 60), None, &config, &FixedClock(NOW)).is_none());
+    }
+
+    // Should detect a trip silent for less than the default threshold once the
+    // threshold is lowered.
+    #[test]
+    fn configured_threshold() {
+        let allocation = allocation(40 * 60);
+        assert!(
+            detect_allocation(&allocation, None, &DilaxConfig::default(), &FixedClock(NOW))
+                .is_none()
+        );
+
+        let config = DilaxConfig { detection_threshold_secs: 1800, ..DilaxConfig::default() };
+        assert!(detect_allocation(&allocation, None, &config, &FixedClock(NOW)).is_some());
+    }
+
+    // Should run detection only within the service window, in Auckland time,
+    // when one is configured.
+    #[test]
+    fn service_window() {
+        // 11:49 in Auckland
+        let clock = FixedClock(NOW);
+        let window = |window: &str| DilaxConfig {
+            service_window: Some(window.parse().expect("should parse")),
+            ..DilaxConfig::default()
+        };
+        assert!(in_service(&DilaxConfig::default(), &clock));
+        assert!(in_service(&window("10:00-12:00"), &clock));
+        assert!(in_service(&window("04:00-01:00"), &clock));
+        assert!(!in_service(&window("12:00-13:00"), &clock));
+        assert!(!in_service(&window("00:00-11:49"), &clock));
+    }
+
+    // Should keep one allocation per vehicle, the earliest starting.
+    #[test]
+    fn overlapping_allocations() {
+        let mut sibling = allocation(60 * 60);
+        sibling.trip_id = "trip-2".to_string();
+        let mut other = allocation(30 * 60);
+        other.vehicle_id = "59124".to_string();
+
+        let kept = one_per_vehicle([sibling, allocation(2 * 60 * 60), other]);
+        let kept: Vec<_> =
+            kept.iter().map(|alloc| (alloc.vehicle_id.as_str(), alloc.trip_id.as_str())).collect();
+        assert_eq!(kept, [("59123", "trip-1"), ("59124", "trip-1")]);
+    }
+
+    // Should order detections longest lost first, then by vehicle, unless
+    // insertion order is asked for.
+    #[test]
+    fn severity_order() {
+        let detection = |vehicle_id: &str, started_secs_ago: i64, last: Option<i64>| {
+            let mut info = trip_info(last);
+            info.vehicle_info.vehicle_id = vehicle_id.to_string();
+            Detection {
+                detection_time: NOW,
+                allocation: allocation(started_secs_ago),
+                vehicle_trip_info: info,
+            }
+        };
+        let detections = vec![
+            // never heard from, on a trip started 2 hours ago
+            detection("59121", 2 * 60 * 60, None),
+            // last heard from 3 hours ago, on a trip started 4 hours ago
+            detection("59122", 4 * 60 * 60, Some(NOW - 3 * 60 * 60)),
+            // last heard from before its trip started 2 hours ago
+            detection("59120", 2 * 60 * 60, Some(NOW - 5 * 60 * 60)),
+        ];
+        let vehicle_ids = |detections: &[Detection]| {
+            detections
+                .iter()
+                .map(|d| d.vehicle_trip_info.vehicle_info.vehicle_id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut sorted = detections.clone();
+        DetectionOrder::Severity.sort(&mut sorted);
+        assert_eq!(vehicle_ids(&sorted), ["59122", "59120", "59121"]);
+        assert_eq!(sorted[0].lost_for_secs(), 3 * 60 * 60);
+        assert_eq!(sorted[1].lost_for_secs(), 2 * 60 * 60);
+
+        let mut inserted = detections.clone();
+        DetectionOrder::Insertion.sort(&mut inserted);
+        assert_eq!(vehicle_ids(&inserted), vehicle_ids(&detections));
+    }
+
+    // Should serialize the reply and stored detections in the shape consumers
+    // read: snake_case keys, with the allocation's own camelCase keys.
+    #[test]
+    fn serialized_shape() {
+        let summary =
+            DetectionSummary { evaluated: 4, running: 3, detected: 1, recovered: 1, suppressed: 1 };
+        let reply = DetectionReply {
+            status: "job detection triggered",
+            detections: 1,
+            summary,
+            new_detections: Vec::new(),
+        };
+        assert_eq!(
+            serde_json::to_value(&reply).expect("should serialize"),
+            serde_json::json!({
+                "status": "job detection triggered",
+                "detections": 1,
+                "summary": {
+                    "evaluated": 4,
+                    "running": 3,
+                    "detected": 1,
+                    "recovered": 1,
+                    "suppressed": 1
+                }
+            })
+        );
+
+        let detection = Detection {
+            detection_time: NOW,
+            allocation: allocation(2 * 60 * 60),
+            vehicle_trip_info: trip_info(Some(NOW - 2 * 60 * 60)),
+        };
+        let value = serde_json::to_value(&detection).expect("should serialize");
+        assert_eq!(value["detection_time"], NOW);
+        assert_eq!(value["allocation"]["tripId"], "trip-1");
+        assert_eq!(value["allocation"]["vehicleLabel"], "AMP        1005");
+        assert_eq!(value["vehicle_trip_info"]["trip_id"], "trip-1");
+        assert_eq!(value["vehicle_trip_info"]["vehicle_info"]["vehicleId"], "59123");
+
+        let round_trip: Detection = serde_json::from_value(value).expect("should deserialize");
+        assert_eq!(round_trip.vehicle_trip_info, detection.vehicle_trip_info);
+    }
+
+    #[test]
+    fn fresh_trip_info() {
+        let max_age = DilaxConfig::default().trip_info_max_age_secs;
+        assert!(!is_stale(&trip_info(Some(NOW - 60)), NOW, max_age));
+        assert!(!is_stale(&trip_info(Some(NOW - max_age)), NOW, max_age));
+    }
+
+    #[test]
+    fn stale_trip_info() {
+        let max_age = DilaxConfig::default().trip_info_max_age_secs;
+        assert!(is_stale(&trip_info(Some(NOW - max_age - 1)), NOW, max_age));
+        assert!(is_stale(&trip_info(Some(NOW - 36 * 60 * 60)), NOW, max_age));
+    }
+
+    #[test]
+    fn trip_info_without_timestamp() {
+        assert!(!is_stale(&trip_info(None), NOW, DilaxConfig::default().trip_info_max_age_secs));
+    }
+
+    #[test]
+    fn default_timestamp_format() {
+        assert_eq!(
+            format_timestamp(NOW, Pacific::Auckland, TIMESTAMP_FORMAT),
+            "2025-11-07 11:49:03 NZDT"
+        );
+    }
+
+    #[test]
+    fn other_timezone() {
+        let tz = chrono_tz::Australia::Sydney;
+        assert_eq!(format_timestamp(NOW, tz, TIMESTAMP_FORMAT), "2025-11-07 09:49:03 AEDT");
+        assert_eq!(
+            format_timestamp(NOW, chrono_tz::UTC, TIMESTAMP_FORMAT),
+            "2025-11-06 22:49:03 UTC"
+        );
+    }
+
+    #[test]
+    fn custom_format() {
+        assert_eq!(
+            format_timestamp(NOW, Pacific::Auckland, "%d/%m/%Y %H:%M %:z"),
+            "07/11/2025 11:49 +13:00"
+        );
+    }
 }
@@ -1,23 +1,33 @@
 use anyhow::Context as _;
-use common::block_mgt;
-use common::fleet::{self, Vehicle};
+use chrono::DateTime;
+use chrono_tz::Pacific;
+use common::block_mgt::{self, Allocation};
+use common::canary;
+use common::fleet::{self, Vehicle, VehicleLabel};
+use common::geo::Coordinate;
+use common::god_mode::{self, TripOverride};
+use common::outcome::ProcessResult;
 use qwasr_sdk::{
     Config, Context, Error, Handler, HttpRequest, Identity, Message, Publisher, Reply, Result,
     StateStore, bad_request,
 };
 
-use crate::gtfs::{self, StopType, StopTypeEntry};
-use crate::trip_state::{self, VehicleInfo, VehicleTripInfo};
-use crate::types::{DilaxMessage, EnrichedEvent};
+use crate::DilaxError;
+use crate::confidence::Signal;
+use crate::config::DilaxConfig;
+use crate::gtfs::{self, StopInfo, StopTime};
+use crate::trip_state::{
+    self, Enrichment, VehicleCapacity, VehicleInfo, VehicleTripInfo, VehicleUpdate,
+};
+use crate::types::{DilaxMessage, EnrichedEvent, TripChange};
 
-const STOP_SEARCH_DISTANCE_METERS: u32 = 150;
 const DILAX_ENRICHED_TOPIC: &str = "realtime-dilax-apc-enriched.v2";
 
 async fn handle<P>(_owner: &str, request: DilaxMessage, provider: &P) -> Result<Reply<()>>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
-    process(request, provider).await?;
+    ProcessResult::report(process(request, provider).await, "dilax")?;
     Ok(Reply::ok(()))
 }
 
@@ -41,74 +51,206 @@ where
 
 /// Enriches a Dilax event with vehicle, stop, trip, and occupancy information.
 ///
+/// By default any enrichment that can't be resolved is an error, except the
+/// stop: the event is still published, with no `stop_id` and `stop` listed in
+/// `unresolved`, so the passenger count isn't lost. When `DILAX_BEST_EFFORT`
+/// is enabled, the event is published with whatever could be resolved and
+/// the missing pieces listed in `unresolved`.
+///
+/// Returns the number of enriched events published: two when the message
+/// moves the vehicle's saved state onto a new trip, otherwise one. The
+/// closing event describes the previous trip as it was last counted; failing
+/// to publish it is logged rather than returned, as the state has moved on.
+/// A duplicate or out-of-order message is skipped, publishing nothing.
+///
 /// # Errors
 ///
 /// Returns an error when one of the providers or the key-value store reports a failure
 /// while augmenting the incoming Dilax event.
-pub async fn process<P>(event: DilaxMessage, provider: &P) -> Result<()>
+pub async fn process<P>(event: DilaxMessage, provider: &P) -> Result<ProcessResult>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
-    let vehicle_label = vehicle_label(&event)
-        .ok_or_else(|| bad_request!("vehicle label missing for device {:?}", event.device))?;
+    let config = DilaxConfig::load(provider).await;
+    let best_effort = config.best_effort;
+    let mut unresolved = Vec::new();
 
-    let vehicle = fleet::vehicle(&vehicle_label, provider)
-        .await
-        .map_err(|err| bad_request!("failed to resolve vehicle for label {vehicle_label}: {err}"))?
-        .ok_or_else(|| bad_request!("vehicle not found for label {vehicle_label}"))?;
+    let vehicle =
+        partial(resolve_vehicle(&event, provider).await, best_effort, "vehicle", &mut unresolved)?;
+
+    let (capacity, allocation, trip_override) = if let Some((vehicle, _)) = &vehicle {
+        let capacity = vehicle_capacity(vehicle)
+            .ok_or_else(|| bad_request!("vehicle {} lacks capacity information", vehicle.id));
+        let capacity = partial(capacity, best_effort, "capacity", &mut unresolved)?;
+
+        // an operator's God Mode override is authoritative over the allocation
+        let trip_override = trip_override(&vehicle.id, provider).await;
+        let allocation = if let Some(trip_override) = &trip_override {
+            tracing::info!(vehicle_id = %vehicle.id, ?trip_override, "using trip override");
+            match trip_override {
+                TripOverride::Trip(trip_id) => override_allocation(trip_id, &event, provider).await,
+                TripOverride::NoTrip => None,
+            }
+        } else {
+            let allocation = resolve_allocation(&vehicle.id, provider).await;
+            if matches!(allocation, Ok(None)) {
+                // hold counts from before sign-on for the trip once allocated
+                trip_state::buffer_pre_allocation(&vehicle.id, &event, &config, provider)
+                    .await
+                    .map_err(|err| match err.downcast::<DilaxError>() {
+                        Ok(err) => err.into(),
+                        Err(err) => {
+                            bad_request!("failed to buffer counts for {}: {err}", vehicle.id)
+                        }
+                    })?;
+            }
+            let allocation = allocation.and_then(|allocation| {
+                allocation.ok_or_else(|| {
+                    bad_request!("block allocation unavailable for vehicle {}", vehicle.id)
+                })
+            });
+            partial(allocation, best_effort, "allocation", &mut unresolved)?
+        };
+        (capacity, allocation, trip_override)
+    } else {
+        unresolved.extend(["capacity".to_string(), "allocation".to_string()]);
+        (None, None, None)
+    };
 
-    let (vehicle_seating, vehicle_total) = vehicle_capacity(&vehicle)
-        .ok_or_else(|| bad_request!("vehicle {} lacks capacity information", vehicle.id))?;
-    let vehicle_id = vehicle.id.clone();
+    let vehicle_id = vehicle.as_ref().map(|(vehicle, _)| vehicle.id.clone());
+    let trip_id = match trip_override {
+        Some(TripOverride::Trip(trip_id)) => Some(trip_id),
+        Some(TripOverride::NoTrip) => None,
+        None => allocation.as_ref().map(|allocation| allocation.trip_id.clone()),
+    };
+    tracing::debug!(vehicle_id = ?vehicle_id, allocation = ?allocation, trip_id = ?trip_id);
+
+    // counts taken between stops aren't committed to a stop
+    let in_transit = in_transit(event.distance_laststop, config.at_stop_distance);
+    let stop = if in_transit {
+        let distance_laststop = event.distance_laststop;
+        tracing::debug!(vehicle_id = ?vehicle_id, ?distance_laststop, "in transit");
+        None
+    } else {
+        let stop = stop_id(
+            vehicle_id.as_deref().unwrap_or("unknown"),
+            trip_id.as_deref(),
+            &event,
+            &config,
+            provider,
+        )
+        .await;
+        // occupancy doesn't depend on the stop, so never drop the count for it
+        partial(stop, true, "stop", &mut unresolved)?
+    };
+    let ambiguous_stop = stop.as_ref().is_some_and(|(_, ambiguous)| *ambiguous);
+    let stop_id = stop.map(|(stop_id, _)| stop_id);
+
+    // score how degraded the enrichment is
+    let mut signals = vec![Signal::Unresolved; unresolved.len()];
+    if ambiguous_stop {
+        signals.push(Signal::AmbiguousStop);
+    }
+    if allocation.as_ref().is_some_and(|allocation| is_stale(allocation, &event)) {
+        signals.push(Signal::StaleAllocation);
+    }
+    let enrichment = Enrichment {
+        stop_id: stop_id.clone(),
+        confidence: config.confidence_weights.confidence(&signals),
+        unresolved,
+    };
 
-    let allocation = block_mgt::allocation(&vehicle_id, provider)
+    let update = if let Some((vehicle_id, capacity)) = vehicle_id.as_ref().zip(capacity) {
+        trip_state::update_vehicle(
+            vehicle_id,
+            trip_id.as_deref(),
+            &enrichment,
+            capacity,
+            &event,
+            &config,
+            provider,
+        )
         .await
-        .map_err(|err| {
-            bad_request!("failed to fetch block allocation for vehicle {vehicle_id}: {err}")
-        })?
-        .ok_or_else(|| bad_request!("block allocation unavailable for vehicle {vehicle_id}"))?;
-
-    let trip_id_value = allocation.trip_id.clone();
-    let start_date_value = allocation.service_date.clone();
-    let start_time_value = allocation.start_time.clone();
-    tracing::debug!(vehicle_id = %vehicle_id, allocation = ?allocation, trip_id = %trip_id_value);
-
-    let stop_id_value: String = stop_id(&vehicle_id, &event, provider).await?;
-
-    trip_state::update_vehicle(
-        &vehicle_id,
-        Some(trip_id_value.as_str()),
-        vehicle_seating,
-        vehicle_total,
-        &event,
-        provider,
-    )
-    .await
-    .map_err(|err| bad_request!("failed to update trip state for vehicle {vehicle_id}: {err}"))?;
-
-    let vt = VehicleTripInfo {
-        vehicle_info: VehicleInfo {
-            vehicle_id: vehicle_id.clone(),
-            label: Some(vehicle_label.clone()),
-        },
-        trip_id: Some(trip_id_value.clone()),
-        stop_id: Some(stop_id_value.clone()),
-        last_received_timestamp: Some(event.clock.utc.clone()),
-        dilax_message: Some(event.clone()),
+        .map_err(|err| match err.downcast::<DilaxError>() {
+            Ok(err) => err.into(),
+            Err(err) => {
+                bad_request!("failed to update trip state for vehicle {vehicle_id}: {err}")
+            }
+        })?;
+        let Some(update) = update else {
+            return Ok(ProcessResult::Skipped("stale message"));
+        };
+        update
+    } else {
+        VehicleUpdate::default()
     };
-    trip_state::set_trip(vt, provider).await.map_err(|err| {
-        bad_request!("failed to persist trip info for vehicle {vehicle_id}: {err}")
-    })?;
+
+    if let Some((vehicle, vehicle_label)) = &vehicle {
+        let vehicle_id = &vehicle.id;
+        let vt = VehicleTripInfo {
+            vehicle_info: VehicleInfo {
+                vehicle_id: vehicle_id.clone(),
+                label: Some(vehicle_label.clone()),
+            },
+            trip_id: trip_id.clone(),
+            stop_id: stop_id.clone(),
+            last_received_timestamp: Some(event.clock.utc.clone()),
+            dilax_message: Some(event.clone()),
+        };
+        trip_state::set_trip(vt, provider).await.map_err(|err| {
+            bad_request!("failed to persist trip info for vehicle {vehicle_id}: {err}")
+        })?;
+    }
+
+    // the event moving the vehicle onto a new trip closes the previous one, as
+    // it was last counted; the state has been saved, so a failure to publish
+    // isn't retried
+    let mut emitted = 1;
+    let trip_changed = update.closed_trip.is_some();
+    if let Some(closed) = update.closed_trip {
+        let last_trip_id = closed.trip_id;
+        tracing::info!(vehicle_id = ?vehicle_id, last_trip_id, trip_id = ?trip_id, "trip changed");
+        let closing = EnrichedEvent {
+            event: DilaxMessage { doors: Vec::new(), ..event.clone() },
+            stop_id: closed.enrichment.stop_id,
+            trip_id: Some(last_trip_id),
+            start_date: None,
+            start_time: None,
+            occupancy_percentage: closed.occupancy_percentage,
+            in_transit,
+            confidence: closed.enrichment.confidence,
+            unresolved: closed.enrichment.unresolved,
+            trip_change: Some(TripChange::Closing),
+        };
+        match publish(&closing, provider).await {
+            Ok(()) => emitted += 1,
+            Err(err) => tracing::warn!(vehicle_id = ?vehicle_id, "failed to close trip: {err:#}"),
+        }
+    }
 
     let enriched = EnrichedEvent {
         event,
-        stop_id: Some(stop_id_value),
-        trip_id: Some(trip_id_value),
-        start_date: Some(start_date_value),
-        start_time: Some(start_time_value),
+        stop_id,
+        trip_id,
+        start_date: allocation.as_ref().map(|allocation| allocation.service_date.clone()),
+        start_time: allocation.as_ref().map(|allocation| allocation.start_time.clone()),
+        occupancy_percentage: update.occupancy_percentage,
+        in_transit,
+        confidence: enrichment.confidence,
+        unresolved: enrichment.unresolved,
+        trip_change: trip_changed.then_some(TripChange::Opening),
     };
+    publish(&enriched, provider).await?;
 
-    let payload = serde_json::to_vec(&enriched).context("serializing event")?;
+    Ok(ProcessResult::Emitted(emitted))
+}
+
+/// Publish the enriched event, keyed by its trip.
+async fn publish<P>(enriched: &EnrichedEvent, provider: &P) -> Result<()>
+where
+    P: Config + Publisher,
+{
+    let payload = serde_json::to_vec(enriched).context("serializing event")?;
     let mut message = Message::new(&payload);
     if let Some(trip_id) = &enriched.trip_id {
         message.headers.insert("key".to_string(), trip_id.clone());
@@ -122,30 +264,157 @@ where
     Ok(())
 }
 
-fn vehicle_label(event: &DilaxMessage) -> Option<String> {
-    let site = &event.device.as_ref()?.site;
+/// In best-effort mode, record an enrichment that couldn't be resolved and
+/// carry on without it; otherwise fail.
+fn partial<T>(
+    result: Result<T>, best_effort: bool, name: &str, unresolved: &mut Vec<String>,
+) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if best_effort => {
+            tracing::warn!(unresolved = name, "partial enrichment: {err}");
+            unresolved.push(name.to_string());
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Resolve the Fleet vehicle, and its label, for the Dilax device.
+async fn resolve_vehicle<P>(event: &DilaxMessage, provider: &P) -> Result<(Vehicle, String)>
+where
+    P: Config + HttpRequest + Identity,
+{
+    let Some(vehicle_label) = vehicle_label(event, fleet::label_width(provider).await) else {
+        LabelResolution::Unresolved.record();
+        return Err(bad_request!("vehicle label missing for device {:?}", event.device));
+    };
+
+    let vehicle = fleet::vehicle(&vehicle_label, provider).await;
+    LabelResolution::of(&vehicle).record();
 
-    let (prefix, suffix) = site
-        .strip_prefix("AM")
-        .map(|suffix| ("AMP", suffix))
-        .or_else(|| site.strip_prefix("AD").map(|suffix| ("ADL", suffix)))?;
+    let vehicle = vehicle
+        .map_err(|err| match err.downcast::<Error>() {
+            // keep upstream failures distinct from bad events
+            Ok(err) => err,
+            Err(err) => bad_request!("failed to resolve vehicle for label {vehicle_label}: {err}"),
+        })?
+        .ok_or_else(|| bad_request!("vehicle not found for label {vehicle_label}"))?;
 
-    // train label: format as 14 characters
-    let width = 14usize.saturating_sub(prefix.len());
-    Some(format!("{prefix}{suffix:>width$}"))
+    Ok((vehicle, vehicle_label))
 }
 
-fn vehicle_capacity(vehicle: &Vehicle) -> Option<(i64, i64)> {
-    vehicle.capacity.as_ref().map(|capacity| (capacity.seating, capacity.total))
+/// Outcome of resolving a Dilax device label to a Fleet vehicle, counted as
+/// `dilax_label_resolution` so drops in the resolution rate can be alerted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LabelResolution {
+    Resolved,
+    Unresolved,
+    ApiError,
+}
+
+impl LabelResolution {
+    const fn of<T>(lookup: &anyhow::Result<Option<T>>) -> Self {
+        match lookup {
+            Ok(Some(_)) => Self::Resolved,
+            Ok(None) => Self::Unresolved,
+            Err(_) => Self::ApiError,
+        }
+    }
+
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Resolved => "resolved",
+            Self::Unresolved => "unresolved",
+            Self::ApiError => "api_error",
+        }
+    }
+
+    fn record(self) {
+        tracing::info!(monotonic_counter.dilax_label_resolution = 1, result = self.as_str());
+    }
+}
+
+/// The vehicle's God Mode trip override, when God Mode is enabled. An override
+/// that can't be read is ignored in favour of the allocation.
+async fn trip_override<P>(vehicle_id: &str, provider: &P) -> Option<TripOverride>
+where
+    P: Config + StateStore,
+{
+    if !god_mode::is_enabled(provider).await {
+        return None;
+    }
+    god_mode::trip_override(provider, vehicle_id).await.unwrap_or_else(|err| {
+        tracing::warn!(vehicle_id, "failed to read trip override: {err}");
+        None
+    })
+}
+
+/// The block allocation of an override trip on the event's service date, so
+/// the trip's start date and time are known, if it is allocated.
+async fn override_allocation<P>(
+    trip_id: &str, event: &DilaxMessage, provider: &P,
+) -> Option<Allocation>
+where
+    P: Config + HttpRequest + Identity,
+{
+    let service_date = event
+        .clock
+        .utc
+        .parse::<i64>()
+        .ok()
+        .and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .map(|dt| dt.with_timezone(&Pacific::Auckland).format("%Y%m%d").to_string())?;
+
+    match block_mgt::allocations_for_service_date(&service_date, provider).await {
+        Ok(allocations) => allocations.into_iter().find(|allocation| allocation.trip_id == trip_id),
+        Err(err) => {
+            tracing::warn!(trip_id, "failed to look up override trip allocation: {err}");
+            None
+        }
+    }
 }
 
-/// Resolve the GTFS stop identifier for the Dilax event waypoint.
+/// Resolve the vehicle's current block allocation, if it has one.
+async fn resolve_allocation<P>(vehicle_id: &str, provider: &P) -> Result<Option<Allocation>>
+where
+    P: Config + HttpRequest + Identity,
+{
+    block_mgt::allocation(vehicle_id, provider).await.map_err(|err| {
+        bad_request!("failed to fetch block allocation for vehicle {vehicle_id}: {err}")
+    })
+}
+
+fn vehicle_label(event: &DilaxMessage, width: usize) -> Option<String> {
+    site_label(&event.device.as_ref()?.site, width)
+}
+
+/// Train label for a Dilax site such as `AM123`, or `None` unless the site is
+/// a train class followed only by digits.
+fn site_label(site: &str, width: usize) -> Option<String> {
+    VehicleLabel::parse(site, width).map(|label| label.to_string())
+}
+
+fn vehicle_capacity(vehicle: &Vehicle) -> Option<VehicleCapacity> {
+    vehicle.capacity.as_ref().map(VehicleCapacity::from)
+}
+
+/// Resolve the GTFS stop identifier for the Dilax event waypoint, and whether
+/// it was assumed from several nearby stations.
+///
+/// When the waypoint is near more than one train station and
+/// `DILAX_SCHEDULED_STOP` is enabled for a canary vehicle, the trip's
+/// scheduled stops are used to pick the station the vehicle is due at.
+/// Otherwise, or when the schedule can't be fetched, the nearest is assumed.
 ///
 /// # Errors
 ///
 /// Returns an error when the waypoint is missing, provider requests fail, or no stop
 /// matching the Dilax waypoint can be determined.
-async fn stop_id<P>(vehicle_id: &str, event: &DilaxMessage, provider: &P) -> Result<String>
+async fn stop_id<P>(
+    vehicle_id: &str, trip_id: Option<&str>, event: &DilaxMessage, config: &DilaxConfig,
+    provider: &P,
+) -> Result<(String, bool)>
 where
     P: Config + HttpRequest + Publisher + StateStore + Identity,
 {
@@ -157,40 +426,236 @@ where
         ))?;
     };
 
+    let distance = config.stop_search_distance;
     let stops =
-        gtfs::location_stops(&waypoint.lat, &waypoint.lon, STOP_SEARCH_DISTANCE_METERS, provider)
-            .await
-            .map_err(|err| {
-                bad_request!("failed to look up stops for vehicle {vehicle_id_owned}: {err}")
-            })?;
+        gtfs::location_stops(&waypoint.lat, &waypoint.lon, distance, provider).await.map_err(
+            |err| bad_request!("failed to look up stops for vehicle {vehicle_id_owned}: {err}"),
+        )?;
     if stops.is_empty() {
         return Err(bad_request!("stop id unavailable for vehicle {vehicle_id_owned}"))?;
     }
 
-    let stop_types = gtfs::stop_types(provider).await.map_err(|err| {
+    let train_stations = gtfs::stop_types(provider).await.map_err(|err| {
         bad_request!("failed to look up stop types for vehicle {vehicle_id_owned}: {err}")
     })?;
-    if stop_types.is_empty() {
+    if train_stations.is_empty() {
         return Err(bad_request!("train stop types unavailable for vehicle {vehicle_id_owned}"))?;
     }
 
-    for stop in &stops {
-        tracing::debug!(vehicle_id = %vehicle_id, stop = ?stop);
+    let stations: Vec<&StopInfo> = stops
+        .iter()
+        .filter(|stop| {
+            tracing::debug!(vehicle_id = %vehicle_id, stop = ?stop);
+            stop.stop_code.as_deref().is_some_and(|code| train_stations.contains(code))
+        })
+        .collect();
 
-        if let Some(code) = stop.stop_code.as_deref()
-            && is_station(&stop_types, code)
-        {
-            tracing::debug!(vehicle_id = %vehicle_id, stop_id = %stop.stop_id, stop_code = code);
-            return Ok(stop.stop_id.clone());
+    if let Some(trip_id) = trip_id
+        && stations.len() > 1
+        && config.scheduled_stop
+        && canary::is_canary(vehicle_id, provider).await
+    {
+        // the schedule only refines the stop, so fall back to the nearest
+        let stop_times = gtfs::trip_stops(trip_id, provider).await.unwrap_or_else(|err| {
+            tracing::warn!(vehicle_id, trip_id, "failed to look up scheduled stops: {err}");
+            Vec::new()
+        });
+        let last_stop = trip_state::get_trip(vehicle_id, provider)
+            .await
+            .unwrap_or_else(|err| {
+                tracing::warn!(vehicle_id, "failed to read last stop: {err}");
+                None
+            })
+            .filter(|info| info.trip_id.as_deref() == Some(trip_id))
+            .and_then(|info| info.stop_id);
+        if let Some(stop) = scheduled_stop(&stations, &stop_times, last_stop.as_deref()) {
+            tracing::debug!(
+                vehicle_id = %vehicle_id,
+                trip_id,
+                stop_id = %stop.stop_id,
+                "resolved scheduled stop"
+            );
+            return Ok((stop.stop_id.clone(), false));
         }
     }
 
-    Err(bad_request!("stop id unavailable for vehicle {vehicle_id_owned}"))
+    let position = Coordinate::parse(&waypoint.lat, &waypoint.lon).ok();
+    let Some(stop) = nearest_station(&stations, position) else {
+        return Err(bad_request!("stop id unavailable for vehicle {vehicle_id_owned}"));
+    };
+    tracing::debug!(vehicle_id = %vehicle_id, stop_id = %stop.stop_id, stop_code = ?stop.stop_code);
+    Ok((stop.stop_id.clone(), stations.len() > 1))
 }
 
-fn is_station(stop_types: &[StopTypeEntry], stop_code: &str) -> bool {
-    stop_types.iter().any(|entry| {
-        entry.parent_stop_code.as_deref() == Some(stop_code)
-            && entry.route_type == Some(StopType::Train as u32)
+/// Whether the count was taken between stops rather than during a platform
+/// dwell, `at_stop_distance` meters or more from the previous stop. Events not
+/// reporting `distance_laststop`, or when the distance isn't configured, are
+/// assumed at a stop.
+fn in_transit(distance_laststop: Option<i64>, at_stop_distance: Option<i64>) -> bool {
+    distance_laststop.zip(at_stop_distance).is_some_and(|(distance, limit)| distance >= limit)
+}
+
+/// Whether the event was received after the allocated trip was due to end.
+fn is_stale(allocation: &Allocation, event: &DilaxMessage) -> bool {
+    event.clock.utc.parse::<i64>().is_ok_and(|event_ts| event_ts > allocation.end_datetime)
+}
+
+/// The candidate station the trip is due at: the first in stop sequence at or
+/// after the vehicle's last stop on the trip, or from the start of the trip
+/// when the last stop isn't known.
+fn scheduled_stop<'a>(
+    stations: &[&'a StopInfo], stop_times: &[StopTime], last_stop: Option<&str>,
+) -> Option<&'a StopInfo> {
+    let from = last_stop
+        .and_then(|last_stop| stop_times.iter().find(|stop_time| stop_time.stop_id == last_stop))
+        .map_or(0, |stop_time| stop_time.stop_sequence);
+
+    let mut stop_times: Vec<&StopTime> =
+        stop_times.iter().filter(|stop_time| stop_time.stop_sequence >= from).collect();
+    stop_times.sort_by_key(|stop_time| stop_time.stop_sequence);
+    stop_times.iter().find_map(|stop_time| {
+        stations.iter().find(|station| station.stop_id == stop_time.stop_id).copied()
     })
 }
+
+/// The candidate station nearest the vehicle. Stations without a known
+/// location rank last, and ties keep the geosearch order.
+fn nearest_station<'a>(
+    stations: &[&'a StopInfo], position: Option<Coordinate>,
+) -> Option<&'a StopInfo> {
+    let distance = |stop: &StopInfo| {
+        position
+            .zip(stop.location)
+            .map_or(f64::INFINITY, |(position, location)| position.distance_meters(&location))
+    };
+    stations.iter().copied().min_by(|a, b| distance(a).total_cmp(&distance(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(stop_id: &str, stop_code: &str) -> StopInfo {
+        StopInfo {
+            stop_id: stop_id.to_string(),
+            stop_code: Some(stop_code.to_string()),
+            location: None,
+        }
+    }
+
+    fn located(stop_id: &str, lat: f64, lon: f64) -> StopInfo {
+        StopInfo { location: Some(Coordinate::new(lat, lon)), ..stop(stop_id, stop_id) }
+    }
+
+    fn stop_time(stop_id: &str, stop_sequence: u32) -> StopTime {
+        StopTime { stop_id: stop_id.to_string(), stop_sequence }
+    }
+
+    #[test]
+    fn scheduled_stop_disambiguates() {
+        let newmarket = stop("9218-a", "9218");
+        let grafton = stop("9219-a", "9219");
+        let stations = [&newmarket, &grafton];
+
+        // the trip is due at Grafton, not Newmarket
+        let stop_times = [stop_time("133-a", 1), stop_time("9219-a", 2), stop_time("134-a", 3)];
+
+        let stop = scheduled_stop(&stations, &stop_times, None).expect("scheduled stop");
+        assert_eq!(stop.stop_id, "9219-a");
+    }
+
+    // Should pick the candidate due next in stop sequence, not the first
+    // candidate found, when the trip calls at both.
+    #[test]
+    fn scheduled_stop_sequence() {
+        let newmarket = stop("9218-a", "9218");
+        let grafton = stop("9219-a", "9219");
+        let stations = [&newmarket, &grafton];
+        let stop_times = [
+            stop_time("9219-a", 4),
+            stop_time("133-a", 1),
+            stop_time("9218-a", 2),
+            stop_time("134-a", 3),
+        ];
+
+        let stop = scheduled_stop(&stations, &stop_times, None).expect("scheduled stop");
+        assert_eq!(stop.stop_id, "9218-a");
+
+        // having left Newmarket, the vehicle is due at Grafton
+        let stop = scheduled_stop(&stations, &stop_times, Some("134-a")).expect("scheduled stop");
+        assert_eq!(stop.stop_id, "9219-a");
+
+        // still dwelling at Newmarket
+        let stop = scheduled_stop(&stations, &stop_times, Some("9218-a")).expect("scheduled stop");
+        assert_eq!(stop.stop_id, "9218-a");
+    }
+
+    // Should pad well-formed sites to the label width and reject any other
+    // shape.
+    #[test]
+    fn site_labels() {
+        assert_eq!(site_label("AM123", 14).as_deref(), Some("AMP        123"));
+        assert_eq!(site_label("AD45", 14).as_deref(), Some("ADL         45"));
+        assert_eq!(site_label("AM1005", 14).map(|label| label.len()), Some(14));
+
+        assert_eq!(site_label("AMP12A", 14), None);
+        assert_eq!(site_label("AM12A", 14), None);
+        assert_eq!(site_label("AM", 14), None);
+        assert_eq!(site_label("12", 14), None);
+        assert_eq!(site_label("", 14), None);
+    }
+
+    // Should classify each outcome of the Fleet label lookup.
+    #[test]
+    fn label_resolution() {
+        let resolved: anyhow::Result<Option<()>> = Ok(Some(()));
+        let unresolved: anyhow::Result<Option<()>> = Ok(None);
+        let api_error: anyhow::Result<Option<()>> = Err(anyhow::anyhow!("fleet unavailable"));
+
+        assert_eq!(LabelResolution::of(&resolved).as_str(), "resolved");
+        assert_eq!(LabelResolution::of(&unresolved).as_str(), "unresolved");
+        assert_eq!(LabelResolution::of(&api_error).as_str(), "api_error");
+    }
+
+    // Should find no scheduled stop when none of the nearby stations is on
+    // the trip.
+    #[test]
+    fn scheduled_stop_unmatched() {
+        let newmarket = stop("9218-a", "9218");
+        let grafton = stop("9219-a", "9219");
+        let stop_times = [stop_time("133-a", 1)];
+
+        assert!(scheduled_stop(&[&newmarket, &grafton], &stop_times, None).is_none());
+    }
+
+    // Should only treat counts as in transit once the vehicle is at least the
+    // configured distance from the previous stop.
+    #[test]
+    fn in_transit_distance() {
+        assert!(!in_transit(Some(0), Some(50)));
+        assert!(!in_transit(Some(49), Some(50)));
+        assert!(in_transit(Some(50), Some(50)));
+        assert!(in_transit(Some(1_200), Some(50)));
+
+        // disabled, or the distance wasn't reported
+        assert!(!in_transit(Some(1_200), None));
+        assert!(!in_transit(None, Some(50)));
+    }
+
+    // Should pick the station nearest the vehicle rather than the first found.
+    #[test]
+    fn nearest_station_tie_break() {
+        let britomart = located("133-a", -36.8443, 174.7676);
+        let newmarket = located("9218-a", -36.8697, 174.7784);
+        let unknown = stop("9219-a", "9219");
+        let stations = [&unknown, &britomart, &newmarket];
+
+        let position = Coordinate::new(-36.8690, 174.7780);
+        let nearest = nearest_station(&stations, Some(position)).expect("nearest station");
+        assert_eq!(nearest.stop_id, "9218-a");
+
+        // without a position, fall back to the geosearch order
+        let nearest = nearest_station(&stations, None).expect("nearest station");
+        assert_eq!(nearest.stop_id, "9219-a");
+    }
+}
@@ -1,11 +1,56 @@
 //! Dilax domain library
 
+mod audit;
+mod clock;
+mod confidence;
+mod config;
 mod gtfs;
 mod handlers;
+mod store;
+mod trigger;
 mod trip_state;
 mod types;
 
+use qwasr_sdk::Error;
+use thiserror::Error;
+
+pub use self::audit::{CountAuditEntry, replay_count};
+pub use self::clock::{Clock, SystemClock};
+pub use self::confidence::{Confidence, Weights};
+pub use self::config::{DilaxConfig, ServiceWindow};
+pub use self::handlers::audit::*;
 pub use self::handlers::detector::*;
 pub use self::handlers::processor::*;
+pub use self::store::{add_to_set, set_contains, set_member, set_members, touch};
+pub use self::trigger::{CountedTriggers, Trigger};
 pub use self::trip_state::*;
 pub use self::types::*;
+
+/// Dilax message error.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DilaxError {
+    /// The message's clock token isn't a plausible timestamp.
+    #[error("{0}")]
+    InvalidTimestamp(String),
+
+    /// The message lists more doors than a vehicle could have.
+    #[error("{0}")]
+    TooManyDoors(String),
+}
+
+impl DilaxError {
+    /// Machine-readable error code.
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidTimestamp(_) => "invalid_timestamp",
+            Self::TooManyDoors(_) => "too_many_doors",
+        }
+    }
+}
+
+impl From<DilaxError> for Error {
+    fn from(err: DilaxError) -> Self {
+        Self::BadRequest { code: err.code().to_string(), description: err.to_string() }
+    }
+}
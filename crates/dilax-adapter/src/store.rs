@@ -0,0 +1,122 @@
+//! State store helpers: extending an entry's expiry, and sets of string
+//! members such as the daily sets of vehicles and trips detected as having
+//! lost their connection.
+//!
+//! A set is stored as an index of its members under the set's key, with each
+//! member's value stored under `{key}:{member}`. Membership is checked against
+//! the member's entry, so the index doesn't need to be loaded. The whole set,
+//! index and members, expires together, `ttl_secs` after it was created.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use qwasr_sdk::StateStore;
+use serde::{Deserialize, Serialize};
+
+/// Extend the expiry of an entry to `ttl_secs` from now, leaving its value
+/// unchanged.
+///
+/// Returns whether the entry existed. An absent entry isn't created.
+///
+/// The state store has no expire operation, so the entry is written back as
+/// it was read. A caller that already holds the value should write it with
+/// its expiry instead, saving the read.
+///
+/// # Errors
+///
+/// Returns an error when the entry can't be read or written.
+pub async fn touch(store: &impl StateStore, key: &str, ttl_secs: u64) -> Result<bool> {
+    let Some(bytes) = StateStore::get(store, key).await? else {
+        return Ok(false);
+    };
+    StateStore::set(store, key, &bytes, Some(ttl_secs)).await?;
+    Ok(true)
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SetEnvelope {
+    expires_at: Option<i64>,
+    members: Vec<String>,
+}
+
+/// Add a member to the set, storing its value under `{key}:{member}`. A new
+/// set expires `ttl_secs` from now.
+///
+/// Returns `false`, leaving the set unchanged, when the member is already in
+/// the set.
+///
+/// # Errors
+///
+/// Returns an error when the set can't be read or written.
+pub async fn add_to_set(
+    store: &impl StateStore, key: &str, member: &str, value: &[u8], ttl_secs: u64,
+) -> Result<bool> {
+    if set_contains(store, key, member).await? {
+        return Ok(false);
+    }
+
+    let now = Utc::now().timestamp();
+    let mut envelope = envelope(store, key, now).await?.unwrap_or_default();
+    #[allow(clippy::cast_possible_wrap)]
+    let expires_at = *envelope.expires_at.get_or_insert(now + ttl_secs as i64);
+    let ttl_secs = expires_at.saturating_sub(now).max(1).unsigned_abs();
+
+    StateStore::set(store, &member_key(key, member), value, Some(ttl_secs)).await?;
+    envelope.members.push(member.to_string());
+    let bytes = serde_json::to_vec(&envelope).context("serializing set")?;
+    StateStore::set(store, key, &bytes, Some(ttl_secs)).await?;
+
+    Ok(true)
+}
+
+/// Whether the member is in the set.
+///
+/// # Errors
+///
+/// Returns an error when the member's entry can't be read.
+pub async fn set_contains(store: &impl StateStore, key: &str, member: &str) -> Result<bool> {
+    Ok(set_member(store, key, member).await?.is_some())
+}
+
+/// The value stored for a member of the set, if it's in the set.
+///
+/// # Errors
+///
+/// Returns an error when the member's entry can't be read.
+pub async fn set_member(
+    store: &impl StateStore, key: &str, member: &str,
+) -> Result<Option<Vec<u8>>> {
+    StateStore::get(store, &member_key(key, member)).await
+}
+
+/// Members of the set, in the order they were added. An expired set has no
+/// members.
+///
+/// # Errors
+///
+/// Returns an error when the set can't be read.
+pub async fn set_members(store: &impl StateStore, key: &str) -> Result<Vec<String>> {
+    let envelope = envelope(store, key, Utc::now().timestamp()).await?;
+    Ok(envelope.map(|envelope| envelope.members).unwrap_or_default())
+}
+
+/// The set's index, or `None` when it doesn't exist or has expired, in which
+/// case it is removed.
+async fn envelope(store: &impl StateStore, key: &str, now: i64) -> Result<Option<SetEnvelope>> {
+    let Some(bytes) = StateStore::get(store, key).await? else {
+        return Ok(None);
+    };
+    let envelope = serde_json::from_slice::<SetEnvelope>(&bytes).unwrap_or_default();
+
+    if envelope.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        for member in &envelope.members {
+            StateStore::delete(store, &member_key(key, member)).await?;
+        }
+        StateStore::delete(store, key).await?;
+        return Ok(None);
+    }
+    Ok(Some(envelope))
+}
+
+fn member_key(key: &str, member: &str) -> String {
+    format!("{key}:{member}")
+}
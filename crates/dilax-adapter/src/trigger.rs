@@ -0,0 +1,122 @@
+//! What caused a Dilax message to be emitted, and which causes have their
+//! counts accumulated.
+
+use common::canary;
+use qwasr_sdk::Config;
+
+use crate::config::DilaxConfig;
+
+/// Cause of a Dilax message, decoded from its `trigger` field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// `station_left_summary`: the counts at a station, sent as the vehicle
+    /// leaves it. The only trigger the units are configured to send.
+    StationLeftSummary,
+
+    /// Any other trigger, as sent.
+    Other(String),
+}
+
+impl From<&str> for Trigger {
+    /// Decode a trigger, ignoring case and surrounding whitespace.
+    fn from(s: &str) -> Self {
+        let trigger = s.trim();
+        if trigger.eq_ignore_ascii_case("station_left_summary") {
+            Self::StationLeftSummary
+        } else {
+            Self::Other(trigger.to_string())
+        }
+    }
+}
+
+/// Triggers whose counts are accumulated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CountedTriggers {
+    /// Counts from every trigger are accumulated.
+    #[default]
+    All,
+
+    /// Only counts from the listed triggers are accumulated.
+    Only(Vec<Trigger>),
+}
+
+impl CountedTriggers {
+    /// Parse a comma-separated list of triggers, e.g. `station_left_summary`. An
+    /// empty list, or `all`, counts every trigger.
+    #[must_use]
+    pub fn parse(value: &str) -> Self {
+        let names: Vec<&str> =
+            value.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+        if names.is_empty() || names.iter().any(|name| name.eq_ignore_ascii_case("all")) {
+            return Self::All;
+        }
+        Self::Only(names.into_iter().map(Trigger::from).collect())
+    }
+
+    /// Whether counts from the trigger are accumulated.
+    #[must_use]
+    pub fn counts(&self, trigger: &Trigger) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(triggers) => triggers.contains(trigger),
+        }
+    }
+}
+
+/// Whether counts from the trigger are accumulated for the vehicle, as set by
+/// `DILAX_COUNTED_TRIGGERS`. Every trigger is counted for vehicles that aren't
+/// canaries.
+pub async fn is_counted(
+    config: &DilaxConfig, vehicle_id: &str, trigger: &Trigger, provider: &impl Config,
+) -> bool {
+    !canary::is_canary(vehicle_id, provider).await || config.counted_triggers.counts(trigger)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DilaxMessage;
+
+    #[test]
+    fn decode() {
+        assert_eq!(Trigger::from("station_left_summary"), Trigger::StationLeftSummary);
+        assert_eq!(Trigger::from(" STATION_LEFT_SUMMARY "), Trigger::StationLeftSummary);
+        assert_eq!(Trigger::from(" manual "), Trigger::Other("manual".to_string()));
+    }
+
+    // Should decode the trigger sent by the units.
+    #[test]
+    fn fixture() {
+        let message: DilaxMessage =
+            serde_json::from_slice(include_bytes!("../data/station_left_summary.json"))
+                .expect("should deserialize");
+        assert_eq!(message.trigger_kind(), Trigger::StationLeftSummary);
+    }
+
+    // Should count every trigger by default.
+    #[test]
+    fn all_counted() {
+        for value in ["", " , ", "all", "ALL"] {
+            assert_eq!(CountedTriggers::parse(value), CountedTriggers::All);
+        }
+        assert!(CountedTriggers::default().counts(&Trigger::StationLeftSummary));
+        assert!(CountedTriggers::default().counts(&Trigger::Other("manual".to_string())));
+    }
+
+    // Should only count the configured triggers.
+    #[test]
+    fn filtered() {
+        let counted = CountedTriggers::parse("station_left_summary, manual");
+        assert_eq!(
+            counted,
+            CountedTriggers::Only(vec![
+                Trigger::StationLeftSummary,
+                Trigger::Other("manual".to_string())
+            ])
+        );
+
+        assert!(counted.counts(&Trigger::StationLeftSummary));
+        assert!(counted.counts(&Trigger::Other("manual".to_string())));
+        assert!(!counted.counts(&Trigger::Other("timer".to_string())));
+    }
+}
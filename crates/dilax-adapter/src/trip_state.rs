@@ -1,34 +1,81 @@
-use std::fmt::{self, Display};
-
 use anyhow::{Context, Result};
-use qwasr_sdk::StateStore;
+use chrono::Utc;
+pub use common::occupancy::{OccupancyStatus, ThresholdRounding, VehicleCapacity};
+use common::trip_info;
+pub use common::trip_info::VehicleInfo;
+use qwasr_sdk::{Config, Message, Publisher, StateStore};
 use serde::{Deserialize, Serialize};
 use tracing::warn;
 
-use crate::types::{DilaxMessage, Door};
+use crate::confidence::Confidence;
+use crate::config::DilaxConfig;
+use crate::types::{DilaxMessage, Door, OccupancyEvent};
+use crate::{DilaxError, audit, trigger};
 
 const KEY_OCCUPANCY: &str = "trip:occupancy";
 const KEY_VEHICLE_STATE: &str = "apc:vehicleIdState";
 const KEY_VEHICLE_ID: &str = "apc:vehicleId";
 const KEY_VEHICLE_ID_MIGRATED: &str = "apc:vehicleIdMigrated";
 const KEY_TRIPS: &str = "apc:trips";
-const KEY_TRIP_INFO: &str = "apc:vehicleTripInfo";
+const KEY_PRE_ALLOCATION: &str = "apc:preAllocation";
 
 const TTL_APC: u64 = 60 * 60; // 1 hour
 const TTL_OCCUPANCY_STATE: u64 = 90 * 60; // 90 minutes
+const OCCUPANCY_REFRESH_SECS: i64 = 45 * 60; // half the occupancy TTL
 const TTL_VEHICLE_TRIP_INFO: u64 = 48 * 60 * 60; // 48 hours
+const MAX_TOKEN_LEAD_SECS: i64 = 365 * 24 * 60 * 60; // 1 year
 
-/// Update the vehicle state with the latest Dilax APC event.
+/// Update the vehicle state with the latest Dilax APC event, enriched as
+/// given.
+///
+/// Returns the vehicle's occupancy after the event, and the trip it closed
+/// when the event moved the vehicle onto a new trip, or `None` when the event
+/// is a duplicate.
+///
+/// Only counts from triggers listed in `DILAX_COUNTED_TRIGGERS` are
+/// accumulated, all by default. Messages from other triggers still advance the
+/// vehicle's state, but leave its count unchanged.
+///
+/// When `DILAX_OCCUPANCY_TOPIC` is set, an [`OccupancyEvent`] is published to
+/// the topic whenever the vehicle's count or occupancy status changes.
+///
+/// Occupancy thresholds are rounded as set by `DILAX_THRESHOLD_ROUNDING`,
+/// down by default, see [`ThresholdRounding`].
+///
+/// When `DILAX_COUNT_AUDIT` is enabled, the counts applied are appended to the
+/// trip's count audit log, see [`audit`]. Counts that can't be logged are
+/// skipped with a warning rather than failing the update.
+///
+/// The state store has no conditional write, so the vehicle's state is saved
+/// over any update made since it was read, which is logged as lost. Messages
+/// for a vehicle are expected to arrive in order from one unit.
 ///
 /// # Errors
 ///
 /// This function will return an error if there is an issue reading or writing
-/// to the state store, or if the event data is malformed.
-pub async fn update_vehicle(
-    vehicle_id: &str, trip_id: Option<&str>, seating_capacity: i64, total_capacity: i64,
-    event: &DilaxMessage, state_store: &impl StateStore,
-) -> Result<()> {
+/// to the state store, or if the event data is malformed. An implausible token
+/// is a [`DilaxError::InvalidTimestamp`], and more than `DILAX_MAX_DOORS` (64
+/// by default) doors a [`DilaxError::TooManyDoors`]. Failing to publish the
+/// occupancy event is only logged, as the state has already been saved.
+pub async fn update_vehicle<P>(
+    vehicle_id: &str, trip_id: Option<&str>, enrichment: &Enrichment, capacity: VehicleCapacity,
+    event: &DilaxMessage, config: &DilaxConfig, state_store: &P,
+) -> Result<Option<VehicleUpdate>>
+where
+    P: Config + Publisher + StateStore,
+{
     let state_key = format!("{KEY_VEHICLE_STATE}:{vehicle_id}");
+    let token = token(event, Utc::now().timestamp())?;
+    check_doors(vehicle_id, event, config.max_doors)?;
+
+    // counts are only taken from counted triggers
+    let trigger = event.trigger_kind();
+    let doors: &[Door] = if trigger::is_counted(config, vehicle_id, &trigger, state_store).await {
+        &event.doors
+    } else {
+        tracing::debug!(vehicle_id = %vehicle_id, ?trigger, "ignoring counts from trigger");
+        &[]
+    };
 
     // fetch existing state or create
     let state_prev = state_store.get(&state_key).await?;
@@ -41,7 +88,6 @@ pub async fn update_vehicle(
     };
 
     // check for duplicate/out-of-order message
-    let token = event.clock.utc.parse::<i64>().context("parsing Dilax token")?;
     if token <= state.token {
         warn!(
             vehicle_id = %vehicle_id,
@@ -49,18 +95,26 @@ pub async fn update_vehicle(
             last_token = state.token,
             "Received duplicate or out-of-order Dilax message"
         );
-        return Ok(());
+        tracing::info!(monotonic_counter.dilax_stale_messages = 1, vehicle_id = %vehicle_id);
+        return Ok(None);
     }
 
     // update token
     state.token = token;
+    let previous = (state.count, state.occupancy_status.clone());
 
-    // reset running count if trip ID changed
+    // reset running count if trip ID changed, closing the previous trip
     let mut reset_running_count = false;
+    let mut closed_trip = None;
     if let Some(trip_id) = trip_id {
         match &state.last_trip_id {
             Some(last) if last != trip_id => {
                 reset_running_count = true;
+                closed_trip = Some(ClosedTrip {
+                    trip_id: last.clone(),
+                    occupancy_percentage: state.occupancy_percentage,
+                    enrichment: state.enrichment.clone(),
+                });
                 state.last_trip_id = Some(trip_id.to_string());
             }
             None => state.last_trip_id = Some(trip_id.to_string()),
@@ -71,41 +125,248 @@ pub async fn update_vehicle(
     }
 
     // update occupancy count
-    if reset_running_count {
-        state.count = occupancy_count(0, &event.doors, vehicle_id, true);
+    let pre_allocation = if trip_id.is_some() {
+        pre_allocation_counts(vehicle_id, state_store).await?
     } else {
-        state.count = occupancy_count(state.count, &event.doors, vehicle_id, false);
-    }
+        None
+    };
+    let (base, skip_out, continued) = if let Some(buffered) = &pre_allocation {
+        // the trip starts with the passengers counted before it was allocated
+        let count = buffered.count;
+        tracing::info!(vehicle_id = %vehicle_id, count, "attributing pre-allocation count");
+        (buffered.count, false, false)
+    } else if reset_running_count {
+        (0, true, false)
+    } else {
+        (state.count, false, state_prev.is_some())
+    };
+    state.count = occupancy_count(base, doors, vehicle_id, skip_out);
+    let (passengers_in, passengers_out) = door_totals(doors, skip_out);
+    let opening = (!continued).then_some(base);
 
     // update occupancy status
-    let status = occupancy_status(state.count, seating_capacity, total_capacity);
-    state.occupancy_status = Some(status);
+    let status =
+        OccupancyStatus::from_counts_rounded(state.count, capacity, config.threshold_rounding);
+    state.occupancy_status = Some(status.to_string());
+    state.occupancy_percentage = occupancy_percentage(state.count, capacity.total);
+    state.enrichment = enrichment.clone();
+
+    // write the occupancy band when it changes, and again once half its TTL
+    // has passed so a vehicle staying in one band doesn't lose it mid-trip
+    let occupancy_key = format!("{KEY_OCCUPANCY}:{vehicle_id}");
+    let stored_occupancy = state_store.get(&occupancy_key).await?;
+    let refresh_due =
+        state.occupancy_written.is_none_or(|written| token - written >= OCCUPANCY_REFRESH_SECS);
+    let write_occupancy = state.occupancy_status.as_ref().filter(|occupancy| {
+        refresh_due || stored_occupancy.as_deref() != Some(occupancy.as_bytes())
+    });
+    if write_occupancy.is_some() {
+        state.occupancy_written = Some(token);
+    }
 
-    // save state
-    let state_json = serde_json::to_string(&state).context("serializing trip state")?;
-    let replaced = state_store.set(&state_key, state_json.as_bytes(), Some(TTL_APC)).await?;
+    // save state, flagging any update written since it was read, which is lost
+    let state_json = serde_json::to_vec(&state).context("serializing trip state")?;
+    let replaced = state_store.set(&state_key, &state_json, Some(TTL_APC)).await?;
+    if replaced != state_prev {
+        warn!(vehicle_id = %vehicle_id, "State overwritten concurrently");
+        tracing::info!(monotonic_counter.dilax_state_overwritten = 1, vehicle_id = %vehicle_id);
+    }
 
-    if let (Some(before), Some(during)) = (&state_prev, &replaced)
-        && before != during
-    {
-        warn!(
-            vehicle_id = %vehicle_id,
-            previous = %String::from_utf8_lossy(before),
-            replaced = %String::from_utf8_lossy(during),
-            "State overwritten concurrently"
-        );
+    // the buffered counts are only released once they're part of the saved state
+    if pre_allocation.is_some() {
+        state_store.delete(&format!("{KEY_PRE_ALLOCATION}:{vehicle_id}")).await?;
     }
 
     // update occupancy status
-    if let Some(ref occupancy) = state.occupancy_status {
-        let key = format!("{KEY_OCCUPANCY}:{vehicle_id}");
-        state_store.set(&key, occupancy.as_bytes(), Some(TTL_OCCUPANCY_STATE)).await?;
+    if let Some(occupancy) = write_occupancy {
+        state_store.set(&occupancy_key, occupancy.as_bytes(), Some(TTL_OCCUPANCY_STATE)).await?;
+    }
+
+    // record how the count was reached, without holding up the update
+    if let Some(trip_id) = trip_id
+        && config.count_audit
+    {
+        let entry = audit::CountAuditEntry {
+            stop_id: enrichment.stop_id.clone(),
+            passengers_in,
+            passengers_out,
+            count: state.count,
+            timestamp: token,
+            reset: false,
+        };
+        let entries = opening.map(|count| audit::CountAuditEntry::opening(count, token));
+        let entries = entries.into_iter().chain([entry]);
+        if let Err(err) = audit::append(vehicle_id, trip_id, entries, state_store).await {
+            warn!(vehicle_id = %vehicle_id, trip_id = %trip_id, "failed to log counts: {err:#}");
+        }
     }
 
     // update count
     let count_key = format!("{KEY_VEHICLE_ID}:{vehicle_id}");
     state_store.set(&count_key, state.count.to_string().as_bytes(), Some(TTL_APC)).await?;
 
+    if previous != (state.count, state.occupancy_status.clone())
+        && let Some(status) = &state.occupancy_status
+    {
+        let occupancy = OccupancyEvent {
+            vehicle_id: vehicle_id.to_string(),
+            trip_id: trip_id.map(ToString::to_string),
+            count: state.count,
+            status: status.clone(),
+            timestamp: token,
+        };
+        if let Err(err) = publish_occupancy(&occupancy, config, state_store).await {
+            warn!(vehicle_id = %vehicle_id, "failed to publish occupancy event: {err:#}");
+        }
+    }
+
+    Ok(Some(VehicleUpdate { occupancy_percentage: state.occupancy_percentage, closed_trip }))
+}
+
+/// Hold the counts of a Dilax event received before the vehicle has a block
+/// allocation, such as when a unit powers on before sign-on, so they can be
+/// attributed to the trip once it is allocated.
+///
+/// Up to `DILAX_PRE_ALLOCATION_MAX_MESSAGES` (120 by default) events are
+/// held, for `DILAX_PRE_ALLOCATION_TTL_SECS` (30 minutes by default) after the
+/// last one. Later events are dropped until the vehicle is allocated.
+///
+/// # Errors
+///
+/// This function will return an error if there is an issue reading or writing
+/// to the state store, or if the event data is malformed, as for
+/// [`update_vehicle`].
+pub async fn buffer_pre_allocation<P>(
+    vehicle_id: &str, event: &DilaxMessage, config: &DilaxConfig, state_store: &P,
+) -> Result<()>
+where
+    P: Config + StateStore,
+{
+    let key = format!("{KEY_PRE_ALLOCATION}:{vehicle_id}");
+    let mut buffered = state_store
+        .get(&key)
+        .await?
+        .and_then(|bytes| serde_json::from_slice::<PreAllocation>(&bytes).ok())
+        .unwrap_or_default();
+
+    let token = token(event, Utc::now().timestamp())?;
+    check_doors(vehicle_id, event, config.max_doors)?;
+    if token <= buffered.token {
+        return Ok(());
+    }
+    let max_messages = config.pre_allocation_max_messages;
+    if buffered.messages >= max_messages {
+        warn!(vehicle_id = %vehicle_id, max_messages, "Pre-allocation buffer full");
+        return Ok(());
+    }
+
+    let doors: &[Door] =
+        if trigger::is_counted(config, vehicle_id, &event.trigger_kind(), state_store).await {
+            &event.doors
+        } else {
+            &[]
+        };
+    // as for a new trip, alightings before the first event aren't known
+    buffered.count = occupancy_count(buffered.count, doors, vehicle_id, buffered.messages == 0);
+    buffered.token = token;
+    buffered.messages += 1;
+
+    let bytes = serde_json::to_vec(&buffered).context("serializing pre-allocation counts")?;
+    state_store.set(&key, &bytes, Some(config.pre_allocation_ttl_secs)).await?;
+
+    Ok(())
+}
+
+/// The event's clock token, a Unix timestamp in seconds, validated against
+/// `now`.
+///
+/// Tokens are only accepted in order, so a token far in the future, such as
+/// one in milliseconds, would block every later message for the vehicle. Old
+/// tokens are harmless, being superseded by the next message.
+fn token(event: &DilaxMessage, now: i64) -> Result<i64, DilaxError> {
+    let utc = &event.clock.utc;
+    let token = utc
+        .trim()
+        .parse::<i64>()
+        .map_err(|e| DilaxError::InvalidTimestamp(format!("invalid Dilax token {utc}: {e}")))?;
+    if token <= 0 || token - now > MAX_TOKEN_LEAD_SECS {
+        return Err(DilaxError::InvalidTimestamp(format!("implausible Dilax token {token}")));
+    }
+    Ok(token)
+}
+
+/// Reject a message claiming more doors than a vehicle could have, as from a
+/// corrupted unit, rather than counting them.
+fn check_doors(vehicle_id: &str, event: &DilaxMessage, max_doors: usize) -> Result<(), DilaxError> {
+    let doors = event.doors.len();
+    if doors > max_doors {
+        tracing::error!(vehicle_id = %vehicle_id, doors, max_doors, "Malformed Dilax message");
+        return Err(DilaxError::TooManyDoors(format!(
+            "Dilax message has {doors} doors, more than {max_doors}"
+        )));
+    }
+    Ok(())
+}
+
+async fn pre_allocation_counts(
+    vehicle_id: &str, state_store: &impl StateStore,
+) -> Result<Option<PreAllocation>> {
+    let key = format!("{KEY_PRE_ALLOCATION}:{vehicle_id}");
+    let Some(bytes) = state_store.get(&key).await? else {
+        return Ok(None);
+    };
+    Ok(serde_json::from_slice(&bytes).ok())
+}
+
+/// Publish the occupancy event to `DILAX_OCCUPANCY_TOPIC`, keyed by vehicle
+/// and trip. Nothing is published when the topic isn't set.
+async fn publish_occupancy<P>(
+    occupancy: &OccupancyEvent, config: &DilaxConfig, provider: &P,
+) -> Result<()>
+where
+    P: Config + Publisher,
+{
+    let Some(topic) = &config.occupancy_topic else {
+        return Ok(());
+    };
+
+    let payload = serde_json::to_vec(occupancy).context("serializing occupancy event")?;
+    let mut message = Message::new(&payload);
+    let key = format!("{}|{}", occupancy.vehicle_id, occupancy.trip_id.as_deref().unwrap_or(""));
+    message.headers.insert("key".to_string(), key);
+
+    let env = Config::get(provider, "ENV").await.unwrap_or_else(|_| "dev".to_string());
+    Publisher::send(provider, &format!("{env}-{topic}"), &message).await?;
+
+    Ok(())
+}
+
+/// Zero a vehicle's running passenger count, as when an operator resets it.
+///
+/// The rest of the vehicle's state, including its last message token and
+/// trip, is kept so the count restarts from the next message on the same
+/// trip. A vehicle without state is left alone.
+///
+/// # Errors
+///
+/// Returns an error when the state can't be read, parsed or saved.
+pub async fn reset_count(vehicle_id: &str, state_store: &impl StateStore) -> Result<()> {
+    let state_key = format!("{KEY_VEHICLE_STATE}:{vehicle_id}");
+    let Some(bytes) = state_store.get(&state_key).await? else {
+        return Ok(());
+    };
+
+    let mut state: TripState =
+        serde_json::from_slice(&bytes).context("deserializing trip state")?;
+    state.count = 0;
+    state.occupancy_status = None;
+    state.occupancy_percentage = None;
+
+    let state_json = serde_json::to_vec(&state).context("serializing trip state")?;
+    state_store.set(&state_key, &state_json, Some(TTL_APC)).await?;
+
+    let count_key = format!("{KEY_VEHICLE_ID}:{vehicle_id}");
+    state_store.set(&count_key, b"0", Some(TTL_APC)).await?;
     Ok(())
 }
 
@@ -118,8 +379,8 @@ pub async fn update_vehicle(
 pub async fn get_trip(
     vehicle_id: &str, state_store: &impl StateStore,
 ) -> Result<Option<VehicleTripInfo>> {
-    let key = &format!("{KEY_TRIP_INFO}:{vehicle_id}");
-    let Some(bytes) = StateStore::get(state_store, key).await? else {
+    let key = trip_info::key(vehicle_id);
+    let Some(bytes) = StateStore::get(state_store, &key).await? else {
         return Ok(None);
     };
     let info = serde_json::from_slice(&bytes).context("deserializing vehicle trip info")?;
@@ -128,12 +389,16 @@ pub async fn get_trip(
 
 /// Update the vehicle trip info with the latest Dilax APC event.
 ///
+/// The trip info is written with its expiry whether or not it changed, keeping
+/// it alive while the vehicle reports. As the store has no expire operation, a
+/// [`touch`](crate::touch) would cost a read on top of the same write.
+///
 /// # Errors
 ///
 /// This function will return an error if there is an issue reading or writing
 /// to the state store, or if the event data is malformed.
 pub async fn set_trip(vehicle_trip: VehicleTripInfo, state_store: &impl StateStore) -> Result<()> {
-    let key = format!("{KEY_TRIP_INFO}:{}", vehicle_trip.vehicle_info.vehicle_id);
+    let key = trip_info::key(&vehicle_trip.vehicle_info.vehicle_id);
 
     let bytes = serde_json::to_vec(&vehicle_trip).context("serializing vehicle trip info")?;
     state_store.set(&key, &bytes, Some(TTL_VEHICLE_TRIP_INFO)).await?;
@@ -172,28 +437,33 @@ async fn migrate_legacy_keys(
     Ok(())
 }
 
-fn occupancy_status(count: i64, seating_capacity: i64, total_capacity: i64) -> String {
-    let occupancy = if count < occupancy_threshold(seating_capacity, 5) {
-        OccupancyStatus::Empty
-    } else if count < occupancy_threshold(seating_capacity, 40) {
-        OccupancyStatus::ManySeatsAvailable
-    } else if count < occupancy_threshold(seating_capacity, 90) {
-        OccupancyStatus::FewSeatsAvailable
-    } else if count < occupancy_threshold(total_capacity, 90) {
-        OccupancyStatus::StandingRoomOnly
-    } else {
-        OccupancyStatus::Full
-    };
-
-    // info!(vehicle_id = %vehicle_id, occupancy = %occupancy, "Updated occupancy status");
-    occupancy.to_string()
+/// Passenger count as a percentage of total capacity, clamped to `0..=100`.
+///
+/// Returns `None` when the total capacity is zero or negative.
+#[must_use]
+pub fn occupancy_percentage(count: i64, total_capacity: i64) -> Option<u8> {
+    if total_capacity <= 0 {
+        return None;
+    }
+    let percent = count.max(0).saturating_mul(100).div_euclid(total_capacity);
+    u8::try_from(percent.min(100)).ok()
 }
 
-const fn occupancy_threshold(base: i64, percent: i64) -> i64 {
-    base.saturating_mul(percent).div_euclid(100)
+fn occupancy_count(previous: i64, doors: &[Door], vehicle_id: &str, skip_out: bool) -> i64 {
+    let (total_in, total_out) = door_totals(doors, skip_out);
+
+    let current = (previous - total_out).max(0) + total_in;
+    if current < 0 {
+        warn!(vehicle_id = %vehicle_id, count = current, "Calculated negative passenger count");
+        tracing::info!(monotonic_counter.dilax_negative_counts = 1, vehicle_id = %vehicle_id);
+    }
+
+    current.max(0)
 }
 
-fn occupancy_count(previous: i64, doors: &[Door], vehicle_id: &str, skip_out: bool) -> i64 {
+/// Passengers boarding and alighting through all doors, ignoring alightings
+/// when `skip_out` is set.
+fn door_totals(doors: &[Door], skip_out: bool) -> (i64, i64) {
     let mut total_in = 0_i64;
     let mut total_out = 0_i64;
 
@@ -204,12 +474,7 @@ fn occupancy_count(previous: i64, doors: &[Door], vehicle_id: &str, skip_out: bo
         }
     }
 
-    let current = (previous - total_out).max(0) + total_in;
-    if current < 0 {
-        warn!(vehicle_id = %vehicle_id, count = current, "Calculated negative passenger count");
-    }
-
-    current.max(0)
+    (total_in, total_out)
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -220,42 +485,87 @@ struct TripState {
     pub last_trip_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub occupancy_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occupancy_percentage: Option<u8>,
+    /// How the last event counted was enriched.
+    #[serde(default)]
+    pub enrichment: Enrichment,
+    /// Token of the message that last wrote the occupancy band.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occupancy_written: Option<i64>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-#[repr(u8)]
-enum OccupancyStatus {
-    Empty = 0,
-    ManySeatsAvailable = 1,
-    FewSeatsAvailable = 2,
-    StandingRoomOnly = 3,
-    CrushedStandingRoomOnly = 4,
-    Full = 5,
-    NotAcceptingPassengers = 6,
+/// How a Dilax event was enriched, kept with the vehicle's state so a trip
+/// can be closed as it was last counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Enrichment {
+    /// The stop the event was counted at, if it was resolved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_id: Option<String>,
+    /// Confidence in the enrichment.
+    #[serde(default)]
+    pub confidence: Confidence,
+    /// Enrichments that couldn't be resolved.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<String>,
 }
 
-impl Display for OccupancyStatus {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(&(*self as u8).to_string())
-    }
+/// A vehicle's occupancy after an event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VehicleUpdate {
+    /// Passenger count as a percentage of total capacity, `None` when the
+    /// capacity is unusable.
+    pub occupancy_percentage: Option<u8>,
+    /// The trip the vehicle was on before the event, when the event moved it
+    /// onto a new trip.
+    pub closed_trip: Option<ClosedTrip>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct VehicleTripInfo {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub last_received_timestamp: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub dilax_message: Option<DilaxMessage>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub trip_id: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub stop_id: Option<String>,
-    pub vehicle_info: VehicleInfo,
+/// A trip a vehicle has moved off, as it was last counted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosedTrip {
+    pub trip_id: String,
+    /// The trip's final occupancy percentage.
+    pub occupancy_percentage: Option<u8>,
+    /// How the trip's last event was enriched.
+    pub enrichment: Enrichment,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct VehicleInfo {
-    pub label: Option<String>,
-    #[serde(rename = "vehicleId")]
-    pub vehicle_id: String,
+/// Counts held for a vehicle without a block allocation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PreAllocation {
+    count: i64,
+    token: i64,
+    messages: usize,
+}
+
+/// Trip info stored for a vehicle, carrying the last Dilax message received.
+pub type VehicleTripInfo = trip_info::VehicleTripInfo<DilaxMessage>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOTAL: i64 = 400;
+
+    #[test]
+    fn percentage_typical() {
+        assert_eq!(occupancy_percentage(0, TOTAL), Some(0));
+        assert_eq!(occupancy_percentage(111, TOTAL), Some(27));
+        assert_eq!(occupancy_percentage(200, TOTAL), Some(50));
+    }
+
+    #[test]
+    fn percentage_full() {
+        assert_eq!(occupancy_percentage(TOTAL, TOTAL), Some(100));
+        assert_eq!(occupancy_percentage(TOTAL * 2, TOTAL), Some(100));
+        assert_eq!(occupancy_percentage(i64::MAX, TOTAL), Some(100));
+    }
+
+    #[test]
+    fn percentage_unusable_capacity() {
+        assert_eq!(occupancy_percentage(10, 0), None);
+        assert_eq!(occupancy_percentage(10, -TOTAL), None);
+        assert_eq!(occupancy_percentage(-10, TOTAL), Some(0));
+    }
 }
@@ -1,5 +1,9 @@
+use chrono::DateTime;
 use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::confidence::Confidence;
+use crate::trigger::Trigger;
+
 /// Raw Dilax payload emitted by the APC hardware on board a train.
 /// The payload mirrors the legacy adapter schema so that parity can be
 /// maintained against the historic Redis records.
@@ -41,6 +45,14 @@ pub struct DilaxMessage {
     pub wpt: Option<Waypoint>,
 }
 
+impl DilaxMessage {
+    /// Decoded cause of the message.
+    #[must_use]
+    pub fn trigger_kind(&self) -> Trigger {
+        Trigger::from(self.trigger.as_str())
+    }
+}
+
 #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
 fn into_u32<'de, D>(deserializer: D) -> anyhow::Result<Option<u32>, D::Error>
 where
@@ -72,6 +84,51 @@ pub struct EnrichedEvent {
     /// Scheduled start time for the resolved trip.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub start_time: Option<String>,
+    /// Passenger count as a percentage (0-100) of the vehicle's total capacity.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occupancy_percentage: Option<u8>,
+    /// Whether the count was taken between stops, as indicated by
+    /// `distance_laststop`, and so not committed to a stop.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub in_transit: bool,
+    /// Aggregate confidence in the enrichment, from how degraded it was.
+    #[serde(default)]
+    pub confidence: Confidence,
+    /// Enrichments that couldn't be resolved: the `stop` in any mode, and
+    /// `vehicle`, `capacity` or `allocation` in best-effort mode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<String>,
+    /// Set on the pair of events published when the vehicle's trip changes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip_change: Option<TripChange>,
+}
+
+/// Role of an event published when a message arrives as the vehicle's trip
+/// changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TripChange {
+    /// Closes the previous trip with its final occupancy. Carries no door
+    /// counts, which belong to the new trip.
+    Closing,
+    /// Opens the new trip, its count reset.
+    Opening,
+}
+
+/// Occupancy of a vehicle on a trip, published to the occupancy topic when it
+/// changes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OccupancyEvent {
+    pub vehicle_id: String,
+    /// Trip the vehicle was allocated to, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip_id: Option<String>,
+    /// Running passenger count.
+    pub count: i64,
+    /// GTFS-RT occupancy status band.
+    pub status: String,
+    /// Dilax clock time of the event that changed the occupancy.
+    pub timestamp: i64,
 }
 
 /// Metadata describing the APC device that emitted the event.
@@ -90,12 +147,35 @@ pub struct Device {
 /// Timestamp metadata accompanying a Dilax message.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Clock {
-    /// UTC timestamp of the reading.
+    /// UTC timestamp of the reading, in seconds since the epoch. Firmware
+    /// sending ISO 8601 timestamps with an offset is normalised to epoch
+    /// seconds on deserialization.
+    #[serde(deserialize_with = "epoch_secs")]
     pub utc: String,
     /// Timezone hint supplied by the device.
     pub tz: String,
 }
 
+fn epoch_secs<'de, D>(deserializer: D) -> anyhow::Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    let raw = match value {
+        serde_json::Value::String(s) => s,
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => return Err(serde::de::Error::custom("expected epoch or ISO 8601 timestamp")),
+    };
+
+    let raw = raw.trim();
+    if raw.parse::<i64>().is_ok() {
+        return Ok(raw.to_string());
+    }
+    DateTime::parse_from_rfc3339(raw)
+        .map(|dt| dt.timestamp().to_string())
+        .map_err(|e| serde::de::Error::custom(format!("invalid clock timestamp {raw}: {e}")))
+}
+
 /// Passenger information system context bundled with the Dilax payload.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Pis {
@@ -151,4 +231,25 @@ mod tests {
         assert_eq!(dilax_message.dlx_vers, "ABCDEFGHIJKLMN");
         assert_eq!(dilax_message.speed, Some(0));
     }
+
+    fn clock(utc: &str) -> Clock {
+        serde_json::from_value(serde_json::json!({ "utc": utc, "tz": "Pacific/Auckland" }))
+            .expect("should deserialize")
+    }
+
+    #[test]
+    fn clock_epoch_or_iso() {
+        // 2025-11-26T22:09:45Z
+        assert_eq!(clock("1764194985").utc, "1764194985");
+        assert_eq!(clock("2025-11-26T22:09:45Z").utc, "1764194985");
+        assert_eq!(clock("2025-11-27T11:09:45+13:00").utc, "1764194985");
+    }
+
+    #[test]
+    fn clock_invalid() {
+        let result = serde_json::from_value::<Clock>(
+            serde_json::json!({ "utc": "yesterday", "tz": "Pacific/Auckland" }),
+        );
+        assert!(result.is_err());
+    }
 }
@@ -0,0 +1,92 @@
+//! Tests for loading Dilax settings.
+
+mod provider;
+
+use dilax_adapter::{
+    CountedTriggers, DilaxConfig, ServiceWindow, ThresholdRounding, Trigger, Weights,
+};
+
+use self::provider::MockProvider;
+
+fn provider(settings: &[(&str, &str)]) -> MockProvider {
+    settings
+        .iter()
+        .fold(MockProvider::default(), |provider, (key, value)| provider.with_config(key, value))
+}
+
+fn window(start: &str, end: &str) -> ServiceWindow {
+    format!("{start}-{end}").parse().expect("should parse")
+}
+
+// Should apply valid overrides.
+#[tokio::test]
+async fn overrides() {
+    let provider = provider(&[
+        ("DILAX_BEST_EFFORT", "true"),
+        ("DILAX_AT_STOP_DISTANCE_METERS", "200"),
+        ("DILAX_STOP_SEARCH_DISTANCE_METERS", " 300 "),
+        ("LOST_CONNECTION_THRESHOLD_SECS", "1800"),
+        ("LOST_CONNECTION_RETENTION_DAYS", "3"),
+        ("DILAX_DETECTION_END_GRACE_SECS", "600"),
+        ("DILAX_EXCLUDED_LABEL_PREFIX", ""),
+        ("DILAX_DETECTION_SERVICE_WINDOW", "04:00 - 01:00"),
+        ("DILAX_CONFIDENCE_WEIGHTS", "low=3"),
+        ("DILAX_COUNTED_TRIGGERS", "station_left_summary"),
+        ("DILAX_COUNT_AUDIT", "on"),
+        ("DILAX_OCCUPANCY_TOPIC", " realtime-dilax-occupancy.v1 "),
+        ("DILAX_THRESHOLD_ROUNDING", "Ceil"),
+        ("DILAX_MAX_DOORS", "8"),
+        ("DILAX_PRE_ALLOCATION_MAX_MESSAGES", "60"),
+        ("DILAX_PRE_ALLOCATION_TTL_SECS", "600"),
+    ]);
+
+    let loaded = DilaxConfig::load(&provider).await;
+    assert_eq!(
+        loaded,
+        DilaxConfig {
+            best_effort: true,
+            at_stop_distance: Some(200),
+            stop_search_distance: 300,
+            detection_threshold_secs: 1800,
+            detection_retention_secs: 3 * 24 * 60 * 60,
+            end_grace_secs: 600,
+            excluded_label_prefix: String::new(),
+            service_window: Some(window("04:00", "01:00")),
+            confidence_weights: Weights { low: 3, ..Weights::default() },
+            counted_triggers: CountedTriggers::Only(vec![Trigger::StationLeftSummary]),
+            count_audit: true,
+            occupancy_topic: Some("realtime-dilax-occupancy.v1".to_string()),
+            threshold_rounding: ThresholdRounding::Ceil,
+            max_doors: 8,
+            pre_allocation_max_messages: 60,
+            pre_allocation_ttl_secs: 600,
+            ..DilaxConfig::default()
+        }
+    );
+    assert!(!loaded.is_excluded("ADL        1005"));
+}
+
+// Should fall back to defaults for unset, unparseable or out of range
+// values.
+#[tokio::test]
+async fn defaults() {
+    let unset = DilaxConfig::load(&MockProvider::default()).await;
+    assert_eq!(unset, DilaxConfig::default());
+    assert!(unset.is_excluded("ADL        1005"));
+    assert!(!unset.is_excluded("AMP        1005"));
+
+    let invalid = provider(&[
+        ("DILAX_BEST_EFFORT", "maybe"),
+        ("DILAX_AT_STOP_DISTANCE_METERS", "-5"),
+        ("DILAX_STOP_SEARCH_DISTANCE_METERS", "0"),
+        ("LOST_CONNECTION_THRESHOLD_SECS", "an hour"),
+        ("DILAX_TRIP_INFO_MAX_AGE_SECS", "-60"),
+        ("LOST_CONNECTION_RETENTION_DAYS", "0"),
+        ("DILAX_DETECTION_SERVICE_WINDOW", "04:00"),
+        ("DILAX_OCCUPANCY_TOPIC", " "),
+        ("DILAX_THRESHOLD_ROUNDING", "nearest"),
+        ("DILAX_MAX_DOORS", "0"),
+        ("DILAX_PRE_ALLOCATION_TTL_SECS", "0"),
+    ]);
+    assert_eq!(DilaxConfig::load(&invalid).await, DilaxConfig::default());
+}
@@ -0,0 +1,385 @@
+//! Tests for re-rendering stored lost-connection detections.
+
+mod provider;
+
+use chrono::Utc;
+use chrono_tz::Pacific;
+use dilax_adapter::{
+    Detection, DetectionLog, DetectionLogRequest, DetectionOrder, DetectionQuery, DetectionRequest,
+    DetectionSummary, VehicleInfo, VehicleTripInfo, set_contains, set_members, set_trip,
+};
+use qwasr_sdk::{Handler, StateStore};
+use serde_json::{Value, json};
+
+use self::provider::MockProvider;
+
+const SET_KEY: &str = "apc:lostConnections20251107";
+const VEHICLE_TRIP: &str = "59123|trip-1";
+
+fn detection() -> Value {
+    let message: Value =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    json!({
+        "detection_time": 1_762_473_000,
+        "allocation": {
+            "operationalBlockId": "101-202",
+            "tripId": "trip-1",
+            "serviceDate": "20251107",
+            "startTime": "08:00:00",
+            "vehicleId": "59123",
+            "vehicleLabel": "AMP        1005",
+            "routeId": "EAST-201",
+            "directionId": 0,
+            "referenceId": "1005",
+            "endTime": "09:00:00",
+            "delay": 0,
+            "startDatetime": 1_762_466_400,
+            "endDatetime": 1_762_473_600,
+            "isCanceled": false,
+            "isCopied": false,
+            "timezone": "Pacific/Auckland",
+            "creationDatetime": "2025-11-06T12:00:00Z"
+        },
+        "vehicle_trip_info": {
+            "last_received_timestamp": "1762469343",
+            "dilax_message": message,
+            "trip_id": "trip-1",
+            "vehicle_info": { "label": "AMP        1005", "vehicleId": "59123" }
+        }
+    })
+}
+
+// Should render a stored detection as it was logged.
+#[tokio::test]
+async fn render_detection() {
+    let provider = MockProvider::default();
+    let bytes = serde_json::to_vec(&detection()).expect("should serialize");
+    provider.set(&format!("{SET_KEY}:{VEHICLE_TRIP}"), &bytes, None).await.expect("should set");
+
+    let request = (SET_KEY.to_string(), VEHICLE_TRIP.to_string());
+    let reply = DetectionLogRequest::handler(request)
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should render");
+
+    assert_eq!(
+        reply.body,
+        DetectionLog {
+            vehicle: "AM1005 - AMP        1005".to_string(),
+            trip_id: "trip-1".to_string(),
+            timestamp: "2025-11-07 11:49:03 NZDT".to_string(),
+            coordinates: "Last Coordinates: Latitude: -36.862813838151354; Longitude: \
+                          174.81012224180958"
+                .to_string(),
+        }
+    );
+}
+
+// Should render the last received time in the configured timezone and format,
+// ignoring an invalid format.
+#[tokio::test]
+async fn render_configured_format() {
+    let bytes = serde_json::to_vec(&detection()).expect("should serialize");
+    let request = || (SET_KEY.to_string(), VEHICLE_TRIP.to_string());
+
+    let sydney = MockProvider::default()
+        .with_config("DILAX_LOG_TIMEZONE", "Australia/Sydney")
+        .with_config("DILAX_LOG_TIMESTAMP_FORMAT", "%d/%m/%Y %H:%M %Z");
+    sydney.set(&format!("{SET_KEY}:{VEHICLE_TRIP}"), &bytes, None).await.expect("should set");
+    let reply = DetectionLogRequest::handler(request())
+        .expect("should create handler")
+        .provider(&sydney)
+        .owner("at")
+        .await
+        .expect("should render");
+    assert_eq!(reply.body.timestamp, "07/11/2025 09:49 AEDT");
+
+    let invalid = MockProvider::default()
+        .with_config("DILAX_LOG_TIMEZONE", "Middle/Earth")
+        .with_config("DILAX_LOG_TIMESTAMP_FORMAT", "%Y-%m-%d %Q");
+    invalid.set(&format!("{SET_KEY}:{VEHICLE_TRIP}"), &bytes, None).await.expect("should set");
+    let reply = DetectionLogRequest::handler(request())
+        .expect("should create handler")
+        .provider(&invalid)
+        .owner("at")
+        .await
+        .expect("should render");
+    assert_eq!(reply.body.timestamp, "2025-11-07 11:49:03 NZDT");
+}
+
+// Should reject detections that were never stored, and keys outside the
+// lost-connection sets.
+#[tokio::test]
+async fn unknown_detection() {
+    let provider = MockProvider::default();
+
+    let request = (SET_KEY.to_string(), VEHICLE_TRIP.to_string());
+    let err = DetectionLogRequest::handler(request)
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect_err("should not be found");
+    assert_eq!(err.code(), "not_found");
+
+    let request = ("apc:vehicleTripInfo".to_string(), "59123".to_string());
+    DetectionLogRequest::handler(request)
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect_err("should reject other keys");
+}
+
+fn vehicle_trips(detections: &[Detection]) -> Vec<String> {
+    detections
+        .iter()
+        .map(|d| {
+            format!("{}|{}", d.vehicle_trip_info.vehicle_info.vehicle_id, d.allocation.trip_id)
+        })
+        .collect()
+}
+
+fn allocation(vehicle_id: &str, label: &str, started_mins_ago: i64, ends_in_mins: i64) -> Value {
+    let now = Utc::now().with_timezone(&Pacific::Auckland);
+    json!({
+        "operationalBlockId": "101-202",
+        "tripId": format!("trip-{vehicle_id}"),
+        "serviceDate": now.format("%Y%m%d").to_string(),
+        "startTime": "08:00:00",
+        "vehicleId": vehicle_id,
+        "vehicleLabel": label,
+        "routeId": "EAST-201",
+        "directionId": 0,
+        "referenceId": "1005",
+        "endTime": "09:00:00",
+        "delay": 0,
+        "startDatetime": now.timestamp() - started_mins_ago * 60,
+        "endDatetime": now.timestamp() + ends_in_mins * 60,
+        "isCanceled": false,
+        "isCopied": false,
+        "timezone": "Pacific/Auckland",
+        "creationDatetime": "2025-11-06T12:00:00Z"
+    })
+}
+
+// Should summarise how allocations were narrowed down, consistently with the
+// detections, suppressing vehicles already detected today.
+#[tokio::test]
+async fn detection_summary() {
+    let allocations = json!({
+        "current": [],
+        "all": [
+            // lost: started two hours ago without a message
+            allocation("59121", "AMP        1001", 120, 60),
+            // reporting on its trip
+            allocation("59122", "AMP        1002", 120, 60),
+            // started within the threshold
+            allocation("59123", "AMP        1003", 10, 60),
+            // ended
+            allocation("59124", "AMP        1004", 120, -60),
+            // diesel trains aren't evaluated
+            allocation("59125", "ADL        1005", 120, 60),
+        ]
+    });
+    let provider = MockProvider::default().with_response("allocations", &allocations.to_string());
+
+    let reporting = VehicleTripInfo {
+        last_received_timestamp: Some(Utc::now().timestamp().to_string()),
+        dilax_message: None,
+        trip_id: Some("trip-59122".to_string()),
+        stop_id: None,
+        vehicle_info: VehicleInfo { label: None, vehicle_id: "59122".to_string() },
+    };
+    set_trip(reporting, &provider).await.expect("should set trip");
+
+    let first = DetectionRequest::handler(DetectionQuery::default())
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should detect");
+    assert_eq!(first.body.detections, 1);
+    assert_eq!(
+        first.body.summary,
+        DetectionSummary { evaluated: 4, running: 3, detected: 1, recovered: 2, suppressed: 0 }
+    );
+
+    let second = DetectionRequest::handler(DetectionQuery::default())
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should detect");
+    assert_eq!(second.body.detections, 0);
+    assert_eq!(
+        second.body.summary,
+        DetectionSummary { evaluated: 4, running: 3, detected: 0, recovered: 2, suppressed: 1 }
+    );
+}
+
+// Should not detect a vehicle going silent as its trip winds down, within the
+// configured grace before the trip ends, counting it as suppressed.
+#[tokio::test]
+async fn end_grace() {
+    let allocations = json!({
+        "current": [],
+        "all": [
+            // ends in 5 minutes
+            allocation("59121", "AMP        1001", 120, 5),
+            // ends in 15 minutes
+            allocation("59122", "AMP        1002", 120, 15),
+        ]
+    });
+    let provider = MockProvider::default()
+        .with_response("allocations", &allocations.to_string())
+        .with_config("DILAX_DETECTION_END_GRACE_SECS", "600");
+
+    let reply = DetectionRequest::handler(DetectionQuery::default())
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should detect");
+    assert_eq!(reply.body.detections, 1);
+    assert_eq!(
+        reply.body.summary,
+        DetectionSummary { evaluated: 2, running: 2, detected: 1, recovered: 0, suppressed: 1 }
+    );
+}
+
+// Should detect a vehicle once when Block Management returns overlapping
+// sibling allocations for it, against the earliest starting.
+#[tokio::test]
+async fn overlapping_allocations() {
+    let mut sibling = allocation("59121", "AMP        1001", 90, 60);
+    sibling["tripId"] = json!("trip-59121-sibling");
+    let allocations = json!({
+        "current": [],
+        "all": [sibling, allocation("59121", "AMP        1001", 120, 60)]
+    });
+    let provider = MockProvider::default().with_response("allocations", &allocations.to_string());
+
+    let reply = DetectionRequest::handler(DetectionQuery::default())
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should detect");
+    assert_eq!(reply.body.detections, 1);
+    assert_eq!(
+        reply.body.summary,
+        DetectionSummary { evaluated: 2, running: 1, detected: 1, recovered: 0, suppressed: 0 }
+    );
+
+    let today = Utc::now().with_timezone(&Pacific::Auckland).format("%Y%m%d");
+    let set_key = format!("apc:lostConnections{today}");
+    let detected = set_contains(&provider, &set_key, "59121|trip-59121").await;
+    assert!(detected.expect("should read set"));
+}
+
+// Should detect a vehicle against its sibling allocation when the earliest
+// starting one is ending within the grace.
+#[tokio::test]
+async fn overlapping_allocation_ending() {
+    let mut sibling = allocation("59121", "AMP        1001", 90, 60);
+    sibling["tripId"] = json!("trip-59121-sibling");
+    let allocations = json!({
+        "current": [],
+        "all": [sibling, allocation("59121", "AMP        1001", 120, 5)]
+    });
+    let provider = MockProvider::default()
+        .with_response("allocations", &allocations.to_string())
+        .with_config("DILAX_DETECTION_END_GRACE_SECS", "600");
+
+    let reply = DetectionRequest::handler(DetectionQuery::default())
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should detect");
+    assert_eq!(
+        reply.body.summary,
+        DetectionSummary { evaluated: 2, running: 1, detected: 1, recovered: 0, suppressed: 0 }
+    );
+
+    let today = Utc::now().with_timezone(&Pacific::Auckland).format("%Y%m%d");
+    let set_key = format!("apc:lostConnections{today}");
+    let detected = set_contains(&provider, &set_key, "59121|trip-59121-sibling").await;
+    assert!(detected.expect("should read set"));
+}
+
+// Should return the detections a pass would record without writing to the
+// store, leaving them to be detected by the next pass.
+#[tokio::test]
+async fn dry_run() {
+    let allocations = json!({
+        "current": [],
+        "all": [
+            allocation("59121", "AMP        1001", 120, 60),
+            allocation("59122", "AMP        1002", 90, 60),
+        ]
+    });
+    let provider = MockProvider::default().with_response("allocations", &allocations.to_string());
+    let today = Utc::now().with_timezone(&Pacific::Auckland).format("%Y%m%d");
+    let set_key = format!("apc:lostConnections{today}");
+
+    let preview =
+        DetectionRequest::handler(DetectionQuery { dry_run: true, ..DetectionQuery::default() })
+            .expect("should create handler")
+            .provider(&provider)
+            .owner("at")
+            .await
+            .expect("should detect");
+    assert_eq!(preview.body.detections, 2);
+    assert_eq!(provider.writes(&set_key), 0);
+    assert!(set_members(&provider, &set_key).await.expect("should read set").is_empty());
+
+    let reply = DetectionRequest::handler(DetectionQuery::default())
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should detect");
+    assert_eq!(reply.body.detections, 2);
+    assert_eq!(reply.body.summary, preview.body.summary);
+    assert_eq!(
+        vehicle_trips(&reply.body.new_detections),
+        vehicle_trips(&preview.body.new_detections)
+    );
+
+    let mut recorded = set_members(&provider, &set_key).await.expect("should read set");
+    recorded.sort();
+    assert_eq!(vehicle_trips(&preview.body.new_detections), recorded);
+}
+
+// Should return new detections longest lost first, unless insertion order is
+// asked for.
+#[tokio::test]
+async fn detection_order() {
+    let allocations = json!({
+        "current": [],
+        "all": [
+            allocation("59122", "AMP        1002", 90, 60),
+            allocation("59121", "AMP        1001", 120, 60),
+        ]
+    });
+    for (order, expected) in [
+        (DetectionOrder::Severity, ["59121|trip-59121", "59122|trip-59122"]),
+        (DetectionOrder::Insertion, ["59122|trip-59122", "59121|trip-59121"]),
+    ] {
+        let provider =
+            MockProvider::default().with_response("allocations", &allocations.to_string());
+        let reply =
+            DetectionRequest::handler(DetectionQuery { order, ..DetectionQuery::default() })
+                .expect("should create handler")
+                .provider(&provider)
+                .owner("at")
+                .await
+                .expect("should detect");
+        assert_eq!(vehicle_trips(&reply.body.new_detections), expected, "{order:?}");
+    }
+}
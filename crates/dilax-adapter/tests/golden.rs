@@ -0,0 +1,71 @@
+//! Golden tests for Dilax enrichment: Dilax messages are processed against
+//! the Fleet, block allocation and stop responses they were enriched with,
+//! and the event published compared to the one recorded from the running
+//! service.
+
+mod provider;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+
+use dilax_adapter::{DilaxMessage, process};
+use serde::Deserialize;
+use serde_json::Value;
+
+use self::provider::MockProvider;
+
+/// Routes the mock provider answers.
+const ROUTES: [&str; 6] =
+    ["fleet", "allocation", "allocations", "stops", "stop_types", "stop_times"];
+
+#[derive(Deserialize)]
+struct Golden {
+    description: String,
+    input: DilaxMessage,
+    /// Recorded response bodies, by route.
+    responses: HashMap<String, Value>,
+    /// Fields the recorded event doesn't carry, such as those added since it
+    /// was recorded, left out of the comparison.
+    #[serde(default)]
+    ignore: Vec<String>,
+    output: Value,
+}
+
+// Load each recorded case. For each, process the message and compare the
+// enriched event published to the one expected.
+#[tokio::test]
+async fn run() {
+    for entry in fs::read_dir("data/golden").expect("should read directory") {
+        let path = entry.expect("should read entry").path();
+        let file = File::open(&path).expect("should open file");
+        let golden: Golden = serde_json::from_reader(file).expect("should deserialize golden");
+        check(golden).await;
+    }
+}
+
+async fn check(golden: Golden) {
+    let mut provider = MockProvider::default();
+    for (route, body) in &golden.responses {
+        let route = ROUTES.iter().find(|known| *known == route).expect("should be a known route");
+        provider = provider.with_response(route, &body.to_string());
+    }
+
+    process(golden.input, &provider).await.expect("should process");
+
+    let published = provider.published();
+    assert_eq!(published.len(), 1, "{}", golden.description);
+    let (topic, message) = &published[0];
+    assert_eq!(topic, "dev-realtime-dilax-apc-enriched.v2");
+
+    let mut actual: Value = serde_json::from_slice(&message.payload).expect("should deserialize");
+    let mut expected = golden.output;
+    for field in &golden.ignore {
+        assert!(actual.get(field).is_some(), "{}: {field} isn't published", golden.description);
+    }
+    for value in [&mut actual, &mut expected] {
+        if let Some(fields) = value.as_object_mut() {
+            fields.retain(|field, _| !golden.ignore.contains(field));
+        }
+    }
+    assert_eq!(actual, expected, "{}", golden.description);
+}
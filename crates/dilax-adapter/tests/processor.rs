@@ -0,0 +1,447 @@
+//! Tests for enriching Dilax events, strictly and in best-effort mode.
+
+mod provider;
+
+use common::outcome::ProcessResult;
+use dilax_adapter::{DilaxMessage, get_trip, process};
+use qwasr_sdk::StateStore;
+use serde_json::Value;
+
+use self::provider::MockProvider;
+
+const VEHICLE: &str = r#"[{
+    "id": "59123",
+    "label": "AMP        1005",
+    "capacity": { "seating": 200, "standing": 200, "total": 400 },
+    "type": { "type": "train" }
+}]"#;
+
+const VEHICLE_NO_CAPACITY: &str = r#"[{
+    "id": "59123",
+    "label": "AMP        1005",
+    "type": { "type": "train" }
+}]"#;
+
+const ALLOCATION: &str = r#"{
+    "current": [{
+        "operationalBlockId": "101-202",
+        "tripId": "trip-1",
+        "serviceDate": "20251107",
+        "startTime": "08:00:00",
+        "vehicleId": "59123",
+        "vehicleLabel": "AMP        1005",
+        "routeId": "EAST-201",
+        "directionId": 0,
+        "referenceId": "1005",
+        "endTime": "09:00:00",
+        "delay": 0,
+        "startDatetime": 1762466400,
+        "endDatetime": 1762473600,
+        "isCanceled": false,
+        "isCopied": false,
+        "timezone": "Pacific/Auckland",
+        "creationDatetime": "2025-11-06T12:00:00Z"
+    }],
+    "all": []
+}"#;
+
+const OVERRIDE_ALLOCATIONS: &str = r#"{
+    "current": [],
+    "all": [{
+        "operationalBlockId": "101-203",
+        "tripId": "trip-2",
+        "serviceDate": "20251107",
+        "startTime": "08:30:00",
+        "vehicleId": "59124",
+        "vehicleLabel": "AMP        1006",
+        "routeId": "EAST-201",
+        "directionId": 1,
+        "referenceId": "1006",
+        "endTime": "09:30:00",
+        "delay": 0,
+        "startDatetime": 1762468200,
+        "endDatetime": 1762471800,
+        "isCanceled": false,
+        "isCopied": false,
+        "timezone": "Pacific/Auckland",
+        "creationDatetime": "2025-11-06T12:00:00Z"
+    }]
+}"#;
+
+const NO_ALLOCATION: &str = r#"{ "current": [], "all": [] }"#;
+const STOPS: &str = r#"[{ "stop_id": "9218-a", "stop_code": "9218" }]"#;
+const STOP_TYPES: &str =
+    r#"[{ "parent_stop_code": "9218", "route_type": 2, "stop_code": "9218-a" }]"#;
+
+fn event() -> DilaxMessage {
+    serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize")
+}
+
+fn provider() -> MockProvider {
+    MockProvider::default()
+        .with_response("fleet", VEHICLE)
+        .with_response("allocation", ALLOCATION)
+        .with_response("stops", STOPS)
+        .with_response("stop_types", STOP_TYPES)
+}
+
+fn best_effort(provider: MockProvider) -> MockProvider {
+    provider.with_config("DILAX_BEST_EFFORT", "true")
+}
+
+fn enriched(provider: &MockProvider) -> Value {
+    let published = provider.published();
+    assert_eq!(published.len(), 1);
+
+    let (topic, message) = &published[0];
+    assert_eq!(topic, "dev-realtime-dilax-apc-enriched.v2");
+    serde_json::from_slice(&message.payload).expect("should deserialize")
+}
+
+// Should fully enrich an event without any unresolved markers.
+#[tokio::test]
+async fn fully_resolved() {
+    let provider = provider();
+    let outcome = process(event(), &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(1)));
+
+    let enriched = enriched(&provider);
+    assert_eq!(enriched["trip_id"], "trip-1");
+    assert_eq!(enriched["stop_id"], "9218-a");
+    assert_eq!(enriched["start_date"], "20251107");
+    assert_eq!(enriched["occupancy_percentage"], 27);
+    assert_eq!(enriched["confidence"], "High");
+    assert!(enriched.get("unresolved").is_none());
+}
+
+// Should attribute the event to a God Mode override trip rather than the
+// allocated one, with the override trip's start date and time.
+#[tokio::test]
+async fn trip_override() {
+    let provider = provider()
+        .with_config("GOD_MODE_ENABLED", "true")
+        .with_response("allocations", OVERRIDE_ALLOCATIONS);
+    let overrides = r#"{"overrides":{"59123":"trip-2"}}"#;
+    provider.set("god_mode:overrides", overrides.as_bytes(), Some(60)).await.expect("should set");
+
+    process(event(), &provider).await.expect("should process");
+
+    let enriched = enriched(&provider);
+    assert_eq!(enriched["trip_id"], "trip-2");
+    assert_eq!(enriched["start_date"], "20251107");
+    assert_eq!(enriched["start_time"], "08:30:00");
+    let info = get_trip("59123", &provider).await.expect("should get trip").expect("trip info");
+    assert_eq!(info.trip_id.as_deref(), Some("trip-2"));
+
+    // once the override expires the allocation applies again
+    provider.advance(61);
+    let mut next = event();
+    next.clock.utc = "1762469403".to_string();
+    process(next, &provider).await.expect("should process");
+
+    let published = provider.published();
+    let (_, message) = published.last().expect("should publish");
+    let enriched: Value = serde_json::from_slice(&message.payload).expect("should deserialize");
+    assert_eq!(enriched["trip_id"], "trip-1");
+}
+
+// Should use the allocation when God Mode is disabled or the override can't
+// be read.
+#[tokio::test]
+async fn trip_override_ignored() {
+    let disabled = provider();
+    let overrides = r#"{"overrides":{"59123":"trip-2"}}"#;
+    disabled.set("god_mode:overrides", overrides.as_bytes(), Some(60)).await.expect("should set");
+    process(event(), &disabled).await.expect("should process");
+    assert_eq!(enriched(&disabled)["trip_id"], "trip-1");
+
+    let unreadable = provider().with_config("GOD_MODE_ENABLED", "true");
+    unreadable.set("god_mode:overrides", b"not json", Some(60)).await.expect("should set");
+    process(event(), &unreadable).await.expect("should process");
+    assert_eq!(enriched(&unreadable)["trip_id"], "trip-1");
+}
+
+// Should lower confidence for an event received after the allocated trip ended.
+#[tokio::test]
+async fn stale_allocation() {
+    let stale = ALLOCATION.replace("1762473600", "1762466400");
+    let defaults = provider().with_response("allocation", &stale);
+    process(event(), &defaults).await.expect("should process");
+    assert_eq!(enriched(&defaults)["confidence"], "Medium");
+
+    let weighted = provider()
+        .with_response("allocation", &stale)
+        .with_config("DILAX_CONFIDENCE_WEIGHTS", "stale_allocation=2");
+    process(event(), &weighted).await.expect("should process");
+    assert_eq!(enriched(&weighted)["confidence"], "Low");
+}
+
+// Should fail, publishing nothing, when the vehicle can't be resolved in
+// strict mode.
+#[tokio::test]
+async fn strict_missing_vehicle() {
+    let provider = provider().with_response("fleet", "[]");
+    process(event(), &provider).await.expect_err("should fail");
+    assert!(provider.published().is_empty());
+}
+
+// Should publish the stop alone when the vehicle can't be resolved.
+#[tokio::test]
+async fn missing_vehicle() {
+    let provider = best_effort(provider().with_response("fleet", "[]"));
+    process(event(), &provider).await.expect("should process");
+
+    let enriched = enriched(&provider);
+    assert_eq!(enriched["unresolved"], serde_json::json!(["vehicle", "capacity", "allocation"]));
+    assert_eq!(enriched["confidence"], "Low");
+    assert_eq!(enriched["stop_id"], "9218-a");
+    assert!(enriched.get("trip_id").is_none());
+
+    assert!(get_trip("59123", &provider).await.expect("should get trip").is_none());
+}
+
+// Should publish the trip and stop, but not track occupancy, when the vehicle
+// has no capacity.
+#[tokio::test]
+async fn missing_capacity() {
+    let provider = best_effort(provider().with_response("fleet", VEHICLE_NO_CAPACITY));
+    process(event(), &provider).await.expect("should process");
+
+    let enriched = enriched(&provider);
+    assert_eq!(enriched["unresolved"], serde_json::json!(["capacity"]));
+    assert_eq!(enriched["trip_id"], "trip-1");
+    assert_eq!(enriched["stop_id"], "9218-a");
+    assert!(enriched.get("occupancy_percentage").is_none());
+
+    let occupancy = provider.get("trip:occupancy:59123").await.expect("should get occupancy");
+    assert!(occupancy.is_none());
+    let info = get_trip("59123", &provider).await.expect("should get trip").expect("trip info");
+    assert_eq!(info.trip_id.as_deref(), Some("trip-1"));
+}
+
+// Should publish the stop and track occupancy when the vehicle has no
+// allocation.
+#[tokio::test]
+async fn missing_allocation() {
+    let provider = best_effort(provider().with_response("allocation", NO_ALLOCATION));
+    process(event(), &provider).await.expect("should process");
+
+    let enriched = enriched(&provider);
+    assert_eq!(enriched["unresolved"], serde_json::json!(["allocation"]));
+    assert_eq!(enriched["stop_id"], "9218-a");
+    assert!(enriched.get("trip_id").is_none());
+    assert!(enriched.get("start_date").is_none());
+
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"111".as_slice()));
+}
+
+// Should publish the trip when no stop can be resolved, whether there are no
+// nearby stops or the stops API fails.
+#[tokio::test]
+async fn missing_stop() {
+    for provider in [
+        best_effort(provider().with_response("stops", "[]")),
+        best_effort(MockProvider::default())
+            .with_response("fleet", VEHICLE)
+            .with_response("allocation", ALLOCATION),
+    ] {
+        process(event(), &provider).await.expect("should process");
+
+        let enriched = enriched(&provider);
+        assert_eq!(enriched["unresolved"], serde_json::json!(["stop"]));
+        assert_eq!(enriched["trip_id"], "trip-1");
+        assert!(enriched.get("stop_id").is_none());
+    }
+}
+
+// Should still publish occupancy in strict mode when the stop can't be
+// resolved.
+#[tokio::test]
+async fn strict_missing_stop() {
+    for provider in [
+        provider().with_response("stops", "[]"),
+        MockProvider::default()
+            .with_response("fleet", VEHICLE)
+            .with_response("allocation", ALLOCATION),
+    ] {
+        process(event(), &provider).await.expect("should process");
+
+        let enriched = enriched(&provider);
+        assert_eq!(enriched["unresolved"], serde_json::json!(["stop"]));
+        assert_eq!(enriched["trip_id"], "trip-1");
+        assert_eq!(enriched["occupancy_percentage"], 27);
+        assert!(enriched.get("stop_id").is_none());
+
+        let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+        assert_eq!(count.as_deref(), Some(b"111".as_slice()));
+    }
+}
+
+// Should commit the count to the stop only when the vehicle is within the
+// configured distance of it, otherwise treating it as in transit.
+#[tokio::test]
+async fn at_stop_distance() {
+    let at_stop = provider().with_config("DILAX_AT_STOP_DISTANCE_METERS", "50");
+    process(event(), &at_stop).await.expect("should process");
+
+    let stopped = enriched(&at_stop);
+    assert_eq!(stopped["stop_id"], "9218-a");
+    assert!(stopped.get("in_transit").is_none());
+
+    let mut moving = event();
+    moving.distance_laststop = Some(800);
+
+    let in_transit = provider().with_config("DILAX_AT_STOP_DISTANCE_METERS", "50");
+    process(moving, &in_transit).await.expect("should process");
+
+    let moved = enriched(&in_transit);
+    assert_eq!(moved["in_transit"], true);
+    assert_eq!(moved["occupancy_percentage"], 27);
+    assert!(moved.get("stop_id").is_none());
+    assert!(moved.get("unresolved").is_none());
+}
+
+const NEARBY_STOPS: &str = r#"[
+    { "stop_id": "9218-a", "stop_code": "9218" },
+    { "stop_id": "9219-a", "stop_code": "9219" }
+]"#;
+const NEARBY_STOP_TYPES: &str = r#"[
+    { "parent_stop_code": "9218", "route_type": 2, "stop_code": "9218-a" },
+    { "parent_stop_code": "9219", "route_type": 2, "stop_code": "9219-a" }
+]"#;
+const STOP_TIMES: &str = r#"[
+    { "stop_id": "133-a", "stop_sequence": 1 },
+    { "stop_id": "9219-a", "stop_sequence": 2 }
+]"#;
+
+fn nearby_stations() -> MockProvider {
+    provider()
+        .with_response("stops", NEARBY_STOPS)
+        .with_response("stop_types", NEARBY_STOP_TYPES)
+        .with_config("DILAX_SCHEDULED_STOP", "true")
+}
+
+// Should pick the station the trip is scheduled at when the waypoint is near
+// several.
+#[tokio::test]
+async fn scheduled_stop() {
+    let provider = nearby_stations().with_response("stop_times", STOP_TIMES);
+    process(event(), &provider).await.expect("should process");
+
+    let enriched = enriched(&provider);
+    assert_eq!(enriched["stop_id"], "9219-a");
+    assert!(enriched.get("unresolved").is_none());
+}
+
+// Should fall back to the nearest station when the trip's stop times can't be
+// fetched, rather than failing the event.
+#[tokio::test]
+async fn scheduled_stop_unavailable() {
+    let provider = nearby_stations();
+    process(event(), &provider).await.expect("should process");
+
+    let enriched = enriched(&provider);
+    assert_eq!(enriched["stop_id"], "9218-a");
+    assert!(enriched.get("unresolved").is_none());
+}
+
+// An event `secs` after the sample event, with no alightings.
+fn boarding(secs: i64) -> DilaxMessage {
+    let mut event = event();
+    event.clock.utc = (1_762_469_343 + secs).to_string();
+    for door in &mut event.doors {
+        door.passengers_out = 0;
+    }
+    event
+}
+
+// Should hold counts received before the vehicle is allocated and attribute
+// them to the trip once the allocation appears.
+#[tokio::test]
+async fn pre_allocation_counts() {
+    let unallocated = provider().with_response("allocation", NO_ALLOCATION);
+    process(event(), &unallocated).await.expect_err("should be unallocated");
+    process(boarding(60), &unallocated).await.expect_err("should be unallocated");
+    assert!(unallocated.published().is_empty());
+
+    let allocated = unallocated.clone().with_response("allocation", ALLOCATION);
+    process(boarding(120), &allocated).await.expect("should process");
+
+    // 111 boarded before sign-on, twice, then 111 more
+    let enriched = enriched(&allocated);
+    assert_eq!(enriched["trip_id"], "trip-1");
+    assert_eq!(enriched["occupancy_percentage"], 83);
+    let count = allocated.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"333".as_slice()));
+
+    // the held counts are only attributed once
+    let held = allocated.get("apc:preAllocation:59123").await.expect("should get counts");
+    assert!(held.is_none());
+}
+
+// Should stop holding counts once the configured number of events are held.
+#[tokio::test]
+async fn pre_allocation_bounded() {
+    let unallocated = provider()
+        .with_response("allocation", NO_ALLOCATION)
+        .with_config("DILAX_PRE_ALLOCATION_MAX_MESSAGES", "1");
+    process(event(), &unallocated).await.expect_err("should be unallocated");
+    process(boarding(60), &unallocated).await.expect_err("should be unallocated");
+
+    let allocated = unallocated.clone().with_response("allocation", ALLOCATION);
+    process(boarding(120), &allocated).await.expect("should process");
+
+    let count = allocated.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"222".as_slice()));
+}
+
+// Should close the previous trip and open the new one when a message arrives
+// as the vehicle's allocation changes.
+#[tokio::test]
+async fn trip_change() {
+    let first = provider();
+    process(event(), &first).await.expect("should process");
+
+    let next = first.clone().with_response("allocation", &ALLOCATION.replace("trip-1", "trip-2"));
+    let outcome = process(boarding(60), &next).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(2)));
+
+    let published = next.published();
+    assert_eq!(published.len(), 3);
+    let events: Vec<Value> = published
+        .iter()
+        .map(|(_, message)| serde_json::from_slice(&message.payload).expect("should deserialize"))
+        .collect();
+    assert!(events[0].get("trip_change").is_none());
+
+    // the previous trip closes as it was last counted, with no counts
+    let closing = &events[1];
+    assert_eq!(closing["trip_change"], "closing");
+    assert_eq!(closing["trip_id"], "trip-1");
+    assert_eq!(closing["occupancy_percentage"], 27);
+    assert_eq!(closing["stop_id"], events[0]["stop_id"]);
+    assert_eq!(closing["confidence"], events[0]["confidence"]);
+    assert_eq!(closing["doors"], Value::Array(Vec::new()));
+    assert_eq!(published[1].1.headers.get("key").map(String::as_str), Some("trip-1"));
+
+    // the new trip opens with its count reset
+    let opening = &events[2];
+    assert_eq!(opening["trip_change"], "opening");
+    assert_eq!(opening["trip_id"], "trip-2");
+    assert_eq!(opening["occupancy_percentage"], 27);
+    assert!(!opening["doors"].as_array().expect("doors").is_empty());
+}
+
+// Should skip a duplicate message, even when it arrives on the vehicle's new
+// trip, rather than publishing it again or closing the previous trip.
+#[tokio::test]
+async fn duplicate_message() {
+    let first = provider();
+    process(event(), &first).await.expect("should process");
+
+    let next = first.clone().with_response("allocation", &ALLOCATION.replace("trip-1", "trip-2"));
+    let outcome = process(event(), &next).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Skipped("stale message")));
+    assert_eq!(next.published().len(), 1);
+}
@@ -0,0 +1,236 @@
+#![allow(missing_docs)]
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use http::{Request, Response};
+use qwasr_sdk::{Config, HttpRequest, Identity, Message, Publisher, StateStore};
+
+/// Error returned by the mock store when it has been made unavailable, allowing
+/// tests to distinguish "no store here" from a genuine store failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreUnavailable;
+
+impl Display for StoreUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("state store unavailable")
+    }
+}
+
+impl std::error::Error for StoreUnavailable {}
+
+#[derive(Clone)]
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<u64>,
+}
+
+/// In-memory provider with TTL tracking against a manually advanced clock.
+///
+/// HTTP requests are answered from canned responses keyed by route: `fleet`,
+/// `allocation`, `allocations`, `stops`, `stop_types` and `stop_times`.
+#[derive(Default, Clone)]
+pub struct MockProvider {
+    store: Arc<Mutex<HashMap<String, Entry>>>,
+    elapsed: Arc<AtomicU64>,
+    writes: Arc<Mutex<Vec<String>>>,
+    unavailable: bool,
+    publish_unavailable: bool,
+    failing_writes: Vec<String>,
+    config: HashMap<String, String>,
+    responses: HashMap<&'static str, String>,
+    published: Arc<Mutex<Vec<(String, Message)>>>,
+    concurrent: Arc<Mutex<Option<(String, Vec<u8>)>>>,
+}
+
+impl MockProvider {
+    /// A provider whose state store fails every operation with [`StoreUnavailable`].
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn unavailable() -> Self {
+        Self { unavailable: true, ..Self::default() }
+    }
+
+    /// A provider that fails every publish.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn with_publish_unavailable(mut self) -> Self {
+        self.publish_unavailable = true;
+        self
+    }
+
+    /// A provider that fails every write to `key`, as if the store failed
+    /// part-way through an update.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn with_failing_write(mut self, key: &str) -> Self {
+        self.failing_writes.push(key.to_string());
+        self
+    }
+
+    /// Set a configuration value.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn with_config(mut self, key: &str, value: &str) -> Self {
+        self.config.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the response body for an HTTP route.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn with_response(mut self, route: &'static str, body: &str) -> Self {
+        self.responses.insert(route, body.to_string());
+        self
+    }
+
+    /// Write `value` to `key` straight after the next read of `key`, as if by
+    /// another instance.
+    #[allow(dead_code)]
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn with_concurrent_write(self, key: &str, value: &[u8]) -> Self {
+        *self.concurrent.lock().expect("lock") = Some((key.to_string(), value.to_vec()));
+        self
+    }
+
+    /// Advance the store's clock, expiring any entries whose TTL has elapsed.
+    #[allow(dead_code)]
+    pub fn advance(&self, secs: u64) {
+        self.elapsed.fetch_add(secs, Ordering::SeqCst);
+    }
+
+    /// Number of times `key` has been written.
+    #[allow(dead_code)]
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn writes(&self, key: &str) -> usize {
+        self.writes.lock().expect("lock").iter().filter(|k| *k == key).count()
+    }
+
+    /// Messages published, with their topics.
+    #[allow(dead_code)]
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn published(&self) -> Vec<(String, Message)> {
+        self.published.lock().expect("lock").clone()
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.unavailable {
+            return Err(StoreUnavailable.into());
+        }
+        Ok(())
+    }
+}
+
+impl StateStore for MockProvider {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.check()?;
+        let now = self.elapsed.load(Ordering::SeqCst);
+        let mut store = self.store.lock().map_err(|e| anyhow!("{e}"))?;
+
+        if store.get(key).and_then(|entry| entry.expires_at).is_some_and(|exp| exp <= now) {
+            store.remove(key);
+        }
+        let value = store.get(key).map(|entry| entry.value.clone());
+
+        let mut concurrent = self.concurrent.lock().map_err(|e| anyhow!("{e}"))?;
+        if concurrent.as_ref().is_some_and(|(concurrent_key, _)| concurrent_key == key)
+            && let Some((key, value)) = concurrent.take()
+        {
+            store.insert(key, Entry { value, expires_at: None });
+        }
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: &[u8], ttl_secs: Option<u64>) -> Result<Option<Vec<u8>>> {
+        self.check()?;
+        if self.failing_writes.iter().any(|failing| failing == key) {
+            return Err(anyhow!("failed to write {key}"));
+        }
+        let now = self.elapsed.load(Ordering::SeqCst);
+        let entry = Entry { value: value.to_vec(), expires_at: ttl_secs.map(|ttl| now + ttl) };
+
+        self.writes.lock().map_err(|e| anyhow!("{e}"))?.push(key.to_string());
+        let mut store = self.store.lock().map_err(|e| anyhow!("{e}"))?;
+        let previous = store.insert(key.to_string(), entry);
+        Ok(previous.filter(|e| e.expires_at.is_none_or(|exp| exp > now)).map(|e| e.value))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.check()?;
+        self.store.lock().map_err(|e| anyhow!("{e}"))?.remove(key);
+        Ok(())
+    }
+}
+
+impl Config for MockProvider {
+    async fn get(&self, key: &str) -> Result<String> {
+        if let Some(value) = self.config.get(key) {
+            return Ok(value.clone());
+        }
+        match key {
+            "ENV" => Ok("dev".to_string()),
+            "AZURE_IDENTITY" => Ok("identity".to_string()),
+            k if k.ends_with("_URL") => Ok("http://localhost:8080/".to_string()),
+            _ => Err(anyhow!("{key} not set")),
+        }
+    }
+}
+
+impl HttpRequest for MockProvider {
+    async fn fetch<T>(&self, request: Request<T>) -> Result<Response<Bytes>>
+    where
+        T: http_body::Body + Any,
+        T::Data: Into<Vec<u8>>,
+        T::Error: Into<Box<dyn Error + Send + Sync + 'static>>,
+    {
+        let path = request.uri().path();
+        let route = if path.contains("/allocations/vehicles/") {
+            "allocation"
+        } else if path.ends_with("/allocations") {
+            "allocations"
+        } else if path.contains("/vehicles") {
+            "fleet"
+        } else if path.contains("/geosearch") {
+            "stops"
+        } else if path.contains("/stopstypes") {
+            "stop_types"
+        } else if path.contains("/stoptimes") {
+            "stop_times"
+        } else {
+            return Err(anyhow!("unexpected request to {}", request.uri()));
+        };
+
+        let Some(body) = self.responses.get(route) else {
+            return Err(anyhow!("{route} unavailable"));
+        };
+        Ok(Response::new(Bytes::from(body.clone())))
+    }
+}
+
+impl Identity for MockProvider {
+    async fn access_token(&self, _identity: String) -> Result<String> {
+        Ok("mock_access_token".to_string())
+    }
+}
+
+impl Publisher for MockProvider {
+    async fn send(&self, topic: &str, message: &Message) -> Result<()> {
+        if self.publish_unavailable {
+            return Err(anyhow!("broker unavailable"));
+        }
+        self.published
+            .lock()
+            .map_err(|e| anyhow!("{e}"))?
+            .push((topic.to_string(), message.clone()));
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+//! Tests for the state store helpers.
+
+mod provider;
+
+use dilax_adapter::{add_to_set, set_contains, set_member, set_members, touch};
+use qwasr_sdk::StateStore;
+
+use self::provider::MockProvider;
+
+const SET_KEY: &str = "apc:lostConnections20251107";
+const TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+// Should add each member once, storing its value.
+#[tokio::test]
+async fn add_member() {
+    let provider = MockProvider::default();
+
+    let added = add_to_set(&provider, SET_KEY, "59123|trip-1", b"first", TTL_SECS)
+        .await
+        .expect("should add");
+    assert!(added);
+    let added = add_to_set(&provider, SET_KEY, "59123|trip-1", b"second", TTL_SECS)
+        .await
+        .expect("should add");
+    assert!(!added);
+    add_to_set(&provider, SET_KEY, "59124|trip-2", b"other", TTL_SECS).await.expect("should add");
+
+    let members = set_members(&provider, SET_KEY).await.expect("should list");
+    assert_eq!(members, ["59123|trip-1", "59124|trip-2"]);
+    let value = set_member(&provider, SET_KEY, "59123|trip-1").await.expect("should get");
+    assert_eq!(value.as_deref(), Some(b"first".as_slice()));
+}
+
+// Should check membership without the set's index.
+#[tokio::test]
+async fn contains_member() {
+    let provider = MockProvider::default();
+    add_to_set(&provider, SET_KEY, "59123|trip-1", b"{}", TTL_SECS).await.expect("should add");
+
+    assert!(set_contains(&provider, SET_KEY, "59123|trip-1").await.expect("should check"));
+    assert!(!set_contains(&provider, SET_KEY, "59123|trip-2").await.expect("should check"));
+    assert!(
+        !set_contains(&provider, "apc:lostConnections20251108", "59123|trip-1")
+            .await
+            .expect("should check")
+    );
+}
+
+// Should expire the whole set, index and members.
+#[tokio::test]
+async fn expired_set() {
+    let provider = MockProvider::default();
+    add_to_set(&provider, SET_KEY, "59123|trip-1", b"{}", TTL_SECS).await.expect("should add");
+    add_to_set(&provider, SET_KEY, "59124|trip-2", b"{}", TTL_SECS).await.expect("should add");
+
+    provider.advance(TTL_SECS - 60);
+    assert_eq!(set_members(&provider, SET_KEY).await.expect("should list").len(), 2);
+
+    provider.advance(60);
+    assert!(set_members(&provider, SET_KEY).await.expect("should list").is_empty());
+    assert!(!set_contains(&provider, SET_KEY, "59123|trip-1").await.expect("should check"));
+    assert!(!set_contains(&provider, SET_KEY, "59124|trip-2").await.expect("should check"));
+}
+
+// Should extend the expiry of an existing entry, leaving its value unchanged.
+#[tokio::test]
+async fn touch_existing() {
+    let provider = MockProvider::default();
+    provider.set("apc:vehicleTripInfo:59123", b"{}", Some(60)).await.expect("should set");
+
+    provider.advance(30);
+    assert!(touch(&provider, "apc:vehicleTripInfo:59123", 60).await.expect("should touch"));
+
+    provider.advance(45);
+    let value = provider.get("apc:vehicleTripInfo:59123").await.expect("should get");
+    assert_eq!(value.as_deref(), Some(b"{}".as_slice()));
+
+    provider.advance(15);
+    assert!(provider.get("apc:vehicleTripInfo:59123").await.expect("should get").is_none());
+}
+
+// Should not create an absent entry.
+#[tokio::test]
+async fn touch_absent() {
+    let provider = MockProvider::default();
+    assert!(!touch(&provider, "apc:vehicleTripInfo:59123", 60).await.expect("should touch"));
+    assert!(provider.get("apc:vehicleTripInfo:59123").await.expect("should get").is_none());
+}
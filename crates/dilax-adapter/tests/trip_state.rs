@@ -0,0 +1,789 @@
+//! Tests for persisting Dilax trip state against an in-memory state store.
+
+mod provider;
+
+use common::trip_info;
+use dilax_adapter::{
+    Confidence, CountAuditRequest, DilaxConfig, DilaxError, DilaxMessage, Enrichment,
+    OccupancyEvent, VehicleCapacity, VehicleInfo, VehicleTripInfo, buffer_pre_allocation, get_trip,
+    reset_count, set_trip, update_vehicle,
+};
+use qwasr_sdk::{Handler, StateStore};
+
+use self::provider::{MockProvider, StoreUnavailable};
+
+const NO_STOP: Enrichment =
+    Enrichment { stop_id: None, confidence: Confidence::High, unresolved: Vec::new() };
+const CAPACITY: VehicleCapacity = VehicleCapacity { seating: 200, standing: 200, total: 400 };
+
+fn at_stop(stop_id: &str) -> Enrichment {
+    Enrichment { stop_id: Some(stop_id.to_string()), ..NO_STOP }
+}
+
+fn vehicle_trip(vehicle_id: &str) -> VehicleTripInfo {
+    VehicleTripInfo {
+        last_received_timestamp: Some("1700000000".to_string()),
+        dilax_message: None,
+        trip_id: Some("trip-1".to_string()),
+        stop_id: Some("stop-1".to_string()),
+        vehicle_info: VehicleInfo {
+            label: Some("AMP        123".to_string()),
+            vehicle_id: vehicle_id.to_string(),
+        },
+    }
+}
+
+// Should read back the trip info that was stored for a vehicle.
+#[tokio::test]
+async fn trip_round_trip() {
+    let provider = MockProvider::default();
+
+    set_trip(vehicle_trip("59123"), &provider).await.expect("should set trip");
+    let info = get_trip("59123", &provider).await.expect("should get trip").expect("trip info");
+
+    assert_eq!(info.trip_id.as_deref(), Some("trip-1"));
+    assert_eq!(info.stop_id.as_deref(), Some("stop-1"));
+    assert_eq!(info.vehicle_info.vehicle_id, "59123");
+}
+
+// Should share one stored format with crates reading trip info through
+// `common`, in both directions.
+#[tokio::test]
+async fn trip_shared_format() {
+    let provider = MockProvider::default();
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    // written by the adapter, read untyped through common
+    let mut written = vehicle_trip("59123");
+    written.dilax_message = Some(event);
+    set_trip(written, &provider).await.expect("should set trip");
+
+    let bytes = provider.get(&trip_info::key("59123")).await.expect("should get").expect("bytes");
+    let shared: trip_info::VehicleTripInfo =
+        serde_json::from_slice(&bytes).expect("should deserialize");
+    assert_eq!(shared.trip_id.as_deref(), Some("trip-1"));
+    assert_eq!(shared.vehicle_info.label.as_deref(), Some("AMP        123"));
+    assert!(shared.dilax_message.is_some());
+
+    // written through common, read by the adapter
+    let shared = trip_info::VehicleTripInfo {
+        trip_id: Some("trip-2".to_string()),
+        vehicle_info: trip_info::VehicleInfo { label: None, vehicle_id: "59124".to_string() },
+        ..shared
+    };
+    let bytes = serde_json::to_vec(&shared).expect("should serialize");
+    provider.set(&trip_info::key("59124"), &bytes, None).await.expect("should set");
+
+    let info = get_trip("59124", &provider).await.expect("should get trip").expect("trip info");
+    assert_eq!(info.trip_id.as_deref(), Some("trip-2"));
+    assert_eq!(info.vehicle_info.vehicle_id, "59124");
+    assert!(info.dilax_message.is_some());
+}
+
+// Should return `None` for a vehicle with no stored trip info.
+#[tokio::test]
+async fn trip_missing() {
+    let provider = MockProvider::default();
+    let info = get_trip("59123", &provider).await.expect("should get trip");
+    assert!(info.is_none());
+}
+
+// Should surface an unavailable store as a typed error.
+#[tokio::test]
+async fn store_unavailable() {
+    let provider = MockProvider::unavailable();
+
+    let err = get_trip("59123", &provider).await.expect_err("should fail");
+    assert_eq!(err.downcast_ref::<StoreUnavailable>(), Some(&StoreUnavailable));
+}
+
+// Should persist the running count and occupancy for a vehicle, expiring each
+// according to its TTL.
+#[tokio::test]
+async fn vehicle_round_trip() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    set_trip(vehicle_trip("59123"), &provider).await.expect("should set trip");
+
+    // new trip: count is the sum of boarding passengers only
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"111".as_slice()));
+    let occupancy = provider.get("trip:occupancy:59123").await.expect("should get occupancy");
+    assert_eq!(occupancy.as_deref(), Some(b"2".as_slice()));
+
+    let state = provider.get("apc:vehicleIdState:59123").await.expect("should get state");
+    let state: serde_json::Value =
+        serde_json::from_slice(&state.expect("state")).expect("should deserialize state");
+    assert_eq!(state["count"], 111);
+    assert_eq!(state["last_trip_id"], "trip-1");
+
+    // count expires after an hour, occupancy after 90 minutes
+    provider.advance(60 * 60);
+    assert!(provider.get("apc:vehicleId:59123").await.expect("should get").is_none());
+    assert!(provider.get("trip:occupancy:59123").await.expect("should get").is_some());
+
+    provider.advance(30 * 60);
+    assert!(provider.get("trip:occupancy:59123").await.expect("should get").is_none());
+
+    // trip info outlives vehicle state
+    let info = get_trip("59123", &provider).await.expect("should get trip");
+    assert_eq!(info.expect("trip info").trip_id.as_deref(), Some("trip-1"));
+
+    provider.advance(48 * 60 * 60);
+    assert!(get_trip("59123", &provider).await.expect("should get trip").is_none());
+}
+
+// Should drop duplicate and out-of-order messages, leaving the vehicle's
+// state unchanged.
+#[tokio::test]
+async fn stale_message() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    let state = provider.get("apc:vehicleIdState:59123").await.expect("should get state");
+
+    let mut stale = event.clone();
+    let token = event.clock.utc.parse::<i64>().expect("token");
+    stale.clock.utc = (token - 60).to_string();
+    for message in [&event, &stale] {
+        let update = update_vehicle(
+            "59123",
+            Some("trip-1"),
+            &NO_STOP,
+            CAPACITY,
+            message,
+            &config,
+            &provider,
+        )
+        .await
+        .expect("should update vehicle");
+        assert_eq!(update, None);
+    }
+
+    let unchanged = provider.get("apc:vehicleIdState:59123").await.expect("should get state");
+    assert_eq!(unchanged, state);
+    assert_eq!(provider.writes("apc:vehicleIdState:59123"), 1);
+    assert_eq!(provider.writes("apc:vehicleId:59123"), 1);
+}
+
+// Should zero a vehicle's count while keeping its token and trip, so the next
+// message on the trip counts on from zero.
+#[tokio::test]
+async fn count_reset() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    reset_count("59123", &provider).await.expect("should reset count");
+
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"0".as_slice()));
+    let state = provider.get("apc:vehicleIdState:59123").await.expect("should get state");
+    let state: serde_json::Value =
+        serde_json::from_slice(&state.expect("state")).expect("should deserialize state");
+    assert_eq!(state["count"], 0);
+    assert_eq!(state["token"], event.clock.utc.parse::<i64>().expect("token"));
+    assert_eq!(state["last_trip_id"], "trip-1");
+    assert!(state["occupancy_status"].is_null());
+
+    // a resend of the counted message is still dropped
+    let resend =
+        update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect("should update vehicle");
+    assert_eq!(resend, None);
+
+    let token = event.clock.utc.parse::<i64>().expect("token");
+    event.clock.utc = (token + 60).to_string();
+    let next =
+        update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect("should update vehicle")
+            .expect("should apply event");
+    assert!(next.closed_trip.is_none());
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"111".as_slice()));
+}
+
+// Should leave a vehicle without state alone.
+#[tokio::test]
+async fn count_reset_missing() {
+    let provider = MockProvider::default();
+
+    reset_count("59123", &provider).await.expect("should reset count");
+    assert!(provider.get("apc:vehicleIdState:59123").await.expect("should get").is_none());
+    assert!(provider.get("apc:vehicleId:59123").await.expect("should get").is_none());
+}
+
+// Should reject a token in milliseconds without storing it, so later messages
+// in seconds are still applied.
+#[tokio::test]
+async fn millisecond_token() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    let mut millis = event.clone();
+    let token = event.clock.utc.parse::<i64>().expect("token");
+    millis.clock.utc = (token * 1000).to_string();
+    let err =
+        update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &millis, &config, &provider)
+            .await
+            .expect_err("should reject token");
+    assert!(matches!(err.downcast_ref::<DilaxError>(), Some(DilaxError::InvalidTimestamp(_))));
+    assert_eq!(provider.writes("apc:vehicleIdState:59123"), 0);
+
+    let update =
+        update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect("should update vehicle");
+    assert!(update.is_some());
+}
+
+// Should reject a token that isn't a timestamp.
+#[tokio::test]
+async fn invalid_token() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    for utc in ["yesterday", "-60", ""] {
+        event.clock.utc = utc.to_string();
+        let err =
+            update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+                .await
+                .expect_err("should reject token");
+        assert!(matches!(err.downcast_ref::<DilaxError>(), Some(DilaxError::InvalidTimestamp(_))));
+    }
+    assert!(provider.get("apc:vehicleIdState:59123").await.expect("should get").is_none());
+}
+
+// Should count a message with a normal number of doors, up to the configured
+// maximum.
+#[tokio::test]
+async fn door_count() {
+    let provider = MockProvider::default().with_config("DILAX_MAX_DOORS", "4");
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    event.doors = vec![event.doors[0].clone(); 4];
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    assert!(provider.get("apc:vehicleIdState:59123").await.expect("should get").is_some());
+}
+
+// Should reject a message claiming an absurd number of doors without
+// counting it.
+#[tokio::test]
+async fn too_many_doors() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    event.doors = vec![event.doors[0].clone(); 5000];
+
+    let err =
+        update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect_err("should reject message");
+    assert!(matches!(err.downcast_ref::<DilaxError>(), Some(DilaxError::TooManyDoors(_))));
+    assert!(provider.get("apc:vehicleIdState:59123").await.expect("should get").is_none());
+}
+
+// Should hold back a message with more doors than the configured maximum
+// from the pre-allocation buffer, as for an allocated vehicle.
+#[tokio::test]
+async fn too_many_doors_pre_allocation() {
+    let provider = MockProvider::default().with_config("DILAX_MAX_DOORS", "4");
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    event.doors = vec![event.doors[0].clone(); 5];
+
+    let err = buffer_pre_allocation("59123", &event, &config, &provider)
+        .await
+        .expect_err("should reject message");
+    assert!(matches!(err.downcast_ref::<DilaxError>(), Some(DilaxError::TooManyDoors(_))));
+    assert!(provider.get("apc:preAllocation:59123").await.expect("should get").is_none());
+
+    event.doors.truncate(4);
+    buffer_pre_allocation("59123", &event, &config, &provider).await.expect("should buffer");
+    assert!(provider.get("apc:preAllocation:59123").await.expect("should get").is_some());
+}
+
+// Should log the counts applied to a vehicle on a trip when enabled, so the
+// log reproduces the vehicle's running count.
+#[tokio::test]
+async fn count_audit() {
+    let provider = MockProvider::default().with_config("DILAX_COUNT_AUDIT", "true");
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    let token = event.clock.utc.parse::<i64>().expect("token");
+
+    update_vehicle("59123", Some("trip-1"), &at_stop("133"), CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    for (offset, passengers_in, passengers_out) in [(60, 3, 500), (120, 10, 2)] {
+        let mut next = event.clone();
+        next.clock.utc = (token + offset).to_string();
+        next.doors.truncate(1);
+        next.doors[0].passengers_in = passengers_in;
+        next.doors[0].passengers_out = passengers_out;
+        update_vehicle(
+            "59123",
+            Some("trip-1"),
+            &at_stop("140"),
+            CAPACITY,
+            &next,
+            &config,
+            &provider,
+        )
+        .await
+        .expect("should update vehicle");
+    }
+
+    let request = ("59123".to_string(), "trip-1".to_string());
+    let reply = CountAuditRequest::handler(request)
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should read audit");
+    let audit = reply.body;
+    assert_eq!(audit.entries.len(), 4);
+    assert!(audit.entries[0].reset);
+    assert_eq!(audit.entries[1].stop_id.as_deref(), Some("133"));
+    assert_eq!(audit.count, 11);
+    assert_eq!(audit.entries.last().map(|entry| entry.count), Some(audit.count));
+
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count, Some(audit.count.to_string().into_bytes()));
+}
+
+// Should merge counts logged by another instance since the log was read,
+// rather than writing over them.
+#[tokio::test]
+async fn count_audit_concurrent() {
+    let logged = serde_json::json!({
+        "entries": [{ "stop_id": "120", "in": 4, "out": 0, "count": 4, "timestamp": 1 }]
+    });
+    let provider = MockProvider::default()
+        .with_config("DILAX_COUNT_AUDIT", "true")
+        .with_concurrent_write("apc:countAudit:59123:trip-1", logged.to_string().as_bytes());
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &at_stop("133"), CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    let request = ("59123".to_string(), "trip-1".to_string());
+    let reply = CountAuditRequest::handler(request)
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should read audit");
+    let stops: Vec<_> = reply.body.entries.iter().map(|entry| entry.stop_id.as_deref()).collect();
+    assert_eq!(stops, [Some("120"), None, Some("133")]);
+    assert_eq!(provider.writes("apc:countAudit:59123:trip-1"), 2);
+}
+
+// Should flag a full log as truncated, keeping the counts it holds.
+#[tokio::test]
+async fn count_audit_truncated() {
+    let provider = MockProvider::default().with_config("DILAX_COUNT_AUDIT", "true");
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    let entries: Vec<_> = (1..=500)
+        .map(|count| serde_json::json!({ "in": 1, "out": 0, "count": count, "timestamp": count }))
+        .collect();
+    let full = serde_json::json!({ "entries": entries });
+    provider
+        .set("apc:countAudit:59123:trip-1", full.to_string().as_bytes(), None)
+        .await
+        .expect("should seed log");
+
+    update_vehicle("59123", Some("trip-1"), &at_stop("133"), CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    let request = ("59123".to_string(), "trip-1".to_string());
+    let reply = CountAuditRequest::handler(request)
+        .expect("should create handler")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect("should read audit");
+    assert!(reply.body.truncated);
+    assert_eq!(reply.body.entries.len(), 500);
+    assert_eq!(reply.body.count, 500);
+}
+
+// Should still update the vehicle when its counts can't be logged.
+#[tokio::test]
+async fn count_audit_failure() {
+    let provider = MockProvider::default()
+        .with_config("DILAX_COUNT_AUDIT", "true")
+        .with_failing_write("apc:countAudit:59123:trip-1");
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &at_stop("133"), CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"111".as_slice()));
+}
+
+// Should not log counts unless enabled.
+#[tokio::test]
+async fn count_audit_disabled() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &at_stop("133"), CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    assert_eq!(provider.writes("apc:countAudit:59123:trip-1"), 0);
+}
+
+// Should save the event over state written by another instance since it was
+// read, as the store has no conditional write.
+#[tokio::test]
+async fn concurrent_update() {
+    let concurrent = br#"{"count":400,"token":1762469000,"last_trip_id":"trip-1"}"#;
+    let provider =
+        MockProvider::default().with_concurrent_write("apc:vehicleIdState:59123", concurrent);
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    // none on board when read, plus 111 boarding
+    let state = provider.get("apc:vehicleIdState:59123").await.expect("should get state");
+    let state: serde_json::Value =
+        serde_json::from_slice(&state.expect("state")).expect("should deserialize state");
+    assert_eq!(state["count"], 111);
+    assert_eq!(state["token"], 1_762_469_343);
+    assert_eq!(provider.writes("apc:vehicleIdState:59123"), 1);
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"111".as_slice()));
+}
+
+// Should only write occupancy when the band changes, while still updating the
+// count.
+#[tokio::test]
+async fn occupancy_unchanged() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    // a later message with no boardings or alightings leaves the band unchanged
+    let mut next = event.clone();
+    let token = event.clock.utc.parse::<i64>().expect("token");
+    next.clock.utc = (token + 60).to_string();
+    next.doors.clear();
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &next, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    assert_eq!(provider.writes("trip:occupancy:59123"), 1);
+    assert_eq!(provider.writes("apc:vehicleId:59123"), 2);
+
+    let occupancy = provider.get("trip:occupancy:59123").await.expect("should get occupancy");
+    assert_eq!(occupancy.as_deref(), Some(b"2".as_slice()));
+}
+
+// Should keep the occupancy of a vehicle staying in one band past its TTL,
+// rewriting it once half the TTL has passed.
+#[tokio::test]
+async fn occupancy_refreshed() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    let token = event.clock.utc.parse::<i64>().expect("token");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    // messages every half hour with no boardings or alightings
+    event.doors.clear();
+    for step in 1..=4 {
+        provider.advance(30 * 60);
+        event.clock.utc = (token + step * 30 * 60).to_string();
+        update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect("should update vehicle");
+
+        let occupancy = provider.get("trip:occupancy:59123").await.expect("should get occupancy");
+        assert_eq!(occupancy.as_deref(), Some(b"2".as_slice()), "after {} minutes", step * 30);
+    }
+    assert_eq!(provider.writes("trip:occupancy:59123"), 3);
+}
+
+// Should keep the updated state when the occupancy event can't be published.
+#[tokio::test]
+async fn occupancy_publish_failure() {
+    let provider = MockProvider::default()
+        .with_config("DILAX_OCCUPANCY_TOPIC", "realtime-dilax-occupancy.v1")
+        .with_publish_unavailable();
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    assert!(provider.published().is_empty());
+    let count = provider.get("apc:vehicleId:59123").await.expect("should read").expect("count");
+    assert_eq!(count, b"111");
+}
+
+// Should publish occupancy to the configured topic when the count or band
+// changes, keyed by vehicle and trip.
+#[tokio::test]
+async fn occupancy_published() {
+    let provider =
+        MockProvider::default().with_config("DILAX_OCCUPANCY_TOPIC", "realtime-dilax-occupancy.v1");
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    let token = event.clock.utc.parse::<i64>().expect("token");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    let published = provider.published();
+    assert_eq!(published.len(), 1);
+    let (topic, message) = &published[0];
+    assert_eq!(topic, "dev-realtime-dilax-occupancy.v1");
+    assert_eq!(message.headers.get("key").map(String::as_str), Some("59123|trip-1"));
+    let occupancy: OccupancyEvent =
+        serde_json::from_slice(&message.payload).expect("should deserialize");
+    assert_eq!(
+        occupancy,
+        OccupancyEvent {
+            vehicle_id: "59123".to_string(),
+            trip_id: Some("trip-1".to_string()),
+            count: 111,
+            status: "2".to_string(),
+            timestamp: token,
+        }
+    );
+
+    // unchanged occupancy isn't republished
+    let mut unchanged = event.clone();
+    unchanged.clock.utc = (token + 60).to_string();
+    unchanged.doors.clear();
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &unchanged, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    assert_eq!(provider.published().len(), 1);
+
+    // more boardings change the count
+    let mut boarded = event.clone();
+    boarded.clock.utc = (token + 120).to_string();
+    for door in &mut boarded.doors {
+        door.passengers_out = 0;
+    }
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &boarded, &config, &provider)
+        .await
+        .expect("should update vehicle");
+
+    let published = provider.published();
+    assert_eq!(published.len(), 2);
+    let occupancy: OccupancyEvent =
+        serde_json::from_slice(&published[1].1.payload).expect("should deserialize");
+    assert_eq!(occupancy.count, 222);
+    assert_eq!(occupancy.status, "3");
+    assert_eq!(occupancy.timestamp, token + 120);
+}
+
+// Should not publish occupancy when no topic is configured.
+#[tokio::test]
+async fn occupancy_not_configured() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    assert!(provider.published().is_empty());
+}
+
+// Should only accumulate counts from the configured triggers.
+#[tokio::test]
+async fn counted_triggers() {
+    let provider =
+        MockProvider::default().with_config("DILAX_COUNTED_TRIGGERS", "station_left_summary");
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    let token = event.clock.utc.parse::<i64>().expect("token");
+
+    // counts from other triggers are ignored
+    event.trigger = "timer".to_string();
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"0".as_slice()));
+
+    // station left summaries are accumulated
+    event.trigger = "station_left_summary".to_string();
+    event.clock.utc = (token + 60).to_string();
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"111".as_slice()));
+}
+
+// Should only filter counts by trigger for canary vehicles, counting every
+// trigger for the rest.
+#[tokio::test]
+async fn canary_vehicles() {
+    let provider = MockProvider::default()
+        .with_config("DILAX_COUNTED_TRIGGERS", "station_left_summary")
+        .with_config("CANARY_VEHICLE_IDS", "59123");
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    event.trigger = "timer".to_string();
+
+    for vehicle_id in ["59123", "59124"] {
+        update_vehicle(vehicle_id, Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect("should update vehicle");
+    }
+
+    let canary = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(canary.as_deref(), Some(b"0".as_slice()));
+    let stable = provider.get("apc:vehicleId:59124").await.expect("should get count");
+    assert_eq!(stable.as_deref(), Some(b"111".as_slice()));
+}
+
+// Should close the previous trip, as it was last counted, only for the event
+// that moves the vehicle onto a new trip.
+#[tokio::test]
+async fn closed_trip() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    let token = event.clock.utc.parse::<i64>().expect("token");
+
+    let first = update_vehicle(
+        "59123",
+        Some("trip-1"),
+        &at_stop("133"),
+        CAPACITY,
+        &event,
+        &config,
+        &provider,
+    )
+    .await
+    .expect("should update vehicle")
+    .expect("should apply event");
+    assert!(first.closed_trip.is_none());
+
+    // a duplicate on the new trip isn't applied, so closes nothing
+    let duplicate =
+        update_vehicle("59123", Some("trip-2"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect("should update vehicle");
+    assert_eq!(duplicate, None);
+
+    event.clock.utc = (token + 60).to_string();
+    let next =
+        update_vehicle("59123", Some("trip-2"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect("should update vehicle")
+            .expect("should apply event");
+    let closed = next.closed_trip.expect("should close trip");
+    assert_eq!(closed.trip_id, "trip-1");
+    assert_eq!(closed.occupancy_percentage, first.occupancy_percentage);
+    assert_eq!(closed.enrichment, at_stop("133"));
+
+    event.clock.utc = (token + 120).to_string();
+    let later =
+        update_vehicle("59123", Some("trip-2"), &NO_STOP, CAPACITY, &event, &config, &provider)
+            .await
+            .expect("should update vehicle")
+            .expect("should apply event");
+    assert!(later.closed_trip.is_none());
+}
+
+// Should keep counts held before allocation until the trip's state is saved.
+#[tokio::test]
+async fn pre_allocation_kept_on_failure() {
+    let provider = MockProvider::default();
+    let config = DilaxConfig::load(&provider).await;
+    let mut event: DilaxMessage =
+        serde_json::from_slice(include_bytes!("../data/message.json")).expect("should deserialize");
+    buffer_pre_allocation("59123", &event, &config, &provider).await.expect("should buffer");
+
+    let token = event.clock.utc.parse::<i64>().expect("token");
+    event.clock.utc = (token + 60).to_string();
+    for door in &mut event.doors {
+        door.passengers_out = 0;
+    }
+    let failing = provider.clone().with_failing_write("apc:vehicleIdState:59123");
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &failing)
+        .await
+        .expect_err("should fail to save state");
+    let held = provider.get("apc:preAllocation:59123").await.expect("should get counts");
+    assert!(held.is_some());
+
+    update_vehicle("59123", Some("trip-1"), &NO_STOP, CAPACITY, &event, &config, &provider)
+        .await
+        .expect("should update vehicle");
+    let count = provider.get("apc:vehicleId:59123").await.expect("should get count");
+    assert_eq!(count.as_deref(), Some(b"222".as_slice()));
+    let held = provider.get("apc:preAllocation:59123").await.expect("should get counts");
+    assert!(held.is_none());
+}
@@ -3,9 +3,11 @@ use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
 use qwasr_sdk::{Config, Context, Error, Handler, IntoBody, Message, Publisher, Reply, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::DilaxMessage;
+use crate::{DilaxMessage, payload};
 
 const DILAX_TOPIC: &str = "realtime-dilax-apc.v2";
+const CODE_INVALID_MESSAGE: &str = "invalid_message";
+const CODE_PUBLISH_FAILED: &str = "publish_failed";
 
 #[allow(clippy::unused_async)]
 async fn handle<P>(_owner: &str, request: DilaxRequest, provider: &P) -> Result<Reply<DilaxReply>>
@@ -28,7 +30,13 @@ where
     let env = Config::get(provider, "ENV").await.unwrap_or_else(|_| "dev".to_string());
     let topic = format!("{env}-{DILAX_TOPIC}");
 
-    Publisher::send(provider, &topic, &msg).await?;
+    // the broker error stays internal; the integrator only needs to retry
+    if Publisher::send(provider, &topic, &msg).await.is_err() {
+        return Err(Error::BadGateway {
+            code: CODE_PUBLISH_FAILED.to_string(),
+            description: "failed to forward message".to_string(),
+        });
+    }
 
     Ok(Reply {
         status: StatusCode::OK,
@@ -49,7 +57,14 @@ where
     type Output = DilaxReply;
 
     fn from_input(input: Vec<u8>) -> Result<Self> {
-        serde_json::from_slice(&input).map_err(Into::into)
+        let json = payload::decode(&input).map_err(|err| Error::BadRequest {
+            code: CODE_INVALID_MESSAGE.to_string(),
+            description: format!("{err:#}"),
+        })?;
+        serde_json::from_slice(&json).map_err(|err| Error::BadRequest {
+            code: CODE_INVALID_MESSAGE.to_string(),
+            description: err.to_string(),
+        })
     }
 
     async fn handle(self, ctx: Context<'_, P>) -> Result<Reply<DilaxReply>> {
@@ -3,6 +3,7 @@
 //! Receives Dilax passenger count requests and forwards to the `realtime-dilax-apc.v2` topic.
 
 mod handler;
+mod payload;
 mod types;
 
 pub use handler::*;
@@ -0,0 +1,51 @@
+//! Unwrapping of Dilax payloads delivered base64-encoded and/or gzipped by
+//! some gateways. Raw JSON is passed through untouched.
+
+use std::borrow::Cow;
+use std::io::Read;
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::read::GzDecoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// base64 may wrap gzip, but no deeper
+const MAX_LAYERS: usize = 2;
+
+// a small compressed payload can inflate to any size, so cap what is decoded
+const MAX_DECODED_BYTES: u64 = 1024 * 1024; // 1 MiB
+
+/// Decode a payload into raw JSON bytes, sniffing for gzip and base64 wrapping.
+///
+/// # Errors
+///
+/// Returns an error if a wrapped payload can't be decoded, decompresses to
+/// more than 1 MiB, or doesn't unwrap to JSON.
+pub fn decode(input: &[u8]) -> Result<Cow<'_, [u8]>> {
+    let mut payload = Cow::Borrowed(input);
+
+    for _ in 0..=MAX_LAYERS {
+        let trimmed = payload.trim_ascii();
+        if trimmed.starts_with(b"{") || trimmed.starts_with(b"[") {
+            return Ok(payload);
+        }
+
+        payload = if trimmed.starts_with(&GZIP_MAGIC) {
+            let mut decoded = Vec::new();
+            GzDecoder::new(trimmed)
+                .take(MAX_DECODED_BYTES + 1)
+                .read_to_end(&mut decoded)
+                .context("decompressing payload")?;
+            if decoded.len() as u64 > MAX_DECODED_BYTES {
+                bail!("decompressed payload exceeds {MAX_DECODED_BYTES} bytes");
+            }
+            Cow::Owned(decoded)
+        } else {
+            Cow::Owned(STANDARD.decode(trimmed).context("decoding base64 payload")?)
+        };
+    }
+
+    bail!("payload is not JSON after unwrapping")
+}
@@ -2,15 +2,23 @@
 
 use std::sync::{Arc, Mutex};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use qwasr_sdk::{Config, Message, Publisher};
 
 #[derive(Default, Clone)]
 pub struct MockProvider {
     published: Arc<Mutex<Vec<(String, Message)>>>,
+    fail_publish: bool,
 }
 
 impl MockProvider {
+    /// A provider whose publisher rejects every message.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn failing() -> Self {
+        Self { fail_publish: true, ..Self::default() }
+    }
+
     #[allow(clippy::missing_panics_doc)]
     #[must_use]
     pub fn published(&self) -> Vec<(String, Message)> {
@@ -25,8 +33,12 @@ impl Publisher for MockProvider {
         let topic = topic.to_string();
         let message = message.clone();
         let published = Arc::clone(&self.published);
+        let fail_publish = self.fail_publish;
 
         async move {
+            if fail_publish {
+                bail!("broker unavailable");
+            }
             published.lock().expect("lock").push((topic, message));
             Ok(())
         }
@@ -1,7 +1,13 @@
 mod provider;
 
+use std::io::Write;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use dilax_apc_connector::{DilaxMessage, DilaxRequest};
-use qwasr_sdk::Handler;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use qwasr_sdk::{Error, Handler};
 
 use self::provider::MockProvider;
 
@@ -79,3 +85,76 @@ async fn device_site_whitespace() {
     let expected_payload = serde_json::to_vec(&message).expect("should serialize");
     assert_eq!(record.payload, expected_payload);
 }
+
+// Should fail with a machine-readable code, without leaking the broker error,
+// when the message can't be published.
+#[tokio::test]
+async fn publish_failure() {
+    let provider = MockProvider::failing();
+    let payload = include_bytes!("../data/dilax-message.json");
+
+    let err = DilaxRequest::handler(payload.to_vec())
+        .expect("should deserialize")
+        .provider(&provider)
+        .owner("owner")
+        .await
+        .expect_err("should fail");
+
+    assert!(matches!(err, Error::BadGateway { .. }));
+    assert_eq!(err.code(), "publish_failed");
+    assert!(!err.to_string().contains("broker unavailable"));
+    assert!(provider.published().is_empty());
+}
+
+// Should reject a malformed payload with a machine-readable code.
+#[test]
+fn invalid_payload() {
+    let err = <DilaxRequest as Handler<MockProvider>>::from_input(b"not json".to_vec())
+        .expect_err("should fail to deserialize");
+    assert_eq!(err.code(), "invalid_message");
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).expect("should compress");
+    encoder.finish().expect("should compress")
+}
+
+fn parse(input: Vec<u8>) -> serde_json::Value {
+    let request =
+        <DilaxRequest as Handler<MockProvider>>::from_input(input).expect("should deserialize");
+    serde_json::to_value(&request.message).expect("should serialize")
+}
+
+// Should unwrap base64 and gzip wrapped payloads to the same message as raw JSON.
+#[test]
+fn wrapped_payloads() {
+    let payload = include_bytes!("../data/dilax-message.json");
+    let expected = parse(payload.to_vec());
+
+    assert_eq!(parse(STANDARD.encode(payload).into_bytes()), expected);
+    assert_eq!(parse(gzip(payload)), expected);
+    assert_eq!(parse(STANDARD.encode(gzip(payload)).into_bytes()), expected);
+}
+
+// Should reject a payload that doesn't unwrap to JSON.
+#[test]
+fn unwrapped_garbage() {
+    let input = STANDARD.encode(b"not json").into_bytes();
+    let err = <DilaxRequest as Handler<MockProvider>>::from_input(input)
+        .expect_err("should fail to deserialize");
+    assert_eq!(err.code(), "invalid_message");
+}
+
+// Should reject a gzipped payload that inflates past the size limit.
+#[test]
+fn gzip_bomb() {
+    let mut json = b"[\"".to_vec();
+    json.resize(2 * 1024 * 1024, b'a');
+    json.extend_from_slice(b"\"]");
+
+    let err = <DilaxRequest as Handler<MockProvider>>::from_input(gzip(&json))
+        .expect_err("should fail to deserialize");
+    assert_eq!(err.code(), "invalid_message");
+    assert!(err.description().contains("exceeds"));
+}
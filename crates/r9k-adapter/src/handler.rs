@@ -5,17 +5,23 @@
 use anyhow::Context as _;
 use bytes::Bytes;
 use chrono::Utc;
+use common::outcome::{ProcessResult, Skippable};
+use common::{config, url};
 use http::header::AUTHORIZATION;
 use http_body_util::Empty;
 use qwasr_sdk::api::{Context, Handler, Reply};
-use qwasr_sdk::{Config, Error, HttpRequest, Identity, Message, Publisher, Result};
+use qwasr_sdk::{Config, Error, HttpRequest, Identity, Message, Publisher, Result, StateStore};
 use serde::Deserialize;
 
-use crate::r9k::TrainUpdate;
-use crate::smartrak::{EventType, MessageData, RemoteData, SmarTrakEvent};
-use crate::stops;
+use crate::r9k::{
+    ChangeType, Direction, MAX_DELAY_SECS, MIN_DELAY_SECS, Parity, StopType, TrainUpdate,
+};
+use crate::smartrak::{EventType, MessageData, RemoteData, SmarTrakEvent, VehicleStopStatus};
+use crate::trip_update::resolve_trip_update;
+use crate::{R9kError, stops};
 
 const SMARTRAK_TOPIC: &str = "realtime-r9k-to-smartrak.v1";
+const KEY_SEEN_UPDATE: &str = "r9k:seenUpdate";
 
 /// R9K train update message as deserialized from the XML received from
 /// KiwiRail.
@@ -29,19 +35,95 @@ pub struct R9kMessage {
 
 async fn handle<P>(owner: &str, request: R9kMessage, provider: &P) -> Result<Reply<()>>
 where
-    P: Config + HttpRequest + Identity + Publisher,
+    P: Config + HttpRequest + Identity + Publisher + StateStore,
+{
+    ProcessResult::report(process(owner, request, provider).await, "r9k")?;
+    Ok(Reply::ok(()))
+}
+
+/// Transform an R9K message into SmarTrak events, and a GTFS-RT trip update
+/// when `R9K_TRIP_UPDATE_TOPIC` is set, and publish them. A trip update that
+/// can't be published is logged rather than failing the SmarTrak events.
+///
+/// Returns the number of messages published, or why none were.
+///
+/// # Errors
+///
+/// Returns an error when the update is invalid or out of date, or a provider
+/// fails.
+pub async fn process<P>(owner: &str, request: R9kMessage, provider: &P) -> Result<ProcessResult>
+where
+    P: Config + HttpRequest + Identity + Publisher + StateStore,
 {
     // validate message
     let update = request.train_update;
-    update.validate()?;
+    let (max_delay, min_delay) = delay_bounds(provider).await;
+    update.validate_with_bounds(max_delay, min_delay)?;
+
+    // skip resent changes, unmarking a change that fails to publish so its
+    // redelivery isn't skipped
+    let mut seen = None;
+    if let Some(window) = dedupe_window(provider).await
+        && let Some(key) = update.seen_key()
+    {
+        if StateStore::set(provider, &key, b"1", Some(window)).await?.is_some() {
+            tracing::info!(monotonic_counter.duplicate_updates = 1);
+            return Ok(ProcessResult::Skipped("duplicate update"));
+        }
+        seen = Some(key);
+    }
+
+    let result = publish(owner, update, provider).await;
+    if result.is_err()
+        && let Some(key) = seen
+        && let Err(err) = StateStore::delete(provider, &key).await
+    {
+        tracing::warn!("failed to unmark update as seen: {err:#}");
+    }
+    result
+}
+
+/// Publish the update's trip update and SmarTrak events.
+async fn publish<P>(owner: &str, update: TrainUpdate, provider: &P) -> Result<ProcessResult>
+where
+    P: Config + HttpRequest + Identity + Publisher,
+{
+    // parity can't be trusted to pick the train id without a direction
+    let preference = train_id_preference(provider).await;
+    if update.direction() == Direction::Unspecified {
+        tracing::warn!(
+            monotonic_counter.unspecified_direction = 1,
+            train_id = %update.train_id_for(preference),
+            "train direction unspecified, using {preference:?} train id preference"
+        );
+    }
+
+    let env = Config::get(provider, "ENV").await.unwrap_or_else(|_| "dev".to_string());
+
+    // publish GTFS-RT trip update when a topic is configured, without holding
+    // up the SmarTrak events when it fails
+    let mut emitted = 0;
+    if let Ok(trip_update_topic) = Config::get(provider, "R9K_TRIP_UPDATE_TOPIC").await {
+        let topic = format!("{env}-{trip_update_topic}");
+        match publish_trip_update(&update, preference, &topic, provider).await {
+            Ok(published) => emitted += usize::from(published),
+            Err(err) => tracing::warn!(
+                monotonic_counter.trip_update_failures = 1,
+                "failed to publish trip update: {err:#}"
+            ),
+        }
+    }
 
     // convert to SmarTrak events
-    let events = update.into_events(owner, provider).await?;
+    let events = match update.into_events(owner, provider, preference).await? {
+        Skippable::Output(events) => events,
+        Skippable::Skipped(_) if emitted > 0 => return Ok(ProcessResult::Emitted(emitted)),
+        Skippable::Skipped(reason) => return Ok(ProcessResult::Skipped(reason)),
+    };
 
     // publish events to SmarTrak topic
     // publish 2x in order to properly signal departure from the station
     // (for schedule adherence)
-    let env = Config::get(provider, "ENV").await.unwrap_or_else(|_| "dev".to_string());
     let topic = format!("{env}-{SMARTRAK_TOPIC}");
 
     for _ in 0..2 {
@@ -58,15 +140,41 @@ where
             message.headers.insert("key".to_string(), external_id.clone());
 
             Publisher::send(provider, &topic, &message).await?;
+            emitted += 1;
         }
     }
 
-    Ok(Reply::ok(()))
+    Ok(ProcessResult::Emitted(emitted))
 }
 
-impl<P> Handler<P> for R9kMessage
+/// Resolve and publish the update's GTFS-RT trip update, returning whether
+/// there was one to publish.
+async fn publish_trip_update<P>(
+    update: &TrainUpdate, preference: Parity, topic: &str, provider: &P,
+) -> anyhow::Result<bool>
 where
     P: Config + HttpRequest + Identity + Publisher,
+{
+    let aliases = train_id_aliases(provider).await;
+    let ignored = ignored_stations(provider).await;
+    let Some(trip_update) =
+        resolve_trip_update(update, preference, aliases, &ignored, provider).await?
+    else {
+        return Ok(false);
+    };
+    tracing::info!(monotonic_counter.trip_updates_published = 1);
+
+    let payload = serde_json::to_vec(&trip_update).context("serializing trip update")?;
+    let mut message = Message::new(&payload);
+    message.headers.insert("key".to_string(), trip_update.trip.trip_id.clone());
+    Publisher::send(provider, topic, &message).await?;
+
+    Ok(true)
+}
+
+impl<P> Handler<P> for R9kMessage
+where
+    P: Config + HttpRequest + Identity + Publisher + StateStore,
 {
     type Error = Error;
     type Input = Vec<u8>;
@@ -74,8 +182,7 @@ where
 
     fn from_input(input: Vec<u8>) -> Result<Self> {
         quick_xml::de::from_reader(input.as_ref())
-            .context("deserializing R9kMessage")
-            .map_err(Into::into)
+            .map_err(|err| R9kError::InvalidXml(err.to_string()).into())
     }
 
     async fn handle(self, ctx: Context<'_, P>) -> Result<Reply<()>> {
@@ -84,38 +191,73 @@ where
 }
 
 impl TrainUpdate {
-    /// Transform the R9K message to SmarTrak events
-    async fn into_events<P>(self, owner: &str, provider: &P) -> Result<Vec<SmarTrakEvent>>
+    /// Key recording the update's change as seen, if it has one.
+    ///
+    /// KiwiRail resends updates, sometimes under the train's other parity id,
+    /// so changes are keyed by the update's registration number and entry id
+    /// rather than the train id.
+    fn seen_key(&self) -> Option<String> {
+        let change = self.first_actual_change()?;
+        Some(format!(
+            "{KEY_SEEN_UPDATE}:{}:{}:{}",
+            self.registration_number, change.entry_id, change.r#type
+        ))
+    }
+
+    /// Transform the R9K message to SmarTrak events, or the reason it has none.
+    async fn into_events<P>(
+        self, owner: &str, provider: &P, preference: Parity,
+    ) -> Result<Skippable<Vec<SmarTrakEvent>>>
     where
         P: Config + HttpRequest + Identity + Publisher,
     {
-        let changes = &self.changes;
-        let change_type = changes[0].r#type;
+        let Some(change) = self.first_actual_change() else {
+            return Ok(Skippable::Skipped("no actual changes"));
+        };
+        let change_type = change.r#type;
 
         // filter out irrelevant updates (not related to trip progress)
         if !change_type.is_relevant() {
             // TODO: do we need this metric?
             tracing::info!(monotonic_counter.irrelevant_change_type = 1, type = %change_type);
-            return Ok(vec![]);
+            return Ok(Skippable::Skipped("irrelevant change type"));
+        }
+
+        // skip stations that intentionally produce no events (depots, sidings)
+        let station = change.station;
+        if ignored_stations(provider).await.contains(&station) {
+            tracing::debug!(station = %station, "ignoring station");
+            return Ok(Skippable::Skipped("ignored station"));
+        }
+
+        // skip stop types that aren't wanted, e.g. pass-throughs with no dwell
+        if let Some(stop_types) = stop_types(provider).await
+            && !stop_types.contains(&change.stop_type)
+        {
+            tracing::debug!(stop_type = ?change.stop_type, "ignoring stop type");
+            return Ok(Skippable::Skipped("ignored stop type"));
         }
 
         // is station is relevant?
-        let station = changes[0].station;
+        let parity = change.parity;
         let Some(stop_info) =
-            stops::stop_info(owner, provider, station, change_type.is_arrival()).await?
+            stops::stop_info(owner, provider, station, parity, change_type.is_arrival()).await?
         else {
             tracing::info!(monotonic_counter.irrelevant_station = 1, station = %station);
-            return Ok(vec![]);
+            return Ok(Skippable::Skipped("irrelevant station"));
         };
 
         // get train allocations for this trip
-        let url = Config::get(provider, "BLOCK_MGT_URL").await?;
+        let base_url = Config::get(provider, "BLOCK_MGT_URL").await?;
         let identity = Config::get(provider, "AZURE_IDENTITY").await?;
 
         let token = Identity::access_token(provider, identity).await?;
 
         let request = http::Request::builder()
-            .uri(format!("{url}/allocations/trips?externalRefId={}", self.train_id()))
+            .uri(url::join(
+                &base_url,
+                &format!("allocations/trips?externalRefId={}", self.train_id_for(preference)),
+            ))
             .header(AUTHORIZATION, format!("Bearer {token}"))
             .body(Empty::<Bytes>::new())
             .context("building block management request")?;
@@ -125,6 +267,27 @@ impl TrainUpdate {
         let bytes = response.into_body();
         let allocated: Vec<String> =
             serde_json::from_slice(&bytes).context("deserializing block management response")?;
+        if allocated.is_empty() {
+            return Ok(Skippable::Skipped("no allocated vehicles"));
+        }
+
+        // a pass-through is not a stop passengers can use
+        let skipped = change_type == ChangeType::PassedStationWithoutStopping;
+
+        // a train at the station is at its stop, and one that has left it is
+        // in transit to the next station's stop, when the update includes it.
+        // The status is only given with the GTFS stop it refers to.
+        let stop_id = match change_type.stop_status() {
+            Some(VehicleStopStatus::StoppedAt) => {
+                Some(stop_info.stop_id.clone()).filter(|id| !id.is_empty())
+            }
+            Some(VehicleStopStatus::InTransitTo) => match self.next_change(change) {
+                Some(next) => stops::station_stop_id(provider, next.station, next.parity).await?,
+                None => None,
+            },
+            None => None,
+        };
+        let current_status = stop_id.as_ref().and(change_type.stop_status());
 
         // publish `SmarTrak` events
         let mut events = Vec::new();
@@ -138,17 +301,83 @@ impl TrainUpdate {
                     ..RemoteData::default()
                 },
                 location_data: stop_info.clone().into(),
+                skipped,
+                stop_id: stop_id.clone(),
+                current_status,
                 ..SmarTrakEvent::default()
             });
         }
 
-        Ok(events)
+        Ok(Skippable::Output(events))
+    }
+}
+
+/// Freshness window for updates, read from `R9K_MAX_DELAY_SECS` and
+/// `R9K_MIN_DELAY_SECS`. Defaults to 60 seconds late and 30 seconds early.
+async fn delay_bounds(provider: &impl Config) -> (i64, i64) {
+    let max_delay = config::setting(provider, "R9K_MAX_DELAY_SECS").await.unwrap_or(MAX_DELAY_SECS);
+    let min_delay = config::setting(provider, "R9K_MIN_DELAY_SECS").await.unwrap_or(MIN_DELAY_SECS);
+    (max_delay, min_delay)
+}
+
+/// How long, in seconds, a change is remembered to drop resends of it, read
+/// from `R9K_DEDUPE_WINDOW_SECS`. Resends aren't dropped unless set.
+async fn dedupe_window(provider: &impl Config) -> Option<u64> {
+    config::checked_setting(provider, "R9K_DEDUPE_WINDOW_SECS", |window: &u64| *window > 0).await
+}
+
+/// Train id to use when the train's parity can't be determined, read from
+/// `R9K_TRAIN_ID_PREFERENCE` (`even` or `odd`). Defaults to even.
+async fn train_id_preference(provider: &impl Config) -> Parity {
+    match Config::get(provider, "R9K_TRAIN_ID_PREFERENCE").await.map(Parity::from) {
+        Ok(Parity::Odd) => Parity::Odd,
+        _ => Parity::Even,
     }
 }
 
+/// Whether trip updates carry both train ids, enabled by
+/// `R9K_TRAIN_ID_ALIASES`. Defaults to the single resolved id.
+async fn train_id_aliases(provider: &impl Config) -> bool {
+    config::flag(provider, "R9K_TRAIN_ID_ALIASES").await
+}
+
+/// R9K station ids listed in `R9K_IGNORED_STATIONS` (comma separated).
+/// Entries that are not valid station ids are skipped.
+async fn ignored_stations(provider: &impl Config) -> Vec<u32> {
+    Config::get(provider, "R9K_IGNORED_STATIONS")
+        .await
+        .map(|value| parse_stations(&value))
+        .unwrap_or_default()
+}
+
+fn parse_stations(value: &str) -> Vec<u32> {
+    value.split(',').filter_map(|id| id.trim().parse().ok()).collect()
+}
+
+/// Stop types to produce events for, listed in `R9K_STOP_TYPES` (comma
+/// separated `original` or `intermediate`). Defaults to all stop types.
+async fn stop_types(provider: &impl Config) -> Option<Vec<StopType>> {
+    Config::get(provider, "R9K_STOP_TYPES")
+        .await
+        .ok()
+        .map(|value| parse_stop_types(&value))
+        .filter(|stop_types| !stop_types.is_empty())
+}
+
+fn parse_stop_types(value: &str) -> Vec<StopType> {
+    value
+        .split(',')
+        .filter_map(|stop_type| match stop_type.trim().to_ascii_lowercase().as_str() {
+            "original" | "4" => Some(StopType::Original),
+            "intermediate" | "5" => Some(StopType::Intermediate),
+            _ => None,
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::R9kMessage;
+    use super::{R9kMessage, StopType, parse_stations, parse_stop_types};
 
     #[test]
     fn deserialization() {
@@ -159,4 +388,19 @@ mod tests {
         assert_eq!(update.even_train_id, Some("1234".to_string()));
         assert!(!update.changes.is_empty(), "should have changes");
     }
+
+    // Should parse a comma-separated list of station ids, skipping invalid ones.
+    #[test]
+    fn ignored_station_list() {
+        assert_eq!(parse_stations("0, 19,depot,,40 "), [0, 19, 40]);
+        assert!(parse_stations("").is_empty());
+    }
+
+    // Should parse a comma-separated list of stop types by name or code.
+    #[test]
+    fn stop_type_list() {
+        assert_eq!(parse_stop_types(" Intermediate"), [StopType::Intermediate]);
+        assert_eq!(parse_stop_types("4,dwell,5"), [StopType::Original, StopType::Intermediate]);
+        assert!(parse_stop_types("").is_empty());
+    }
 }
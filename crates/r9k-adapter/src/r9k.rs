@@ -4,14 +4,17 @@ use std::fmt::{Display, Formatter};
 
 use chrono::{NaiveDate, Utc};
 use chrono_tz::Pacific;
-use qwasr_sdk::Result;
 use serde::Deserialize;
 use serde_repr::Deserialize_repr;
 
 use crate::R9kError;
+use crate::smartrak::VehicleStopStatus;
 
-const MAX_DELAY_SECS: i64 = 60;
-const MIN_DELAY_SECS: i64 = -30;
+/// Default maximum age, in seconds, of an update.
+pub const MAX_DELAY_SECS: i64 = 60;
+
+/// Default earliest an update may be future-dated, in (negative) seconds.
+pub const MIN_DELAY_SECS: i64 = -30;
 
 /// R9000 (R9K) train update as received from KiwiRail.
 /// Defines the XML mappings as defined by the R9K provider - in Spanish.
@@ -56,7 +59,8 @@ pub struct TrainUpdate {
     /// The list includes one entry for the station that the train has arrived
     /// at, with additional entries for stations not yet visited.
     ///
-    /// N.B. Only the first entry is used as the remainder are a schedule only.
+    /// N.B. Only one entry is used, see [`TrainUpdate::first_actual_change`].
+    /// It is usually the first, as the remainder are a schedule only.
     #[serde(rename(deserialize = "pasoTren"), default)]
     pub changes: Vec<Change>,
 }
@@ -70,46 +74,113 @@ where
 }
 
 impl TrainUpdate {
+    /// The change the update is for: the first with an actual arrival or
+    /// departure, or the first change when none has.
+    ///
+    /// KiwiRail sometimes sends a schedule-only entry ahead of the station the
+    /// train has actually arrived at or departed from.
+    #[must_use]
+    pub fn first_actual_change(&self) -> Option<&Change> {
+        self.changes
+            .iter()
+            .find(|change| change.actual_time().is_some_and(|secs| secs > 0))
+            .or_else(|| self.changes.first())
+    }
+
+    /// The first change after `change` at another station: the station the
+    /// train is heading to next, if the update includes it.
+    #[must_use]
+    pub fn next_change(&self, change: &Change) -> Option<&Change> {
+        let position = self.changes.iter().position(|other| std::ptr::eq(other, change))?;
+        self.changes[position + 1..].iter().find(|next| next.station != change.station)
+    }
+
     /// Get the train ID, preferring even over odd.
     #[must_use]
     pub fn train_id(&self) -> String {
         self.even_train_id.clone().unwrap_or_else(|| self.odd_train_id.clone().unwrap_or_default())
     }
 
+    /// Get the train ID to identify the train by.
+    ///
+    /// As for [`Self::train_id`], the even ID is preferred. The parity of the
+    /// ID can't be relied on when the direction of travel is unspecified, so
+    /// then `preference` decides instead. Falls back to the other ID when the
+    /// selected one is missing.
+    #[must_use]
+    pub fn train_id_for(&self, preference: Parity) -> String {
+        if self.direction() == Direction::Unspecified && preference == Parity::Odd {
+            return self.odd_train_id.clone().unwrap_or_else(|| self.train_id());
+        }
+        self.train_id()
+    }
+
+    /// Get the train ID not selected by [`Self::train_id_for`], for consumers
+    /// keying on the other ID. `None` when the train has only one ID.
+    #[must_use]
+    pub fn alias_train_id_for(&self, preference: Parity) -> Option<String> {
+        let train_id = self.train_id_for(preference);
+        [&self.even_train_id, &self.odd_train_id]
+            .into_iter()
+            .flatten()
+            .find(|id| **id != train_id)
+            .cloned()
+    }
+
+    /// Direction of travel at the station the update is for.
+    #[must_use]
+    pub fn direction(&self) -> Direction {
+        self.first_actual_change().map_or(Direction::Unspecified, |change| change.train_direction)
+    }
+
+    /// Timestamp of local midnight on the creation date. R9K times are
+    /// expressed as seconds from this instant.
+    #[must_use]
+    pub fn midnight_ts(&self) -> Option<i64> {
+        let naive_dt = self.created_date.and_hms_opt(0, 0, 0)?;
+        naive_dt.and_local_timezone(Pacific::Auckland).earliest().map(|dt| dt.timestamp())
+    }
+
     /// Validate the message.
     ///
     /// # Errors
     ///
-    /// Will return one of the following errors:
-    ///  - `Error::NoUpdate` if there are no changes
-    ///  - `Error::NoActualUpdate` if the arrival or departure time is -ve or 0
-    ///  - `Error::Outdated` if the message is too old
-    ///  - `Error::WrongTime` if the message is from the future
-    pub fn validate(&self) -> Result<()> {
-        if self.changes.is_empty() {
-            return Err(R9kError::NoUpdate("contains no updates".to_string()).into());
-        }
+    /// Will return one of the following errors, identified by
+    /// [`R9kError::kind`]:
+    ///  - `NoUpdate` if there are no changes
+    ///  - `NoActualUpdate` if the arrival or departure time is -ve or 0
+    ///  - `BadTime` if the local time can't be determined
+    ///  - `Outdated` if the message is too old
+    ///  - `WrongTime` if the message is from the future
+    pub fn validate(&self) -> Result<(), R9kError> {
+        self.validate_with_bounds(MAX_DELAY_SECS, MIN_DELAY_SECS)
+    }
+
+    /// Validate the message, accepting updates delayed by at most `max_delay`
+    /// seconds and future-dated by no more than `-min_delay` seconds.
+    ///
+    /// # Errors
+    ///
+    /// Will return the same errors as [`TrainUpdate::validate`].
+    pub fn validate_with_bounds(&self, max_delay: i64, min_delay: i64) -> Result<(), R9kError> {
+        let Some(change) = self.first_actual_change() else {
+            return Err(R9kError::NoUpdate("contains no updates".to_string()));
+        };
 
         // an *actual* update will have a +ve arrival or departure time
-        let change = &self.changes[0];
-        let since_midnight_secs = if change.has_departed {
-            change.actual_departure_time
-        } else if change.has_arrived {
-            change.actual_arrival_time
-        } else {
-            return Err(R9kError::NoUpdate("arrival/departure time <= 0".to_string()).into());
+        let Some(since_midnight_secs) = change.actual_time() else {
+            return Err(R9kError::NoActualUpdate);
         };
 
         if since_midnight_secs <= 0 {
-            return Err(R9kError::NoUpdate("arrival/departure time <= 0".to_string()).into());
+            return Err(R9kError::NoActualUpdate);
         }
 
         // rebuild the event timestamp from the creation date + seconds from midnight
-        let naive_dt = self.created_date.and_hms_opt(0, 0, 0).unwrap_or_default();
-        let Some(midnight_dt) = naive_dt.and_local_timezone(Pacific::Auckland).earliest() else {
-            return Err(R9kError::BadTime(format!("invalid local time: {naive_dt}")).into());
+        let Some(midnight_ts) = self.midnight_ts() else {
+            let naive_dt = self.created_date.and_hms_opt(0, 0, 0).unwrap_or_default();
+            return Err(R9kError::BadTime(format!("invalid local time: {naive_dt}")));
         };
-        let midnight_ts = midnight_dt.timestamp();
         let event_ts = midnight_ts + i64::from(since_midnight_secs);
 
         // calculate delay from 'now'
@@ -119,13 +190,11 @@ impl TrainUpdate {
         // TODO: do we need this metric?;
         tracing::info!(gauge.r9k_delay = delay_secs);
 
-        if delay_secs > MAX_DELAY_SECS {
-            return Err(R9kError::BadTime(format!("outdated by {delay_secs} seconds")).into());
+        if delay_secs > max_delay {
+            return Err(R9kError::Outdated(delay_secs));
         }
-        if delay_secs < MIN_DELAY_SECS {
-            return Err(
-                R9kError::BadTime(format!("too early by {} seconds", delay_secs.abs())).into()
-            );
+        if delay_secs < min_delay {
+            return Err(R9kError::WrongTime(delay_secs.abs()));
         }
 
         Ok(())
@@ -218,9 +287,25 @@ pub struct Change {
     #[serde(rename(deserialize = "tipoParada"))]
     pub stop_type: StopType,
 
-    /// N.B. Not sure what this is used for.
+    /// Parity of the train, indicating the platform side at stations with
+    /// island platforms.
     #[serde(rename(deserialize = "paridad"))]
-    pub parity: String,
+    pub parity: Parity,
+}
+
+impl Change {
+    /// Actual departure time once departed, otherwise the actual arrival time
+    /// once arrived. `None` when the train has neither arrived nor departed.
+    #[must_use]
+    pub const fn actual_time(&self) -> Option<i32> {
+        if self.has_departed {
+            Some(self.actual_departure_time)
+        } else if self.has_arrived {
+            Some(self.actual_arrival_time)
+        } else {
+            None
+        }
+    }
 }
 
 /// The type of change that triggered the update message.
@@ -297,6 +382,21 @@ impl ChangeType {
     pub const fn is_arrival(&self) -> bool {
         matches!(self, Self::ArrivedAtStation | Self::ReachedFinalDestination)
     }
+
+    /// Status of the train relative to the station: stopped at it on arrival,
+    /// or in transit once it has departed or passed through.
+    #[must_use]
+    pub const fn stop_status(&self) -> Option<VehicleStopStatus> {
+        match self {
+            Self::ArrivedAtStation | Self::ReachedFinalDestination => {
+                Some(VehicleStopStatus::StoppedAt)
+            }
+            Self::ExitedFirstStation | Self::ExitedStation | Self::PassedStationWithoutStopping => {
+                Some(VehicleStopStatus::InTransitTo)
+            }
+            _ => None,
+        }
+    }
 }
 
 /// Type of train.
@@ -328,6 +428,31 @@ pub enum Direction {
     Unspecified = -1,
 }
 
+/// Train parity (`paridad`): even (`p`, *par*) or odd (`i`, *impar*).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum Parity {
+    /// Even train.
+    Even,
+
+    /// Odd train.
+    Odd,
+
+    /// Missing or unrecognised parity.
+    #[default]
+    Unspecified,
+}
+
+impl From<String> for Parity {
+    fn from(value: String) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "p" | "par" | "even" => Self::Even,
+            "i" | "impar" | "odd" => Self::Odd,
+            _ => Self::Unspecified,
+        }
+    }
+}
+
 /// Direction of travel.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize_repr)]
 #[repr(i8)]
@@ -339,3 +464,225 @@ pub enum StopType {
     /// Intermediate stop (there is a dwell time in the time table).
     Intermediate = 5,
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+    use crate::{R9kErrorKind, R9kMessage};
+
+    fn update(sentido: i8, paridad: &str) -> TrainUpdate {
+        let xml = format!(
+            "<CCO><ActualizarDatosTren>
+                <trenPar>1234</trenPar>
+                <trenImpar>1235</trenImpar>
+                <fechaCreacion>02/08/2025</fechaCreacion>
+                <pasoTren>
+                    <tipoCambio>3</tipoCambio>
+                    <estacion>0</estacion>
+                    <idPaso>entry-001</idPaso>
+                    <horaEntrada>3600</horaEntrada>
+                    <horaEntradaReal>3620</horaEntradaReal>
+                    <haEntrado>true</haEntrado>
+                    <retrasoEntrada>20</retrasoEntrada>
+                    <horaSalida>3700</horaSalida>
+                    <horaSalidaReal>-1</horaSalidaReal>
+                    <haSalido>false</haSalido>
+                    <retrasoSalida>0</retrasoSalida>
+                    <horaInicioDetencion>0</horaInicioDetencion>
+                    <duracionDetencion>0</duracionDetencion>
+                    <viaEntradaMallas>1</viaEntradaMallas>
+                    <viaCirculacionMallas>A</viaCirculacionMallas>
+                    <sentido>{sentido}</sentido>
+                    <tipoParada>5</tipoParada>
+                    <paridad>{paridad}</paridad>
+                </pasoTren>
+            </ActualizarDatosTren></CCO>"
+        );
+        let message: R9kMessage = quick_xml::de::from_str(&xml).expect("should deserialize");
+        message.train_update
+    }
+
+    // Should keep preferring the even train id when the direction is known,
+    // whatever the parity or preference.
+    #[test]
+    fn train_id_specified_direction() {
+        assert_eq!(update(0, "p").train_id_for(Parity::Odd), "1234");
+        assert_eq!(update(1, "i").train_id_for(Parity::Even), "1234");
+        assert_eq!(update(1, "i").train_id_for(Parity::Odd), "1234");
+        assert_eq!(update(1, "i").train_id_for(Parity::Even), update(1, "i").train_id());
+    }
+
+    // Should fall back to the configured preference when the direction is
+    // unspecified, whatever the parity.
+    #[test]
+    fn unspecified_direction() {
+        let update = update(-1, "i");
+        assert_eq!(update.direction(), Direction::Unspecified);
+        assert_eq!(update.train_id_for(Parity::Even), "1234");
+        assert_eq!(update.train_id_for(Parity::Odd), "1235");
+    }
+
+    // Should fall back to the other train id when the selected one is missing.
+    #[test]
+    fn missing_train_id() {
+        let mut even = update(0, "p");
+        even.even_train_id = None;
+        assert_eq!(even.train_id_for(Parity::Even), "1235");
+
+        let mut odd = update(-1, "i");
+        odd.odd_train_id = None;
+        assert_eq!(odd.train_id_for(Parity::Odd), "1234");
+    }
+
+    // Should alias the train id that wasn't selected, if there is one.
+    #[test]
+    fn alias_train_id() {
+        assert_eq!(update(0, "p").alias_train_id_for(Parity::Even).as_deref(), Some("1235"));
+        assert_eq!(update(-1, "i").alias_train_id_for(Parity::Odd).as_deref(), Some("1234"));
+
+        let mut update = update(0, "p");
+        update.odd_train_id = None;
+        assert_eq!(update.alias_train_id_for(Parity::Even), None);
+    }
+
+    // Should surface validation errors of each kind.
+    #[test]
+    fn validation_kinds() {
+        let kind = |update: &TrainUpdate| update.validate().expect_err("should be invalid").kind();
+
+        let mut no_update = update(0, "p");
+        no_update.changes.clear();
+        assert_eq!(kind(&no_update), R9kErrorKind::NoUpdate);
+
+        let mut no_actual_update = update(0, "p");
+        no_actual_update.changes[0].has_arrived = false;
+        assert_eq!(kind(&no_actual_update), R9kErrorKind::NoActualUpdate);
+
+        assert_eq!(kind(&update(0, "p")), R9kErrorKind::Outdated);
+
+        let mut wrong_time = update(0, "p");
+        wrong_time.created_date = Utc::now().date_naive() + chrono::Days::new(2);
+        assert_eq!(kind(&wrong_time), R9kErrorKind::WrongTime);
+    }
+
+    // Should use a real arrival following a schedule-only entry, and the first
+    // entry when none is real.
+    #[test]
+    fn first_actual_change() {
+        let arrival = Utc::now().with_timezone(&Pacific::Auckland) - chrono::Duration::seconds(10);
+        let xml = format!(
+            "<CCO><ActualizarDatosTren>
+                <trenPar>1234</trenPar>
+                <fechaCreacion>{date}</fechaCreacion>
+                <pasoTren>
+                    <tipoCambio>11</tipoCambio>
+                    <estacion>19</estacion>
+                    <idPaso>entry-001</idPaso>
+                    <horaEntrada>3600</horaEntrada>
+                    <horaEntradaReal>-1</horaEntradaReal>
+                    <haEntrado>false</haEntrado>
+                    <retrasoEntrada>0</retrasoEntrada>
+                    <horaSalida>3700</horaSalida>
+                    <horaSalidaReal>-1</horaSalidaReal>
+                    <haSalido>false</haSalido>
+                    <retrasoSalida>0</retrasoSalida>
+                    <horaInicioDetencion>0</horaInicioDetencion>
+                    <duracionDetencion>0</duracionDetencion>
+                    <viaEntradaMallas>1</viaEntradaMallas>
+                    <viaCirculacionMallas>A</viaCirculacionMallas>
+                    <sentido>1</sentido>
+                    <tipoParada>5</tipoParada>
+                    <paridad>p</paridad>
+                </pasoTren>
+                <pasoTren>
+                    <tipoCambio>3</tipoCambio>
+                    <estacion>40</estacion>
+                    <idPaso>entry-002</idPaso>
+                    <horaEntrada>{secs}</horaEntrada>
+                    <horaEntradaReal>{secs}</horaEntradaReal>
+                    <haEntrado>true</haEntrado>
+                    <retrasoEntrada>0</retrasoEntrada>
+                    <horaSalida>{secs}</horaSalida>
+                    <horaSalidaReal>-1</horaSalidaReal>
+                    <haSalido>false</haSalido>
+                    <retrasoSalida>0</retrasoSalida>
+                    <horaInicioDetencion>0</horaInicioDetencion>
+                    <duracionDetencion>0</duracionDetencion>
+                    <viaEntradaMallas>2</viaEntradaMallas>
+                    <viaCirculacionMallas>A</viaCirculacionMallas>
+                    <sentido>0</sentido>
+                    <tipoParada>5</tipoParada>
+                    <paridad>i</paridad>
+                </pasoTren>
+            </ActualizarDatosTren></CCO>",
+            date = arrival.format("%d/%m/%Y"),
+            secs = arrival.num_seconds_from_midnight(),
+        );
+        let message: R9kMessage = quick_xml::de::from_str(&xml).expect("should deserialize");
+        let update = message.train_update;
+
+        let change = update.first_actual_change().expect("change");
+        assert_eq!(change.station, 40);
+        assert_eq!(change.r#type, ChangeType::ArrivedAtStation);
+        assert_eq!(update.direction(), Direction::Right);
+        update.validate().expect("should be valid");
+
+        // without a real arrival, the first entry is used
+        let mut schedule_only = update.clone();
+        schedule_only.changes[1].has_arrived = false;
+        assert_eq!(schedule_only.first_actual_change().expect("change").station, 19);
+        let err = schedule_only.validate().expect_err("should have no actual update");
+        assert_eq!(err.kind(), R9kErrorKind::NoActualUpdate);
+    }
+
+    // Update the train arrived `delay_secs` ago.
+    fn arrived(delay_secs: i64) -> TrainUpdate {
+        let arrived =
+            Utc::now().with_timezone(&Pacific::Auckland) - chrono::Duration::seconds(delay_secs);
+        let mut update = update(0, "p");
+        update.created_date = arrived.date_naive();
+        update.changes[0].actual_arrival_time =
+            i32::try_from(arrived.num_seconds_from_midnight()).expect("seconds");
+        update
+    }
+
+    // Should accept a delayed update within caller-supplied bounds that the
+    // default bounds reject.
+    #[test]
+    fn delay_bounds() {
+        let delayed = arrived(90);
+        delayed.validate_with_bounds(120, MIN_DELAY_SECS).expect("should be within bounds");
+
+        let err = delayed.validate().expect_err("should be outdated");
+        assert_eq!(err.kind(), R9kErrorKind::Outdated);
+        assert!(err.to_string().starts_with("outdated by 9"));
+
+        let early = arrived(-45);
+        early.validate_with_bounds(MAX_DELAY_SECS, -60).expect("should be within bounds");
+        let err = early.validate().expect_err("should be too early");
+        assert_eq!(err.kind(), R9kErrorKind::WrongTime);
+    }
+
+    // Should report trains stopped at the station on arrival, and in transit
+    // once departed or passed through.
+    #[test]
+    fn stop_status() {
+        let stopped = [ChangeType::ArrivedAtStation, ChangeType::ReachedFinalDestination];
+        for change_type in stopped {
+            assert_eq!(change_type.stop_status(), Some(VehicleStopStatus::StoppedAt));
+        }
+
+        let departed = [
+            ChangeType::ExitedFirstStation,
+            ChangeType::ExitedStation,
+            ChangeType::PassedStationWithoutStopping,
+        ];
+        for change_type in departed {
+            assert_eq!(change_type.stop_status(), Some(VehicleStopStatus::InTransitTo));
+        }
+
+        assert_eq!(ChangeType::ScheduleChange.stop_status(), None);
+    }
+}
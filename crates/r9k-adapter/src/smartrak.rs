@@ -39,6 +39,20 @@ pub struct SmarTrakEvent {
     /// Serial data associated with the event.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub serial_data: Option<SerialData>,
+
+    /// The train passed the stop without stopping, so the stop should not be
+    /// shown to passengers as served.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+
+    /// GTFS stop the train is stopped at or in transit to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_id: Option<String>,
+
+    /// Whether the train is stopped at or in transit to the stop, only set
+    /// with the stop.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_status: Option<VehicleStopStatus>,
 }
 
 fn with_nanos<S>(dt: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
@@ -60,6 +74,17 @@ pub enum EventType {
     SerialData,
 }
 
+/// GTFS-RT status of a vehicle relative to its stop.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum VehicleStopStatus {
+    /// The vehicle is standing at the stop.
+    StoppedAt,
+
+    /// The vehicle has departed the previous stop and is in transit.
+    InTransitTo,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageData {
@@ -0,0 +1,166 @@
+//! Configurable R9K station to GTFS stop mapping, so onboarding a line doesn't
+//! require a release.
+
+use std::collections::HashMap;
+
+use anyhow::Context as _;
+use bytes::Bytes;
+use common::god_mode;
+use http::header::CACHE_CONTROL;
+use http_body_util::Empty;
+use qwasr_sdk::api::{Context, Handler, Reply};
+use qwasr_sdk::{Config, Error, HttpRequest, Identity, IntoBody, Publisher, Result, bad_request};
+use serde::{Deserialize, Serialize};
+
+use crate::r9k::Parity;
+
+/// Stop codes keyed by R9K station id, loaded from the JSON object at
+/// `R9K_STATION_MAP_URL`, e.g. `{"0": "133", "19": "9218"}`.
+///
+/// A station with island platforms maps to a stop for each side, selected by
+/// the train's parity, e.g. `{"19": {"even": "9218", "odd": "9219"}}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct StationMap(HashMap<u32, StationStops>);
+
+/// The stop, or platform stops, mapped to a station.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum StationStops {
+    /// The station's single stop.
+    Station(String),
+
+    /// Stops for each side of an island platform.
+    Platforms {
+        /// Stop for even trains.
+        even: String,
+
+        /// Stop for odd trains.
+        odd: String,
+    },
+}
+
+impl StationMap {
+    /// Stop code mapped to the station, if any.
+    ///
+    /// Where the station's platforms are mapped by side, parity selects the
+    /// stop, and there is none when parity is unspecified.
+    #[must_use]
+    pub fn stop_code(&self, station: u32, parity: Parity) -> Option<&str> {
+        match (self.0.get(&station)?, parity) {
+            (StationStops::Station(stop_code), _) => Some(stop_code),
+            (StationStops::Platforms { even, .. }, Parity::Even) => Some(even),
+            (StationStops::Platforms { odd, .. }, Parity::Odd) => Some(odd),
+            (StationStops::Platforms { .. }, Parity::Unspecified) => None,
+        }
+    }
+
+    /// Number of mapped stations.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Load the station map, or `None` when `R9K_STATION_MAP_URL` is not set.
+///
+/// Like other static data, the map is cached for 5 minutes. A `refresh`
+/// bypasses the cache, replacing the cached map.
+///
+/// # Errors
+///
+/// Returns an error when the map can't be fetched or deserialized.
+pub async fn station_map<P>(provider: &P, refresh: bool) -> anyhow::Result<Option<StationMap>>
+where
+    P: Config + HttpRequest,
+{
+    let Ok(url) = Config::get(provider, "R9K_STATION_MAP_URL").await else {
+        return Ok(None);
+    };
+
+    let cache_control = if refresh { "no-cache" } else { "max-age=300" }; // 5 minutes
+    let request = http::Request::builder()
+        .uri(url)
+        .header(CACHE_CONTROL, cache_control)
+        .body(Empty::<Bytes>::new())
+        .context("building station map request")?;
+    let response = HttpRequest::fetch(provider, request).await.context("fetching station map")?;
+
+    let map = serde_json::from_slice(&response.into_body()).context("deserializing station map")?;
+    Ok(Some(map))
+}
+
+/// God Mode request to reload the station map, bypassing the cache.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StationMapRequest;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StationMapReply {
+    pub message: String,
+    pub stations: usize,
+}
+
+async fn handle<P>(
+    _owner: &str, _request: StationMapRequest, provider: &P,
+) -> Result<Reply<StationMapReply>>
+where
+    P: Config + HttpRequest,
+{
+    if !god_mode::is_enabled(provider).await {
+        return Err(bad_request!("God mode not enabled"));
+    }
+
+    let Some(map) = station_map(provider, true).await? else {
+        return Err(bad_request!("R9K_STATION_MAP_URL not set"));
+    };
+    tracing::info!(stations = map.len(), "refreshed station map");
+
+    Ok(StationMapReply { message: "Ok".to_string(), stations: map.len() }.into())
+}
+
+impl<P> Handler<P> for StationMapRequest
+where
+    P: Config + HttpRequest + Identity + Publisher,
+{
+    type Error = Error;
+    type Input = ();
+    type Output = StationMapReply;
+
+    fn from_input(_input: ()) -> Result<Self> {
+        Ok(Self)
+    }
+
+    async fn handle(self, ctx: Context<'_, P>) -> Result<Reply<StationMapReply>> {
+        handle(ctx.owner, self, ctx.provider).await
+    }
+}
+
+impl IntoBody for StationMapReply {
+    fn into_body(self) -> anyhow::Result<Vec<u8>> {
+        serde_json::to_vec(&self).context("serializing reply")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Should select the platform stop matching the train's parity.
+    #[test]
+    fn parity_selects_platform() {
+        let map: StationMap =
+            serde_json::from_str(r#"{"0": "133", "19": {"even": "9218", "odd": "9219"}}"#)
+                .expect("should deserialize");
+
+        assert_eq!(map.stop_code(19, Parity::Even), Some("9218"));
+        assert_eq!(map.stop_code(19, Parity::Odd), Some("9219"));
+        assert_eq!(map.stop_code(19, Parity::Unspecified), None);
+        assert_eq!(map.stop_code(0, Parity::Odd), Some("133"));
+        assert_eq!(map.stop_code(40, Parity::Even), None);
+    }
+}
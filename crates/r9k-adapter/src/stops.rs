@@ -3,55 +3,110 @@ use std::sync::LazyLock;
 
 use anyhow::{Context, Result, anyhow};
 use bytes::Bytes;
+use common::url;
 use http_body_util::Empty;
 use qwasr_sdk::{Config, HttpRequest, Identity, Publisher};
 use serde::{Deserialize, Serialize};
 
+use crate::r9k::Parity;
+use crate::station_map;
+
 /// Stop information from GTFS
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct StopInfo {
+    #[serde(default)]
+    pub stop_id: String,
     pub stop_code: String,
     pub stop_lat: f64,
     pub stop_lon: f64,
 }
 
 pub async fn stop_info<P>(
-    _owner: &str, provider: &P, station: u32, is_arrival: bool,
+    _owner: &str, provider: &P, station: u32, parity: Parity, is_arrival: bool,
 ) -> Result<Option<StopInfo>>
 where
     P: Config + HttpRequest + Identity + Publisher,
 {
-    if !ACTIVE_STATIONS.contains(&station) {
+    // FIXME: if station is mapped, we should always get location data
+    // get station's stop code
+    let Some(stop_code) = station_stop_code(provider, station, parity).await else {
         return Ok(None);
+    };
+
+    let stops = gtfs_stops(provider).await?;
+    let Some(mut stop_info) = stops.into_iter().find(|stop| stop.stop_code == stop_code) else {
+        return Err(anyhow!("stop info not found for stop code {stop_code}"));
+    };
+
+    if !is_arrival && let Some(departure) = DEPARTURES.get(&stop_info.stop_code) {
+        stop_info.stop_lat = departure.stop_lat;
+        stop_info.stop_lon = departure.stop_lon;
     }
 
-    // FIXME: if station is in list above, we should always get location data
-    // get station's stop code
-    let Some(stop_code) = STATION_STOP.get(&station) else {
+    Ok(Some(stop_info))
+}
+
+/// Stop code for an R9K station, from the configured station map or, when
+/// none is configured or it can't be loaded, the built-in mapping of active
+/// stations.
+pub async fn station_stop_code<P>(provider: &P, station: u32, parity: Parity) -> Option<String>
+where
+    P: Config + HttpRequest,
+{
+    let map = match station_map::station_map(provider, false).await {
+        Ok(map) => map,
+        Err(err) => {
+            tracing::warn!("failed to load station map, using built-in stations: {err:#}");
+            None
+        }
+    };
+
+    // a configured station map replaces the built-in mapping
+    match map {
+        Some(map) => map.stop_code(station, parity).map(ToString::to_string),
+        None if ACTIVE_STATIONS.contains(&station) => stop_code(station).map(ToString::to_string),
+        None => None,
+    }
+}
+
+/// GTFS stop id for an R9K station, if it's mapped to a stop that has one.
+pub async fn station_stop_id<P>(
+    provider: &P, station: u32, parity: Parity,
+) -> Result<Option<String>>
+where
+    P: Config + HttpRequest,
+{
+    let Some(stop_code) = station_stop_code(provider, station, parity).await else {
         return Ok(None);
     };
+    let stops = gtfs_stops(provider).await?;
+    Ok(stops
+        .into_iter()
+        .find(|stop| stop.stop_code == stop_code && !stop.stop_id.is_empty())
+        .map(|stop| stop.stop_id))
+}
 
+/// GTFS stops, with their ids, codes and locations.
+pub async fn gtfs_stops<P>(provider: &P) -> Result<Vec<StopInfo>>
+where
+    P: Config + HttpRequest,
+{
     let cc_static_api_url =
         Config::get(provider, "CC_STATIC_URL").await.context("getting `CC_STATIC_URL`")?;
     let request = http::Request::builder()
-        .uri(format!("{cc_static_api_url}/gtfs/stops?fields=stop_code,stop_lon,stop_lat"))
+        .uri(url::join(&cc_static_api_url, "gtfs/stops?fields=stop_id,stop_code,stop_lon,stop_lat"))
         .body(Empty::<Bytes>::new())
         .context("building block management request")?;
     let response = HttpRequest::fetch(provider, request).await.context("fetching stops")?;
 
     let bytes = response.into_body();
-    let stops: Vec<StopInfo> =
-        serde_json::from_slice(&bytes).context("deserializing block management response")?;
-
-    let Some(mut stop_info) = stops.into_iter().find(|stop| stop.stop_code == *stop_code) else {
-        return Err(anyhow!("stop info not found for stop code {stop_code}"));
-    };
-
-    if !is_arrival {
-        stop_info = DEPARTURES.get(&stop_info.stop_code).cloned().unwrap_or(stop_info);
-    }
+    serde_json::from_slice(&bytes).context("deserializing block management response")
+}
 
-    Ok(Some(stop_info))
+/// Stop code mapped to an R9K station by the built-in mapping, if any.
+#[must_use]
+pub fn stop_code(station: u32) -> Option<&'static str> {
+    STATION_STOP.get(&station).copied()
 }
 
 const ACTIVE_STATIONS: &[u32] = &[0, 19, 40];
@@ -64,15 +119,52 @@ static DEPARTURES: LazyLock<HashMap<String, StopInfo>> = LazyLock::new(|| {
     HashMap::from([
         (
             "133".to_string(),
-            StopInfo { stop_code: "133".to_string(), stop_lat: -36.84448, stop_lon: 174.76915 },
+            StopInfo {
+                stop_code: "133".to_string(),
+                stop_lat: -36.84448,
+                stop_lon: 174.76915,
+                ..StopInfo::default()
+            },
         ),
         (
             "134".to_string(),
-            StopInfo { stop_code: "134".to_string(), stop_lat: -37.20299, stop_lon: 174.90990 },
+            StopInfo {
+                stop_code: "134".to_string(),
+                stop_lat: -37.20299,
+                stop_lon: 174.90990,
+                ..StopInfo::default()
+            },
         ),
         (
             "9218".to_string(),
-            StopInfo { stop_code: "9218".to_string(), stop_lat: -36.99412, stop_lon: 174.8770 },
+            StopInfo {
+                stop_code: "9218".to_string(),
+                stop_lat: -36.99412,
+                stop_lon: 174.8770,
+                ..StopInfo::default()
+            },
         ),
     ])
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Should map each active station to its single stop.
+    #[test]
+    fn unambiguous_station() {
+        assert_eq!(stop_code(0), Some("133"));
+        assert_eq!(stop_code(19), Some("9218"));
+        assert_eq!(stop_code(101), None);
+    }
+
+    // Should decode the even and odd parity codes, and anything else as
+    // unspecified.
+    #[test]
+    fn decode_parity() {
+        assert_eq!(Parity::from("p".to_string()), Parity::Even);
+        assert_eq!(Parity::from("i".to_string()), Parity::Odd);
+        assert_eq!(Parity::from(String::new()), Parity::Unspecified);
+    }
+}
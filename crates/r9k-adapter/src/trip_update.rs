@@ -0,0 +1,364 @@
+//! GTFS-RT style trip updates built from R9K station changes.
+
+use std::collections::HashMap;
+
+use anyhow::{Context as _, Result};
+use common::block_mgt;
+use qwasr_sdk::{Config, HttpRequest, Identity};
+use serde::{Deserialize, Serialize};
+
+use crate::r9k::{Change, Parity, TrainUpdate};
+use crate::stops;
+
+/// GTFS-RT `TripUpdate`: predicted or actual arrival and departure times for
+/// the stops of a trip.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TripUpdate {
+    /// The trip this update applies to.
+    pub trip: TripDescriptor,
+
+    /// Updates for each mapped station, in message order.
+    pub stop_time_update: Vec<StopTimeUpdate>,
+
+    /// Time the update was created, in seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+}
+
+/// GTFS-RT `TripDescriptor`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TripDescriptor {
+    /// GTFS trip id, from the train's block allocation.
+    pub trip_id: String,
+
+    /// Service date of the trip, formatted `YYYYMMDD`.
+    pub start_date: String,
+
+    /// Scheduled start time of the trip, formatted `HH:MM:SS`.
+    pub start_time: String,
+
+    /// R9K train id (even train id preferred over odd).
+    pub train_id: String,
+
+    /// The train's other R9K train id, carried when `R9K_TRAIN_ID_ALIASES`
+    /// is enabled so consumers keying on either id can match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias_train_id: Option<String>,
+}
+
+/// GTFS-RT `StopTimeUpdate`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopTimeUpdate {
+    /// GTFS stop id mapped from the R9K station.
+    pub stop_id: String,
+
+    /// Arrival at the stop, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrival: Option<StopTimeEvent>,
+
+    /// Departure from the stop, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub departure: Option<StopTimeEvent>,
+}
+
+/// GTFS-RT `StopTimeEvent`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StopTimeEvent {
+    /// Delay in seconds relative to the schedule. Positive is late.
+    pub delay: i32,
+
+    /// Actual or estimated time, in seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<i64>,
+}
+
+/// Resolve the GTFS trip and stops of an R9K update and build its trip update.
+///
+/// The trip is the block allocation, on the update's service date, whose
+/// reference is the train id selected using `preference` or, with `aliases`,
+/// the train's other id. Changes at `ignored` stations are left out.
+///
+/// Returns `None` when no trip is allocated to the train or no change maps to
+/// a GTFS stop.
+///
+/// # Errors
+///
+/// Returns an error when the block allocations, station map or GTFS stops
+/// can't be fetched.
+pub async fn resolve_trip_update<P>(
+    update: &TrainUpdate, preference: Parity, aliases: bool, ignored: &[u32], provider: &P,
+) -> Result<Option<TripUpdate>>
+where
+    P: Config + HttpRequest + Identity,
+{
+    let train_id = update.train_id_for(preference);
+    let alias_train_id = if aliases { update.alias_train_id_for(preference) } else { None };
+    let start_date = update.created_date.format("%Y%m%d").to_string();
+
+    let allocations = block_mgt::allocations_for_service_date(&start_date, provider)
+        .await
+        .context("fetching block allocations")?;
+    let allocation =
+        [Some(&train_id), alias_train_id.as_ref()].into_iter().flatten().find_map(|train_id| {
+            allocations.iter().find(|allocation| allocation.reference_id.trim() == train_id)
+        });
+    let Some(allocation) = allocation else {
+        tracing::debug!(train_id, start_date, "no trip allocated to train");
+        return Ok(None);
+    };
+
+    let stops = stops::gtfs_stops(provider).await?;
+    let mut stop_ids = HashMap::new();
+    for change in &update.changes {
+        if ignored.contains(&change.station) || stop_ids.contains_key(&change.station) {
+            continue;
+        }
+        let Some(stop_code) =
+            stops::station_stop_code(provider, change.station, change.parity).await
+        else {
+            continue;
+        };
+        if let Some(stop) =
+            stops.iter().find(|stop| stop.stop_code == stop_code && !stop.stop_id.is_empty())
+        {
+            stop_ids.insert(change.station, stop.stop_id.clone());
+        }
+    }
+
+    let trip = TripDescriptor {
+        trip_id: allocation.trip_id.clone(),
+        start_date,
+        start_time: allocation.start_time.clone(),
+        train_id,
+        alias_train_id,
+    };
+    Ok(update.trip_update(trip, &stop_ids))
+}
+
+impl TrainUpdate {
+    /// Build a trip update for `trip` from the changes for stations with a
+    /// GTFS stop id in `stop_ids`.
+    ///
+    /// Returns `None` when no change maps to a stop.
+    #[must_use]
+    pub fn trip_update(
+        &self, trip: TripDescriptor, stop_ids: &HashMap<u32, String>,
+    ) -> Option<TripUpdate> {
+        let midnight_ts = self.midnight_ts();
+
+        let stop_time_update: Vec<StopTimeUpdate> = self
+            .changes
+            .iter()
+            .filter_map(|change| {
+                let stop_id = stop_ids.get(&change.station)?;
+                Some(StopTimeUpdate {
+                    stop_id: stop_id.clone(),
+                    arrival: stop_time_event(
+                        change.arrival_time,
+                        change.actual_arrival_time,
+                        midnight_ts,
+                    ),
+                    departure: stop_time_event(
+                        change.departure_time,
+                        change.actual_departure_time,
+                        midnight_ts,
+                    ),
+                })
+            })
+            .collect();
+
+        if stop_time_update.is_empty() {
+            return None;
+        }
+
+        Some(TripUpdate {
+            trip,
+            stop_time_update,
+            timestamp: self.first_actual_change().and_then(|change| event_ts(change, midnight_ts)),
+        })
+    }
+}
+
+// Actual (or estimated) times are -1 when not available.
+fn stop_time_event(scheduled: i32, actual: i32, midnight_ts: Option<i64>) -> Option<StopTimeEvent> {
+    (actual > 0).then(|| StopTimeEvent {
+        delay: actual - scheduled,
+        time: midnight_ts.map(|ts| ts + i64::from(actual)),
+    })
+}
+
+fn event_ts(change: &Change, midnight_ts: Option<i64>) -> Option<i64> {
+    let secs = change.actual_time()?;
+    midnight_ts.map(|ts| ts + i64::from(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::TripDescriptor;
+    use crate::R9kMessage;
+
+    const XML: &str = r"<CCO>
+        <ActualizarDatosTren>
+            <trenPar>1234</trenPar>
+            <fechaCreacion>02/08/2025</fechaCreacion>
+            <pasoTren>
+                <tipoCambio>4</tipoCambio>
+                <estacion>0</estacion>
+                <idPaso>entry-001</idPaso>
+                <horaEntrada>3600</horaEntrada>
+                <horaEntradaReal>3620</horaEntradaReal>
+                <haEntrado>true</haEntrado>
+                <retrasoEntrada>20</retrasoEntrada>
+                <horaSalida>3700</horaSalida>
+                <horaSalidaReal>3730</horaSalidaReal>
+                <haSalido>true</haSalido>
+                <retrasoSalida>30</retrasoSalida>
+                <horaInicioDetencion>0</horaInicioDetencion>
+                <duracionDetencion>0</duracionDetencion>
+                <viaEntradaMallas>1</viaEntradaMallas>
+                <viaCirculacionMallas>A</viaCirculacionMallas>
+                <sentido>0</sentido>
+                <tipoParada>5</tipoParada>
+                <paridad>even</paridad>
+            </pasoTren>
+            <pasoTren>
+                <tipoCambio>11</tipoCambio>
+                <estacion>101</estacion>
+                <idPaso>entry-002</idPaso>
+                <horaEntrada>4000</horaEntrada>
+                <horaEntradaReal>4030</horaEntradaReal>
+                <haEntrado>false</haEntrado>
+                <retrasoEntrada>0</retrasoEntrada>
+                <horaSalida>4060</horaSalida>
+                <horaSalidaReal>4090</horaSalidaReal>
+                <haSalido>false</haSalido>
+                <retrasoSalida>0</retrasoSalida>
+                <horaInicioDetencion>0</horaInicioDetencion>
+                <duracionDetencion>0</duracionDetencion>
+                <viaEntradaMallas>1</viaEntradaMallas>
+                <viaCirculacionMallas>A</viaCirculacionMallas>
+                <sentido>0</sentido>
+                <tipoParada>5</tipoParada>
+                <paridad>even</paridad>
+            </pasoTren>
+            <pasoTren>
+                <tipoCambio>11</tipoCambio>
+                <estacion>19</estacion>
+                <idPaso>entry-003</idPaso>
+                <horaEntrada>4500</horaEntrada>
+                <horaEntradaReal>4490</horaEntradaReal>
+                <haEntrado>false</haEntrado>
+                <retrasoEntrada>0</retrasoEntrada>
+                <horaSalida>4560</horaSalida>
+                <horaSalidaReal>4580</horaSalidaReal>
+                <haSalido>false</haSalido>
+                <retrasoSalida>0</retrasoSalida>
+                <horaInicioDetencion>0</horaInicioDetencion>
+                <duracionDetencion>0</duracionDetencion>
+                <viaEntradaMallas>1</viaEntradaMallas>
+                <viaCirculacionMallas>A</viaCirculacionMallas>
+                <sentido>0</sentido>
+                <tipoParada>5</tipoParada>
+                <paridad>even</paridad>
+            </pasoTren>
+            <pasoTren>
+                <tipoCambio>11</tipoCambio>
+                <estacion>40</estacion>
+                <idPaso>entry-004</idPaso>
+                <horaEntrada>5000</horaEntrada>
+                <horaEntradaReal>5040</horaEntradaReal>
+                <haEntrado>false</haEntrado>
+                <retrasoEntrada>0</retrasoEntrada>
+                <horaSalida>5000</horaSalida>
+                <horaSalidaReal>-1</horaSalidaReal>
+                <haSalido>false</haSalido>
+                <retrasoSalida>0</retrasoSalida>
+                <horaInicioDetencion>0</horaInicioDetencion>
+                <duracionDetencion>0</duracionDetencion>
+                <viaEntradaMallas>1</viaEntradaMallas>
+                <viaCirculacionMallas>A</viaCirculacionMallas>
+                <sentido>0</sentido>
+                <tipoParada>4</tipoParada>
+                <paridad>even</paridad>
+            </pasoTren>
+        </ActualizarDatosTren>
+    </CCO>";
+
+    fn trip() -> TripDescriptor {
+        TripDescriptor {
+            trip_id: "50-EAST-201-1234".to_string(),
+            start_date: "20250802".to_string(),
+            start_time: "08:00:00".to_string(),
+            train_id: "1234".to_string(),
+            alias_train_id: None,
+        }
+    }
+
+    fn stop_ids() -> HashMap<u32, String> {
+        HashMap::from([
+            (0, "133-a".to_string()),
+            (19, "9218-a".to_string()),
+            (40, "134-a".to_string()),
+        ])
+    }
+
+    // Should build stop-time updates with delays for each mapped station.
+    #[test]
+    fn multi_station() {
+        let message: R9kMessage = quick_xml::de::from_str(XML).expect("should deserialize");
+        let update = message.train_update;
+        let trip_update = update.trip_update(trip(), &stop_ids()).expect("should have trip update");
+
+        assert_eq!(trip_update.trip.trip_id, "50-EAST-201-1234");
+        assert_eq!(trip_update.trip.start_date, "20250802");
+
+        // station 101 is not mapped to a stop
+        let stops: Vec<&str> =
+            trip_update.stop_time_update.iter().map(|stu| stu.stop_id.as_str()).collect();
+        assert_eq!(stops, ["133-a", "9218-a", "134-a"]);
+
+        let delays: Vec<(Option<i32>, Option<i32>)> = trip_update
+            .stop_time_update
+            .iter()
+            .map(|stu| (stu.arrival.map(|a| a.delay), stu.departure.map(|d| d.delay)))
+            .collect();
+        assert_eq!(delays, [(Some(20), Some(30)), (Some(-10), Some(20)), (Some(40), None)]);
+
+        let midnight_ts = update.midnight_ts().expect("midnight");
+        let departure = trip_update.stop_time_update[0].departure.expect("departure");
+        assert_eq!(departure.time, Some(midnight_ts + 3730));
+        assert_eq!(trip_update.timestamp, Some(midnight_ts + 3730));
+    }
+
+    // Should carry the train's other id only when given one.
+    #[test]
+    fn alias_train_id() {
+        let message: R9kMessage = quick_xml::de::from_str(XML).expect("should deserialize");
+        let update = message.train_update;
+
+        let trip_update = update.trip_update(trip(), &stop_ids()).expect("should have trip update");
+        let json = serde_json::to_value(&trip_update).expect("should serialize");
+        assert_eq!(json["trip"]["tripId"], "50-EAST-201-1234");
+        assert_eq!(json["trip"]["trainId"], "1234");
+        assert!(json["trip"].get("aliasTrainId").is_none());
+
+        let aliased = TripDescriptor { alias_train_id: Some("1235".to_string()), ..trip() };
+        let trip_update =
+            update.trip_update(aliased, &stop_ids()).expect("should have trip update");
+        let json = serde_json::to_value(&trip_update).expect("should serialize");
+        assert_eq!(json["trip"]["aliasTrainId"], "1235");
+    }
+
+    // Should not build a trip update when no station maps to a stop.
+    #[test]
+    fn unmapped_stations() {
+        let xml = include_str!("../data/sample.xml");
+        let message: R9kMessage = quick_xml::de::from_str(xml).expect("should deserialize");
+        assert!(message.train_update.trip_update(trip(), &stop_ids()).is_none());
+    }
+}
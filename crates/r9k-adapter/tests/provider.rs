@@ -2,6 +2,7 @@
 
 use core::panic;
 use std::any::Any;
+use std::collections::HashMap;
 use std::error::Error;
 use std::sync::{Arc, Mutex};
 
@@ -10,8 +11,9 @@ use augentic_test::{Fetcher, Fixture, PreparedTestCase, TestDef, TestResult};
 use bytes::Bytes;
 use chrono::{Timelike, Utc};
 use chrono_tz::Pacific::Auckland;
+use http::header::CACHE_CONTROL;
 use http::{Request, Response};
-use qwasr_sdk::{Config, HttpRequest, Identity, Message, Publisher};
+use qwasr_sdk::{Config, HttpRequest, Identity, Message, Publisher, StateStore};
 use r9k_adapter::{R9kMessage, SmarTrakEvent};
 use serde::Deserialize;
 
@@ -92,6 +94,10 @@ impl Fixture for Replay {
 pub struct MockProvider {
     test_case: PreparedTestCase<Replay>,
     events: Arc<Mutex<Vec<SmarTrakEvent>>>,
+    config: HashMap<String, String>,
+    store: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    cache_control: Arc<Mutex<Vec<String>>>,
+    failing: bool,
 }
 
 impl MockProvider {
@@ -105,12 +111,59 @@ impl MockProvider {
     #[allow(dead_code)]
     #[must_use]
     pub fn new(test_case: PreparedTestCase<Replay>) -> Self {
-        Self { test_case, events: Arc::new(Mutex::new(Vec::new())) }
+        Self {
+            test_case,
+            events: Arc::new(Mutex::new(Vec::new())),
+            config: HashMap::new(),
+            store: Arc::new(Mutex::new(HashMap::new())),
+            cache_control: Arc::new(Mutex::new(Vec::new())),
+            failing: false,
+        }
+    }
+
+    /// Fail every publish.
+    #[allow(dead_code)]
+    #[must_use]
+    pub const fn with_publish_failure(mut self) -> Self {
+        self.failing = true;
+        self
+    }
+
+    /// `Cache-Control` header of each HTTP request made, in order.
+    #[allow(clippy::missing_panics_doc)]
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn cache_control(&self) -> Vec<String> {
+        self.cache_control.lock().expect("should lock").clone()
+    }
+
+    /// Set a configuration value.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn with_config(mut self, key: &str, value: &str) -> Self {
+        self.config.insert(key.to_string(), value.to_string());
+        self
     }
 }
 
 impl Config for MockProvider {
-    async fn get(&self, _key: &str) -> Result<String> {
+    async fn get(&self, key: &str) -> Result<String> {
+        if let Some(value) = self.config.get(key) {
+            return Ok(value.clone());
+        }
+        // trip updates are not published, no stations or stop types ignored,
+        // no train id preference and no station map set by default
+        if matches!(
+            key,
+            "R9K_TRIP_UPDATE_TOPIC"
+                | "R9K_IGNORED_STATIONS"
+                | "R9K_TRAIN_ID_PREFERENCE"
+                | "R9K_STATION_MAP_URL"
+                | "R9K_DEDUPE_WINDOW_SECS"
+                | "R9K_STOP_TYPES"
+        ) {
+            return Err(anyhow!("{key} not set"));
+        }
         // BLOCK_MGT_URL, CC_STATIC_URL
         Ok("http://localhost:8080".to_string())
     }
@@ -123,6 +176,10 @@ impl HttpRequest for MockProvider {
         T::Data: Into<Vec<u8>>,
         T::Error: Into<Box<dyn Error + Send + Sync + 'static>>,
     {
+        if let Some(cache_control) = request.headers().get(CACHE_CONTROL) {
+            let cache_control = cache_control.to_str()?.to_string();
+            self.cache_control.lock().map_err(|e| anyhow!("{e}"))?.push(cache_control);
+        }
         let Some(http_requests) = &self.test_case.http_requests else {
             return Err(anyhow!("no http requests defined in replay session"));
         };
@@ -133,6 +190,9 @@ impl HttpRequest for MockProvider {
 
 impl Publisher for MockProvider {
     async fn send(&self, _topic: &str, message: &Message) -> Result<()> {
+        if self.failing {
+            return Err(anyhow!("publish unavailable"));
+        }
         let event: SmarTrakEvent =
             serde_json::from_slice(&message.payload).context("deserializing event")?;
         self.events.lock().map_err(|e| anyhow!("{e}"))?.push(event);
@@ -140,6 +200,23 @@ impl Publisher for MockProvider {
     }
 }
 
+impl StateStore for MockProvider {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.store.lock().map_err(|e| anyhow!("{e}"))?.get(key).cloned())
+    }
+
+    async fn set(
+        &self, key: &str, value: &[u8], _ttl_secs: Option<u64>,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self.store.lock().map_err(|e| anyhow!("{e}"))?.insert(key.to_string(), value.to_vec()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.store.lock().map_err(|e| anyhow!("{e}"))?.remove(key);
+        Ok(())
+    }
+}
+
 impl Identity for MockProvider {
     async fn access_token(&self, _identity: String) -> Result<String> {
         Ok("mock_access_token".to_string())
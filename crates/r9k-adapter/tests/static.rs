@@ -7,11 +7,15 @@ use std::fs::File;
 use std::ops::Sub;
 
 use augentic_test::{TestCase, TestDef};
-use chrono::{Duration, Timelike, Utc};
+use chrono::{Duration, NaiveDate, Timelike, Utc};
 use chrono_tz::Pacific::Auckland;
+use common::outcome::ProcessResult;
 use qwasr_sdk::Error;
 use qwasr_sdk::api::Client;
-use r9k_adapter::{ChangeType, EventType, R9kMessage};
+use r9k_adapter::{
+    ChangeType, EventType, Parity, R9kMessage, StopType, VehicleStopStatus, process,
+    resolve_trip_update, station_map,
+};
 
 use self::provider::MockProvider;
 use crate::provider::{Replay, shift_time};
@@ -53,6 +57,10 @@ async fn arrival_event() {
     assert!(event.location_data.latitude.eq(&-36.12345));
     assert!(event.location_data.longitude.eq(&174.12345));
     assert_eq!(event.remote_data.external_id, "vehicle1");
+
+    // stopped at Britomart
+    assert_eq!(event.stop_id.as_deref(), Some("133-a"));
+    assert_eq!(event.current_status, Some(VehicleStopStatus::StoppedAt));
 }
 
 // Should create a departure event with an stop location updated.
@@ -77,6 +85,67 @@ async fn departure_event() {
     // confirm departure location has been updated
     assert!(event.location_data.latitude.eq(&-36.84448));
     assert!(event.location_data.longitude.eq(&174.76915));
+
+    // the update doesn't include the next station
+    assert_eq!(event.stop_id, None);
+    assert_eq!(event.current_status, None);
+}
+
+// Should report a departed train in transit to the next station's stop.
+#[tokio::test]
+async fn in_transit_to_next_stop() {
+    let file = File::open("data/static/0013.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.as_ref().expect("should have input message").clone();
+    let provider = MockProvider::new(test_case);
+
+    let client = Client::new("at").provider(provider.clone());
+    client.request(message).await.expect("should process");
+
+    // departed Britomart, heading for Middlemore
+    let event = &provider.events()[0];
+    assert_eq!(event.stop_id.as_deref(), Some("9218-a"));
+    assert_eq!(event.current_status, Some(VehicleStopStatus::InTransitTo));
+}
+
+// Should tag events for a train passing the station without stopping.
+#[tokio::test]
+async fn passed_without_stopping() {
+    let file = File::open("data/static/0001.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let mut message = test_case.input.as_ref().expect("should have input message").clone();
+    let provider = MockProvider::new(test_case);
+
+    // tipoCambio 5
+    message.train_update.changes[0].r#type = ChangeType::PassedStationWithoutStopping;
+
+    let client = Client::new("at").provider(provider.clone());
+    client.request(message).await.expect("should process");
+
+    let events = provider.events();
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|event| event.skipped));
+}
+
+// Should not tag events for a train stopping at the station.
+#[tokio::test]
+async fn stopped_at_station() {
+    let file = File::open("data/static/0001.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.as_ref().expect("should have input message").clone();
+    let provider = MockProvider::new(test_case);
+
+    let client = Client::new("at").provider(provider.clone());
+    client.request(message).await.expect("should process");
+
+    let payload = serde_json::to_value(&provider.events()[0]).expect("should serialize");
+    assert!(payload.get("skipped").is_none());
 }
 
 // Should return no events for an unmapped station.
@@ -96,6 +165,23 @@ async fn unmapped_station() {
     assert!(events.is_empty());
 }
 
+// Should return no events for a station on the ignore list.
+#[tokio::test]
+async fn ignored_station() {
+    let file = File::open("data/static/0001.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.as_ref().expect("should have input message").clone();
+    let provider = MockProvider::new(test_case).with_config("R9K_IGNORED_STATIONS", "0");
+
+    let client = Client::new("at").provider(provider.clone());
+    client.request(message).await.expect("should process");
+
+    let events = provider.events();
+    assert!(events.is_empty());
+}
+
 // Should return no events when there are no vehicles found for the train id.
 #[tokio::test]
 async fn no_matching_vehicle() {
@@ -259,6 +345,212 @@ async fn too_early() {
     }
 }
 
+// Should report events published, skips with their reason, and rejections.
+#[tokio::test]
+async fn outcomes() {
+    let test_case = |path: &str| {
+        let file = File::open(path).expect("should open file");
+        let test_def: TestDef<Error> =
+            serde_json::from_reader(&file).expect("should deserialize test file");
+        TestCase::<Replay>::new(test_def).prepare(shift_time)
+    };
+
+    // one vehicle, published twice
+    let arrival = test_case("data/static/0001.json");
+    let message = arrival.input.clone().expect("should have input message");
+    let outcome = process("at", message.clone(), &MockProvider::new(arrival.clone()))
+        .await
+        .expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(2)));
+
+    let ignoring = MockProvider::new(arrival).with_config("R9K_IGNORED_STATIONS", "0");
+    let outcome = process("at", message, &ignoring).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Skipped("ignored station")));
+
+    let unmapped = test_case("data/static/0003.json");
+    let message = unmapped.input.clone().expect("should have input message");
+    let outcome =
+        process("at", message, &MockProvider::new(unmapped)).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Skipped("irrelevant station")));
+
+    let too_late = test_case("data/static/0009.json");
+    let message = too_late.input.clone().expect("should have input message");
+    process("at", message, &MockProvider::new(too_late)).await.expect_err("should reject");
+}
+
+// Should publish a change once when KiwiRail resends it under the train's
+// other parity id within the deduplication window.
+#[tokio::test]
+async fn resent_under_other_parity() {
+    let file = File::open("data/static/0001.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.clone().expect("should have input message");
+    let provider = MockProvider::new(test_case).with_config("R9K_DEDUPE_WINDOW_SECS", "300");
+
+    let mut even = message.clone();
+    even.train_update.even_train_id = Some(message.train_update.train_id());
+    even.train_update.odd_train_id = None;
+    let outcome = process("at", even, &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(2)));
+
+    let mut odd = message.clone();
+    odd.train_update.even_train_id = None;
+    odd.train_update.odd_train_id = Some(message.train_update.train_id());
+    let outcome = process("at", odd, &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Skipped("duplicate update")));
+    assert_eq!(provider.events().len(), 2);
+
+    // without a window, resends are published again
+    let provider = provider.with_config("R9K_DEDUPE_WINDOW_SECS", "0");
+    let outcome = process("at", message, &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(2)));
+}
+
+// Should publish a change again when it failed to publish the first time.
+#[tokio::test]
+async fn resent_after_failure() {
+    let file = File::open("data/static/0001.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.clone().expect("should have input message");
+    let provider = MockProvider::new(test_case).with_config("R9K_DEDUPE_WINDOW_SECS", "300");
+
+    let failing = provider.clone().with_publish_failure();
+    process("at", message.clone(), &failing).await.expect_err("should fail to publish");
+
+    let outcome = process("at", message, &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(2)));
+    assert_eq!(provider.events().len(), 2);
+}
+
+// Should only produce events for the configured stop types.
+#[tokio::test]
+async fn filtered_stop_type() {
+    let file = File::open("data/static/0001.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.clone().expect("should have input message");
+    let provider = MockProvider::new(test_case).with_config("R9K_STOP_TYPES", "intermediate");
+
+    // tipoParada 4
+    let outcome = process("at", message.clone(), &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Skipped("ignored stop type")));
+    assert!(provider.events().is_empty());
+
+    // tipoParada 5
+    let mut intermediate = message;
+    intermediate.train_update.changes[0].stop_type = StopType::Intermediate;
+    let outcome = process("at", intermediate, &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(2)));
+}
+
+// Should resolve stations through the loaded map, leaving others unmapped.
+#[tokio::test]
+async fn station_map_loaded() {
+    let file = File::open("data/static/0011.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let provider = MockProvider::new(test_case)
+        .with_config("R9K_STATION_MAP_URL", "http://localhost:8080/stations.json");
+
+    let map = station_map(&provider, false).await.expect("should load").expect("map");
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.stop_code(19, Parity::Even), Some("9218"));
+    assert_eq!(map.stop_code(101, Parity::Even), None);
+
+    station_map(&provider, true).await.expect("should refresh");
+    assert_eq!(provider.cache_control(), ["max-age=300", "no-cache"]);
+}
+
+// Should fall back to the built-in mapping when no map is configured.
+#[tokio::test]
+async fn station_map_not_configured() {
+    let file = File::open("data/static/0011.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let provider = MockProvider::new(test_case);
+
+    assert!(station_map(&provider, false).await.expect("should load").is_none());
+    assert!(provider.cache_control().is_empty());
+}
+
+// Should fall back to the built-in mapping when the configured map can't be
+// loaded, rather than failing every update.
+#[tokio::test]
+async fn station_map_unavailable() {
+    let file = File::open("data/static/0011.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.clone().expect("should have input message");
+    let provider = MockProvider::new(test_case)
+        .with_config("R9K_STATION_MAP_URL", "http://localhost:8080/missing.json");
+
+    let outcome = process("at", message, &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(2)));
+    assert_eq!(provider.events().len(), 2);
+}
+
+// Should identify the trip and its stops by their GTFS ids, leaving out
+// ignored stations.
+#[tokio::test]
+async fn trip_update_gtfs_ids() {
+    let file = File::open("data/static/0012.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.clone().expect("should have input message");
+    let provider = MockProvider::new(test_case);
+
+    // the train is allocated on the fixture's service date
+    let mut update = message.train_update;
+    update.created_date = NaiveDate::from_ymd_opt(2026, 1, 20).expect("date");
+
+    let trip_update = resolve_trip_update(&update, Parity::Even, false, &[], &provider)
+        .await
+        .expect("should resolve")
+        .expect("should have trip update");
+    assert_eq!(trip_update.trip.trip_id, "50-EAST-201-5226");
+    assert_eq!(trip_update.trip.start_date, "20260120");
+    assert_eq!(trip_update.trip.start_time, "13:15:00");
+    assert_eq!(trip_update.trip.train_id, "5226");
+    assert!(trip_update.stop_time_update.iter().all(|stu| stu.stop_id == "133-a"));
+
+    let ignored = resolve_trip_update(&update, Parity::Even, false, &[0], &provider)
+        .await
+        .expect("should resolve");
+    assert!(ignored.is_none());
+
+    update.created_date = NaiveDate::from_ymd_opt(2026, 1, 21).expect("date");
+    let unallocated = resolve_trip_update(&update, Parity::Even, false, &[], &provider)
+        .await
+        .expect("should resolve");
+    assert!(unallocated.is_none());
+}
+
+// Should still publish SmarTrak events when the trip update can't be
+// resolved.
+#[tokio::test]
+async fn trip_update_failure() {
+    let file = File::open("data/static/0001.json").expect("should open file");
+    let test_def: TestDef<Error> =
+        serde_json::from_reader(&file).expect("should deserialize test file");
+    let test_case = TestCase::<Replay>::new(test_def).prepare(shift_time);
+    let message = test_case.input.clone().expect("should have input message");
+    let provider = MockProvider::new(test_case)
+        .with_config("R9K_TRIP_UPDATE_TOPIC", "realtime-r9k-trip-updates.v1");
+
+    let outcome = process("at", message, &provider).await.expect("should process");
+    assert!(matches!(outcome, ProcessResult::Emitted(2)));
+    assert_eq!(provider.events().len(), 2);
+}
+
 struct XmlBuilder<'a> {
     station: u64,
     vehicle: &'a str,
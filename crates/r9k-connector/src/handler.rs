@@ -10,6 +10,8 @@ use qwasr_sdk::api::{Context, Handler, Reply};
 use qwasr_sdk::{Config, Error, IntoBody, Message, Publisher, Result, bad_request};
 use serde::{Deserialize, Serialize};
 
+use crate::R9kError;
+
 const R9K_TOPIC: &str = "realtime-r9k.v1";
 const ERROR: Fault =
     Fault { status_code: 500, response: FaultMessage { message: "Internal Server Error" } };
@@ -21,7 +23,7 @@ where
 {
     let message = &request.body.receive_message.axml_message;
 
-    // verify message
+    // verify message, replying with the SOAP fault KiwiRail expects
     if message.is_empty() || !message.contains("<ActualizarDatosTren>") {
         return Err(bad_request!("{ERROR}"));
     }
@@ -51,8 +53,7 @@ where
 
     fn from_input(input: Vec<u8>) -> Result<Self> {
         quick_xml::de::from_reader(input.as_slice())
-            .context("deserializing R9kRequest")
-            .map_err(Into::into)
+            .map_err(|err| R9kError::InvalidXml(err.to_string()).into())
     }
 
     // TODO: implement "owner"
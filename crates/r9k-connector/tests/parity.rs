@@ -0,0 +1,60 @@
+//! Tests that the R9K connector and adapter report the shared errors for the
+//! same conditions.
+
+mod provider;
+
+use qwasr_sdk::Handler;
+use r9k_adapter::R9kMessage;
+use r9k_connector::{R9kError, R9kErrorKind, R9kRequest};
+
+use self::provider::MockProvider;
+
+fn envelope(message: &str) -> Vec<u8> {
+    format!(
+        "<Envelope><Body><ReceiveMessage><AXMLMessage><![CDATA[{message}]]></AXMLMessage>\
+         </ReceiveMessage></Body></Envelope>"
+    )
+    .into_bytes()
+}
+
+// Should reply to a message without a train update with the SOAP fault,
+// leaving the adapter to report why.
+#[tokio::test]
+async fn no_update() {
+    let message = "<CCO></CCO>";
+    let provider = MockProvider::default();
+
+    let connector_err = R9kRequest::handler(envelope(message))
+        .expect("should deserialize")
+        .provider(&provider)
+        .owner("at")
+        .await
+        .expect_err("connector should reject message");
+    assert!(provider.published().is_empty());
+    assert!(connector_err.description().starts_with("<Fault>"));
+
+    let update: R9kMessage = quick_xml::de::from_str(message).expect("should deserialize");
+    let adapter_err = update.train_update.validate().expect_err("adapter should reject message");
+    assert_eq!(adapter_err.kind(), R9kErrorKind::NoUpdate);
+    assert_eq!(adapter_err.code(), R9kError::NoUpdate(String::new()).code());
+}
+
+// Should report the shared code for an outdated message.
+#[test]
+fn bad_time() {
+    let message: R9kMessage =
+        quick_xml::de::from_str(include_str!("../../r9k-adapter/data/sample.xml"))
+            .expect("should deserialize");
+    let adapter_err = message.train_update.validate().expect_err("should be outdated");
+
+    assert_eq!(adapter_err.kind(), R9kErrorKind::Outdated);
+    assert_eq!(adapter_err.code(), R9kError::BadTime(String::new()).code());
+}
+
+// Should report malformed XML with the shared code.
+#[test]
+fn invalid_xml() {
+    let err = <R9kRequest as Handler<MockProvider>>::from_input(b"<Envelope>".to_vec())
+        .expect_err("should fail to deserialize");
+    assert_eq!(err.code(), R9kError::InvalidXml(String::new()).code());
+}
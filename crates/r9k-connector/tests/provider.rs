@@ -0,0 +1,33 @@
+#![allow(missing_docs)]
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use qwasr_sdk::{Config, Message, Publisher};
+
+#[derive(Default, Clone)]
+pub struct MockProvider {
+    published: Arc<Mutex<Vec<(String, Message)>>>,
+}
+
+impl MockProvider {
+    #[allow(clippy::missing_panics_doc)]
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn published(&self) -> Vec<(String, Message)> {
+        self.published.lock().expect("lock").clone()
+    }
+}
+
+impl Publisher for MockProvider {
+    async fn send(&self, topic: &str, message: &Message) -> Result<()> {
+        self.published.lock().expect("lock").push((topic.to_string(), message.clone()));
+        Ok(())
+    }
+}
+
+impl Config for MockProvider {
+    async fn get(&self, _key: &str) -> Result<String> {
+        Ok("dev".to_string())
+    }
+}
@@ -0,0 +1,136 @@
+//! Congestion of a vehicle's movement, from its speed relative to the speed
+//! expected on its route.
+//!
+//! Expected speeds are configured with `ROUTE_EXPECTED_SPEEDS`, as
+//! comma-separated `route=km/h` pairs, e.g. `EAST-201=60,WEST-201=50`. No
+//! congestion level is emitted for routes without one, so nothing is emitted
+//! by default.
+//!
+//! A vehicle at or near a standstill may as well be dwelling or held as stuck
+//! in traffic, so it's only rated when reported in transit to its next stop.
+
+use std::fmt::{self, Display};
+
+use common::config;
+use qwasr_sdk::Config;
+
+/// Speed (km/h) below which a vehicle isn't known to be moving.
+const CRAWLING_KMH: f64 = 5.0;
+
+/// GTFS-RT congestion level, displayed as its GTFS-RT name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionLevel {
+    RunningSmoothly,
+    StopAndGo,
+    Congestion,
+    SevereCongestion,
+}
+
+impl CongestionLevel {
+    /// Congestion level for a vehicle moving at `ratio` of its expected speed.
+    const fn from_ratio(ratio: f64) -> Self {
+        if ratio >= 0.75 {
+            Self::RunningSmoothly
+        } else if ratio >= 0.5 {
+            Self::StopAndGo
+        } else if ratio >= 0.25 {
+            Self::Congestion
+        } else {
+            Self::SevereCongestion
+        }
+    }
+
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::RunningSmoothly => "RUNNING_SMOOTHLY",
+            Self::StopAndGo => "STOP_AND_GO",
+            Self::Congestion => "CONGESTION",
+            Self::SevereCongestion => "SEVERE_CONGESTION",
+        }
+    }
+}
+
+impl Display for CongestionLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The congestion level of a vehicle on the route moving at `speed_kmh`,
+/// given the expected speeds in `mapping`.
+fn from_mapping(
+    mapping: &str, route_id: &str, speed_kmh: f64, in_transit: bool,
+) -> Option<CongestionLevel> {
+    if speed_kmh < CRAWLING_KMH && !in_transit {
+        return None;
+    }
+    let expected = config::pairs(mapping)
+        .find(|(route, _)| *route == route_id)
+        .and_then(|(_, speed)| speed.parse::<f64>().ok())
+        .filter(|expected| *expected > 0.0)?;
+    Some(CongestionLevel::from_ratio(speed_kmh / expected))
+}
+
+/// The congestion level of a vehicle on the route moving at `speed_kmh`, if
+/// the route has an expected speed in `ROUTE_EXPECTED_SPEEDS`. A vehicle
+/// barely moving is only rated when `in_transit` to its next stop.
+pub async fn level(
+    provider: &impl Config, route_id: &str, speed_kmh: f64, in_transit: bool,
+) -> Option<CongestionLevel> {
+    let mapping = Config::get(provider, "ROUTE_EXPECTED_SPEEDS").await.ok()?;
+    from_mapping(&mapping, route_id, speed_kmh, in_transit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPING: &str = "EAST-201=80, WEST-201 = 50,ONE=0";
+
+    // Should rate a vehicle crawling on a fast route as congested.
+    #[test]
+    fn slow_on_fast_route() {
+        let cases = [
+            (70.0, CongestionLevel::RunningSmoothly),
+            (50.0, CongestionLevel::StopAndGo),
+            (25.0, CongestionLevel::Congestion),
+            (10.0, CongestionLevel::SevereCongestion),
+        ];
+        for (speed, level) in cases {
+            assert_eq!(
+                from_mapping(MAPPING, "EAST-201", speed, false),
+                Some(level),
+                "speed {speed}"
+            );
+        }
+        assert_eq!(
+            from_mapping(MAPPING, "WEST-201", 50.0, false),
+            Some(CongestionLevel::RunningSmoothly)
+        );
+        assert_eq!(CongestionLevel::SevereCongestion.to_string(), "SEVERE_CONGESTION");
+    }
+
+    // Should not rate routes without a usable expected speed.
+    #[test]
+    fn unconfigured_route() {
+        assert_eq!(from_mapping(MAPPING, "STH-201", 10.0, true), None);
+        assert_eq!(from_mapping(MAPPING, "ONE", 10.0, true), None);
+        assert_eq!(from_mapping("", "EAST-201", 10.0, true), None);
+    }
+
+    // Should rate a vehicle at a standstill only when it's known to be in
+    // transit, rather than dwelling or held.
+    #[test]
+    fn standstill() {
+        assert_eq!(from_mapping(MAPPING, "EAST-201", 0.0, false), None);
+        assert_eq!(from_mapping(MAPPING, "EAST-201", 4.0, false), None);
+        assert_eq!(
+            from_mapping(MAPPING, "EAST-201", 0.0, true),
+            Some(CongestionLevel::SevereCongestion)
+        );
+        assert_eq!(
+            from_mapping(MAPPING, "EAST-201", 10.0, false),
+            Some(CongestionLevel::SevereCongestion)
+        );
+    }
+}
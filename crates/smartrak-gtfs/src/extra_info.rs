@@ -0,0 +1,91 @@
+//! Vehicle status encoded by some AVL sources in a location event's
+//! `extraInfo`.
+//!
+//! Codes differ between sources and none are assumed, so `extraInfo` is
+//! ignored unless its codes are mapped with `SMARTRAK_EXTRA_INFO_MAP`, as
+//! comma-separated `code=state` pairs taken from the source's documentation,
+//! e.g. `D1=DOORS_OPEN,D0=DOORS_CLOSED`. States are `DOORS_OPEN`,
+//! `DOORS_CLOSED`, `ENGINE_ON` and `ENGINE_OFF`. An `extraInfo` value holds
+//! codes separated by commas, semicolons or whitespace; unmapped codes are
+//! ignored.
+
+use common::config;
+use qwasr_sdk::Config;
+
+/// Door and engine state of a vehicle, where its source reported them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtraInfo {
+    pub doors_open: Option<bool>,
+    pub engine_on: Option<bool>,
+}
+
+impl ExtraInfo {
+    /// Parse the codes in an `extraInfo` value mapped by `mapping`, matched
+    /// case-insensitively.
+    #[must_use]
+    pub fn parse(mapping: &str, value: &str) -> Self {
+        let mut info = Self::default();
+        for code in value.split([',', ';', ' ', '\t']).filter(|code| !code.is_empty()) {
+            let Some((_, state)) =
+                config::pairs(mapping).find(|(mapped, _)| mapped.eq_ignore_ascii_case(code))
+            else {
+                continue;
+            };
+            match state.to_ascii_uppercase().as_str() {
+                "DOORS_OPEN" => info.doors_open = Some(true),
+                "DOORS_CLOSED" => info.doors_open = Some(false),
+                "ENGINE_ON" => info.engine_on = Some(true),
+                "ENGINE_OFF" => info.engine_on = Some(false),
+                _ => tracing::warn!(code, state, "unknown extraInfo state"),
+            }
+        }
+        info
+    }
+
+    /// Whether occupancy should be withheld from the position of a vehicle
+    /// moving with its doors known to be closed. A vehicle with its engine
+    /// known to be off isn't in transit, whatever its reported speed.
+    #[must_use]
+    pub const fn suppresses_occupancy(self, moving: bool) -> bool {
+        moving && !matches!(self.engine_on, Some(false)) && matches!(self.doors_open, Some(false))
+    }
+}
+
+/// The vehicle status in `extra_info`, when its codes are mapped with
+/// `SMARTRAK_EXTRA_INFO_MAP`.
+pub async fn load(provider: &impl Config, extra_info: Option<&str>) -> Option<ExtraInfo> {
+    let extra_info = extra_info?;
+    let mapping = Config::get(provider, "SMARTRAK_EXTRA_INFO_MAP").await.ok()?;
+    Some(ExtraInfo::parse(&mapping, extra_info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPING: &str = "D1=DOORS_OPEN,D0=DOORS_CLOSED,IGN1=ENGINE_ON,IGN0=ENGINE_OFF";
+
+    // Should parse mapped codes, ignoring case and unmapped codes.
+    #[test]
+    fn mapped_codes() {
+        let info = ExtraInfo::parse(MAPPING, "d0; IGN1,GPS1");
+        assert_eq!(info, ExtraInfo { doors_open: Some(false), engine_on: Some(true) });
+        assert_eq!(ExtraInfo::parse(MAPPING, "GPS1"), ExtraInfo::default());
+        assert_eq!(ExtraInfo::parse(MAPPING, ""), ExtraInfo::default());
+        assert_eq!(ExtraInfo::parse("", "D0"), ExtraInfo::default());
+        assert_eq!(ExtraInfo::parse("D0=SHUT", "D0"), ExtraInfo::default());
+    }
+
+    // Should only suppress occupancy in transit with the doors known closed.
+    #[test]
+    fn suppressed_occupancy() {
+        let closed = ExtraInfo::parse(MAPPING, "D0");
+        assert!(closed.suppresses_occupancy(true));
+        assert!(!closed.suppresses_occupancy(false));
+        assert!(!ExtraInfo::parse(MAPPING, "D0,IGN0").suppresses_occupancy(true));
+
+        let open = ExtraInfo::parse(MAPPING, "D1");
+        assert!(!open.suppresses_occupancy(true));
+        assert!(!ExtraInfo::parse(MAPPING, "IGN1").suppresses_occupancy(true));
+    }
+}
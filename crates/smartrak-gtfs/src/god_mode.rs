@@ -1,35 +1,9 @@
-use std::collections::HashMap;
-
-use anyhow::{Context, Result};
+use anyhow::Result;
+use common::god_mode::{self, GodModeState, TripOverride, load_state, save_state};
 use qwasr_sdk::{Config, StateStore};
-use serde::{Deserialize, Serialize};
 
 use crate::{EventType, SmarTrakMessage};
 
-const KEY_GOD_MODE: &str = "god_mode:overrides";
-const TTL_GOD_MODE: u64 = 24 * 60 * 60; // 24 hours
-
-#[derive(Debug, Default, Serialize, Deserialize)]
-struct GodModeState {
-    overrides: HashMap<String, String>,
-}
-
-/// Load the current God Mode state from the state store.
-async fn load_state(state_store: &impl StateStore) -> Result<GodModeState> {
-    let Some(bytes) = state_store.get(KEY_GOD_MODE).await? else {
-        return Ok(GodModeState::default());
-    };
-    let state = serde_json::from_slice(&bytes).context("deserializing god mode state")?;
-    Ok(state)
-}
-
-/// Save the current God Mode state to the state store.
-async fn save_state(state_store: &impl StateStore, state: &GodModeState) -> Result<()> {
-    let bytes = serde_json::to_vec(state).context("serializing god mode state")?;
-    state_store.set(KEY_GOD_MODE, &bytes, Some(TTL_GOD_MODE)).await?;
-    Ok(())
-}
-
 /// Reset all vehicle overrides.
 ///
 /// # Errors
@@ -85,11 +59,7 @@ pub async fn preprocess(state_store: &impl StateStore, event: &mut SmarTrakMessa
         return Ok(());
     }
 
-    let Some(remote_data) = event.remote_data.as_ref() else {
-        return Ok(());
-    };
-
-    let Some(vehicle_id) = remote_data.external_id.as_deref() else {
+    let Some(vehicle_id) = event.vehicle_identifier().map(ToString::to_string) else {
         return Ok(());
     };
 
@@ -101,16 +71,18 @@ pub async fn preprocess(state_store: &impl StateStore, event: &mut SmarTrakMessa
         return Ok(());
     };
 
-    let state = load_state(state_store).await?;
-    if let Some(override_trip) = state.overrides.get(vehicle_id) {
+    if let Some(trip_override) = god_mode::trip_override(state_store, &vehicle_id).await? {
         decoded.line_id = None;
 
-        if override_trip == "empty" {
-            decoded.trip_id = None;
-            decoded.trip_number = None;
-        } else {
-            decoded.trip_id = Some(override_trip.clone());
-            decoded.trip_number = Some(override_trip.clone());
+        match trip_override {
+            TripOverride::NoTrip => {
+                decoded.trip_id = None;
+                decoded.trip_number = None;
+            }
+            TripOverride::Trip(trip_id) => {
+                decoded.trip_id = Some(trip_id.clone());
+                decoded.trip_number = Some(trip_id);
+            }
         }
     }
 
@@ -123,8 +95,67 @@ pub async fn preprocess(state_store: &impl StateStore, event: &mut SmarTrakMessa
 ///
 /// Returns an error if the configuration cannot be read.
 pub async fn is_enabled(provider: &impl Config) -> Result<bool> {
-    Ok(Config::get(provider, "GOD_MODE_ENABLED").await.ok().is_some_and(|value| {
-        let normalized = value.trim().to_ascii_lowercase();
-        matches!(normalized.as_str(), "1" | "true" | "yes" | "on")
-    }))
+    Ok(god_mode::is_enabled(provider).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::anyhow;
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    #[derive(Default, Clone)]
+    struct MockStore(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+
+    impl StateStore for MockStore {
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().map_err(|e| anyhow!("{e}"))?.get(key).cloned())
+        }
+
+        async fn set(
+            &self, key: &str, value: &[u8], _ttl_secs: Option<u64>,
+        ) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().map_err(|e| anyhow!("{e}"))?.insert(key.to_string(), value.to_vec()))
+        }
+
+        async fn delete(&self, key: &str) -> Result<()> {
+            self.0.lock().map_err(|e| anyhow!("{e}"))?.remove(key);
+            Ok(())
+        }
+    }
+
+    async fn vehicle_state(store: &MockStore) -> Value {
+        let bytes = store.get("apc:vehicleIdState:59").await.expect("get").expect("state");
+        serde_json::from_slice(&bytes).expect("json")
+    }
+
+    async fn seed(store: &MockStore) {
+        let state = json!({
+            "count": 42,
+            "token": 1_762_469_343,
+            "last_trip_id": "1234-56789-70000",
+            "occupancy_status": "2",
+            "occupancy_percentage": 35,
+        });
+        let bytes = serde_json::to_vec(&state).expect("serialize");
+        store.set("apc:vehicleIdState:59", &bytes, None).await.expect("set");
+        store.set("apc:vehicleId:59", b"42", None).await.expect("set");
+        set_vehicle_to_trip(store, "59", "1234-56789-70000").await.expect("override");
+    }
+
+    // Should clear the trip override, leaving the passenger count alone.
+    #[tokio::test]
+    async fn reset_override() {
+        let store = MockStore::default();
+        seed(&store).await;
+
+        reset_vehicle(&store, "59").await.expect("reset");
+
+        assert!(god_mode::trip_override(&store, "59").await.expect("override").is_none());
+        assert_eq!(vehicle_state(&store).await["count"], 42);
+    }
 }
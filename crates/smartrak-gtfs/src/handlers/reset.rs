@@ -8,7 +8,30 @@ use serde::{Deserialize, Serialize};
 use crate::god_mode;
 
 #[derive(Debug, Clone, Deserialize)]
-pub struct ResetRequest(String);
+pub struct ResetRequest {
+    vehicle_id: String,
+    scope: ResetScope,
+}
+
+/// Query parameters accepted by the reset endpoint.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ResetQuery {
+    #[serde(default)]
+    pub scope: ResetScope,
+}
+
+/// What a reset clears for a vehicle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetScope {
+    /// Clear the vehicle's trip override.
+    #[default]
+    All,
+
+    /// Zero the vehicle's running passenger count, keeping its trip state.
+    #[serde(rename = "count")]
+    CountOnly,
+}
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ResetReply {
@@ -20,16 +43,25 @@ async fn handle<P>(_owner: &str, request: ResetRequest, provider: &P) -> Result<
 where
     P: HttpRequest + Publisher + StateStore + Identity + Config,
 {
-    let vehicle_id = request.0;
+    let ResetRequest { vehicle_id, scope } = request;
 
     if !god_mode::is_enabled(provider).await? {
         return Err(bad_request!("God mode not enabled"));
     }
 
-    if vehicle_id == "all" {
-        god_mode::reset_all(provider).await.context("resetting all vehicles")?;
-    } else {
-        god_mode::reset_vehicle(provider, &vehicle_id).await.context("resetting vehicle")?;
+    match scope {
+        ResetScope::CountOnly if vehicle_id == "all" => {
+            return Err(bad_request!("count reset requires a vehicle id"));
+        }
+        ResetScope::CountOnly => {
+            dilax_adapter::reset_count(&vehicle_id, provider).await.context("resetting count")?;
+        }
+        ResetScope::All if vehicle_id == "all" => {
+            god_mode::reset_all(provider).await.context("resetting all vehicles")?;
+        }
+        ResetScope::All => {
+            god_mode::reset_vehicle(provider, &vehicle_id).await.context("resetting vehicle")?;
+        }
     }
 
     Ok(ResetReply { message: "Ok".to_string(), process: 0 }.into())
@@ -40,11 +72,11 @@ where
     P: Config + HttpRequest + Identity + Publisher + StateStore,
 {
     type Error = Error;
-    type Input = String;
+    type Input = (String, ResetScope);
     type Output = ResetReply;
 
-    fn from_input(input: String) -> Result<Self> {
-        Ok(Self(input))
+    fn from_input((vehicle_id, scope): (String, ResetScope)) -> Result<Self> {
+        Ok(Self { vehicle_id, scope })
     }
 
     async fn handle(self, ctx: Context<'_, P>) -> Result<Reply<ResetReply>> {
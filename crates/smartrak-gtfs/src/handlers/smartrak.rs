@@ -1,17 +1,40 @@
 use chrono::{DateTime, Utc};
+use common::outcome::{ProcessResult, Skippable};
 use qwasr_sdk::api::{Context, Handler, Reply};
 use qwasr_sdk::{
     Config, HttpRequest, Identity, Message, Publisher, Result, StateStore, bad_request,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::location::Location;
-use crate::{god_mode, location, serial_data};
+use crate::occupancy::{OccupancyMapping, OccupancyStatus};
+use crate::trip::Source;
+use crate::{god_mode, heartbeat, location, routing, serial_data, throttle};
 
-async fn handle<P>(_owner: &str, message: SmarTrakMessage, provider: &P) -> Result<Reply<()>>
+/// Process a SmarTrak event received from `source`, recording the outcome.
+pub(crate) async fn process<P>(
+    message: SmarTrakMessage, source: Source, provider: &P,
+) -> Result<Reply<()>>
 where
     P: Config + HttpRequest + Identity + Publisher + StateStore,
 {
+    ProcessResult::report(process_event(message, source, provider).await, "smartrak")?;
+    Ok(Reply::ok(()))
+}
+
+/// Process a SmarTrak event received from `source`, returning the number of
+/// messages published or why none were.
+async fn process_event<P>(
+    message: SmarTrakMessage, source: Source, provider: &P,
+) -> Result<ProcessResult>
+where
+    P: Config + HttpRequest + Identity + Publisher + StateStore,
+{
+    // signal we're consuming, even when the message produces no output
+    if let Err(err) = heartbeat::beat(provider, Utc::now().timestamp()).await {
+        tracing::warn!("failed to publish heartbeat: {err:#}");
+    }
+
     // serial data event
     if message.event_type == EventType::SerialData {
         let mut message = message.clone();
@@ -19,32 +42,78 @@ where
             god_mode::preprocess(provider, &mut message).await?;
         }
         serial_data::process(&message, provider).await?;
-        return Ok(Reply::ok(()));
+        return Ok(ProcessResult::Skipped("serial data"));
     }
 
     // must be a location event
-    let Some(location) = location::process(&message, provider).await? else {
-        return Ok(Reply::ok(()));
+    let location = match location::process(&message, source, provider).await? {
+        Skippable::Output(location) => location,
+        Skippable::Skipped(reason) => return Ok(ProcessResult::Skipped(reason)),
     };
 
-    let (payload, key, topic) = match location {
-        Location::VehiclePosition(feed) => {
-            (serde_json::to_vec(&feed)?, feed.id, "realtime-gtfs-vp.v1")
+    let Location { mut vehicle_position, dead_reckoning, tag } = location;
+
+    // suppress near-identical positions, publishing if the check fails
+    if let Some(feed) = &vehicle_position
+        && let Some(position) = &feed.vehicle
+    {
+        match throttle::should_publish(provider, &feed.id, position).await {
+            Ok(true) => {}
+            Ok(false) => vehicle_position = None,
+            Err(err) => tracing::warn!("failed to throttle vehicle position: {err:#}"),
         }
-        Location::DeadReckoning(dr) => {
-            (serde_json::to_vec(&dr)?, dr.id, "realtime-dead-reckoning.v1")
+    }
+
+    let mut outbound = Vec::new();
+    if let Some(feed) = vehicle_position {
+        let payload = serde_json::to_vec(&feed)?;
+        for topic in routing::vp_topics(provider, tag.as_deref()).await {
+            outbound.push((payload.clone(), feed.id.clone(), topic));
         }
-    };
 
+        // a consumer with its own occupancy scale gets its own copy, leaving
+        // the shared feed GTFS-RT
+        if let Some(mapping) = OccupancyMapping::load(provider).await {
+            let mut mapped = feed.clone();
+            if let Some(position) = &mut mapped.vehicle {
+                position.occupancy_status =
+                    position.occupancy_status.as_deref().map(|status| mapping.remap(status));
+            }
+            let payload = serde_json::to_vec(&mapped)?;
+            outbound.push((payload, feed.id.clone(), mapping.topic().to_string()));
+        }
+    }
+    if outbound.is_empty() && dead_reckoning.is_none() {
+        return Ok(ProcessResult::Skipped("throttled"));
+    }
+
+    // publish each position to its topic
     let env = Config::get(provider, "ENV").await.unwrap_or_else(|_| "dev".to_string());
-    let topic = format!("{env}-{topic}");
+    let mut emitted = outbound.len();
+    for (payload, key, topic) in outbound {
+        send(provider, &format!("{env}-{topic}"), key, &payload).await?;
+    }
 
-    // publish
-    let mut message = Message::new(&payload);
-    message.headers.insert("key".to_string(), key.clone());
-    Publisher::send(provider, &topic, &message).await?;
+    // dead reckoning only fills gaps, so failing to publish it doesn't fail
+    // the positions already published, which a retry would duplicate
+    if let Some(dr) = dead_reckoning {
+        let topic = format!("{env}-realtime-dead-reckoning.v1");
+        match send(provider, &topic, dr.id.clone(), &serde_json::to_vec(&dr)?).await {
+            Ok(()) => emitted += 1,
+            Err(err) => tracing::warn!(dr_id = %dr.id, "failed to publish dead reckoning: {err:#}"),
+        }
+    }
 
-    Ok(Reply::ok(()))
+    Ok(ProcessResult::Emitted(emitted))
+}
+
+/// Publish the payload to the topic, keyed for partitioning.
+async fn send<P: Publisher>(
+    provider: &P, topic: &str, key: String, payload: &[u8],
+) -> anyhow::Result<()> {
+    let mut message = Message::new(payload);
+    message.headers.insert("key".to_string(), key);
+    Publisher::send(provider, topic, &message).await
 }
 
 impl<P> Handler<P> for SmarTrakMessage
@@ -60,7 +129,7 @@ where
     }
 
     async fn handle(self, ctx: Context<'_, P>) -> Result<Reply<()>> {
-        handle(ctx.owner, self, ctx.provider).await
+        process(self, Source::R9k, ctx.provider).await
     }
 }
 
@@ -76,6 +145,15 @@ pub struct SmarTrakMessage {
     #[serde(default)]
     pub event_data: EventData,
     pub serial_data: Option<SerialData>,
+    /// GTFS stop the event was reported at, set for R9K events.
+    pub stop_id: Option<String>,
+    /// `STOPPED_AT` or `IN_TRANSIT_TO` the stop, set for R9K events.
+    pub current_status: Option<String>,
+    /// Occupancy as reported by the vehicle, in the source's own scheme.
+    pub occupancy: Option<String>,
+    /// Reported occupancy normalised to GTFS-RT, set by the source's handler.
+    #[serde(skip)]
+    pub occupancy_status: Option<OccupancyStatus>,
 }
 
 impl SmarTrakMessage {
@@ -85,23 +163,56 @@ impl SmarTrakMessage {
             .map_err(|e| bad_request!("invalid timestamp: {}", e))
     }
 
-    pub(crate) fn vehicle_id(&self) -> Option<&str> {
-        self.remote_data
-            .as_ref()
-            .and_then(|rd| rd.external_id.as_deref().or(rd.remote_name.as_deref()))
+    /// The vehicle's external id, falling back to its remote name when the
+    /// external id is missing or blank.
+    pub(crate) fn vehicle_identifier(&self) -> Option<&str> {
+        let remote_data = self.remote_data.as_ref()?;
+        let present = |id: &&str| !id.trim().is_empty();
+        remote_data
+            .external_id
+            .as_deref()
+            .filter(present)
+            .or_else(|| remote_data.remote_name.as_deref().filter(present))
     }
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EventType {
-    #[serde(rename = "serialData", alias = "SERIAL_DATA")]
+    /// `serialData`, or `SERIAL_DATA`.
     SerialData,
 
-    #[serde(rename = "location", alias = "LOCATION")]
+    /// `location`, or `LOCATION`.
     Location,
 
-    #[serde(other)]
-    Unknown,
+    /// Any other event type, as received.
+    Unknown(String),
+}
+
+impl EventType {
+    fn as_str(&self) -> &str {
+        match self {
+            Self::SerialData => "serialData",
+            Self::Location => "location",
+            Self::Unknown(raw) => raw,
+        }
+    }
+}
+
+impl Serialize for EventType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for EventType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        Ok(match raw.as_str() {
+            "serialData" | "SERIAL_DATA" => Self::SerialData,
+            "location" | "LOCATION" => Self::Location,
+            _ => Self::Unknown(raw),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -133,6 +244,8 @@ pub struct LocationData {
 #[serde(rename_all = "camelCase")]
 pub struct EventData {
     pub odometer: Option<f64>,
+    /// Source-specific vehicle status codes, see [`crate::extra_info`].
+    pub extra_info: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -151,3 +264,150 @@ pub struct DecodedSerialData {
     #[serde(alias = "lineId")]
     pub line_id: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockProvider};
+
+    fn message(external_id: Option<&str>, remote_name: Option<&str>) -> SmarTrakMessage {
+        serde_json::from_value(serde_json::json!({
+            "eventType": "location",
+            "remoteData": { "externalId": external_id, "remoteName": remote_name },
+            "messageData": { "timestamp": "2025-11-07T08:00:00Z" }
+        }))
+        .expect("should deserialize")
+    }
+
+    // Should identify the vehicle by its external id, then its remote name.
+    #[test]
+    fn vehicle_identifier() {
+        assert_eq!(message(Some("59123"), Some("AMP 123")).vehicle_identifier(), Some("59123"));
+        assert_eq!(message(None, Some("AMP 123")).vehicle_identifier(), Some("AMP 123"));
+        assert_eq!(message(Some(""), Some("AMP 123")).vehicle_identifier(), Some("AMP 123"));
+        assert_eq!(message(Some(" "), Some("AMP 123")).vehicle_identifier(), Some("AMP 123"));
+    }
+
+    // Should keep the raw type of an event type that isn't handled.
+    #[test]
+    fn unknown_event_type() {
+        let message: SmarTrakMessage = serde_json::from_value(serde_json::json!({
+            "eventType": "Heartbeat",
+            "messageData": { "timestamp": "2025-11-07T08:00:00Z" }
+        }))
+        .expect("should deserialize");
+        assert_eq!(message.event_type, EventType::Unknown("Heartbeat".to_string()));
+
+        let value = serde_json::to_value(&message).expect("should serialize");
+        assert_eq!(value["eventType"], "Heartbeat");
+    }
+
+    // Should accept both spellings of handled event types.
+    #[test]
+    fn event_type_aliases() {
+        for (raw, event_type) in [
+            ("serialData", EventType::SerialData),
+            ("SERIAL_DATA", EventType::SerialData),
+            ("location", EventType::Location),
+            ("LOCATION", EventType::Location),
+        ] {
+            let parsed: EventType = serde_json::from_value(raw.into()).expect("should deserialize");
+            assert_eq!(parsed, event_type);
+        }
+    }
+
+    // Should not identify a vehicle without an external id or remote name.
+    #[test]
+    fn no_vehicle_identifier() {
+        assert_eq!(message(Some(""), None).vehicle_identifier(), None);
+        assert_eq!(message(None, Some("")).vehicle_identifier(), None);
+
+        let mut message = message(Some("59123"), None);
+        message.remote_data = None;
+        assert_eq!(message.vehicle_identifier(), None);
+    }
+
+    // Should tag published positions and dead reckoning with the feed the event
+    // was received on.
+    #[tokio::test]
+    async fn source_tag() {
+        let cases = [(Source::R9k, "r9k"), (Source::Caf, "caf"), (Source::SmarTrak, "smartrak")];
+        for (source, tag) in cases {
+            let provider = MockProvider::new().on_trip().with_config("EMIT_DR_WITH_VP", "true");
+            let outcome =
+                process_event(mock::location(), source, &provider).await.expect("should process");
+            assert!(matches!(outcome, ProcessResult::Emitted(2)));
+
+            let positions = provider.payloads("dev-realtime-gtfs-vp.v1");
+            assert_eq!(positions[0]["source"], tag);
+            let dead_reckoning = provider.payloads("dev-realtime-dead-reckoning.v1");
+            assert_eq!(dead_reckoning[0]["source"], tag);
+        }
+    }
+
+    // Should keep the published position when dead reckoning fails to
+    // publish, rather than failing the event.
+    #[tokio::test]
+    async fn dead_reckoning_publish_failure() {
+        let provider = MockProvider::new()
+            .on_trip()
+            .with_config("EMIT_DR_WITH_VP", "true")
+            .with_failing_topic("dev-realtime-dead-reckoning.v1");
+
+        let outcome = process_event(mock::location(), Source::SmarTrak, &provider)
+            .await
+            .expect("should process");
+        assert!(matches!(outcome, ProcessResult::Emitted(1)));
+        assert_eq!(provider.payloads("dev-realtime-gtfs-vp.v1").len(), 1);
+    }
+
+    // Should skip, with the reason, location events that publish nothing.
+    #[tokio::test]
+    async fn skipped() {
+        let unknown = MockProvider::default().with_response("fleet", "[]");
+        let outcome = process_event(mock::location(), Source::SmarTrak, &unknown)
+            .await
+            .expect("should process");
+        assert!(matches!(outcome, ProcessResult::Skipped("vehicle not found")));
+
+        let provider = MockProvider::new().on_trip();
+        let mut inaccurate = mock::location();
+        inaccurate.location_data.gps_accuracy = -1.0;
+        let outcome =
+            process_event(inaccurate, Source::SmarTrak, &provider).await.expect("should process");
+        assert!(matches!(outcome, ProcessResult::Skipped("inaccurate position")));
+
+        let outcome = process_event(mock::location(), Source::SmarTrak, &provider)
+            .await
+            .expect("should process");
+        assert!(matches!(outcome, ProcessResult::Emitted(1)));
+        let outcome = process_event(mock::location(), Source::SmarTrak, &provider)
+            .await
+            .expect("should process");
+        assert!(matches!(outcome, ProcessResult::Skipped("throttled")));
+    }
+
+    // Should publish remapped occupancy only on the consumer's own topic,
+    // leaving the shared feed GTFS-RT.
+    #[tokio::test]
+    async fn occupancy_output_topic() {
+        let provider = MockProvider::new()
+            .on_trip()
+            .with_config("OCCUPANCY_OUTPUT_TOPIC", "realtime-regional-vp.v1")
+            .with_config(
+                "OCCUPANCY_OUTPUT_MAP",
+                "FEW_SEATS_AVAILABLE=MEDIUM,STANDING_ROOM_ONLY=MEDIUM",
+            );
+        let mut message = mock::location();
+        message.occupancy_status = Some(OccupancyStatus::StandingRoomOnly);
+
+        let outcome =
+            process_event(message, Source::SmarTrak, &provider).await.expect("should process");
+        assert!(matches!(outcome, ProcessResult::Emitted(2)));
+
+        let shared = provider.payloads("dev-realtime-gtfs-vp.v1");
+        assert_eq!(shared[0]["vehicle"]["occupancyStatus"], "3");
+        let regional = provider.payloads("dev-realtime-regional-vp.v1");
+        assert_eq!(regional[0]["vehicle"]["occupancyStatus"], "MEDIUM");
+    }
+}
@@ -1,23 +1,25 @@
 use common::fleet;
-use http::HeaderMap;
+use common::topic::TopicKind;
 use qwasr_sdk::api::{Context, Handler, Reply};
 use qwasr_sdk::{Config, HttpRequest, Identity, Publisher, Result, StateStore};
 use serde::Deserialize;
 
 use crate::SmarTrakMessage;
+use crate::handlers::smartrak;
+use crate::trip::Source;
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(transparent)]
 pub struct TrainAvlMessage(SmarTrakMessage);
 
-async fn handle<P>(owner: &str, request: TrainAvlMessage, provider: &P) -> Result<Reply<()>>
+async fn handle<P>(_owner: &str, request: TrainAvlMessage, provider: &P) -> Result<Reply<()>>
 where
     P: Config + HttpRequest + Identity + Publisher + StateStore,
 {
     let request = request.0;
 
     // verify vehicle tag is 'train'
-    let Some(vehicle_id) = request.vehicle_id() else {
+    let Some(vehicle_id) = request.vehicle_identifier() else {
         tracing::debug!("no vehicle identifier found");
         return Ok(Reply::ok(()));
     };
@@ -25,15 +27,12 @@ where
         tracing::debug!("vehicle info not found for {vehicle_id}");
         return Ok(Reply::ok(()));
     };
-    if let Some(tag) = vehicle.tag.as_deref().map(str::to_lowercase)
-        && tag != "smartrak"
-    {
-        tracing::debug!("vehicle tag {tag} did not match rules");
+    if !TopicKind::TrainAvl.accepts_tag(vehicle.tag.as_deref()) {
+        tracing::debug!("vehicle tag {:?} did not match rules", vehicle.tag);
         return Ok(Reply::ok(()));
     }
 
-    let headers = HeaderMap::default();
-    SmarTrakMessage::handle(request, Context { owner, provider, headers: &headers }).await?;
+    smartrak::process(request, Source::SmarTrak, provider).await?;
 
     Ok(Reply::ok(()))
 }
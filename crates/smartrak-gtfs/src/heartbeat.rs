@@ -0,0 +1,137 @@
+//! Throttled heartbeat signalling the SmarTrak consumer is processing
+//! messages, even when a message results in no output.
+
+use anyhow::{Context, Result};
+use common::config;
+use qwasr_sdk::{Config, Message, Publisher, StateStore};
+use serde::Serialize;
+
+const HEARTBEAT_TOPIC: &str = "realtime-smartrak-heartbeat.v1";
+const KEY_HEARTBEAT: &str = "smartrak:heartbeat";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Heartbeat {
+    timestamp: i64,
+}
+
+/// Publish a heartbeat when `SMARTRAK_HEARTBEAT_SECS` is set and at least that
+/// many seconds have passed since the last one. Disabled by default.
+///
+/// # Errors
+///
+/// Returns an error if the heartbeat state cannot be read or saved, or the
+/// heartbeat cannot be published.
+pub async fn beat<P>(provider: &P, now_ts: i64) -> Result<()>
+where
+    P: Config + Publisher + StateStore,
+{
+    let Some(interval) = interval(provider).await else {
+        return Ok(());
+    };
+
+    let last_ts = StateStore::get(provider, KEY_HEARTBEAT)
+        .await?
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|value| value.parse::<i64>().ok());
+    if !is_due(last_ts, now_ts, interval) {
+        return Ok(());
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    let ttl = (interval * 2) as u64;
+    StateStore::set(provider, KEY_HEARTBEAT, now_ts.to_string().as_bytes(), Some(ttl)).await?;
+
+    let payload =
+        serde_json::to_vec(&Heartbeat { timestamp: now_ts }).context("serializing heartbeat")?;
+    let env = Config::get(provider, "ENV").await.unwrap_or_else(|_| "dev".to_string());
+    Publisher::send(provider, &format!("{env}-{HEARTBEAT_TOPIC}"), &Message::new(&payload)).await
+}
+
+async fn interval(provider: &impl Config) -> Option<i64> {
+    config::checked_setting(provider, "SMARTRAK_HEARTBEAT_SECS", |secs: &i64| *secs > 0).await
+}
+
+fn is_due(last_ts: Option<i64>, now_ts: i64, interval: i64) -> bool {
+    last_ts.is_none_or(|last| now_ts - last >= interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[derive(Default, Clone)]
+    struct MockProvider {
+        interval: Option<&'static str>,
+        store: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        published: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Config for MockProvider {
+        async fn get(&self, key: &str) -> Result<String> {
+            match key {
+                "SMARTRAK_HEARTBEAT_SECS" => {
+                    self.interval.map(ToString::to_string).ok_or_else(|| anyhow!("not set"))
+                }
+                _ => Ok("dev".to_string()),
+            }
+        }
+    }
+
+    impl StateStore for MockProvider {
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.store.lock().map_err(|e| anyhow!("{e}"))?.get(key).cloned())
+        }
+
+        async fn set(
+            &self, key: &str, value: &[u8], _ttl_secs: Option<u64>,
+        ) -> Result<Option<Vec<u8>>> {
+            Ok(self
+                .store
+                .lock()
+                .map_err(|e| anyhow!("{e}"))?
+                .insert(key.to_string(), value.to_vec()))
+        }
+
+        async fn delete(&self, key: &str) -> Result<()> {
+            self.store.lock().map_err(|e| anyhow!("{e}"))?.remove(key);
+            Ok(())
+        }
+    }
+
+    impl Publisher for MockProvider {
+        async fn send(&self, topic: &str, _message: &Message) -> Result<()> {
+            self.published.lock().map_err(|e| anyhow!("{e}"))?.push(topic.to_string());
+            Ok(())
+        }
+    }
+
+    const NOW: i64 = 1_762_469_343;
+
+    // Should publish at most one heartbeat per configured interval.
+    #[tokio::test]
+    async fn throttled() {
+        let provider = MockProvider { interval: Some("60"), ..MockProvider::default() };
+
+        for offset in [0, 1, 30, 59, 60, 61, 119, 120] {
+            beat(&provider, NOW + offset).await.expect("should beat");
+        }
+
+        let published = provider.published.lock().expect("lock").clone();
+        assert_eq!(published.len(), 3);
+        assert!(published.iter().all(|topic| topic == "dev-realtime-smartrak-heartbeat.v1"));
+    }
+
+    // Should not publish heartbeats unless an interval is configured.
+    #[tokio::test]
+    async fn disabled() {
+        let provider = MockProvider::default();
+        beat(&provider, NOW).await.expect("should beat");
+        assert!(provider.published.lock().expect("lock").is_empty());
+    }
+}
@@ -1,14 +1,25 @@
 //! SmarTrak GTFS adapter.
 
+mod congestion;
+mod extra_info;
 mod god_mode;
+pub mod gtfs_rt;
 mod handlers;
+mod heartbeat;
 mod location;
+#[cfg(test)]
+mod mock;
+mod occupancy;
 // pub mod rest;
+mod routing;
 mod serial_data;
+mod throttle;
 mod trip;
 
+pub use congestion::CongestionLevel;
 pub use god_mode::*;
 pub use handlers::*;
+pub use occupancy::OccupancyStatus;
 use qwasr_sdk::Error;
 use thiserror::Error;
 
@@ -1,64 +1,89 @@
 use anyhow::Context as _;
-use chrono::{Duration, NaiveDate, TimeZone};
+use chrono::Duration;
 use chrono_tz::Tz;
 use common::block_mgt::{self, BlockInstance};
+use common::config;
 use common::fleet::{self, Vehicle};
+use common::outcome::Skippable;
 use qwasr_sdk::{Config, HttpRequest, Identity, Publisher, Result, StateStore};
 use serde::de::DeserializeOwned;
 use uuid::Uuid;
 
 use crate::trip::{
-    self, DeadReckoningMessage, FeedEntity, Position, PositionDr, TripDescriptor, TripInstance,
-    VehicleDescriptor, VehicleDr, VehiclePosition,
+    self, DeadReckoningMessage, FeedEntity, Position, PositionDr, Source, TripDescriptor,
+    TripInstance, VehicleDescriptor, VehicleDr, VehiclePosition,
 };
-use crate::{EventType, SmarTrakMessage};
+use crate::{EventType, SmarTrakMessage, congestion, extra_info};
 
 const TTL_TRIP_TRAIN: Duration = Duration::seconds(3 * 60 * 60);
 const TTL_SIGN_ON: Duration = Duration::seconds(24 * 60 * 60);
+const TRIP_BUFFER: Duration = Duration::seconds(60 * 60);
+const SIGN_ON_SKEW_WARN: Duration = Duration::seconds(5 * 60);
 const TIMEZONE: Tz = chrono_tz::Pacific::Auckland;
 
 const fn duration_secs(duration: Duration) -> u64 {
     duration.num_seconds().unsigned_abs()
 }
 
-pub enum Location {
-    VehiclePosition(FeedEntity),
-    DeadReckoning(DeadReckoningMessage),
+/// Messages emitted for a location event: a vehicle position for the live
+/// feed, a dead reckoning record for gap-filling, or both.
+pub struct Location {
+    pub vehicle_position: Option<FeedEntity>,
+    pub dead_reckoning: Option<DeadReckoningMessage>,
+    /// The vehicle's Fleet tag, used to route its position.
+    pub tag: Option<String>,
 }
 
-/// Processes a Smartrak Kafka payload and emits outbound messages when applicable.
+/// Processes a Smartrak Kafka payload and emits outbound messages when applicable,
+/// or the reason no messages are emitted.
 ///
 /// # Errors
 ///
 /// Returns an error when the incoming payload cannot be parsed or when domain logic
 /// encounters an unrecoverable condition.
-pub async fn process<P>(message: &SmarTrakMessage, provider: &P) -> Result<Option<Location>>
+pub async fn process<P>(
+    message: &SmarTrakMessage, source: Source, provider: &P,
+) -> Result<Skippable<Location>>
 where
     P: HttpRequest + Publisher + StateStore + Identity + Config,
 {
     // check for location event
-    if message.event_type != EventType::Location {
-        tracing::debug!("unsupported request type: {:?}", message.event_type);
-        return Ok(None);
+    match &message.event_type {
+        EventType::Location => {}
+        EventType::Unknown(raw) => {
+            tracing::warn!(event_type = %raw, "unknown event type");
+            return Ok(Skippable::Skipped("unsupported event type"));
+        }
+        EventType::SerialData => {
+            tracing::debug!("unsupported request type: {:?}", message.event_type);
+            return Ok(Skippable::Skipped("unsupported event type"));
+        }
     }
 
     let location = &message.location_data;
 
-    if message.remote_data.is_none() || location.gps_accuracy < 0.0 {
+    if message.remote_data.is_none() {
         tracing::debug!("invalid location event");
-        return Ok(None);
+        return Ok(Skippable::Skipped("missing remote data"));
     }
 
     // get vehicle info
-    let Some(vehicle_id) = message.vehicle_id() else {
+    let Some(vehicle_id) = message.vehicle_identifier() else {
         tracing::debug!("no vehicle identifier found");
-        return Ok(None);
+        return Ok(Skippable::Skipped("missing vehicle identifier"));
     };
     let Some(vehicle) = fleet::vehicle(vehicle_id, provider).await? else {
         tracing::debug!("vehicle info not found for {vehicle_id}");
-        return Ok(None);
+        return Ok(Skippable::Skipped("vehicle not found"));
     };
 
+    // sources encode accuracy differently, so validate it per source (tag)
+    let accuracy = GpsAccuracy::for_source(provider, vehicle.tag.as_deref()).await;
+    if !accuracy.is_valid(location.gps_accuracy) {
+        tracing::debug!(?accuracy, gps_accuracy = location.gps_accuracy, "invalid location event");
+        return Ok(Skippable::Skipped("inaccurate position"));
+    }
+
     let timestamp = message.timestamp()?;
 
     if vehicle.is_train() {
@@ -69,18 +94,30 @@ where
     let trip_desc = trip_inst.as_ref().map(TripDescriptor::from);
     let odometer = location.odometer.or(message.event_data.odometer);
 
-    if (location.latitude.is_none() || location.longitude.is_none())
-        && let (Some(odometer), Some(descriptor)) = (odometer, trip_desc.clone())
-    {
-        let dr_message = DeadReckoningMessage {
-            id: Uuid::new_v4().to_string(),
-            received_at: timestamp,
-            position: PositionDr { odometer },
-            trip: descriptor,
-            vehicle: VehicleDr { id: vehicle.id.clone() },
+    let has_coordinates = location.latitude.is_some() && location.longitude.is_some();
+    let dr_with_vp = has_coordinates && emit_dr_with_vp(provider).await;
+    let (emit_vp, emit_dr) =
+        emitted(has_coordinates, odometer.is_some() && trip_desc.is_some(), dr_with_vp);
+
+    let dead_reckoning =
+        if emit_dr && let (Some(odometer), Some(descriptor)) = (odometer, trip_desc.clone()) {
+            Some(DeadReckoningMessage {
+                id: Uuid::new_v4().to_string(),
+                received_at: timestamp,
+                position: PositionDr { odometer },
+                trip: descriptor,
+                vehicle: VehicleDr { id: vehicle.id.clone() },
+                source,
+            })
+        } else {
+            None
         };
-
-        return Ok(Some(Location::DeadReckoning(dr_message)));
+    if !emit_vp {
+        return Ok(Skippable::Output(Location {
+            vehicle_position: None,
+            dead_reckoning,
+            tag: vehicle.tag,
+        }));
     }
 
     let descriptor = VehicleDescriptor {
@@ -89,30 +126,138 @@ where
         license_plate: vehicle.registration.clone(),
     };
 
+    // prefer passenger counts over occupancy reported by the vehicle
     let occupancy_status = if let Some(trip) = trip_desc.as_ref() {
         get_occupancy_status(provider, &vehicle, trip).await?
     } else {
         None
     };
+    let occupancy_status =
+        occupancy_status.or_else(|| message.occupancy_status.map(|status| status.to_string()));
+
+    // withhold occupancy while in transit with the doors closed
+    let moving = message.current_status.as_deref() != Some("STOPPED_AT")
+        && location.speed.is_some_and(|speed| speed > 0.0);
+    let extra_info = extra_info::load(provider, message.event_data.extra_info.as_deref()).await;
+    let occupancy_status = occupancy_status
+        .filter(|_| !extra_info.is_some_and(|info| info.suppresses_occupancy(moving)));
 
     let position = Position {
         latitude: location.latitude,
         longitude: location.longitude,
         bearing: location.heading,
-        speed: location.speed.map(|value| value * 1000.0 / 3600.0),
+        speed: location.speed.and_then(Position::speed_from_kmh),
         odometer,
     };
 
+    // a vehicle stopped at a station isn't held up
+    let in_transit = message.current_status.as_deref() == Some("IN_TRANSIT_TO");
+    let congestion_level = match (&trip_desc, location.speed.filter(|_| position.speed.is_some())) {
+        (Some(trip), Some(speed)) if message.current_status.as_deref() != Some("STOPPED_AT") => {
+            congestion::level(provider, &trip.route_id, speed, in_transit)
+                .await
+                .map(|level| level.to_string())
+        }
+        _ => None,
+    };
+
     let vehicle_position = VehiclePosition {
         position: Some(position),
         trip: trip_desc,
         vehicle: Some(descriptor),
         occupancy_status,
         timestamp,
+        stop_id: message.stop_id.clone(),
+        current_status: message.current_status.clone(),
+        congestion_level,
     };
 
-    let entity = FeedEntity { id: vehicle.id.clone(), vehicle: Some(vehicle_position) };
-    Ok(Some(Location::VehiclePosition(entity)))
+    let entity = FeedEntity {
+        id: vehicle.id.clone(),
+        vehicle: Some(vehicle_position),
+        source: Some(source),
+    };
+    Ok(Skippable::Output(Location {
+        vehicle_position: Some(entity),
+        dead_reckoning,
+        tag: vehicle.tag,
+    }))
+}
+
+/// Which of a vehicle position and dead reckoning record a location event
+/// emits, given whether it has coordinates and could be dead reckoned.
+///
+/// Dead reckoning stands in for a position without coordinates. When
+/// `dr_with_vp` is set, it is also emitted alongside positions with them.
+const fn emitted(has_coordinates: bool, can_dead_reckon: bool, dr_with_vp: bool) -> (bool, bool) {
+    let dead_reckoning = can_dead_reckon && (!has_coordinates || dr_with_vp);
+    (has_coordinates || !dead_reckoning, dead_reckoning)
+}
+
+/// Whether dead reckoning records are emitted alongside vehicle positions,
+/// read from `EMIT_DR_WITH_VP`. Off by default.
+async fn emit_dr_with_vp(provider: &impl Config) -> bool {
+    config::flag(provider, "EMIT_DR_WITH_VP").await
+}
+
+/// How an AVL source encodes `gps_accuracy`.
+///
+/// Configured per source (the vehicle's Fleet tag) with `GPS_ACCURACY_MODES`,
+/// as comma-separated `source=mode` pairs, e.g. `caf=meters:25,train=dop:5`.
+/// Sources not listed use [`GpsAccuracy::Flag`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum GpsAccuracy {
+    /// `flag`: a quality flag, where any non-negative value is a fix.
+    #[default]
+    Flag,
+
+    /// `meters:<max>`: estimated horizontal error in meters, valid from zero
+    /// up to and including `max`.
+    Meters(f64),
+
+    /// `dop:<max>`: dilution of precision, valid when positive and up to and
+    /// including `max`. A DOP of zero means no fix.
+    Dop(f64),
+}
+
+impl GpsAccuracy {
+    /// Parse a mode, e.g. `flag`, `meters:25` or `dop:5`.
+    fn parse(mode: &str) -> Option<Self> {
+        let (kind, limit) = mode.trim().split_once(':').unwrap_or((mode.trim(), ""));
+        let max = || limit.trim().parse::<f64>().ok().filter(|max| *max >= 0.0);
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "flag" => Some(Self::Flag),
+            "meters" => max().map(Self::Meters),
+            "dop" => max().map(Self::Dop),
+            _ => None,
+        }
+    }
+
+    /// The mode configured for the source in `GPS_ACCURACY_MODES`.
+    fn from_config(modes: &str, source: &str) -> Self {
+        config::pairs(modes)
+            .find(|(name, _)| name.eq_ignore_ascii_case(source))
+            .and_then(|(_, mode)| Self::parse(mode))
+            .unwrap_or_default()
+    }
+
+    async fn for_source(provider: &impl Config, source: Option<&str>) -> Self {
+        let Some(source) = source else {
+            return Self::Flag;
+        };
+        Config::get(provider, "GPS_ACCURACY_MODES")
+            .await
+            .map(|modes| Self::from_config(&modes, source))
+            .unwrap_or_default()
+    }
+
+    fn is_valid(self, accuracy: f64) -> bool {
+        match self {
+            Self::Flag => accuracy >= 0.0,
+            Self::Meters(max) => (0.0..=max).contains(&accuracy),
+            Self::Dop(max) => accuracy > 0.0 && accuracy <= max,
+        }
+    }
 }
 
 fn deserialize_optional<T>(bytes: Option<&[u8]>) -> Option<T>
@@ -152,10 +297,13 @@ where
     // is this trip the same as the previous one?
     if let Some(bytes) = StateStore::get(provider, &trip_key).await? {
         let prev = serde_json::from_slice::<TripInstance>(&bytes)?;
-        if prev.trip_id == alloc.trip_id
-            && prev.start_time == alloc.start_time
-            && prev.service_date == alloc.service_date
-        {
+        let allocated = TripInstance {
+            trip_id: alloc.trip_id.clone(),
+            service_date: alloc.service_date.clone(),
+            start_time: alloc.start_time.clone(),
+            ..TripInstance::default()
+        };
+        if prev.same_trip(&allocated) {
             return Ok(());
         }
     }
@@ -197,13 +345,22 @@ where
     if let Some(instance) = deserialize_optional::<TripInstance>(bytes.as_deref()) {
         let sign_on_bytes = StateStore::get(provider, &sign_on_key).await?;
         let sign_on = deserialize_optional::<i64>(sign_on_bytes.as_deref());
-        if let (Some(sign_on_ts), Some(start), Some(end)) = (
-            sign_on,
-            time_to_timestamp(&instance.service_date, &instance.start_time, TIMEZONE),
-            time_to_timestamp(&instance.service_date, &instance.end_time, TIMEZONE),
-        ) {
-            let duration = end - start + Duration::seconds(3_600).num_seconds();
-            if timestamp - duration > sign_on_ts {
+        if let (Some(sign_on_ts), Some((start, end))) = (sign_on, instance.window(TIMEZONE)) {
+            // sign-on ahead of the event indicates the onboard clock is skewed
+            let skew = sign_on_ts - timestamp;
+            if skew > SIGN_ON_SKEW_WARN.num_seconds() {
+                tracing::warn!(
+                    vehicle_id,
+                    sign_on_ts,
+                    timestamp,
+                    skew,
+                    "sign-on clock skew detected"
+                );
+            }
+
+            let duration = end - start + TRIP_BUFFER.num_seconds();
+            let allowance = sign_on_skew(provider).await;
+            if sign_on_expired(sign_on_ts, timestamp, duration, allowance) {
                 StateStore::delete(provider, &sign_on_key).await?;
                 StateStore::delete(provider, &trip_key).await?;
                 return Ok(None);
@@ -215,6 +372,19 @@ where
     Ok(None)
 }
 
+/// Whether a sign-on has outlived the trip it was made for.
+///
+/// The `allowance` (in seconds) extends the trip duration to tolerate clock skew
+/// between the onboard unit and the server.
+const fn sign_on_expired(sign_on_ts: i64, timestamp: i64, duration: i64, allowance: i64) -> bool {
+    timestamp - duration - allowance > sign_on_ts
+}
+
+/// Sign-on clock skew allowance in seconds, read from `SIGN_ON_SKEW_SECS`.
+async fn sign_on_skew(provider: &impl Config) -> i64 {
+    config::setting(provider, "SIGN_ON_SKEW_SECS").await.map_or(0, i64::abs)
+}
+
 async fn get_occupancy_status<P>(
     provider: &P, vehicle: &Vehicle, trip: &TripDescriptor,
 ) -> Result<Option<String>>
@@ -242,21 +412,198 @@ where
     Ok(Some(occupancy_status))
 }
 
-fn time_to_timestamp(date: &str, time: &str, tz: Tz) -> Option<i64> {
-    if date.is_empty() || time.is_empty() {
-        return None;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockProvider};
+    use crate::occupancy::OccupancyStatus;
+
+    const SIGN_ON: i64 = 1_700_000_000;
+    const DURATION: i64 = 2 * 60 * 60;
+
+    // Should keep a sign-on active for its duration and no longer.
+    #[test]
+    fn sign_on_active() {
+        assert!(!sign_on_expired(SIGN_ON, SIGN_ON + DURATION, DURATION, 0));
+        assert!(sign_on_expired(SIGN_ON, SIGN_ON + DURATION + 1, DURATION, 0));
+    }
+
+    // Should keep a sign-on active when the onboard clock runs fast by no
+    // more than the allowance.
+    #[test]
+    fn skew_within_allowance() {
+        // onboard clock running 2 minutes fast
+        let timestamp = SIGN_ON + DURATION + 120;
+        assert!(sign_on_expired(SIGN_ON, timestamp, DURATION, 0));
+        assert!(!sign_on_expired(SIGN_ON, timestamp, DURATION, 300));
+    }
+
+    // Should accept any position not flagged inaccurate by default.
+    #[test]
+    fn gps_accuracy_flag() {
+        let flag = GpsAccuracy::Flag;
+        assert!(flag.is_valid(0.0));
+        assert!(flag.is_valid(250.0));
+        assert!(!flag.is_valid(-1.0));
+    }
+
+    // Should accept positions accurate to within the configured metres.
+    #[test]
+    fn gps_accuracy_meters() {
+        let meters = GpsAccuracy::parse("meters:25").expect("should parse");
+        assert_eq!(meters, GpsAccuracy::Meters(25.0));
+        assert!(meters.is_valid(0.0));
+        assert!(meters.is_valid(25.0));
+        assert!(!meters.is_valid(25.1));
+        assert!(!meters.is_valid(-1.0));
+    }
+
+    // Should accept positions with a positive dilution of precision up to the
+    // configured limit.
+    #[test]
+    fn gps_accuracy_dop() {
+        let dop = GpsAccuracy::parse(" DOP : 5 ").expect("should parse");
+        assert_eq!(dop, GpsAccuracy::Dop(5.0));
+        assert!(dop.is_valid(1.2));
+        assert!(dop.is_valid(5.0));
+        assert!(!dop.is_valid(5.01));
+        assert!(!dop.is_valid(0.0));
     }
 
-    let date = NaiveDate::parse_from_str(date, "%Y%m%d").ok()?;
-    let parts: Vec<_> = time.split(':').collect();
-    if parts.len() != 3 {
-        return None;
+    // Should read each source's accuracy mode, falling back to the flag for
+    // sources without a valid mode.
+    #[test]
+    fn gps_accuracy_config() {
+        let modes = "caf=meters:25, train=dop:5,smartrak=bogus";
+        assert_eq!(GpsAccuracy::from_config(modes, "CAF"), GpsAccuracy::Meters(25.0));
+        assert_eq!(GpsAccuracy::from_config(modes, "train"), GpsAccuracy::Dop(5.0));
+        assert_eq!(GpsAccuracy::from_config(modes, "smartrak"), GpsAccuracy::Flag);
+        assert_eq!(GpsAccuracy::from_config(modes, "other"), GpsAccuracy::Flag);
+        assert_eq!(GpsAccuracy::parse("meters"), None);
+        assert_eq!(GpsAccuracy::parse("meters:-5"), None);
     }
 
-    let hours: i64 = parts[0].parse().ok()?;
-    let minutes: i64 = parts[1].parse().ok()?;
-    let seconds: i64 = parts[2].parse().ok()?;
-    let base = date.and_hms_opt(0, 0, 0)?;
-    let datetime = tz.from_local_datetime(&base).single()?;
-    Some((datetime + Duration::seconds(hours * 3_600 + minutes * 60 + seconds)).timestamp())
+    // Should emit dead reckoning in place of positions without coordinates,
+    // and alongside positions with them only when configured.
+    #[test]
+    fn emitted_messages() {
+        let cases = [
+            // (has coordinates, can dead reckon, DR with VP) => (VP, DR)
+            ((true, true, false), (true, false)),
+            ((true, true, true), (true, true)),
+            ((true, false, true), (true, false)),
+            ((false, true, false), (false, true)),
+            ((false, true, true), (false, true)),
+            ((false, false, false), (true, false)),
+        ];
+        for ((coordinates, dead_reckon, dr_with_vp), expected) in cases {
+            assert_eq!(emitted(coordinates, dead_reckon, dr_with_vp), expected, "{coordinates}");
+        }
+    }
+
+    // Should expire a sign-on when the onboard clock runs fast by more than
+    // the allowance.
+    #[test]
+    fn skew_beyond_allowance() {
+        // onboard clock running 10 minutes fast
+        let timestamp = SIGN_ON + DURATION + 600;
+        assert!(sign_on_expired(SIGN_ON, timestamp, DURATION, 300));
+    }
+
+    // Should emit only a vehicle position for an event with coordinates, by
+    // default.
+    #[tokio::test]
+    async fn position_only() {
+        let provider = MockProvider::new().on_trip();
+        let location = process(&mock::location(), Source::SmarTrak, &provider)
+            .await
+            .expect("should process")
+            .output()
+            .expect("should emit");
+
+        assert!(location.vehicle_position.is_some());
+        assert!(location.dead_reckoning.is_none());
+        assert_eq!(location.tag.as_deref(), Some("CAF"));
+    }
+
+    // Should also emit dead reckoning for an event with coordinates when
+    // enabled.
+    #[tokio::test]
+    async fn dead_reckoning_with_position() {
+        let provider = MockProvider::new().on_trip().with_config("EMIT_DR_WITH_VP", "true");
+        let location = process(&mock::location(), Source::SmarTrak, &provider)
+            .await
+            .expect("should process")
+            .output()
+            .expect("should emit");
+
+        assert!(location.vehicle_position.is_some());
+        let dead_reckoning = location.dead_reckoning.expect("should dead reckon");
+        assert!((dead_reckoning.position.odometer - 1200.0).abs() < f64::EPSILON);
+        assert_eq!(dead_reckoning.trip.trip_id, mock::TRIP_ID);
+    }
+
+    // Should emit only dead reckoning for an event without coordinates.
+    #[tokio::test]
+    async fn dead_reckoning_without_coordinates() {
+        let provider = MockProvider::new().on_trip();
+        let mut message = mock::location();
+        message.location_data.latitude = None;
+        message.location_data.longitude = None;
+
+        let location = process(&message, Source::SmarTrak, &provider)
+            .await
+            .expect("should process")
+            .output()
+            .expect("should emit");
+
+        assert!(location.vehicle_position.is_none());
+        assert!(location.dead_reckoning.is_some());
+    }
+
+    // Should not rate a stationary vehicle as congested unless it's reported
+    // in transit to its next stop.
+    #[tokio::test]
+    async fn congestion_at_standstill() {
+        let provider =
+            MockProvider::new().on_trip().with_config("ROUTE_EXPECTED_SPEEDS", "STH-201=60");
+        let mut message = mock::location();
+        message.location_data.speed = Some(0.0);
+
+        for (status, expected) in [(None, None), (Some("IN_TRANSIT_TO"), Some("SEVERE_CONGESTION"))]
+        {
+            message.current_status = status.map(ToString::to_string);
+            let location = process(&message, Source::SmarTrak, &provider)
+                .await
+                .expect("should process")
+                .output()
+                .expect("should emit");
+            let entity = location.vehicle_position.expect("should emit position");
+            let position = entity.vehicle.expect("should have vehicle");
+            assert_eq!(position.congestion_level.as_deref(), expected, "{status:?}");
+        }
+    }
+
+    // Should withhold occupancy from a moving vehicle whose mapped extraInfo
+    // reports its doors closed, and ignore extraInfo unless mapped.
+    #[tokio::test]
+    async fn doors_closed_in_transit() {
+        let mut message = mock::location();
+        message.event_data.extra_info = Some("D0".to_string());
+        message.occupancy_status = Some(OccupancyStatus::FewSeatsAvailable);
+
+        let mapped = MockProvider::new()
+            .on_trip()
+            .with_config("SMARTRAK_EXTRA_INFO_MAP", "D1=DOORS_OPEN,D0=DOORS_CLOSED");
+        for (provider, expected) in [(MockProvider::new().on_trip(), Some("2")), (mapped, None)] {
+            let location = process(&message, Source::SmarTrak, &provider)
+                .await
+                .expect("should process")
+                .output()
+                .expect("should emit");
+            let entity = location.vehicle_position.expect("should emit position");
+            let position = entity.vehicle.expect("should have vehicle");
+            assert_eq!(position.occupancy_status.as_deref(), expected);
+        }
+    }
 }
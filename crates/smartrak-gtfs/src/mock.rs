@@ -0,0 +1,204 @@
+//! In-memory provider for tests that run messages through the handlers.
+
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use http::{Request, Response, StatusCode};
+use qwasr_sdk::{Config, HttpRequest, Identity, Message, Publisher, StateStore};
+
+use crate::SmarTrakMessage;
+use crate::trip::TripInstance;
+
+/// A Fleet API record for train `59144`, tagged `CAF`.
+pub const FLEET: &str = r#"[{
+    "id": "59144",
+    "label": "AMP        144",
+    "type": { "type": "Train" },
+    "tag": "CAF"
+}]"#;
+
+/// The trip train `59144` is allocated to by [`MockProvider::on_trip`].
+pub const TRIP_ID: &str = "trip-1";
+
+/// A location event for train `59144`, moving with coordinates and an
+/// odometer reading.
+pub fn location() -> SmarTrakMessage {
+    serde_json::from_value(serde_json::json!({
+        "eventType": "location",
+        "remoteData": { "externalId": "59144" },
+        "messageData": { "timestamp": "2025-11-07T08:00:00Z" },
+        "locationData": {
+            "latitude": -36.84448,
+            "longitude": 174.76915,
+            "heading": 90.0,
+            "speed": 36.0,
+            "odometer": 1200.0,
+            "gpsAccuracy": 0
+        }
+    }))
+    .expect("should deserialize")
+}
+
+/// Provider answering HTTP requests from canned responses keyed by route:
+/// `fleet`, `allocation` and `trips`. Routes without a response are not
+/// found.
+#[derive(Default, Clone)]
+pub struct MockProvider {
+    config: HashMap<String, String>,
+    responses: HashMap<&'static str, String>,
+    store: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    published: Arc<Mutex<Vec<(String, Message)>>>,
+    failing: HashSet<String>,
+}
+
+impl MockProvider {
+    /// A provider that knows train `59144`.
+    pub fn new() -> Self {
+        Self::default().with_response("fleet", FLEET)
+    }
+
+    /// Allocate train `59144` to [`TRIP_ID`], already signed on to it.
+    pub fn on_trip(self) -> Self {
+        let trip = TripInstance {
+            trip_id: TRIP_ID.to_string(),
+            route_id: "STH-201".to_string(),
+            service_date: "20251107".to_string(),
+            start_time: "20:30:00".to_string(),
+            end_time: "21:30:00".to_string(),
+            ..TripInstance::default()
+        };
+        let allocation = serde_json::json!({
+            "tripId": trip.trip_id,
+            "serviceDate": trip.service_date,
+            "startTime": trip.start_time,
+            "vehicleIds": ["59144"]
+        });
+        let provider = self.with_response("allocation", &allocation.to_string());
+        let trip = serde_json::to_vec(&trip).expect("should serialize");
+        provider.put("smartrakGtfs:trip:vehicle:59144", &trip);
+        provider
+    }
+
+    /// Set a configuration value.
+    pub fn with_config(mut self, key: &str, value: &str) -> Self {
+        self.config.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the response body for an HTTP route.
+    pub fn with_response(mut self, route: &'static str, body: &str) -> Self {
+        self.responses.insert(route, body.to_string());
+        self
+    }
+
+    /// Fail every publish to `topic`.
+    pub fn with_failing_topic(mut self, topic: &str) -> Self {
+        self.failing.insert(topic.to_string());
+        self
+    }
+
+    /// Seed the store.
+    pub fn put(&self, key: &str, value: &[u8]) {
+        self.store.lock().expect("lock").insert(key.to_string(), value.to_vec());
+    }
+
+    /// The stored value of `key`.
+    #[allow(dead_code)]
+    pub fn stored(&self, key: &str) -> Option<Vec<u8>> {
+        self.store.lock().expect("lock").get(key).cloned()
+    }
+
+    /// Messages published, with their topics.
+    pub fn published(&self) -> Vec<(String, Message)> {
+        self.published.lock().expect("lock").clone()
+    }
+
+    /// Payloads published to `topic`, as JSON.
+    pub fn payloads(&self, topic: &str) -> Vec<serde_json::Value> {
+        self.published()
+            .into_iter()
+            .filter(|(published, _)| published == topic)
+            .map(|(_, message)| serde_json::from_slice(&message.payload).expect("should be json"))
+            .collect()
+    }
+}
+
+impl Config for MockProvider {
+    async fn get(&self, key: &str) -> Result<String> {
+        if let Some(value) = self.config.get(key) {
+            return Ok(value.clone());
+        }
+        match key {
+            "ENV" => Ok("dev".to_string()),
+            "AZURE_IDENTITY" => Ok("identity".to_string()),
+            k if k.ends_with("_URL") => Ok("http://localhost:8080/".to_string()),
+            _ => Err(anyhow!("{key} not set")),
+        }
+    }
+}
+
+impl StateStore for MockProvider {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.store.lock().map_err(|e| anyhow!("{e}"))?.get(key).cloned())
+    }
+
+    async fn set(
+        &self, key: &str, value: &[u8], _ttl_secs: Option<u64>,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self.store.lock().map_err(|e| anyhow!("{e}"))?.insert(key.to_string(), value.to_vec()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.store.lock().map_err(|e| anyhow!("{e}"))?.remove(key);
+        Ok(())
+    }
+}
+
+impl HttpRequest for MockProvider {
+    async fn fetch<T>(&self, request: Request<T>) -> Result<Response<Bytes>>
+    where
+        T: http_body::Body + Any,
+        T::Data: Into<Vec<u8>>,
+        T::Error: Into<Box<dyn Error + Send + Sync + 'static>>,
+    {
+        let path = request.uri().path();
+        let route = if path.contains("/allocations/vehicles/") {
+            "allocation"
+        } else if path.contains("/vehicles") {
+            "fleet"
+        } else if path.contains("/tripinstances") {
+            "trips"
+        } else {
+            return Err(anyhow!("unexpected request to {}", request.uri()));
+        };
+
+        let response = match self.responses.get(route) {
+            Some(body) => Response::new(Bytes::from(body.clone())),
+            None => Response::builder().status(StatusCode::NOT_FOUND).body(Bytes::new())?,
+        };
+        Ok(response)
+    }
+}
+
+impl Identity for MockProvider {
+    async fn access_token(&self, _identity: String) -> Result<String> {
+        Ok("mock_access_token".to_string())
+    }
+}
+
+impl Publisher for MockProvider {
+    async fn send(&self, topic: &str, message: &Message) -> Result<()> {
+        if self.failing.contains(topic) {
+            return Err(anyhow!("failed to publish to {topic}"));
+        }
+        self.published
+            .lock()
+            .map_err(|e| anyhow!("{e}"))?
+            .push((topic.to_string(), message.clone()));
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! Normalisation of vehicle-reported occupancy to GTFS-RT occupancy status.
+//!
+//! CAF units report occupancy in their own scheme, so values are mapped with
+//! `CAF_OCCUPANCY_MAP`, as comma-separated `value=status` pairs, e.g.
+//! `LOW=MANY_SEATS_AVAILABLE,MEDIUM=FEW_SEATS_AVAILABLE,HIGH=FULL`. Statuses
+//! are GTFS-RT names or their numeric values.
+//!
+//! Statuses are published as their GTFS-RT numeric values. Consumers wanting
+//! a coarser or differently coded scale are served their own copy of each
+//! position on `OCCUPANCY_OUTPUT_TOPIC`, with statuses mapped by
+//! `OCCUPANCY_OUTPUT_MAP`, as comma-separated `status=code` pairs, e.g.
+//! `FEW_SEATS_AVAILABLE=MEDIUM,STANDING_ROOM_ONLY=MEDIUM`. The shared feed is
+//! never remapped.
+
+use common::config;
+pub use common::occupancy::OccupancyStatus;
+use qwasr_sdk::Config;
+
+/// The status mapped to a CAF-reported occupancy value in `mapping`, matched
+/// case-insensitively.
+fn from_mapping(mapping: &str, value: &str) -> Option<OccupancyStatus> {
+    config::pairs(mapping)
+        .find(|(caf, _)| caf.eq_ignore_ascii_case(value.trim()))
+        .and_then(|(_, status)| OccupancyStatus::parse(status))
+}
+
+/// Normalise a CAF-reported occupancy value with `CAF_OCCUPANCY_MAP`.
+///
+/// Values that aren't mapped, or are mapped to an unknown status, are flagged
+/// with a warning and dropped rather than published as-is.
+pub async fn caf_status(provider: &impl Config, value: &str) -> Option<OccupancyStatus> {
+    let mapping = Config::get(provider, "CAF_OCCUPANCY_MAP").await.unwrap_or_default();
+    let status = from_mapping(&mapping, value);
+    if status.is_none() {
+        tracing::warn!(occupancy = value, "unmapped CAF occupancy");
+    }
+    status
+}
+
+/// Output codes for occupancy statuses, for a consumer wanting a coarser or
+/// differently coded scale than GTFS-RT, published on its own topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OccupancyMapping {
+    topic: String,
+    codes: Vec<(OccupancyStatus, String)>,
+}
+
+impl OccupancyMapping {
+    /// Map statuses published on `topic` with `status=code` pairs, skipping
+    /// those with an unknown status or no code.
+    fn new(topic: &str, mapping: &str) -> Self {
+        let codes = config::pairs(mapping)
+            .filter_map(|(status, code)| {
+                OccupancyStatus::parse(status)
+                    .filter(|_| !code.is_empty())
+                    .map(|status| (status, code.to_string()))
+            })
+            .collect();
+        Self { topic: topic.to_string(), codes }
+    }
+
+    /// Load the mapping from `OCCUPANCY_OUTPUT_MAP`, when
+    /// `OCCUPANCY_OUTPUT_TOPIC` names the consumer's topic.
+    pub async fn load(provider: &impl Config) -> Option<Self> {
+        let topic = Config::get(provider, "OCCUPANCY_OUTPUT_TOPIC").await.ok()?;
+        let topic = topic.trim();
+        if topic.is_empty() {
+            return None;
+        }
+        let mapping = Config::get(provider, "OCCUPANCY_OUTPUT_MAP").await.unwrap_or_default();
+        Some(Self::new(topic, &mapping))
+    }
+
+    /// The consumer's topic, without the environment prefix.
+    #[must_use]
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// The code published for the status: its mapped code, or its GTFS-RT
+    /// numeric value.
+    #[must_use]
+    pub fn code(&self, status: OccupancyStatus) -> String {
+        self.codes
+            .iter()
+            .find(|(mapped, _)| *mapped == status)
+            .map_or_else(|| status.to_string(), |(_, code)| code.clone())
+    }
+
+    /// The published occupancy status remapped, leaving values that aren't
+    /// GTFS-RT statuses as they are.
+    #[must_use]
+    pub fn remap(&self, published: &str) -> String {
+        OccupancyStatus::parse(published).map_or_else(|| published.to_string(), |s| self.code(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPING: &str = "low=MANY_SEATS_AVAILABLE, Medium=2,HIGH=standing_room_only,\
+                           FULL=FULL,WRONG=HALF_FULL";
+
+    // Should map configured CAF values regardless of case.
+    #[test]
+    fn mapped() {
+        let cases = [
+            ("LOW", OccupancyStatus::ManySeatsAvailable),
+            ("medium", OccupancyStatus::FewSeatsAvailable),
+            ("HIGH", OccupancyStatus::StandingRoomOnly),
+            (" FULL ", OccupancyStatus::Full),
+        ];
+        for (value, status) in cases {
+            assert_eq!(from_mapping(MAPPING, value), Some(status), "value {value}");
+        }
+    }
+
+    // Should not map values missing from the table or mapped to unknown
+    // statuses.
+    #[test]
+    fn unmapped() {
+        assert_eq!(from_mapping(MAPPING, "CRUSH"), None);
+        assert_eq!(from_mapping(MAPPING, "WRONG"), None);
+        assert_eq!(from_mapping("", "LOW"), None);
+    }
+
+    // Should publish GTFS-RT numeric values by default.
+    #[test]
+    fn default_output() {
+        let mapping = OccupancyMapping::new("realtime-occupancy.v1", "");
+        assert_eq!(mapping.code(OccupancyStatus::Empty), "0");
+        assert_eq!(mapping.code(OccupancyStatus::FewSeatsAvailable), "2");
+        assert_eq!(mapping.code(OccupancyStatus::NotAcceptingPassengers), "6");
+        assert_eq!(mapping.remap("2"), "2");
+    }
+
+    // Should publish configured codes, collapsing statuses into buckets.
+    #[test]
+    fn three_level_output() {
+        let mapping = OccupancyMapping::new(
+            "realtime-occupancy.v1",
+            "EMPTY=LOW, many_seats_available=LOW, 2=MEDIUM, STANDING_ROOM_ONLY=MEDIUM,\
+             CRUSHED_STANDING_ROOM_ONLY=HIGH,FULL=HIGH,HALF_FULL=MEDIUM,NOT_ACCEPTING_PASSENGERS=",
+        );
+        let cases = [
+            (OccupancyStatus::Empty, "LOW"),
+            (OccupancyStatus::ManySeatsAvailable, "LOW"),
+            (OccupancyStatus::FewSeatsAvailable, "MEDIUM"),
+            (OccupancyStatus::StandingRoomOnly, "MEDIUM"),
+            (OccupancyStatus::CrushedStandingRoomOnly, "HIGH"),
+            (OccupancyStatus::Full, "HIGH"),
+            (OccupancyStatus::NotAcceptingPassengers, "6"),
+        ];
+        for (status, code) in cases {
+            assert_eq!(mapping.code(status), code, "status {status:?}");
+        }
+        assert_eq!(mapping.remap("3"), "MEDIUM");
+        assert_eq!(mapping.remap("UNKNOWN"), "UNKNOWN");
+    }
+}
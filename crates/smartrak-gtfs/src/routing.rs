@@ -0,0 +1,67 @@
+//! Routing of vehicle positions to topics by the vehicle's Fleet tag.
+
+use common::config;
+use qwasr_sdk::Config;
+
+/// Topic vehicle positions are published to, unless routed elsewhere.
+pub const VP_TOPIC: &str = "realtime-gtfs-vp.v1";
+
+/// The topics a position for a vehicle with `tag` is published to, given the
+/// routes in `mapping`.
+///
+/// A tag routed to `topic` is published there as well as to [`VP_TOPIC`], and
+/// one routed to `only:topic` is published there instead.
+fn from_mapping<'a>(mapping: &'a str, tag: Option<&str>) -> Vec<&'a str> {
+    let Some(tag) = tag else {
+        return vec![VP_TOPIC];
+    };
+    let routed = config::pairs(mapping)
+        .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+        .map(|(_, topic)| topic)
+        .filter(|topic| !topic.is_empty());
+
+    match routed {
+        None => vec![VP_TOPIC],
+        Some(topic) => topic
+            .strip_prefix("only:")
+            .map_or_else(|| vec![VP_TOPIC, topic], |topic| vec![topic.trim()]),
+    }
+}
+
+/// The topics a position for a vehicle with the Fleet `tag` is published to,
+/// routed with `VP_TAG_TOPICS` as comma-separated `tag=topic` pairs, e.g.
+/// `caf=realtime-caf-vp.v1,smartrak=only:realtime-smartrak-vp.v1`. Tags not
+/// listed are published to [`VP_TOPIC`] only.
+pub async fn vp_topics(provider: &impl Config, tag: Option<&str>) -> Vec<String> {
+    let mapping = Config::get(provider, "VP_TAG_TOPICS").await.unwrap_or_default();
+    from_mapping(&mapping, tag).into_iter().map(ToString::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPING: &str = "CAF=realtime-caf-vp.v1, smartrak = only:realtime-smartrak-vp.v1,train=";
+
+    // Should publish a tagged vehicle's position to its tag's topic as well as
+    // the default one.
+    #[test]
+    fn extra_topic() {
+        assert_eq!(from_mapping(MAPPING, Some("caf")), [VP_TOPIC, "realtime-caf-vp.v1"]);
+    }
+
+    // Should publish a tagged vehicle's position to its tag's topic only.
+    #[test]
+    fn replacement_topic() {
+        assert_eq!(from_mapping(MAPPING, Some("Smartrak")), ["realtime-smartrak-vp.v1"]);
+    }
+
+    // Should publish to the default topic only when the tag isn't routed.
+    #[test]
+    fn unrouted() {
+        assert_eq!(from_mapping(MAPPING, Some("bus")), [VP_TOPIC]);
+        assert_eq!(from_mapping(MAPPING, Some("train")), [VP_TOPIC]);
+        assert_eq!(from_mapping(MAPPING, None), [VP_TOPIC]);
+        assert_eq!(from_mapping("", Some("caf")), [VP_TOPIC]);
+    }
+}
@@ -9,24 +9,24 @@ const TTL_TRIP_SERIAL_SECS: u64 = 4 * 60 * 60;
 const TTL_SIGN_ON_SECS: u64 = 24 * 60 * 60;
 const TTL_SERIAL_TIMESTAMP_SECS: u64 = 24 * 60 * 60;
 
-const SERIAL_DATA_THRESHOLD: i64 = 900;
+/// How far, in seconds, serial data may be future-dated.
+const MAX_FUTURE_SECS: i64 = 15 * 60;
+/// How old, in seconds, serial data may be.
+const MAX_AGE_SECS: i64 = 60 * 60;
 
 // Processes SmarTrak serial data events, updating allocations and  state.
 pub async fn process<P>(message: &SmarTrakMessage, provider: &P) -> Result<()>
 where
     P: HttpRequest + Publisher + StateStore + Identity + Config,
 {
-    let Some(vehicle_id) = message.vehicle_id() else {
+    let Some(vehicle_id) = message.vehicle_identifier() else {
         return Err(bad_request!("missing vehicle identifier"));
     };
 
     // validate timestamp
     let timestamp = message.timestamp()?;
 
-    // is this a future-dated (by 900 secs) timestamp?
-    if timestamp > Utc::now().timestamp() + SERIAL_DATA_THRESHOLD {
-        return Err(SmarTrakError::BadTime("future-dated serial data message".to_string()).into());
-    }
+    check_window(timestamp, Utc::now().timestamp())?;
 
     update_timestamp(provider, timestamp, vehicle_id).await?;
 
@@ -40,13 +40,28 @@ where
     allocate(vehicle_id, decoded, timestamp, provider).await
 }
 
+/// Reject serial data future-dated by more than 15 minutes, or older than an
+/// hour.
+fn check_window(timestamp: i64, now: i64) -> Result<()> {
+    let delta = timestamp - now;
+    if delta > MAX_FUTURE_SECS {
+        return Err(SmarTrakError::BadTime("future-dated serial data message".to_string()).into());
+    }
+    if delta < -MAX_AGE_SECS {
+        return Err(SmarTrakError::BadTime("stale serial data message".to_string()).into());
+    }
+    Ok(())
+}
+
 // Updates the timestamp if it is newer than the previously stored timestamp.
 async fn update_timestamp(store: &impl StateStore, timestamp: i64, vehicle_id: &str) -> Result<()> {
     let key = format!("smartrakGtfs:serialTimestamp:{vehicle_id}");
 
-    // check previous timestamp
-    let previous = StateStore::get(store, &key).await?;
-    if serde_json::from_value::<i64>(previous.into()).is_ok_and(|prev| prev >= timestamp) {
+    // check previous timestamp, persisted so it survives restarts
+    let previous = StateStore::get(store, &key)
+        .await?
+        .and_then(|bytes| serde_json::from_slice::<i64>(&bytes).ok());
+    if previous.is_some_and(|prev| prev >= timestamp) {
         return Err(SmarTrakError::BadTime("outdated serial data message".to_string()).into());
     }
 
@@ -64,36 +79,76 @@ where
     P: HttpRequest + Publisher + StateStore + Identity + Config,
 {
     let trip_key = format!("smartrakGtfs:trip:vehicle:{vehicle_id}");
-    let sign_on_key = format!("smartrakGtfs:vehicle:signOn:{vehicle_id}");
-    let serial_timestamp_key = format!("smartrakGtfs:serialTimestamp:{vehicle_id}");
-
-    let Some(trip_id) = decoded.trip_id.as_deref() else {
-        tracing::debug!(vehicle_id, "no trip id found, clearing state");
-
-        StateStore::delete(provider, &sign_on_key).await?;
-        StateStore::delete(provider, &trip_key).await?;
-        StateStore::delete(provider, &serial_timestamp_key).await?;
+    let prev = StateStore::get(provider, &trip_key).await?;
 
-        return Ok(());
+    // only look up the nearest trip instance when the vehicle changes trip
+    let nearest = match reassigned_trip(prev.as_deref(), decoded) {
+        Some(trip_id) => trip::get_nearest(trip_id, event_timestamp, provider).await?,
+        None => None,
     };
 
-    let Some(prev) = StateStore::get(provider, &trip_key).await? else {
-        return Ok(());
-    };
-    if serde_json::from_slice::<TripInstance>(&prev).is_ok_and(|t| t.trip_id == trip_id) {
-        return Ok(());
+    match allocation(prev.as_deref(), decoded, nearest) {
+        Allocation::Keep => Ok(()),
+        Allocation::Clear => {
+            tracing::debug!(vehicle_id, trip_id = ?decoded.trip_id, "clearing allocation state");
+            clear_trip(vehicle_id, provider).await
+        }
+        Allocation::Reassign(trip) => save_trip(vehicle_id, event_timestamp, trip, provider).await,
     }
+}
+
+/// Change to a vehicle's serial data allocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Allocation {
+    /// Leave the stored trip, if any, as it is.
+    Keep,
 
-    if let Some(trip) = trip::get_nearest(trip_id, event_timestamp, provider).await?
-        && !trip.has_error()
-    {
-        return save_trip(vehicle_id, event_timestamp, trip, provider).await;
+    /// Clear the stored trip, sign-on and serial data timestamp.
+    Clear,
+
+    /// Store the trip instance, signing the vehicle on to it.
+    Reassign(TripInstance),
+}
+
+/// Decide how the vehicle's allocation changes, from its stored trip (`prev`),
+/// its latest decoded serial data, and the nearest instance of the decoded
+/// trip when the vehicle changes trip.
+///
+/// | Decoded trip id | Stored trip     | Nearest instance | Allocation |
+/// |-----------------|-----------------|------------------|------------|
+/// | missing         | any             | -                | `Clear`    |
+/// | present         | none            | -                | `Keep`     |
+/// | present         | same trip       | -                | `Keep`     |
+/// | present         | other trip      | found            | `Reassign` |
+/// | present         | other trip      | missing or error | `Clear`    |
+fn allocation(
+    prev: Option<&[u8]>, decoded: &DecodedSerialData, nearest: Option<TripInstance>,
+) -> Allocation {
+    if decoded.trip_id.is_none() {
+        return Allocation::Clear;
+    }
+    if reassigned_trip(prev, decoded).is_none() {
+        return Allocation::Keep;
     }
+    match nearest {
+        Some(trip) if !trip.has_error() => Allocation::Reassign(trip),
+        _ => Allocation::Clear,
+    }
+}
 
-    StateStore::delete(provider, &sign_on_key).await?;
-    StateStore::delete(provider, &trip_key).await?;
-    StateStore::delete(provider, &serial_timestamp_key).await?;
+/// The decoded trip id, when it differs from the stored trip. A stored trip
+/// that can't be read is treated as different.
+fn reassigned_trip<'a>(prev: Option<&[u8]>, decoded: &'a DecodedSerialData) -> Option<&'a str> {
+    let trip_id = decoded.trip_id.as_deref()?;
+    let unchanged =
+        serde_json::from_slice::<TripInstance>(prev?).is_ok_and(|t| t.trip_id == trip_id);
+    (!unchanged).then_some(trip_id)
+}
 
+async fn clear_trip(vehicle_id: &str, store: &impl StateStore) -> Result<()> {
+    StateStore::delete(store, &format!("smartrakGtfs:vehicle:signOn:{vehicle_id}")).await?;
+    StateStore::delete(store, &format!("smartrakGtfs:trip:vehicle:{vehicle_id}")).await?;
+    StateStore::delete(store, &format!("smartrakGtfs:serialTimestamp:{vehicle_id}")).await?;
     Ok(())
 }
 
@@ -115,3 +170,141 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[derive(Default, Clone)]
+    struct MockStore {
+        store: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    }
+
+    impl StateStore for MockStore {
+        async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(self.store.lock().map_err(|e| anyhow!("{e}"))?.get(key).cloned())
+        }
+
+        async fn set(
+            &self, key: &str, value: &[u8], _ttl_secs: Option<u64>,
+        ) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(self
+                .store
+                .lock()
+                .map_err(|e| anyhow!("{e}"))?
+                .insert(key.to_string(), value.to_vec()))
+        }
+
+        async fn delete(&self, key: &str) -> anyhow::Result<()> {
+            self.store.lock().map_err(|e| anyhow!("{e}"))?.remove(key);
+            Ok(())
+        }
+    }
+
+    const NOW: i64 = 1_762_469_343;
+
+    fn decoded(trip_id: Option<&str>) -> DecodedSerialData {
+        DecodedSerialData {
+            trip_number: None,
+            trip_id: trip_id.map(ToString::to_string),
+            line_id: None,
+        }
+    }
+
+    fn trip(trip_id: &str, error: bool) -> TripInstance {
+        TripInstance { trip_id: trip_id.to_string(), error, ..TripInstance::default() }
+    }
+
+    // Should decide each allocation change from the stored trip, decoded trip
+    // id and nearest trip instance.
+    #[test]
+    fn allocation_table() {
+        let stored = serde_json::to_vec(&trip("trip-1", false)).expect("should serialize");
+        let stored = Some(stored.as_slice());
+        let unreadable = Some(b"not a trip".as_slice());
+
+        let cases = [
+            (None, None, None, Allocation::Clear),
+            (stored, None, Some(trip("trip-2", false)), Allocation::Clear),
+            (None, Some("trip-2"), Some(trip("trip-2", false)), Allocation::Keep),
+            (stored, Some("trip-1"), Some(trip("trip-1", false)), Allocation::Keep),
+            (
+                stored,
+                Some("trip-2"),
+                Some(trip("trip-2", false)),
+                Allocation::Reassign(trip("trip-2", false)),
+            ),
+            (
+                unreadable,
+                Some("trip-2"),
+                Some(trip("trip-2", false)),
+                Allocation::Reassign(trip("trip-2", false)),
+            ),
+            (stored, Some("trip-2"), Some(trip("trip-2", true)), Allocation::Clear),
+            (stored, Some("trip-2"), None, Allocation::Clear),
+        ];
+
+        for (prev, trip_id, nearest, expected) in cases {
+            let serial = decoded(trip_id);
+            assert_eq!(allocation(prev, &serial, nearest), expected, "{prev:?} {trip_id:?}");
+        }
+    }
+
+    // Should only look up the nearest trip instance when the vehicle changes
+    // trip.
+    #[test]
+    fn reassigned() {
+        let stored = serde_json::to_vec(&trip("trip-1", false)).expect("should serialize");
+
+        assert_eq!(reassigned_trip(Some(&stored), &decoded(Some("trip-2"))), Some("trip-2"));
+        assert_eq!(reassigned_trip(Some(&stored), &decoded(Some("trip-1"))), None);
+        assert_eq!(reassigned_trip(Some(&stored), &decoded(None)), None);
+        assert_eq!(reassigned_trip(None, &decoded(Some("trip-2"))), None);
+    }
+
+    // Should only accept serial data from at most an hour ago up to 15
+    // minutes ahead.
+    #[test]
+    fn timestamp_window() {
+        let future = check_window(NOW + 15 * 60 + 1, NOW).expect_err("future-dated");
+        assert_eq!(future.code(), "bad_time");
+        let past = check_window(NOW - 60 * 60 - 1, NOW).expect_err("stale");
+        assert_eq!(past.code(), "bad_time");
+
+        for timestamp in [NOW, NOW + 15 * 60, NOW - 60 * 60] {
+            check_window(timestamp, NOW).expect("should accept");
+        }
+    }
+
+    // Should reject serial data no newer than the last persisted timestamp.
+    #[tokio::test]
+    async fn outdated() {
+        let store = MockStore::default();
+        update_timestamp(&store, NOW, "1234").await.expect("should update");
+
+        let err = update_timestamp(&store, NOW, "1234").await.expect_err("duplicate");
+        assert_eq!(err.code(), "bad_time");
+        update_timestamp(&store, NOW - 1, "1234").await.expect_err("older");
+        update_timestamp(&store, NOW + 1, "1234").await.expect("should update");
+    }
+
+    // Should reject an older event after a restart, with only the persisted
+    // timestamp to go on.
+    #[tokio::test]
+    async fn restart() {
+        let persisted = MockStore::default();
+        update_timestamp(&persisted, NOW, "1234").await.expect("should update");
+
+        // a fresh instance sharing nothing but the store
+        let restarted = MockStore { store: Arc::clone(&persisted.store) };
+        update_timestamp(&restarted, NOW - 60, "1234").await.expect_err("older");
+
+        // other vehicles are unaffected
+        update_timestamp(&restarted, NOW - 60, "5678").await.expect("should update");
+    }
+}
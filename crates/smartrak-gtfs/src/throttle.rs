@@ -0,0 +1,175 @@
+//! Suppression of near-identical vehicle positions, such as those from trains
+//! idling at termini, so they don't flood the vehicle position topic.
+
+use anyhow::{Context, Result};
+use common::{config, geo};
+use qwasr_sdk::{Config, StateStore};
+use serde::{Deserialize, Serialize};
+
+use crate::trip::VehiclePosition;
+
+const KEY_LAST_POSITION: &str = "smartrakGtfs:lastPosition";
+const MIN_DISTANCE_METERS: f64 = 10.0;
+
+/// Summary of the last position published for a vehicle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Summary {
+    timestamp: i64,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    occupancy_status: Option<String>,
+    #[serde(default)]
+    trip_id: Option<String>,
+    #[serde(default)]
+    current_status: Option<String>,
+}
+
+impl Summary {
+    fn of(position: &VehiclePosition) -> Self {
+        Self {
+            timestamp: position.timestamp,
+            latitude: position.position.as_ref().and_then(|p| p.latitude),
+            longitude: position.position.as_ref().and_then(|p| p.longitude),
+            occupancy_status: position.occupancy_status.clone(),
+            trip_id: position.trip.as_ref().map(|trip| trip.trip_id.clone()),
+            current_status: position.current_status.clone(),
+        }
+    }
+}
+
+/// Whether a vehicle position should be published.
+///
+/// When `VP_MIN_INTERVAL_SECS` is set, a position is suppressed if the last
+/// one published for the vehicle was less than that many seconds apart, it
+/// has moved less than `VP_MIN_DISTANCE_METERS` (10 by default), and its
+/// trip, stop status and occupancy status are unchanged. Disabled by default.
+///
+/// # Errors
+///
+/// Returns an error if the last published position cannot be read or saved.
+pub async fn should_publish<P>(
+    provider: &P, vehicle_id: &str, position: &VehiclePosition,
+) -> Result<bool>
+where
+    P: Config + StateStore,
+{
+    let Some(interval) = interval(provider).await else {
+        return Ok(true);
+    };
+
+    let key = format!("{KEY_LAST_POSITION}:{vehicle_id}");
+    let last = StateStore::get(provider, &key)
+        .await?
+        .and_then(|bytes| serde_json::from_slice::<Summary>(&bytes).ok());
+
+    let next = Summary::of(position);
+    if is_duplicate(last.as_ref(), &next, interval, min_distance(provider).await) {
+        tracing::debug!(vehicle_id, "suppressing unchanged vehicle position");
+        return Ok(false);
+    }
+
+    let bytes = serde_json::to_vec(&next).context("serializing last position")?;
+    StateStore::set(provider, &key, &bytes, Some(interval.unsigned_abs())).await?;
+    Ok(true)
+}
+
+async fn interval(provider: &impl Config) -> Option<i64> {
+    config::checked_setting(provider, "VP_MIN_INTERVAL_SECS", |secs: &i64| *secs > 0).await
+}
+
+async fn min_distance(provider: &impl Config) -> f64 {
+    config::setting(provider, "VP_MIN_DISTANCE_METERS").await.unwrap_or(MIN_DISTANCE_METERS)
+}
+
+fn is_duplicate(last: Option<&Summary>, next: &Summary, interval: i64, min_distance: f64) -> bool {
+    let Some(last) = last else {
+        return false;
+    };
+    if (next.timestamp - last.timestamp).abs() >= interval
+        || next.occupancy_status != last.occupancy_status
+        || next.trip_id != last.trip_id
+        || next.current_status != last.current_status
+    {
+        return false;
+    }
+
+    match (last.latitude.zip(last.longitude), next.latitude.zip(next.longitude)) {
+        (Some((lat1, lon1)), Some((lat2, lon2))) => {
+            geo::haversine_meters(lat1, lon1, lat2, lon2) < min_distance
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_762_469_343;
+
+    fn summary(timestamp: i64, latitude: f64, occupancy_status: &str) -> Summary {
+        Summary {
+            timestamp,
+            latitude: Some(latitude),
+            longitude: Some(174.7676),
+            occupancy_status: Some(occupancy_status.to_string()),
+            trip_id: Some("trip-1".to_string()),
+            current_status: None,
+        }
+    }
+
+    // Should suppress an idle vehicle's position within the interval.
+    #[test]
+    fn idle() {
+        let last = summary(NOW, -36.8443, "1");
+
+        assert!(is_duplicate(Some(&last), &summary(NOW + 10, -36.8443, "1"), 60, 10.0));
+        // ~5.5 m of GPS jitter
+        assert!(is_duplicate(Some(&last), &summary(NOW + 59, -36.84435, "1"), 60, 10.0));
+    }
+
+    // Should publish once the vehicle has moved, its occupancy has changed or
+    // the interval has passed.
+    #[test]
+    fn changed() {
+        let last = summary(NOW, -36.8443, "1");
+
+        assert!(!is_duplicate(None, &last, 60, 10.0));
+        // ~111 m
+        assert!(!is_duplicate(Some(&last), &summary(NOW + 10, -36.8453, "1"), 60, 10.0));
+        assert!(!is_duplicate(Some(&last), &summary(NOW + 10, -36.8443, "2"), 60, 10.0));
+        assert!(!is_duplicate(Some(&last), &summary(NOW + 60, -36.8443, "1"), 60, 10.0));
+
+        let unlocated = Summary { latitude: None, ..summary(NOW + 10, -36.8443, "1") };
+        assert!(!is_duplicate(Some(&last), &unlocated, 60, 10.0));
+    }
+
+    // Should publish once the vehicle has started another trip or arrived at
+    // or left a stop, even when it hasn't moved.
+    #[test]
+    fn trip_or_status_changed() {
+        let last = summary(NOW, -36.8443, "1");
+
+        let next_trip =
+            Summary { trip_id: Some("trip-2".to_string()), ..summary(NOW + 10, -36.8443, "1") };
+        assert!(!is_duplicate(Some(&last), &next_trip, 60, 10.0));
+
+        let stopped = Summary {
+            current_status: Some("STOPPED_AT".to_string()),
+            ..summary(NOW + 10, -36.8443, "1")
+        };
+        assert!(!is_duplicate(Some(&last), &stopped, 60, 10.0));
+    }
+
+    // Should compare the interval either way, so a late position from well
+    // before the last isn't suppressed.
+    #[test]
+    fn out_of_order() {
+        let last = summary(NOW, -36.8443, "1");
+
+        assert!(is_duplicate(Some(&last), &summary(NOW - 10, -36.8443, "1"), 60, 10.0));
+        assert!(!is_duplicate(Some(&last), &summary(NOW - 60, -36.8443, "1"), 60, 10.0));
+    }
+}
@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use bytes::Bytes;
 use chrono::{Duration, NaiveDate, TimeZone, Timelike};
 use chrono_tz::Tz;
+use common::url;
 use http::header::{CACHE_CONTROL, CONTENT_TYPE};
 use http::{Method, StatusCode};
 use http_body_util::Full;
@@ -77,18 +78,42 @@ where
         trips.extend(previous);
     }
 
-    if trips.is_empty() {
-        return Ok(None);
-    }
+    let tie_break = TieBreak::from_config(provider).await;
+    Ok(nearest(trips, event_dt.timestamp(), &current_date, tie_break, tz))
+}
 
-    trips.sort_by(|left, right| {
-        let event_ts = event_dt.timestamp();
-        let left_diff = difference(event_ts, left, tz);
-        let right_diff = difference(event_ts, right, tz);
-        left_diff.cmp(&right_diff)
-    });
+/// How to choose between trip instances equally close to an event, such as a
+/// current and previous service day instance either side of midnight.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// Prefer the instance on the event's service day.
+    #[default]
+    CurrentDay,
+
+    /// Prefer the instance running at the time of the event, then the
+    /// instance on the event's service day.
+    Containing,
+}
+
+impl TieBreak {
+    /// Read from `TRIP_TIE_BREAK` (`current-day` or `containing`), defaulting
+    /// to [`TieBreak::CurrentDay`].
+    pub async fn from_config(provider: &impl Config) -> Self {
+        let value = Config::get(provider, "TRIP_TIE_BREAK").await.unwrap_or_default();
+        match value.trim().to_ascii_lowercase().as_str() {
+            "containing" => Self::Containing,
+            _ => Self::CurrentDay,
+        }
+    }
+}
 
-    Ok(trips.into_iter().next())
+fn nearest(
+    trips: Vec<TripInstance>, event_ts: i64, current_date: &str, tie_break: TieBreak, tz: Tz,
+) -> Option<TripInstance> {
+    trips.into_iter().min_by_key(|trip| {
+        let outside = tie_break == TieBreak::Containing && !trip.is_active_at(event_ts, tz, 0);
+        (difference(event_ts, trip, tz), outside, trip.service_date != current_date)
+    })
 }
 
 async fn fetch<P>(trip_id: &str, service_date: &str, provider: &P) -> Result<Vec<TripInstance>>
@@ -96,7 +121,7 @@ where
     P: HttpRequest + Config,
 {
     let base_url = Config::get(provider, "TRIP_MANAGEMENT_URL").await?;
-    let endpoint = format!("{}/tripinstances", base_url.trim_end_matches('/'));
+    let endpoint = url::join(&base_url, "tripinstances");
 
     let payload = serde_json::json!({
         "tripIds": [trip_id],
@@ -183,18 +208,22 @@ fn difference(event_ts: i64, trip: &TripInstance, tz: Tz) -> i64 {
 }
 
 fn timestamp(trip: &TripInstance, tz: Tz) -> Option<i64> {
-    let date = NaiveDate::parse_from_str(&trip.service_date, "%Y%m%d").ok()?;
-    let total_seconds = parse_time(&trip.start_time)?;
-    let days = total_seconds.div_euclid(86_400);
-    let remaining = total_seconds.rem_euclid(86_400);
-
-    let hours = u32::try_from(remaining / 3_600).ok()?;
-    let minutes = u32::try_from((remaining % 3_600) / 60).ok()?;
-    let seconds = u32::try_from(remaining % 60).ok()?;
-
-    let date = date + Duration::days(days);
-    let local = date.and_hms_opt(hours, minutes, seconds)?;
-    tz.from_local_datetime(&local).single().map(|dt| dt.timestamp())
+    local_timestamp(&trip.service_date, &trip.start_time, tz)
+}
+
+/// Timestamp of a GTFS `HH:MM:SS` time on a `YYYYMMDD` service date, in local
+/// time.
+///
+/// Times past `24:00:00`, for service running after midnight, roll into the
+/// following days. A local time repeated when clocks go back resolves to its
+/// first occurrence, and one skipped when clocks go forward to an hour later.
+fn local_timestamp(service_date: &str, time: &str, tz: Tz) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(service_date, "%Y%m%d").ok()?;
+    let local = date.and_hms_opt(0, 0, 0)? + Duration::seconds(parse_time(time)?);
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(local + Duration::hours(1))).earliest())
+        .map(|dt| dt.timestamp())
 }
 
 fn parse_time(time: &str) -> Option<i64> {
@@ -202,6 +231,9 @@ fn parse_time(time: &str) -> Option<i64> {
     let hours: i64 = parts.next()?.parse().ok()?;
     let minutes: i64 = parts.next()?.parse().ok()?;
     let seconds: i64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
     Some(hours * 3_600 + minutes * 60 + seconds)
 }
 
@@ -229,6 +261,32 @@ impl TripInstance {
         self.error
     }
 
+    /// Whether both are the same instance of a trip: the same trip, service
+    /// date and start time. The `error` flag is ignored so an error placeholder
+    /// and a resolved instance of the same trip compare equal.
+    #[must_use]
+    pub fn same_trip(&self, other: &Self) -> bool {
+        self.trip_id == other.trip_id
+            && self.service_date == other.service_date
+            && self.start_time == other.start_time
+    }
+
+    /// The trip's scheduled start and end timestamps, in `tz`, or `None`
+    /// when either time can't be parsed.
+    #[must_use]
+    pub fn window(&self, tz: Tz) -> Option<(i64, i64)> {
+        let start = local_timestamp(&self.service_date, &self.start_time, tz)?;
+        let end = local_timestamp(&self.service_date, &self.end_time, tz)?;
+        Some((start, end))
+    }
+
+    /// Whether the trip is running at `timestamp`: from its scheduled start
+    /// until `buffer` seconds after its scheduled end.
+    #[must_use]
+    pub fn is_active_at(&self, timestamp: i64, tz: Tz, buffer: i64) -> bool {
+        self.window(tz).is_some_and(|(start, end)| (start..=end + buffer).contains(&timestamp))
+    }
+
     #[must_use]
     pub fn remap(&self, trip_id: &str, route_id: &str) -> Self {
         let mut clone = self.clone();
@@ -255,6 +313,22 @@ impl From<&TripInstance> for TripDescriptor {
     }
 }
 
+/// The feed a location event was received on, recorded on emitted events so
+/// positions can be attributed to their source.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// R9K train updates converted to SmarTrak events
+    /// (`realtime-r9k-to-smartrak.v1`).
+    R9k,
+
+    /// CAF train AVL (`realtime-caf-avl.v1`).
+    Caf,
+
+    /// Native SmarTrak train AVL (`realtime-train-avl.v1`).
+    SmarTrak,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DeadReckoningMessage {
@@ -263,6 +337,7 @@ pub struct DeadReckoningMessage {
     pub position: PositionDr,
     pub trip: TripDescriptor,
     pub vehicle: VehicleDr,
+    pub source: Source,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -282,6 +357,8 @@ pub struct VehicleDr {
 pub struct FeedEntity {
     pub id: String,
     pub vehicle: Option<VehiclePosition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -292,6 +369,12 @@ pub struct VehiclePosition {
     pub vehicle: Option<VehicleDescriptor>,
     pub occupancy_status: Option<String>,
     pub timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub congestion_level: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -304,6 +387,23 @@ pub struct Position {
     pub odometer: Option<f64>,
 }
 
+impl Position {
+    /// Fastest plausible speed, in km/h, of a metro train.
+    pub const MAX_SPEED_KMH: f64 = 120.0;
+
+    /// Convert a speed reported in km/h to the m/s GTFS-RT expects.
+    ///
+    /// Negative or implausibly fast readings are dropped with a warning.
+    #[must_use]
+    pub fn speed_from_kmh(kmh: f64) -> Option<f64> {
+        if !(0.0..=Self::MAX_SPEED_KMH).contains(&kmh) {
+            tracing::warn!(speed = kmh, "implausible speed, dropping");
+            return None;
+        }
+        Some(kmh * 1000.0 / 3600.0)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct VehicleDescriptor {
@@ -350,4 +450,130 @@ mod tests {
         // 12:15 UTC — 44_100 seconds from midnight.
         assert_eq!(timestamp % 86_400, 44_100);
     }
+
+    // Should roll times past 24:00:00 into the next day across the end of
+    // daylight saving, taking the first of the repeated hour.
+    #[test]
+    fn extended_hours_nzdt_to_nzst() {
+        let tz = chrono_tz::Pacific::Auckland;
+
+        // 02:30 on 6 April 2025 occurs in NZDT, then again in NZST
+        let timestamp = local_timestamp("20250405", "26:30:00", tz);
+        assert_eq!(timestamp, Some(1_743_859_800)); // 2025-04-05T13:30:00Z
+
+        // 30 hours and more
+        let timestamp = local_timestamp("20250405", "30:30:00", tz);
+        assert_eq!(timestamp, Some(1_743_877_800)); // 2025-04-05T18:30:00Z
+    }
+
+    // Should roll times past 24:00:00 into the next day across the start of
+    // daylight saving, moving times in the skipped hour an hour later.
+    #[test]
+    fn extended_hours_nzst_to_nzdt() {
+        let tz = chrono_tz::Pacific::Auckland;
+
+        // 02:30 on 28 September 2025 is skipped, becoming 03:30 NZDT
+        let timestamp = local_timestamp("20250927", "26:30:00", tz);
+        assert_eq!(timestamp, Some(1_758_983_400)); // 2025-09-27T14:30:00Z
+
+        assert_eq!(local_timestamp("20250927", "26:30", tz), None);
+        assert_eq!(local_timestamp("20250927", "26:30:00:00", tz), None);
+        assert_eq!(local_timestamp("20250927", "", tz), None);
+    }
+
+    // Should run from the trip's start until the buffer after its end,
+    // including a trip ending past midnight.
+    #[test]
+    fn active_window() {
+        let tz = chrono_tz::Pacific::Auckland;
+        let trip = TripInstance {
+            service_date: "20251107".to_string(),
+            start_time: "25:00:00".to_string(),
+            end_time: "26:00:00".to_string(),
+            ..TripInstance::default()
+        };
+        let (start, end) = (1_762_516_800, 1_762_520_400); // 2025-11-07T12:00Z, 13:00Z
+        assert_eq!(trip.window(tz), Some((start, end)));
+
+        assert!(!trip.is_active_at(start - 1, tz, 3_600));
+        assert!(trip.is_active_at(start, tz, 3_600));
+        assert!(trip.is_active_at(end, tz, 0));
+        assert!(!trip.is_active_at(end + 1, tz, 0));
+        assert!(trip.is_active_at(end + 3_600, tz, 3_600));
+        assert!(!trip.is_active_at(end + 3_601, tz, 3_600));
+
+        let unscheduled = TripInstance { end_time: "26:00".to_string(), ..trip };
+        assert_eq!(unscheduled.window(tz), None);
+        assert!(!unscheduled.is_active_at(start, tz, 3_600));
+    }
+
+    // Should treat error and resolved instances of a trip as the same trip.
+    #[test]
+    fn same_trip() {
+        let trip = TripInstance {
+            trip_id: "trip-1".to_string(),
+            route_id: "STH-201".to_string(),
+            service_date: "20251107".to_string(),
+            start_time: "08:00:00".to_string(),
+            ..TripInstance::default()
+        };
+        let errored = TripInstance { error: true, route_id: String::new(), ..trip.clone() };
+
+        assert!(trip.same_trip(&trip));
+        assert!(trip.same_trip(&errored));
+        assert!(errored.same_trip(&trip));
+        assert_ne!(trip, errored);
+
+        let later = TripInstance { start_time: "09:00:00".to_string(), ..trip.clone() };
+        assert!(!trip.same_trip(&later));
+        let next_day = TripInstance { service_date: "20251108".to_string(), ..trip.clone() };
+        assert!(!trip.same_trip(&next_day));
+        assert!(!trip.same_trip(&TripInstance { error: true, ..TripInstance::default() }));
+    }
+
+    // Should break ties between equidistant instances either side of midnight
+    // by the configured strategy.
+    #[test]
+    fn midnight_tie_break() {
+        let tz = chrono_tz::Pacific::Auckland;
+        // 00:30 NZDT on 8 November
+        let event_ts = 1_762_515_000;
+        let instance = |service_date: &str, start_time: &str, end_time: &str| TripInstance {
+            trip_id: "trip-1".to_string(),
+            service_date: service_date.to_string(),
+            start_time: start_time.to_string(),
+            end_time: end_time.to_string(),
+            ..TripInstance::default()
+        };
+        // 30 minutes after the event, not yet running
+        let current = instance("20251108", "01:00:00", "02:00:00");
+        // 30 minutes before the event, still running
+        let previous = instance("20251107", "24:00:00", "25:00:00");
+        assert_eq!(difference(event_ts, &current, tz), difference(event_ts, &previous, tz));
+
+        for trips in
+            [vec![current.clone(), previous.clone()], vec![previous.clone(), current.clone()]]
+        {
+            let nearest_current =
+                nearest(trips.clone(), event_ts, "20251108", TieBreak::CurrentDay, tz);
+            assert_eq!(nearest_current, Some(current.clone()));
+            let nearest_containing = nearest(trips, event_ts, "20251108", TieBreak::Containing, tz);
+            assert_eq!(nearest_containing, Some(previous.clone()));
+        }
+
+        // the closest instance still wins regardless of strategy
+        let closer = instance("20251108", "00:45:00", "01:45:00");
+        let trips = vec![previous, closer.clone()];
+        assert_eq!(nearest(trips, event_ts, "20251108", TieBreak::Containing, tz), Some(closer));
+    }
+
+    // Should convert plausible speeds to m/s and drop the rest.
+    #[test]
+    fn speed_from_kmh() {
+        assert_eq!(Position::speed_from_kmh(0.0), Some(0.0));
+        let speed = Position::speed_from_kmh(50.0).expect("plausible");
+        assert!((speed - 13.888_888).abs() < 1e-6);
+        assert_eq!(Position::speed_from_kmh(-5.0), None);
+        assert_eq!(Position::speed_from_kmh(400.0), None);
+    }
 }
@@ -1,21 +1,31 @@
 #![cfg(target_arch = "wasm32")]
 
+use std::any::Any;
+use std::error::Error as StdError;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::Result;
 use axum::Router;
-use axum::extract::Path;
+use axum::extract::{DefaultBodyLimit, Path, Query};
 use axum::routing::{get, post};
 use bytes::Bytes;
-use dilax_adapter::{DetectionReply, DetectionRequest, DilaxMessage};
+use common::topic::{TopicKind, TopicPrefixes};
+use common::{capture, limit, payload};
+use dilax_adapter::{
+    CountAuditReply, CountAuditRequest, DetectionLog, DetectionLogRequest, DetectionQuery,
+    DetectionReply, DetectionRequest, DilaxMessage,
+};
 use dilax_apc_connector::{DilaxReply, DilaxRequest};
+use http::{Request, Response};
 use qwasr_sdk::{
     Config, Handler, HttpRequest, HttpResult, Identity, Publisher, Reply, StateStore, ensure_env,
 };
 use qwasr_wasi_messaging::types::{Error, Message};
-use r9k_adapter::R9kMessage;
+use r9k_adapter::{R9kMessage, StationMapReply, StationMapRequest};
 use r9k_connector::{R9kReply, R9kRequest};
 use smartrak_gtfs::{
-    CafAvlMessage, PassengerCountMessage, ResetReply, ResetRequest, SetTripReply, SetTripRequest,
-    SmarTrakMessage, TrainAvlMessage, VehicleInfoReply, VehicleInfoRequest,
+    CafAvlMessage, PassengerCountMessage, ResetQuery, ResetReply, ResetRequest, SetTripReply,
+    SetTripRequest, SmarTrakMessage, TrainAvlMessage, VehicleInfoReply, VehicleInfoRequest,
 };
 use tracing::Level;
 use wasip3::exports::http::handler::Guest;
@@ -27,13 +37,19 @@ wasip3::http::proxy::export!(Http);
 impl Guest for Http {
     #[qwasr_wasi_otel::instrument(name = "http_guest_handle", level = Level::INFO)]
     async fn handle(request: p3::Request) -> Result<p3::Response, p3::ErrorCode> {
+        // oversized bodies are rejected with `413 Payload Too Large`
+        let max_payload = payload::max_size(&Provider).await;
         let router = Router::new()
             .route("/api/apc", post(dilax_message))
             .route("/inbound/xml", post(r9k_message))
             .route("/jobs/detector", get(detector))
+            .route("/debug/dilax/detection/{set_key}/{vehicle_trip}", get(detection))
+            .route("/debug/dilax/count/{vehicle_id}/{trip_id}", get(count_audit))
             .route("/info/{vehicle_id}", get(vehicle_info))
             .route("/god-mode/set-trip/{vehicle_id}/{trip_id}", get(set_trip))
-            .route("/god-mode/reset/{vehicle_id}", get(reset));
+            .route("/god-mode/reset/{vehicle_id}", get(reset))
+            .route("/god-mode/r9k/stations/refresh", get(refresh_stations))
+            .layer(DefaultBodyLimit::max(max_payload));
         qwasr_wasi_http::serve(router, request).await
     }
 }
@@ -54,8 +70,32 @@ async fn r9k_message(body: Bytes) -> HttpResult<Reply<R9kReply>> {
         .map_err(Into::into)
 }
 
-async fn detector() -> HttpResult<Reply<DetectionReply>> {
-    DetectionRequest::handler(())?.provider(&Provider::new()).owner("at").await.map_err(Into::into)
+async fn detector(Query(query): Query<DetectionQuery>) -> HttpResult<Reply<DetectionReply>> {
+    DetectionRequest::handler(query)?
+        .provider(&Provider::new())
+        .owner("at")
+        .await
+        .map_err(Into::into)
+}
+
+async fn detection(
+    Path((set_key, vehicle_trip)): Path<(String, String)>,
+) -> HttpResult<Reply<DetectionLog>> {
+    DetectionLogRequest::handler((set_key, vehicle_trip))?
+        .provider(&Provider::new())
+        .owner("at")
+        .await
+        .map_err(Into::into)
+}
+
+async fn count_audit(
+    Path((vehicle_id, trip_id)): Path<(String, String)>,
+) -> HttpResult<Reply<CountAuditReply>> {
+    CountAuditRequest::handler((vehicle_id, trip_id))?
+        .provider(&Provider::new())
+        .owner("at")
+        .await
+        .map_err(Into::into)
 }
 
 async fn vehicle_info(Path(vehicle_id): Path<String>) -> HttpResult<Reply<VehicleInfoReply>> {
@@ -76,28 +116,55 @@ async fn set_trip(
         .map_err(Into::into)
 }
 
-async fn reset(Path(vehicle_id): Path<String>) -> HttpResult<Reply<ResetReply>> {
-    ResetRequest::handler(vehicle_id)?
+async fn reset(
+    Path(vehicle_id): Path<String>, Query(query): Query<ResetQuery>,
+) -> HttpResult<Reply<ResetReply>> {
+    ResetRequest::handler((vehicle_id, query.scope))?
         .provider(&Provider::new())
         .owner("at")
         .await
         .map_err(Into::into)
 }
 
+async fn refresh_stations() -> HttpResult<Reply<StationMapReply>> {
+    StationMapRequest::handler(())?.provider(&Provider::new()).owner("at").await.map_err(Into::into)
+}
+
 pub struct Messaging;
 qwasr_wasi_messaging::export!(Messaging with_types_in qwasr_wasi_messaging);
 
 impl qwasr_wasi_messaging::incoming_handler::Guest for Messaging {
     #[qwasr_wasi_otel::instrument(name = "messaging_guest_handle")]
     async fn handle(message: Message) -> Result<(), Error> {
-        if let Err(e) = match &message.topic().unwrap_or_default() {
-            t if t.contains("realtime-r9k.v1") => r9k(message.data()).await,
-            t if t.contains("realtime-r9k-to-smartrak.v1") => smartrak(message.data()).await,
-            t if t.contains("realtime-dilax-apc.v2") => dilax(message.data()).await,
-            t if t.contains("realtime-caf-avl.v1") => caf_avl(message.data()).await,
-            t if t.contains("realtime-train-avl.v1") => train_avl(message.data()).await,
-            t if t.contains("realtime-passenger-count.v1") => passenger_count(message.data()).await,
-            _ => {
+        let max_payload = payload::max_size(&Provider).await;
+        if let Err(e) = payload::check(&message.data(), max_payload) {
+            return Err(Error::Other(e.to_string()));
+        }
+
+        // when strict, only this environment's topics, or shared ones, are handled
+        let topic = message.topic().unwrap_or_default();
+        let Some(name) = TopicPrefixes::load(&Provider).await.strip(&topic) else {
+            return Err(Error::Other("Incorrect environment".to_string()));
+        };
+
+        // keep the raw payload for replay when capture is enabled
+        let kind = TopicKind::classify(name);
+        let received_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| u64::try_from(since.as_millis()).unwrap_or(u64::MAX))
+            .unwrap_or_default();
+        if let Err(e) = capture::capture(&Provider, kind, received_at, &message.data()).await {
+            tracing::warn!("failed to capture payload: {e}");
+        }
+
+        if let Err(e) = match kind {
+            TopicKind::R9k => r9k(message.data()).await,
+            TopicKind::R9kToSmarTrak => smartrak(message.data()).await,
+            TopicKind::DilaxApc => dilax(message.data()).await,
+            TopicKind::CafAvl => caf_avl(message.data()).await,
+            TopicKind::TrainAvl => train_avl(message.data()).await,
+            TopicKind::PassengerCount => passenger_count(message.data()).await,
+            TopicKind::Unknown => {
                 return Err(Error::Other("Unhandled topic".to_string()));
             }
         } {
@@ -186,7 +253,22 @@ impl Provider {
 }
 
 impl Config for Provider {}
-impl HttpRequest for Provider {}
+
+impl HttpRequest for Provider {
+    async fn fetch<T>(&self, request: Request<T>) -> Result<Response<Bytes>>
+    where
+        T: http_body::Body + Any,
+        T::Data: Into<Vec<u8>>,
+        T::Error: Into<Box<dyn StdError + Send + Sync + 'static>>,
+    {
+        limit::fetch(self, Upstream.fetch(request)).await
+    }
+}
+
+/// Unrestricted host fetch wrapped by `Provider`.
+struct Upstream;
+impl HttpRequest for Upstream {}
+
 impl Identity for Provider {}
 impl Publisher for Provider {}
 impl StateStore for Provider {}
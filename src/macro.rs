@@ -1,14 +1,24 @@
 #![cfg(target_arch = "wasm32")]
 
-use dilax_adapter::{DetectionReply, DetectionRequest, DilaxMessage};
+use std::any::Any;
+use std::error::Error;
+
+use anyhow::Result;
+use bytes::Bytes;
+use common::limit;
+use dilax_adapter::{
+    CountAuditReply, CountAuditRequest, DetectionLog, DetectionLogRequest, DetectionReply,
+    DetectionRequest, DilaxMessage,
+};
 use dilax_apc_connector::{DilaxReply, DilaxRequest};
-use r9k_adapter::R9kMessage;
+use http::{Request, Response};
+use qwasr_sdk::{Config, HttpRequest, Identity, Publisher, StateStore, ensure_env};
+use r9k_adapter::{R9kMessage, StationMapReply, StationMapRequest};
 use r9k_connector::{R9kReply, R9kRequest};
 use smartrak_gtfs::{
     CafAvlMessage, PassengerCountMessage, ResetReply, ResetRequest, SetTripReply, SetTripRequest,
     SmarTrakMessage, TrainAvlMessage, VehicleInfoReply, VehicleInfoRequest,
 };
-use qwasr_sdk::{Config, HttpRequest, Identity, Publisher, StateStore, ensure_env};
 
 qwasr_sdk::guest!({
     owner: "at",
@@ -17,9 +27,12 @@ qwasr_sdk::guest!({
         "/api/apc": post(DilaxRequest with_body, DilaxReply),
         "/inbound/xml": post(R9kRequest with_body, R9kReply),
         "/jobs/detector": get(DetectionRequest, DetectionReply),
+        "/debug/dilax/detection/{set_key}/{vehicle_trip}": get(DetectionLogRequest, DetectionLog),
+        "/debug/dilax/count/{vehicle_id}/{trip_id}": get(CountAuditRequest, CountAuditReply),
         "/info/{vehicle_id}": get(VehicleInfoRequest, VehicleInfoReply),
         "/god-mode/set-trip/{vehicle_id}/{trip_id}": get(SetTripRequest, SetTripReply),
         "/god-mode/reset/{vehicle_id}": get(ResetRequest, ResetReply),
+        "/god-mode/r9k/stations/refresh": get(StationMapRequest, StationMapReply),
     ],
     messaging: [
         "realtime-r9k.v1": R9kMessage,
@@ -50,7 +63,22 @@ impl Provider {
 }
 
 impl Config for Provider {}
-impl HttpRequest for Provider {}
+
+impl HttpRequest for Provider {
+    async fn fetch<T>(&self, request: Request<T>) -> Result<Response<Bytes>>
+    where
+        T: http_body::Body + Any,
+        T::Data: Into<Vec<u8>>,
+        T::Error: Into<Box<dyn Error + Send + Sync + 'static>>,
+    {
+        limit::fetch(self, Upstream.fetch(request)).await
+    }
+}
+
+/// Unrestricted host fetch wrapped by `Provider`.
+struct Upstream;
+impl HttpRequest for Upstream {}
+
 impl Identity for Provider {}
 impl Publisher for Provider {}
 impl StateStore for Provider {}